@@ -0,0 +1,576 @@
+//! Deflate64 ("enhanced deflate") compression of zip entries
+//!
+//! Deflate64 is deflate with a 64 KiB window: distance codes 30 and 31 reach back 65536
+//! bytes, and length code 285 carries 16 extra bits for matches of up to 65538 bytes.
+//! Input is matched through hash chains and written as dynamic Huffman blocks, or as
+//! stored blocks when those are smaller.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+
+const WINDOW: usize = 1 << 16;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 65538;
+/// Input compressed per block
+const BLOCK_INPUT: usize = 1 << 18;
+const HASH_BITS: u32 = 15;
+
+const END_OF_BLOCK: usize = 256;
+const LITERAL_CODES: usize = 286;
+const DISTANCE_CODES: usize = 32;
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+
+const LENGTH_BASE: [u32; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 3,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 16,
+];
+const DISTANCE_BASE: [u32; 32] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577, 32769, 49153,
+];
+const DISTANCE_EXTRA: [u8; 32] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13, 14, 14,
+];
+/// Order of the code length code lengths in a block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u32, distance: u32 },
+}
+
+/// Length code (index into the length tables) of a match length
+fn length_code(length: u32) -> usize {
+    if length > 258 {
+        return 28;
+    }
+    // Lengths up to 258 use codes 257..=284; code 284 covers 227..=258
+    LENGTH_BASE[..28]
+        .iter()
+        .rposition(|base| *base <= length)
+        .unwrap_or(0)
+}
+
+fn distance_code(distance: u32) -> usize {
+    DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base <= distance)
+        .unwrap_or(0)
+}
+
+/// Bits written least significant first
+struct BitWriter<W: Write> {
+    writer: W,
+    bits: u64,
+    count: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> BitWriter<W> {
+    fn write_bits(&mut self, value: u32, count: u8) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count as u32;
+        while self.count >= 8 {
+            self.buffer.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write_bits(0, (8 - self.count) as u8);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Huffman code lengths of the symbols, at most `limit` bits. At least two symbols get a code.
+fn code_lengths(frequencies: &[u32], limit: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    // A complete code needs two symbols; give unused ones a nominal frequency
+    let used = frequencies.iter().filter(|f| **f > 0).count();
+    for frequency in frequencies
+        .iter_mut()
+        .filter(|f| **f == 0)
+        .take(2usize.saturating_sub(used))
+    {
+        *frequency = 1;
+    }
+
+    loop {
+        let lengths = huffman_lengths(&frequencies);
+        if lengths.iter().all(|l| *l <= limit) {
+            return lengths;
+        }
+        // Flatten the distribution until the tree is shallow enough
+        for frequency in frequencies.iter_mut().filter(|f| **f > 0) {
+            *frequency = (*frequency >> 1) | 1;
+        }
+    }
+}
+
+/// Unlimited Huffman code lengths
+fn huffman_lengths(frequencies: &[u32]) -> Vec<u8> {
+    // Nodes: leaves first, then internal nodes; the heap orders by weight, then by id
+    let mut parents = vec![usize::MAX; frequencies.len()];
+    let mut heap = BinaryHeap::new();
+    for (symbol, frequency) in frequencies.iter().enumerate() {
+        if *frequency > 0 {
+            heap.push(Reverse((*frequency as u64, symbol)));
+        }
+    }
+    while heap.len() > 1 {
+        let (Some(Reverse((a, left))), Some(Reverse((b, right)))) = (heap.pop(), heap.pop()) else {
+            break;
+        };
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[left] = node;
+        parents[right] = node;
+        heap.push(Reverse((a + b, node)));
+    }
+
+    (0..frequencies.len())
+        .map(|symbol| {
+            if frequencies[symbol] == 0 {
+                return 0;
+            }
+            let mut depth = 0u32;
+            let mut node = symbol;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                depth += 1;
+            }
+            depth.min(u8::MAX as u32) as u8
+        })
+        .collect()
+}
+
+/// Canonical codes for the lengths, bit-reversed for writing least significant first
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut counts = [0u32; 16];
+    for length in lengths.iter().filter(|l| **l > 0) {
+        counts[*length as usize] += 1;
+    }
+    let mut next = [0u32; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + counts[bits - 1]) << 1;
+        next[bits] = code;
+    }
+    lengths
+        .iter()
+        .map(|length| {
+            if *length == 0 {
+                return 0;
+            }
+            let code = next[*length as usize];
+            next[*length as usize] += 1;
+            code.reverse_bits() >> (32 - *length as u32)
+        })
+        .collect()
+}
+
+/// Run-length encoding of code lengths as (symbol, extra bits value, extra bits count)
+fn run_lengths(lengths: &[u8]) -> Vec<(usize, u32, u8)> {
+    let mut symbols = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let length = lengths[i];
+        let run = lengths[i..].iter().take_while(|l| **l == length).count();
+        if length == 0 && run >= 3 {
+            let run = run.min(138);
+            if run <= 10 {
+                symbols.push((17, run as u32 - 3, 3));
+            } else {
+                symbols.push((18, run as u32 - 11, 7));
+            }
+            i += run;
+        } else if length != 0 && run >= 4 {
+            symbols.push((length as usize, 0, 0));
+            let repeat = (run - 1).min(6);
+            symbols.push((16, repeat as u32 - 3, 2));
+            i += 1 + repeat;
+        } else {
+            symbols.push((length as usize, 0, 0));
+            i += 1;
+        }
+    }
+    symbols
+}
+
+/// Streaming Deflate64 compressor; call [`Encoder::finish`] to end the stream
+pub struct Encoder<W: Write> {
+    output: BitWriter<W>,
+    /// Up to one window of history followed by pending input
+    buffer: Vec<u8>,
+    /// First byte of `buffer` that is not yet compressed
+    start: usize,
+    /// Most recent position + 1 of each hash, 0 for none
+    head: Vec<u32>,
+    /// Previous position + 1 with the same hash, by position
+    prev: Vec<u32>,
+    max_chain: usize,
+    nice_length: usize,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Compression `level` from 1 (fastest) to 9 (smallest)
+    pub fn new(writer: W, level: u32) -> Self {
+        let (max_chain, nice_length) = match level {
+            0 | 1 => (4, 16),
+            2 => (8, 32),
+            3 => (16, 64),
+            4 => (32, 128),
+            5 => (64, 258),
+            6 => (128, 258),
+            7 => (256, 1024),
+            8 => (1024, 8192),
+            _ => (4096, MAX_MATCH),
+        };
+        Self {
+            output: BitWriter {
+                writer,
+                bits: 0,
+                count: 0,
+                buffer: Vec::new(),
+            },
+            buffer: Vec::new(),
+            start: 0,
+            head: vec![0; 1 << HASH_BITS],
+            prev: Vec::new(),
+            max_chain,
+            nice_length,
+        }
+    }
+
+    /// Compress the remaining input and return the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.compress(true)?;
+        self.output.align();
+        self.output.flush()?;
+        Ok(self.output.writer)
+    }
+
+    fn hash(&self, position: usize) -> usize {
+        let bytes = &self.buffer[position..position + MIN_MATCH];
+        (((bytes[0] as usize) << 10) ^ ((bytes[1] as usize) << 5) ^ bytes[2] as usize)
+            & ((1 << HASH_BITS) - 1)
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + MIN_MATCH <= self.buffer.len() {
+            let hash = self.hash(position);
+            self.prev[position] = self.head[hash];
+            self.head[hash] = position as u32 + 1;
+        }
+    }
+
+    /// Longest earlier match at `position` as (length, distance)
+    fn find_match(&self, position: usize) -> Option<(usize, usize)> {
+        if position + MIN_MATCH > self.buffer.len() {
+            return None;
+        }
+        let limit = (self.buffer.len() - position).min(MAX_MATCH);
+        let current = &self.buffer[position..position + limit];
+
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[self.hash(position)];
+        let mut chain = 0;
+        while candidate > 0 && chain < self.max_chain {
+            let earlier = candidate as usize - 1;
+            let distance = position - earlier;
+            if distance > WINDOW {
+                break;
+            }
+            let best_length = best.map_or(MIN_MATCH - 1, |(l, _)| l);
+            // Cheap rejection on the byte that would extend the best match
+            if self.buffer[earlier + best_length.min(limit - 1)]
+                == current[best_length.min(limit - 1)]
+            {
+                let length = current
+                    .iter()
+                    .zip(&self.buffer[earlier..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best = Some((length, distance));
+                    if length >= self.nice_length || length == limit {
+                        break;
+                    }
+                }
+            }
+            candidate = self.prev[earlier];
+            chain += 1;
+        }
+        best.filter(|(length, distance)| *length > MIN_MATCH || *distance <= 4096)
+    }
+
+    /// Compress pending input; unless `last`, keep enough lookahead for the longest match
+    fn compress(&mut self, last: bool) -> io::Result<()> {
+        self.prev.resize(self.buffer.len(), 0);
+        let end = if last {
+            self.buffer.len()
+        } else {
+            self.buffer.len().saturating_sub(MAX_MATCH)
+        };
+
+        let block_start = self.start;
+        let mut tokens = Vec::new();
+        let mut position = self.start;
+        while position < end {
+            match self.find_match(position) {
+                Some((length, distance)) => {
+                    tokens.push(Token::Match {
+                        length: length as u32,
+                        distance: distance as u32,
+                    });
+                    for p in position..position + length {
+                        self.insert(p);
+                    }
+                    position += length;
+                }
+                None => {
+                    tokens.push(Token::Literal(self.buffer[position]));
+                    self.insert(position);
+                    position += 1;
+                }
+            }
+        }
+        self.start = position;
+
+        if !tokens.is_empty() || last {
+            self.write_block(&tokens, block_start..position, last)?;
+        }
+
+        // Keep one window of history
+        let drop = self.start.saturating_sub(WINDOW);
+        if drop > 0 {
+            self.buffer.drain(..drop);
+            self.prev.drain(..drop);
+            let shift = |p: &mut u32| *p = p.saturating_sub(drop as u32);
+            self.head.iter_mut().for_each(shift);
+            self.prev.iter_mut().for_each(shift);
+            self.start -= drop;
+        }
+        Ok(())
+    }
+
+    fn write_block(
+        &mut self,
+        tokens: &[Token],
+        input: std::ops::Range<usize>,
+        last: bool,
+    ) -> io::Result<()> {
+        let mut literal_frequencies = [0u32; LITERAL_CODES];
+        let mut distance_frequencies = [0u32; DISTANCE_CODES];
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => literal_frequencies[byte as usize] += 1,
+                Token::Match { length, distance } => {
+                    literal_frequencies[257 + length_code(length)] += 1;
+                    distance_frequencies[distance_code(distance)] += 1;
+                }
+            }
+        }
+        literal_frequencies[END_OF_BLOCK] += 1;
+
+        let literal_lengths = code_lengths(&literal_frequencies, MAX_CODE_LENGTH);
+        let distance_lengths = code_lengths(&distance_frequencies, MAX_CODE_LENGTH);
+        let literal_count = 257.max(literal_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1);
+        let distance_count = 1.max(distance_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1);
+
+        let mut all_lengths = literal_lengths[..literal_count].to_vec();
+        all_lengths.extend_from_slice(&distance_lengths[..distance_count]);
+        let runs = run_lengths(&all_lengths);
+        let mut code_length_frequencies = [0u32; 19];
+        for (symbol, _, _) in &runs {
+            code_length_frequencies[*symbol] += 1;
+        }
+        let code_length_lengths =
+            code_lengths(&code_length_frequencies, MAX_CODE_LENGTH_CODE_LENGTH);
+        let code_length_count = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|s| code_length_lengths[*s] > 0)
+                .unwrap_or(0)
+                + 1,
+        );
+
+        // Size of the dynamic block in bits, to compare with stored blocks
+        let mut dynamic_bits = 3 + 5 + 5 + 4 + 3 * code_length_count as u64;
+        for (symbol, _, extra) in &runs {
+            dynamic_bits += code_length_lengths[*symbol] as u64 + *extra as u64;
+        }
+        for (symbol, frequency) in literal_frequencies.iter().enumerate() {
+            let extra = if symbol > 256 {
+                LENGTH_EXTRA[symbol - 257]
+            } else {
+                0
+            };
+            dynamic_bits += *frequency as u64 * (literal_lengths[symbol] as u64 + extra as u64);
+        }
+        for (code, frequency) in distance_frequencies.iter().enumerate() {
+            dynamic_bits +=
+                *frequency as u64 * (distance_lengths[code] as u64 + DISTANCE_EXTRA[code] as u64);
+        }
+        let stored_bits = (input.len() as u64).div_ceil(65535).max(1) * 40 + 8 * input.len() as u64;
+
+        if stored_bits < dynamic_bits {
+            self.write_stored(input, last)?;
+        } else {
+            let output = &mut self.output;
+            output.write_bits(last as u32, 1);
+            output.write_bits(2, 2);
+            output.write_bits(literal_count as u32 - 257, 5);
+            output.write_bits(distance_count as u32 - 1, 5);
+            output.write_bits(code_length_count as u32 - 4, 4);
+            for symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+                output.write_bits(code_length_lengths[*symbol] as u32, 3);
+            }
+            let code_length_codes = canonical_codes(&code_length_lengths);
+            for (symbol, value, extra) in &runs {
+                output.write_bits(code_length_codes[*symbol], code_length_lengths[*symbol]);
+                output.write_bits(*value, *extra);
+            }
+
+            let literal_codes = canonical_codes(&literal_lengths);
+            let distance_codes = canonical_codes(&distance_lengths);
+            for token in tokens {
+                match *token {
+                    Token::Literal(byte) => {
+                        let symbol = byte as usize;
+                        output.write_bits(literal_codes[symbol], literal_lengths[symbol]);
+                    }
+                    Token::Match { length, distance } => {
+                        let code = length_code(length);
+                        let symbol = 257 + code;
+                        output.write_bits(literal_codes[symbol], literal_lengths[symbol]);
+                        output.write_bits(length - LENGTH_BASE[code], LENGTH_EXTRA[code]);
+                        let code = distance_code(distance);
+                        output.write_bits(distance_codes[code], distance_lengths[code]);
+                        output.write_bits(distance - DISTANCE_BASE[code], DISTANCE_EXTRA[code]);
+                    }
+                }
+            }
+            output.write_bits(literal_codes[END_OF_BLOCK], literal_lengths[END_OF_BLOCK]);
+        }
+        self.output.flush()
+    }
+
+    fn write_stored(&mut self, input: std::ops::Range<usize>, last: bool) -> io::Result<()> {
+        let data = &self.buffer[input];
+        let mut chunks = data.chunks(65535).peekable();
+        if data.is_empty() {
+            chunks = [].chunks(1).peekable();
+        }
+        let output = &mut self.output;
+        if data.is_empty() {
+            output.write_bits(last as u32, 1);
+            output.write_bits(0, 2);
+            output.align();
+            output.buffer.extend([0, 0, 0xff, 0xff]);
+        }
+        while let Some(chunk) = chunks.next() {
+            let final_chunk = last && chunks.peek().is_none();
+            output.write_bits(final_chunk as u32, 1);
+            output.write_bits(0, 2);
+            output.align();
+            let length = chunk.len() as u16;
+            output.buffer.extend(length.to_le_bytes());
+            output.buffer.extend((!length).to_le_bytes());
+            output.buffer.extend_from_slice(chunk);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() - self.start >= BLOCK_INPUT + MAX_MATCH {
+            self.compress(false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()?;
+        self.output.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(length_code(3), 0);
+        assert_eq!(length_code(10), 7);
+        assert_eq!(length_code(11), 8);
+        assert_eq!(length_code(227), 27);
+        assert_eq!(length_code(258), 27);
+        assert_eq!(length_code(259), 28);
+        assert_eq!(length_code(65538), 28);
+        assert_eq!(distance_code(1), 0);
+        assert_eq!(distance_code(32768), 29);
+        assert_eq!(distance_code(32769), 30);
+        assert_eq!(distance_code(65536), 31);
+    }
+
+    #[test]
+    fn test_code_lengths() {
+        // Kraft sum of a complete prefix code is exactly 1
+        let kraft = |lengths: &[u8]| {
+            lengths
+                .iter()
+                .filter(|l| **l > 0)
+                .map(|l| 1.0 / (1u64 << l) as f64)
+                .sum::<f64>()
+        };
+
+        let fibonacci = (0..30)
+            .scan((1u32, 1u32), |(a, b), _| {
+                let value = *a;
+                (*a, *b) = (*b, *a + *b);
+                Some(value)
+            })
+            .collect::<Vec<_>>();
+        let lengths = code_lengths(&fibonacci, 15);
+        assert!(lengths.iter().all(|l| *l <= 15));
+        assert!((kraft(&lengths) - 1.0).abs() < 1e-9);
+
+        // A single used symbol still gets a two-symbol code
+        let lengths = code_lengths(&[0, 0, 5, 0], 15);
+        assert_eq!(lengths.iter().filter(|l| **l > 0).count(), 2);
+        assert_eq!(lengths[2], 1);
+        assert_eq!(code_lengths(&[0, 0, 0], 7), [1, 1, 0]);
+    }
+
+    #[test]
+    fn test_run_lengths() {
+        let mut lengths = vec![8u8; 7];
+        lengths.extend([0; 20]);
+        lengths.extend([5, 5]);
+        assert_eq!(
+            run_lengths(&lengths),
+            [(8, 0, 0), (16, 3, 2), (18, 9, 7), (5, 0, 0), (5, 0, 0)]
+        );
+    }
+}
@@ -0,0 +1,475 @@
+//! Archive formats of `--archive` and their compression settings
+//!
+//! [`Contents`] lists the entries of an archive with the files they are read from, so
+//! copied files are streamed from their original locations and only generated or
+//! modified files are staged. The entries are written as a tar stream (gzip, xz, zstd
+//! or bzip2), a zip archive with a compression method per entry, or a 7z archive.
+
+pub mod deflate64;
+pub mod sevenz;
+pub mod tar;
+pub mod zip;
+
+use crate::error::Error;
+use crate::manifest::{ArchiveFormat, Manifest, ZipMethod};
+use crate::result::Result;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Extensions of already-compressed files, stored as is in zip archives
+const STORED_EXTENSIONS: &[&str] = &[
+    "zip", "gz", "tgz", "xz", "txz", "bz2", "zst", "7z", "rar", "jar", "apk", "png", "jpg", "jpeg",
+    "gif", "webp", "avif", "heic", "mp3", "mp4", "m4a", "m4v", "mov", "mkv", "webm", "ogg", "opus",
+    "flac", "aac", "woff", "woff2",
+];
+
+/// Archive settings resolved from the manifest `archive` table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub format: ArchiveFormat,
+    /// Compression level, validated for the format (and zip method)
+    pub level: u32,
+    /// Compression threads, at least 1
+    pub threads: u32,
+    pub zip_method: ZipMethod,
+    /// Lowercase extensions of files stored without compression in zip archives
+    pub store: Vec<String>,
+}
+
+impl Options {
+    /// Settings of `manifest`, with `default_format` when the manifest has none
+    pub fn from_manifest(manifest: &Manifest, default_format: ArchiveFormat) -> Result<Self> {
+        let config = manifest.archive.clone().unwrap_or_default();
+        let format = config.format.unwrap_or(default_format);
+        let zip_method = config.zip_method.unwrap_or_default();
+
+        let (range, default) = level_range(format, zip_method);
+        let level = config.level.unwrap_or(default);
+        if !range.contains(&level) {
+            return Err(Error::InvalidManifest(format!(
+                "archive level {} is out of range for {} ({}-{})",
+                level,
+                method_name(format, zip_method),
+                range.start(),
+                range.end()
+            )));
+        }
+
+        let threads = match config.threads {
+            Some(threads) if threads > 0 => threads,
+            _ => std::thread::available_parallelism().map_or(1, |n| n.get() as u32),
+        };
+
+        let store = STORED_EXTENSIONS
+            .iter()
+            .map(|e| e.to_string())
+            .chain(
+                config
+                    .store
+                    .iter()
+                    .map(|e| e.trim_start_matches('.').to_lowercase()),
+            )
+            .collect();
+
+        Ok(Options {
+            format,
+            level,
+            threads,
+            zip_method,
+            store,
+        })
+    }
+
+    /// Archive file name of `filename` (the manifest `filename`, without extension)
+    pub fn file_name(&self, filename: &str) -> String {
+        format!("{}.{}", filename, self.format.as_str())
+    }
+
+    /// Whether a zip entry for `path` is stored without compression
+    pub fn is_stored(&self, path: &Path) -> bool {
+        self.zip_method == ZipMethod::Store
+            || path
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .is_some_and(|e| self.store.contains(&e))
+    }
+}
+
+/// Valid and default compression levels of a format
+fn level_range(
+    format: ArchiveFormat,
+    zip_method: ZipMethod,
+) -> (std::ops::RangeInclusive<u32>, u32) {
+    match format {
+        ArchiveFormat::TarGz => (0..=9, 6),
+        ArchiveFormat::TarXz => (0..=9, 6),
+        ArchiveFormat::TarZst => (1..=22, 3),
+        ArchiveFormat::TarBz2 => (1..=9, 9),
+        ArchiveFormat::SevenZ => (0..=9, 6),
+        ArchiveFormat::Zip => match zip_method {
+            ZipMethod::Store | ZipMethod::Deflate => (0..=9, 6),
+            ZipMethod::Deflate64 => (1..=9, 6),
+            ZipMethod::Zstd => (1..=22, 3),
+        },
+    }
+}
+
+fn method_name(format: ArchiveFormat, zip_method: ZipMethod) -> String {
+    match format {
+        ArchiveFormat::Zip => format!("zip ({})", zip_method.as_str()),
+        _ => format.as_str().to_string(),
+    }
+}
+
+/// Kind of a file system entry, shared by the archive formats and the pkg payload
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    Directory,
+    File,
+    /// Symbolic link with its target
+    Symlink(String),
+}
+
+/// Permission bits of an entry; fixed defaults where the file system has none
+#[cfg(unix)]
+pub fn permissions(metadata: &std::fs::Metadata, _kind: &Kind) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+pub fn permissions(_metadata: &std::fs::Metadata, kind: &Kind) -> u32 {
+    match kind {
+        Kind::File => 0o644,
+        Kind::Directory | Kind::Symlink(_) => 0o755,
+    }
+}
+
+/// An archive entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// `/`-separated path in the archive
+    pub path: String,
+    /// File the contents are read from, `None` for directories
+    pub source: Option<PathBuf>,
+    /// Permission bits
+    pub mode: u32,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.source.is_none()
+    }
+
+    /// Reader of the contents that fails if the file no longer has the listed size
+    pub fn open<'a>(&self, progress: &'a dyn Fn(u64)) -> Result<EntryReader<'a>> {
+        let source = self.source.clone().unwrap_or_default();
+        Ok(EntryReader {
+            file: File::open(&source)?.take(self.size),
+            remaining: self.size,
+            source,
+            progress,
+        })
+    }
+}
+
+/// Files and directories of an archive with the locations they are read from
+#[derive(Debug, Default)]
+pub struct Contents {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Contents {
+    /// Add `source`, a file or a directory tree, as `path`; an empty `path` adds the
+    /// children of a directory at the root. Entries with the same paths are replaced.
+    pub fn add(&mut self, source: &Path, path: &str) -> Result<()> {
+        for item in WalkDir::new(source).follow_links(true).sort_by_file_name() {
+            let item = item?;
+            let relative = item.path().strip_prefix(source).unwrap_or(item.path());
+            let entry_path = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .fold(path.trim_matches('/').to_string(), |path, name| {
+                    if path.is_empty() {
+                        name.to_string()
+                    } else {
+                        format!("{}/{}", path, name)
+                    }
+                });
+            if entry_path.is_empty() {
+                continue;
+            }
+
+            let metadata = item
+                .metadata()
+                .map_err(|e| Error::Custom(format!("{}: {}", item.path().display(), e)))?;
+            let kind = if metadata.is_dir() {
+                Kind::Directory
+            } else {
+                Kind::File
+            };
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            self.insert(Entry {
+                path: entry_path,
+                source: (kind == Kind::File).then(|| item.path().to_path_buf()),
+                mode: permissions(&metadata, &kind),
+                size: if kind == Kind::File {
+                    metadata.len()
+                } else {
+                    0
+                },
+                mtime,
+            });
+        }
+        Ok(())
+    }
+
+    /// Insert an entry and any missing parent directories
+    fn insert(&mut self, entry: Entry) {
+        let mut parent = entry.path.as_str();
+        while let Some((directory, _)) = parent.rsplit_once('/') {
+            parent = directory;
+            self.entries
+                .entry(directory.to_string())
+                .or_insert_with(|| Entry {
+                    path: directory.to_string(),
+                    source: None,
+                    mode: 0o755,
+                    size: 0,
+                    mtime: entry.mtime,
+                });
+        }
+        self.entries.insert(entry.path.clone(), entry);
+    }
+
+    /// Set the permission bits of the file at `path` and of the files below it
+    pub fn set_mode(&mut self, path: &str, mode: u32) {
+        let prefix = format!("{}/", path);
+        for entry in self.entries.values_mut() {
+            if !entry.is_dir() && (entry.path == path || entry.path.starts_with(&prefix)) {
+                entry.mode = mode;
+            }
+        }
+    }
+
+    /// Copy the files matching `filter` (by source) to the same paths below `root`, so
+    /// they can be modified, and read them from there; returns the copies
+    pub fn stage(&mut self, root: &Path, filter: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>> {
+        let mut staged = Vec::new();
+        for entry in self.entries.values_mut() {
+            let Some(source) = entry.source.as_ref().filter(|s| filter(s)) else {
+                continue;
+            };
+            let copy = root.join(&entry.path);
+            if let Some(parent) = copy.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(source, &copy)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&copy, fs::Permissions::from_mode(entry.mode))?;
+            }
+            entry.source = Some(copy.clone());
+            staged.push(copy);
+        }
+        Ok(staged)
+    }
+
+    /// Entries in archive order, parents first
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    /// Total size of the files in bytes
+    pub fn size(&self) -> u64 {
+        self.entries.values().map(|e| e.size).sum()
+    }
+}
+
+/// Contents of an entry, reporting the bytes read to a progress callback
+pub struct EntryReader<'a> {
+    file: io::Take<File>,
+    remaining: u64,
+    source: PathBuf,
+    progress: &'a dyn Fn(u64),
+}
+
+impl Read for EntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read(buf)?;
+        if read == 0 && self.remaining > 0 && !buf.is_empty() {
+            return Err(io::Error::other(format!(
+                "{} changed while it was archived",
+                self.source.display()
+            )));
+        }
+        self.remaining -= read as u64;
+        (self.progress)(read as u64);
+        Ok(read)
+    }
+}
+
+/// Write `contents` as an archive to `output_path` with a progress bar in bytes
+pub fn write_with_progress(
+    options: &Options,
+    contents: &Contents,
+    output_path: &Path,
+) -> Result<()> {
+    let name = output_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let progress = crate::output::Progress::bytes(contents.size());
+    progress.start(format!("Writing {}", name));
+    match write(options, contents, output_path, &|bytes| progress.inc(bytes)) {
+        Ok(()) => {
+            progress.stop(format!("Wrote {}", name));
+            Ok(())
+        }
+        Err(e) => {
+            progress.error(format!("Failed to write {}", name));
+            Err(e)
+        }
+    }
+}
+
+/// Write `contents` as an archive to `output_path`, reporting the bytes read to `progress`
+pub fn write(
+    options: &Options,
+    contents: &Contents,
+    output_path: &Path,
+    progress: &dyn Fn(u64),
+) -> Result<()> {
+    match options.format {
+        ArchiveFormat::Zip => zip::write(options, contents, output_path, progress),
+        ArchiveFormat::SevenZ => sevenz::write(options, contents, output_path, progress),
+        _ => tar::write(options, contents, output_path, progress),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::tests::sample_manifest;
+
+    #[test]
+    fn test_options() {
+        let (_, manifest) = sample_manifest("");
+        let options = Options::from_manifest(&manifest, ArchiveFormat::Zip).unwrap();
+        assert_eq!(options.format, ArchiveFormat::Zip);
+        assert_eq!(options.level, 6);
+        assert!(options.threads >= 1);
+        assert_eq!(options.zip_method, ZipMethod::Deflate);
+        assert_eq!(options.file_name("sample-1.2.3"), "sample-1.2.3.zip");
+        assert!(options.is_stored(Path::new("data/intro.MP4")));
+        assert!(!options.is_stored(Path::new("data/level.pak")));
+
+        let (_, manifest) = sample_manifest(
+            r#"
+archive = { format = "tar.zst", level = 19, threads = 4, store = [".PAK"] }
+"#,
+        );
+        let options = Options::from_manifest(&manifest, ArchiveFormat::Zip).unwrap();
+        assert_eq!(options.format, ArchiveFormat::TarZst);
+        assert_eq!((options.level, options.threads), (19, 4));
+        assert_eq!(options.file_name("sample"), "sample.tar.zst");
+        assert!(options.is_stored(Path::new("data/level.pak")));
+
+        let (_, manifest) = sample_manifest(
+            r#"
+archive = { zip-method = "store" }
+"#,
+        );
+        let options = Options::from_manifest(&manifest, ArchiveFormat::Zip).unwrap();
+        assert!(options.is_stored(Path::new("data/level.pak")));
+    }
+
+    #[test]
+    fn test_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let assets = dir.path().join("assets");
+        fs::create_dir_all(assets.join("maps")).unwrap();
+        fs::write(assets.join("maps/level.dat"), "level").unwrap();
+        fs::write(dir.path().join("app"), "binary").unwrap();
+        let generated = dir.path().join("generated");
+        fs::create_dir_all(generated.join("sample/share")).unwrap();
+        fs::write(
+            generated.join("sample/share/app.desktop"),
+            "[Desktop Entry]",
+        )
+        .unwrap();
+        fs::write(generated.join("sample/app"), "universal binary").unwrap();
+
+        let mut contents = Contents::default();
+        contents.add(&dir.path().join("app"), "sample/app").unwrap();
+        contents.add(&assets, "sample/data/assets").unwrap();
+        contents.set_mode("sample/app", 0o755);
+        let paths = |contents: &Contents| {
+            contents
+                .entries()
+                .map(|e| (e.path.clone(), e.is_dir(), e.size))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(&contents),
+            [
+                ("sample".to_string(), true, 0),
+                ("sample/app".to_string(), false, 6),
+                ("sample/data".to_string(), true, 0),
+                ("sample/data/assets".to_string(), true, 0),
+                ("sample/data/assets/maps".to_string(), true, 0),
+                ("sample/data/assets/maps/level.dat".to_string(), false, 5),
+            ]
+        );
+        assert_eq!(contents.entries().nth(1).unwrap().mode, 0o755);
+        assert_eq!(contents.size(), 11);
+
+        // Staged copies are read instead of the sources
+        let staging = dir.path().join("staging");
+        let staged = contents
+            .stage(&staging, |source| source.ends_with("level.dat"))
+            .unwrap();
+        assert_eq!(staged, [staging.join("sample/data/assets/maps/level.dat")]);
+        let level = contents.entries().last().unwrap();
+        assert_eq!(level.source.as_ref(), Some(&staged[0]));
+
+        // Generated files replace and extend the copied ones
+        contents.add(&generated, "").unwrap();
+        assert_eq!(contents.entries().count(), 8);
+        let app = contents.entries().find(|e| e.path == "sample/app").unwrap();
+        assert_eq!(app.source, Some(generated.join("sample/app")));
+        assert_eq!(app.size, 16);
+    }
+
+    #[test]
+    fn test_level_validation() {
+        for (archive, message) in [
+            (
+                r#"archive = { format = "tar.xz", level = 10 }"#,
+                "archive level 10 is out of range for tar.xz (0-9)",
+            ),
+            (
+                r#"archive = { format = "tar.bz2", level = 0 }"#,
+                "archive level 0 is out of range for tar.bz2 (1-9)",
+            ),
+            (
+                r#"archive = { format = "zip", zip-method = "zstd", level = 23 }"#,
+                "archive level 23 is out of range for zip (zstd) (1-22)",
+            ),
+        ] {
+            let (_, manifest) = sample_manifest(archive);
+            let error = Options::from_manifest(&manifest, ArchiveFormat::TarGz).unwrap_err();
+            assert_eq!(error.to_string(), format!("Invalid manifest: {}", message));
+        }
+    }
+}
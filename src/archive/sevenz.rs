@@ -0,0 +1,114 @@
+//! 7z archives, compressed with multithreaded LZMA2
+
+use crate::archive::{Contents, Options};
+use crate::error::Error;
+use crate::result::Result;
+use sevenz_rust2::encoder_options::Lzma2Options;
+use sevenz_rust2::{ArchiveEntry, ArchiveWriter};
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
+
+/// Uncompressed size of the chunks compressed in parallel
+const CHUNK_SIZE: u64 = 16 << 20;
+
+pub fn write(
+    options: &Options,
+    contents: &Contents,
+    output_path: &Path,
+    progress: &dyn Fn(u64),
+) -> Result<()> {
+    let output = BufWriter::new(File::create(output_path)?);
+    write_stream(options, contents, output, progress)?.flush()?;
+    Ok(())
+}
+
+/// Write `contents` as a 7z archive into `writer`
+pub fn write_stream<W: Write + Seek>(
+    options: &Options,
+    contents: &Contents,
+    writer: W,
+    progress: &dyn Fn(u64),
+) -> Result<W> {
+    let error = |e: sevenz_rust2::Error| Error::Custom(format!("7z: {}", e));
+
+    let mut archive = ArchiveWriter::new(writer).map_err(error)?;
+    let lzma2 = if options.threads > 1 {
+        Lzma2Options::from_level_mt(options.level, options.threads, CHUNK_SIZE)
+    } else {
+        Lzma2Options::from_level(options.level)
+    };
+    archive.set_content_methods(vec![lzma2.into()]);
+
+    for entry in contents.entries() {
+        match &entry.source {
+            Some(source) => {
+                let item = ArchiveEntry::from_path(source, entry.path.clone());
+                archive
+                    .push_archive_entry(item, Some(entry.open(progress)?))
+                    .map_err(error)?;
+            }
+            None => {
+                let item = ArchiveEntry::new_directory(&entry.path);
+                archive
+                    .push_archive_entry::<File>(item, None)
+                    .map_err(error)?;
+            }
+        }
+    }
+    Ok(archive.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{ArchiveFormat, ZipMethod};
+    use sevenz_rust2::{ArchiveReader, Password};
+    use std::fs;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sample/assets")).unwrap();
+        let asset = (0..300_000u32).map(|i| (i % 253) as u8).collect::<Vec<_>>();
+        fs::write(dir.path().join("sample/assets/level.dat"), &asset).unwrap();
+        fs::write(dir.path().join("sample/README.md"), "# Sample").unwrap();
+
+        for threads in [1, 4] {
+            let options = Options {
+                format: ArchiveFormat::SevenZ,
+                level: 6,
+                threads,
+                zip_method: ZipMethod::Deflate,
+                store: Vec::new(),
+            };
+            let mut contents = Contents::default();
+            contents.add(dir.path(), "").unwrap();
+            let data = write_stream(&options, &contents, Cursor::new(Vec::new()), &|_| {})
+                .unwrap()
+                .into_inner();
+            assert_eq!(&data[..6], b"7z\xbc\xaf\x27\x1c");
+            assert!(data.len() < 10_000);
+
+            let mut reader = ArchiveReader::new(Cursor::new(data), Password::empty()).unwrap();
+            let names = reader
+                .archive()
+                .files
+                .iter()
+                .map(|f| (f.name().to_string(), f.is_directory()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                names,
+                [
+                    ("sample".to_string(), true),
+                    ("sample/README.md".to_string(), false),
+                    ("sample/assets".to_string(), true),
+                    ("sample/assets/level.dat".to_string(), false),
+                ]
+            );
+            assert_eq!(reader.read_file("sample/assets/level.dat").unwrap(), asset);
+            assert_eq!(reader.read_file("sample/README.md").unwrap(), b"# Sample");
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Compressed tar archives: tar.gz, tar.xz and tar.zst (multithreaded) and tar.bz2
+
+use crate::archive::{Contents, Options};
+use crate::error::Error;
+use crate::manifest::ArchiveFormat;
+use crate::result::Result;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub fn write(
+    options: &Options,
+    contents: &Contents,
+    output_path: &Path,
+    progress: &dyn Fn(u64),
+) -> Result<()> {
+    let output = BufWriter::new(File::create(output_path)?);
+    write_stream(options, contents, output, progress)?.flush()?;
+    Ok(())
+}
+
+/// Write `contents` as a compressed tar stream into `writer`
+pub fn write_stream<W: Write>(
+    options: &Options,
+    contents: &Contents,
+    writer: W,
+    progress: &dyn Fn(u64),
+) -> Result<W> {
+    let level = options.level;
+    match options.format {
+        ArchiveFormat::TarGz => {
+            let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::new(level));
+            Ok(append(contents, encoder, progress)?.finish()?)
+        }
+        ArchiveFormat::TarXz => {
+            let stream = liblzma::stream::MtStreamBuilder::new()
+                .threads(options.threads)
+                .preset(level)
+                .check(liblzma::stream::Check::Crc64)
+                .encoder()
+                .map_err(|e| Error::Custom(format!("xz encoder: {}", e)))?;
+            let encoder = liblzma::write::XzEncoder::new_stream(writer, stream);
+            Ok(append(contents, encoder, progress)?.finish()?)
+        }
+        ArchiveFormat::TarZst => {
+            let mut encoder = zstd::stream::write::Encoder::new(writer, level as i32)?;
+            if options.threads > 1 {
+                encoder.multithread(options.threads)?;
+            }
+            encoder.include_checksum(true)?;
+            Ok(append(contents, encoder, progress)?.finish()?)
+        }
+        ArchiveFormat::TarBz2 => {
+            let encoder = bzip2::write::BzEncoder::new(writer, bzip2::Compression::new(level));
+            Ok(append(contents, encoder, progress)?.finish()?)
+        }
+        ArchiveFormat::Zip | ArchiveFormat::SevenZ => Err(Error::Custom(format!(
+            "{} is not a tar format",
+            options.format.as_str()
+        ))),
+    }
+}
+
+fn append<W: Write>(contents: &Contents, writer: W, progress: &dyn Fn(u64)) -> Result<W> {
+    let mut tar = ::tar::Builder::new(writer);
+    for entry in contents.entries() {
+        let mut header = ::tar::Header::new_gnu();
+        header.set_mode(entry.mode);
+        header.set_mtime(entry.mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_size(entry.size);
+        if entry.is_dir() {
+            header.set_entry_type(::tar::EntryType::Directory);
+            tar.append_data(&mut header, format!("{}/", entry.path), io::empty())?;
+        } else {
+            header.set_entry_type(::tar::EntryType::Regular);
+            tar.append_data(&mut header, &entry.path, entry.open(progress)?)?;
+        }
+    }
+    Ok(tar.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ZipMethod;
+    use std::fs;
+    use std::io::Read;
+
+    fn options(format: ArchiveFormat, level: u32, threads: u32) -> Options {
+        Options {
+            format,
+            level,
+            threads,
+            zip_method: ZipMethod::Deflate,
+            store: Vec::new(),
+        }
+    }
+
+    /// Files of a tar stream as (path, contents)
+    fn entries<R: Read>(reader: R) -> Vec<(String, Vec<u8>)> {
+        let mut archive = ::tar::Archive::new(reader);
+        let mut files = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.header().entry_type().is_file() {
+                let path = entry.path().unwrap().to_string_lossy().to_string();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                files.push((path, data));
+            }
+        }
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir_all(source.join("sample/assets")).unwrap();
+        let asset = (0..200_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        fs::write(source.join("sample/assets/level.dat"), &asset).unwrap();
+        fs::write(source.join("sample/README.md"), "# Sample").unwrap();
+        let mut contents = Contents::default();
+        contents.add(&source, "").unwrap();
+        let expected = vec![
+            ("sample/README.md".to_string(), b"# Sample".to_vec()),
+            ("sample/assets/level.dat".to_string(), asset),
+        ];
+
+        for (format, level, threads) in [
+            (ArchiveFormat::TarGz, 9, 1),
+            (ArchiveFormat::TarXz, 6, 4),
+            (ArchiveFormat::TarZst, 19, 4),
+            (ArchiveFormat::TarBz2, 1, 1),
+        ] {
+            let read = std::cell::Cell::new(0);
+            let progress = |bytes| read.set(read.get() + bytes);
+            let options = options(format, level, threads);
+            let data = write_stream(&options, &contents, Vec::new(), &progress).unwrap();
+            assert_eq!(read.get(), 200_008);
+            let files = match format {
+                ArchiveFormat::TarGz => entries(flate2::read::GzDecoder::new(data.as_slice())),
+                ArchiveFormat::TarXz => entries(liblzma::read::XzDecoder::new(data.as_slice())),
+                ArchiveFormat::TarZst => {
+                    entries(zstd::stream::read::Decoder::new(data.as_slice()).unwrap())
+                }
+                _ => entries(bzip2::read::BzDecoder::new(data.as_slice())),
+            };
+            assert!(files == expected, "{}", format.as_str());
+        }
+
+        let options = options(ArchiveFormat::Zip, 6, 1);
+        let error = write_stream(&options, &contents, Vec::new(), &|_| {});
+        assert!(error.is_err());
+    }
+}
@@ -0,0 +1,676 @@
+//! Zip archives with a compression method per entry
+//!
+//! Entries are compressed with Store, Deflate, Deflate64 or Zstd; files with the
+//! extension of an already-compressed format are stored. Each local header is written
+//! first and patched with the checksum and sizes once the entry data is written.
+//! Zip64 fields are used for entries of 3.75 GiB and more, for offsets past 4 GiB and
+//! for more than 65534 entries.
+
+use crate::archive::{Contents, Entry, Options, deflate64};
+use crate::error::Error;
+use crate::manifest::ZipMethod;
+use crate::result::Result;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR: u32 = 0x07064b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const ZIP64_EXTRA: u16 = 0x0001;
+/// Names and comments are UTF-8
+const FLAG_UTF8: u16 = 1 << 11;
+/// Version made by: Unix, specification 6.3
+const VERSION_MADE_BY: u16 = (3 << 8) | 63;
+const VERSION_ZIP64: u16 = 45;
+/// MS-DOS directory attribute
+const DOS_DIRECTORY: u32 = 0x10;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+/// Thresholds from which Zip64 fields are written
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// Uncompressed size of an entry; below 4 GiB, as compressed data can be larger
+    size: u64,
+    /// Sizes and offsets in the central directory
+    offset: u64,
+    /// Number of entries
+    count: u64,
+}
+
+const LIMITS: Limits = Limits {
+    size: 0xF000_0000,
+    offset: 0xFFFF_FFFF,
+    count: 0xFFFF,
+};
+
+/// Compression method of an entry, with its zip method id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Store,
+    Deflate,
+    Deflate64,
+    Zstd,
+}
+
+impl Method {
+    fn id(self) -> u16 {
+        match self {
+            Method::Store => 0,
+            Method::Deflate => 8,
+            Method::Deflate64 => 9,
+            Method::Zstd => 93,
+        }
+    }
+
+    /// Version needed to extract
+    fn version(self) -> u16 {
+        match self {
+            Method::Store | Method::Deflate => 20,
+            Method::Deflate64 => 21,
+            Method::Zstd => 63,
+        }
+    }
+}
+
+/// An entry written to the archive, for the central directory
+struct Written {
+    name: String,
+    method: Method,
+    /// Sizes are in a Zip64 extra field of the local header
+    zip64: bool,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    external_attributes: u32,
+    offset: u64,
+}
+
+impl Written {
+    fn version(&self) -> u16 {
+        if self.zip64 {
+            self.method.version().max(VERSION_ZIP64)
+        } else {
+            self.method.version()
+        }
+    }
+}
+
+pub fn write(
+    options: &Options,
+    contents: &Contents,
+    output_path: &Path,
+    progress: &dyn Fn(u64),
+) -> Result<()> {
+    let output = BufWriter::new(File::create(output_path)?);
+    write_stream(options, contents, output, progress)?.flush()?;
+    Ok(())
+}
+
+/// Write `contents` as a zip archive into `writer`
+pub fn write_stream<W: Write + Seek>(
+    options: &Options,
+    contents: &Contents,
+    writer: W,
+    progress: &dyn Fn(u64),
+) -> Result<W> {
+    write_with_limits(options, contents, writer, progress, LIMITS)
+}
+
+fn write_with_limits<W: Write + Seek>(
+    options: &Options,
+    contents: &Contents,
+    writer: W,
+    progress: &dyn Fn(u64),
+    limits: Limits,
+) -> Result<W> {
+    let mut archive = Writer {
+        writer,
+        offset: 0,
+        written: Vec::new(),
+        limits,
+    };
+    for entry in contents.entries() {
+        archive.add(options, entry, progress)?;
+    }
+    archive.finish()
+}
+
+struct Writer<W: Write + Seek> {
+    writer: W,
+    /// Current position, relative to the start of the archive
+    offset: u64,
+    written: Vec<Written>,
+    limits: Limits,
+}
+
+impl<W: Write + Seek> Writer<W> {
+    fn add(&mut self, options: &Options, entry: &Entry, progress: &dyn Fn(u64)) -> Result<()> {
+        let mut name = entry.path.clone();
+        let method = match &entry.source {
+            None => {
+                name.push('/');
+                Method::Store
+            }
+            Some(source) if options.is_stored(source) => Method::Store,
+            Some(_) => match options.zip_method {
+                ZipMethod::Store => Method::Store,
+                ZipMethod::Deflate => Method::Deflate,
+                ZipMethod::Deflate64 => Method::Deflate64,
+                ZipMethod::Zstd => Method::Zstd,
+            },
+        };
+        let (time, date) = dos_date_time(entry.mtime);
+        let external_attributes = if entry.is_dir() {
+            ((S_IFDIR | entry.mode) << 16) | DOS_DIRECTORY
+        } else {
+            (S_IFREG | entry.mode) << 16
+        };
+        let mut written = Written {
+            name,
+            method,
+            zip64: entry.size >= self.limits.size,
+            time,
+            date,
+            crc: 0,
+            compressed_size: 0,
+            size: 0,
+            external_attributes,
+            offset: self.offset,
+        };
+        self.write_local_header(&written)?;
+
+        let mut output = Counter {
+            writer: &mut self.writer,
+            count: 0,
+        };
+        let mut input = CrcWriter::default();
+        if !entry.is_dir() {
+            let mut source = entry.open(progress)?;
+            match method {
+                Method::Store => input.copy(&mut source, &mut output)?,
+                Method::Deflate => {
+                    let compression = flate2::Compression::new(options.level);
+                    let mut encoder = flate2::write::DeflateEncoder::new(&mut output, compression);
+                    input.copy(&mut source, &mut encoder)?;
+                    encoder.finish()?;
+                }
+                Method::Deflate64 => {
+                    let mut encoder = deflate64::Encoder::new(&mut output, options.level);
+                    input.copy(&mut source, &mut encoder)?;
+                    encoder.finish()?;
+                }
+                Method::Zstd => {
+                    let mut encoder =
+                        zstd::stream::write::Encoder::new(&mut output, options.level as i32)?;
+                    if options.threads > 1 {
+                        encoder.multithread(options.threads)?;
+                    }
+                    input.copy(&mut source, &mut encoder)?;
+                    encoder.finish()?;
+                }
+            }
+        }
+        written.compressed_size = output.count;
+        written.size = input.size;
+        written.crc = input.hasher.finalize();
+
+        if !written.zip64 && written.compressed_size.max(written.size) >= 0xFFFF_FFFF {
+            return Err(Error::Custom(format!(
+                "{} grew past 4 GiB when it was compressed; use zip-method = \"store\"",
+                entry.path
+            )));
+        }
+
+        // Patch the checksum and sizes into the local header
+        let end = self.offset + written.compressed_size;
+        self.writer
+            .seek(SeekFrom::Current(written.offset as i64 - end as i64))?;
+        self.offset = written.offset;
+        self.write_local_header(&written)?;
+        self.writer
+            .seek(SeekFrom::Current(written.compressed_size as i64))?;
+        self.offset = end;
+
+        self.written.push(written);
+        Ok(())
+    }
+
+    fn write_local_header(&mut self, written: &Written) -> Result<()> {
+        let name = written.name.as_bytes();
+        let (compressed_size, size) = if written.zip64 {
+            (u32::MAX, u32::MAX)
+        } else {
+            (written.compressed_size as u32, written.size as u32)
+        };
+        let mut extra = Vec::new();
+        if written.zip64 {
+            extra.extend(ZIP64_EXTRA.to_le_bytes());
+            extra.extend(16u16.to_le_bytes());
+            extra.extend(written.size.to_le_bytes());
+            extra.extend(written.compressed_size.to_le_bytes());
+        }
+
+        let mut header = Vec::with_capacity(30 + name.len() + extra.len());
+        header.extend(LOCAL_HEADER.to_le_bytes());
+        header.extend(written.version().to_le_bytes());
+        header.extend(FLAG_UTF8.to_le_bytes());
+        header.extend(written.method.id().to_le_bytes());
+        header.extend(written.time.to_le_bytes());
+        header.extend(written.date.to_le_bytes());
+        header.extend(written.crc.to_le_bytes());
+        header.extend(compressed_size.to_le_bytes());
+        header.extend(size.to_le_bytes());
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend((extra.len() as u16).to_le_bytes());
+        header.extend(name);
+        header.extend(extra);
+        self.writer.write_all(&header)?;
+        self.offset += header.len() as u64;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<W> {
+        let directory_offset = self.offset;
+        let mut directory = Vec::new();
+        for written in &self.written {
+            // Zip64 fields replace the sizes of Zip64 entries and large offsets
+            let mut extra = Vec::new();
+            let (compressed_size, size) = if written.zip64 {
+                extra.extend(written.size.to_le_bytes());
+                extra.extend(written.compressed_size.to_le_bytes());
+                (u32::MAX, u32::MAX)
+            } else {
+                (written.compressed_size as u32, written.size as u32)
+            };
+            let offset = if written.offset >= self.limits.offset {
+                extra.extend(written.offset.to_le_bytes());
+                u32::MAX
+            } else {
+                written.offset as u32
+            };
+            if !extra.is_empty() {
+                let fields = extra;
+                extra = Vec::with_capacity(4 + fields.len());
+                extra.extend(ZIP64_EXTRA.to_le_bytes());
+                extra.extend((fields.len() as u16).to_le_bytes());
+                extra.extend(fields);
+            }
+            let version = if extra.is_empty() {
+                written.version()
+            } else {
+                written.version().max(VERSION_ZIP64)
+            };
+
+            directory.extend(CENTRAL_HEADER.to_le_bytes());
+            directory.extend(VERSION_MADE_BY.to_le_bytes());
+            directory.extend(version.to_le_bytes());
+            directory.extend(FLAG_UTF8.to_le_bytes());
+            directory.extend(written.method.id().to_le_bytes());
+            directory.extend(written.time.to_le_bytes());
+            directory.extend(written.date.to_le_bytes());
+            directory.extend(written.crc.to_le_bytes());
+            directory.extend(compressed_size.to_le_bytes());
+            directory.extend(size.to_le_bytes());
+            directory.extend((written.name.len() as u16).to_le_bytes());
+            directory.extend((extra.len() as u16).to_le_bytes());
+            // Comment length, disk number and internal attributes
+            directory.extend([0; 6]);
+            directory.extend(written.external_attributes.to_le_bytes());
+            directory.extend(offset.to_le_bytes());
+            directory.extend(written.name.as_bytes());
+            directory.extend(extra);
+        }
+
+        let count = self.written.len() as u64;
+        let directory_size = directory.len() as u64;
+        let zip64 = count >= self.limits.count
+            || directory_offset >= self.limits.offset
+            || directory_size >= self.limits.offset;
+        if zip64 {
+            let record_offset = directory_offset + directory_size;
+            directory.extend(ZIP64_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+            // Size of the remaining record
+            directory.extend(44u64.to_le_bytes());
+            directory.extend(VERSION_MADE_BY.to_le_bytes());
+            directory.extend(VERSION_ZIP64.to_le_bytes());
+            // Disk numbers
+            directory.extend([0; 8]);
+            directory.extend(count.to_le_bytes());
+            directory.extend(count.to_le_bytes());
+            directory.extend(directory_size.to_le_bytes());
+            directory.extend(directory_offset.to_le_bytes());
+
+            directory.extend(ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR.to_le_bytes());
+            directory.extend(0u32.to_le_bytes());
+            directory.extend(record_offset.to_le_bytes());
+            // Total number of disks
+            directory.extend(1u32.to_le_bytes());
+        }
+
+        let clamp = |value: u64, max: u64| if zip64 && value >= max { max } else { value };
+        let entries = clamp(count, 0xFFFF) as u16;
+        directory.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        // Disk numbers
+        directory.extend([0; 4]);
+        directory.extend(entries.to_le_bytes());
+        directory.extend(entries.to_le_bytes());
+        directory.extend((clamp(directory_size, 0xFFFF_FFFF) as u32).to_le_bytes());
+        directory.extend((clamp(directory_offset, 0xFFFF_FFFF) as u32).to_le_bytes());
+        // Comment length
+        directory.extend([0; 2]);
+        self.writer.write_all(&directory)?;
+        Ok(self.writer)
+    }
+}
+
+/// MS-DOS time and date of a Unix timestamp, clamped to 1980-2107
+fn dos_date_time(mtime: u64) -> (u16, u16) {
+    let time = time::OffsetDateTime::from_unix_timestamp(mtime as i64)
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    if time.year() > 2107 {
+        return ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31);
+    }
+    let dos_time =
+        ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | (time.second() as u16 / 2);
+    let dos_date =
+        (((time.year() - 1980) as u16) << 9) | ((time.month() as u16) << 5) | time.day() as u16;
+    (dos_time, dos_date)
+}
+
+/// Sink counting the bytes written through it
+struct Counter<W: Write> {
+    writer: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// CRC-32 and size of the uncompressed entry data
+#[derive(Default)]
+struct CrcWriter {
+    hasher: crc32fast::Hasher,
+    size: u64,
+}
+
+impl CrcWriter {
+    fn copy<R: Read, W: Write>(&mut self, reader: &mut R, writer: &mut W) -> io::Result<()> {
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.hasher.update(&buffer[..read]);
+            self.size += read as u64;
+            writer.write_all(&buffer[..read])?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ArchiveFormat;
+    use std::fs;
+    use std::io::Cursor;
+
+    /// Incompressible bytes from a xorshift generator
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x9e37_79b9u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Zip archive of the files below `dir`
+    fn archive(options: &Options, dir: &Path) -> Vec<u8> {
+        let mut contents = Contents::default();
+        contents.add(dir, "").unwrap();
+        write_stream(options, &contents, Cursor::new(Vec::new()), &|_| {})
+            .unwrap()
+            .into_inner()
+    }
+
+    fn options(zip_method: ZipMethod, level: u32) -> Options {
+        Options {
+            format: ArchiveFormat::Zip,
+            level,
+            threads: 2,
+            zip_method,
+            store: vec!["png".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_methods() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir_all(source.join("sample/assets")).unwrap();
+        // Repetitive data with matches further apart than the 32 KiB deflate window
+        let block = noise(50_000);
+        let asset = [block.as_slice(), &block, &block[..20_000], &block].concat();
+        fs::write(source.join("sample/assets/level.dat"), &asset).unwrap();
+        fs::write(source.join("sample/assets/icon.png"), b"\x89PNG data").unwrap();
+        fs::write(source.join("sample/README.md"), "").unwrap();
+
+        for (zip_method, level, expected) in [
+            (ZipMethod::Store, 0, ::zip::CompressionMethod::Stored),
+            (ZipMethod::Deflate, 9, ::zip::CompressionMethod::Deflated),
+            (ZipMethod::Deflate64, 1, ::zip::CompressionMethod::Deflate64),
+            (ZipMethod::Deflate64, 9, ::zip::CompressionMethod::Deflate64),
+            (ZipMethod::Zstd, 19, ::zip::CompressionMethod::Zstd),
+        ] {
+            let data = archive(&options(zip_method, level), &source);
+            let mut archive = ::zip::ZipArchive::new(Cursor::new(data)).unwrap();
+            let names = archive.file_names().collect::<Vec<_>>();
+            assert_eq!(
+                names,
+                [
+                    "sample/",
+                    "sample/README.md",
+                    "sample/assets/",
+                    "sample/assets/icon.png",
+                    "sample/assets/level.dat",
+                ]
+            );
+
+            let mut read = |name: &str| {
+                let mut file = archive.by_name(name).unwrap();
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).unwrap();
+                (file.compression(), data)
+            };
+            assert_eq!(read("sample/README.md").1, b"");
+            assert_eq!(
+                read("sample/assets/icon.png"),
+                (::zip::CompressionMethod::Stored, b"\x89PNG data".to_vec())
+            );
+            let (method, data) = read("sample/assets/level.dat");
+            assert_eq!(method, expected);
+            assert!(data == asset, "{} level {}", zip_method.as_str(), level);
+
+            let directory = archive.by_name("sample/assets/").unwrap();
+            assert!(directory.is_dir());
+            assert_eq!(directory.unix_mode().unwrap() & 0o170000, 0o040000);
+        }
+    }
+
+    #[test]
+    fn test_deflate64_window() {
+        // Only Deflate64 can refer back more than 32 KiB
+        let dir = tempfile::tempdir().unwrap();
+        let block = noise(40_000);
+        fs::write(dir.path().join("data"), [block.as_slice(), &block].concat()).unwrap();
+
+        let size = |zip_method| {
+            let data = archive(&options(zip_method, 9), dir.path());
+            ::zip::ZipArchive::new(Cursor::new(data))
+                .unwrap()
+                .by_name("data")
+                .unwrap()
+                .compressed_size()
+        };
+        assert!(size(ZipMethod::Deflate64) < 45_000);
+        assert!(size(ZipMethod::Deflate) > 75_000);
+    }
+
+    #[test]
+    fn test_deflate64_blocks() {
+        // Long runs, several blocks and incompressible data stored as is
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("empty", Vec::new()),
+            ("zeros", vec![0u8; 1_500_000]),
+            ("noise", noise(700_000)),
+            (
+                "mixed",
+                [
+                    noise(100_000),
+                    vec![7; 200_000],
+                    noise(70_000),
+                    b"tail".to_vec(),
+                ]
+                .concat(),
+            ),
+        ];
+        for (name, data) in &files {
+            fs::write(dir.path().join(name), data).unwrap();
+        }
+
+        for level in [1, 5, 9] {
+            let data = archive(&options(ZipMethod::Deflate64, level), dir.path());
+            let mut archive = ::zip::ZipArchive::new(Cursor::new(data)).unwrap();
+            for (name, expected) in &files {
+                let mut file = archive.by_name(name).unwrap();
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).unwrap();
+                assert!(data == *expected, "{} at level {}", name, level);
+                if *name == "zeros" {
+                    assert!(file.compressed_size() < 1_000);
+                }
+                if *name == "noise" {
+                    assert!(file.compressed_size() < 700_000 + 100);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_zip64() {
+        // Zip64 fields for every entry, offset and the end of the central directory
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sample")).unwrap();
+        fs::write(dir.path().join("sample/a.dat"), vec![1u8; 70_000]).unwrap();
+        fs::write(dir.path().join("sample/b.png"), noise(1_000)).unwrap();
+        let mut contents = Contents::default();
+        contents.add(dir.path(), "").unwrap();
+
+        let limits = Limits {
+            size: 0,
+            offset: 0,
+            count: 0,
+        };
+        let options = options(ZipMethod::Deflate, 6);
+        let data = write_with_limits(
+            &options,
+            &contents,
+            Cursor::new(Vec::new()),
+            &|_| {},
+            limits,
+        )
+        .unwrap()
+        .into_inner();
+        let find = |signature: u32| {
+            data.windows(4)
+                .rposition(|w| w == signature.to_le_bytes())
+                .unwrap()
+        };
+        let record = find(ZIP64_END_OF_CENTRAL_DIRECTORY);
+        let locator = find(ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR);
+        assert_eq!(locator, record + 56);
+        assert_eq!(
+            u64::from_le_bytes(data[locator + 8..locator + 16].try_into().unwrap()),
+            record as u64
+        );
+        // Local header of the first file: both sizes in the extra field
+        assert_eq!(&data[0..4], &LOCAL_HEADER.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([data[4], data[5]]), VERSION_ZIP64);
+        assert_eq!(&data[18..26], &[0xff; 8]);
+
+        let mut archive = ::zip::ZipArchive::new(Cursor::new(data.clone())).unwrap();
+        assert_eq!(archive.len(), 3);
+        let mut file = archive.by_name("sample/a.dat").unwrap();
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).unwrap();
+        assert_eq!(read, vec![1u8; 70_000]);
+        assert_eq!(file.size(), 70_000);
+        drop(file);
+        let mut file = archive.by_name("sample/b.png").unwrap();
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).unwrap();
+        assert_eq!(read, noise(1_000));
+        assert_eq!(file.compression(), ::zip::CompressionMethod::Stored);
+    }
+
+    #[test]
+    fn test_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), vec![0u8; 100_000]).unwrap();
+        fs::write(dir.path().join("b"), b"data").unwrap();
+        let mut contents = Contents::default();
+        contents.add(dir.path(), "").unwrap();
+        assert_eq!(contents.size(), 100_004);
+
+        let read = std::cell::Cell::new(0);
+        let progress = |bytes| read.set(read.get() + bytes);
+        let options = options(ZipMethod::Zstd, 3);
+        write_stream(&options, &contents, Cursor::new(Vec::new()), &progress).unwrap();
+        assert_eq!(read.get(), 100_004);
+
+        // Files that shrink after they were listed fail the archive
+        fs::write(dir.path().join("a"), b"shorter").unwrap();
+        let error = write_stream(&options, &contents, Cursor::new(Vec::new()), &|_| {});
+        assert!(
+            error
+                .unwrap_err()
+                .to_string()
+                .contains("changed while it was archived")
+        );
+    }
+
+    #[test]
+    fn test_dos_date_time() {
+        // 2024-02-29 13:45:58 UTC
+        assert_eq!(
+            dos_date_time(1709214358),
+            ((13 << 11) | (45 << 5) | 29, (44 << 9) | (2 << 5) | 29)
+        );
+        assert_eq!(dos_date_time(0), (0, (1 << 5) | 1));
+    }
+}
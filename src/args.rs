@@ -1,18 +1,50 @@
-use clap::{Arg, ArgAction, Command};
+use crate::manifest::ArchiveFormat;
+use clap::builder::PossibleValuesParser;
+use clap::{Arg, ArgAction, Command, value_parser};
 use std::path::PathBuf;
 
 /// Command-line arguments for the emerge tool
 #[derive(Debug)]
 pub struct Args {
+    /// `verify` subcommand: check the artifacts against the output folder's SHA256SUMS
+    pub verify: bool,
+
+    /// `keygen` subcommand: create a minisign keypair
+    pub keygen: Option<Keygen>,
+
     /// Enable verbose output
     pub verbose: bool,
 
+    /// Print JSON records of the artifacts on stdout instead of the cliclack UI
+    pub json: bool,
+
     /// Create an archived setup (.tar.gz or .zip)
     pub archive: bool,
 
+    /// Archive format, overriding the manifest (implies `--archive`)
+    pub archive_format: Option<ArchiveFormat>,
+
+    /// Archive compression level, overriding the manifest
+    pub archive_level: Option<u32>,
+
+    /// Archive compression threads, overriding the manifest (0 = all cores)
+    pub archive_threads: Option<u32>,
+
     /// Create DMG image (default on macOS)
     pub dmg: bool,
 
+    /// Generate an NSIS installer script (and compile it if makensis is available)
+    pub nsis: bool,
+
+    /// Generate an Inno Setup installer script (and compile it if iscc is available)
+    pub inno: bool,
+
+    /// Create a self-extracting Linux installer (.run)
+    pub run: bool,
+
+    /// Create a flat macOS installer package (.pkg)
+    pub pkg: bool,
+
     /// Skip build commands (use existing binaries)
     pub no_build: bool,
 
@@ -23,6 +55,19 @@ pub struct Args {
     pub manifest: Option<PathBuf>,
 }
 
+/// Arguments of `cargo emerge keygen`
+#[derive(Debug)]
+pub struct Keygen {
+    /// Secret key file to write
+    pub secret_key: PathBuf,
+
+    /// Public key file to write
+    pub public_key: PathBuf,
+
+    /// Overwrite existing key files
+    pub force: bool,
+}
+
 impl Args {
     /// Parse command-line arguments
     pub fn parse() -> Self {
@@ -47,6 +92,7 @@ impl Args {
                     .short('p')
                     .long("path")
                     .value_name("PATH")
+                    .global(true)
                     .help("Path to Cargo.toml or directory containing it")
             )
             .arg(
@@ -54,6 +100,7 @@ impl Args {
                     .short('m')
                     .long("manifest")
                     .value_name("FILE")
+                    .global(true)
                     .help("Path to alternative manifest file (e.g., EXAMPLE.toml) for emerge configuration")
             )
             .arg(
@@ -61,8 +108,18 @@ impl Args {
                     .short('v')
                     .long("verbose")
                     .action(ArgAction::SetTrue)
+                    .global(true)
                     .help("Enable verbose output")
             )
+            .arg(
+                Arg::new("message-format")
+                    .long("message-format")
+                    .value_name("FORMAT")
+                    .value_parser(PossibleValuesParser::new(["human", "json"]))
+                    .default_value("human")
+                    .global(true)
+                    .help("Output format: human-readable, or JSON records of the artifacts on stdout")
+            )
             .arg(
                 Arg::new("archive")
                     .short('a')
@@ -70,24 +127,116 @@ impl Args {
                     .action(ArgAction::SetTrue)
                     .help("Create an archived setup (.tar.gz or .zip)")
             )
+            .arg(
+                Arg::new("archive-format")
+                    .long("archive-format")
+                    .value_name("FORMAT")
+                    .value_parser(PossibleValuesParser::new(ArchiveFormat::ALL.map(|f| f.as_str())))
+                    .help("Archive format (implies --archive)")
+            )
+            .arg(
+                Arg::new("archive-level")
+                    .long("archive-level")
+                    .value_name("LEVEL")
+                    .value_parser(value_parser!(u32))
+                    .help("Archive compression level")
+            )
+            .arg(
+                Arg::new("archive-threads")
+                    .long("archive-threads")
+                    .value_name("THREADS")
+                    .value_parser(value_parser!(u32))
+                    .help("Archive compression threads (0 = all cores)")
+            )
             .arg(
                 Arg::new("dmg")
                     .long("dmg")
                     .action(ArgAction::SetTrue)
                     .help("Create DMG image (default on macOS)")
             )
+            .arg(
+                Arg::new("nsis")
+                    .long("nsis")
+                    .action(ArgAction::SetTrue)
+                    .help("Generate an NSIS installer script (compiled if makensis is on PATH)")
+            )
+            .arg(
+                Arg::new("inno")
+                    .long("inno")
+                    .action(ArgAction::SetTrue)
+                    .help("Generate an Inno Setup installer script (compiled if iscc is on PATH)")
+            )
+            .arg(
+                Arg::new("run")
+                    .long("run")
+                    .action(ArgAction::SetTrue)
+                    .help("Create a self-extracting Linux installer (.run)")
+            )
+            .arg(
+                Arg::new("pkg")
+                    .long("pkg")
+                    .action(ArgAction::SetTrue)
+                    .help("Create a flat macOS installer package (.pkg)")
+            )
             .arg(
                 Arg::new("no-build")
                 .long("no-build")
                 .action(ArgAction::SetTrue)
                 .help("Skip build commands (use existing binaries)")
             )
+            .subcommand(
+                Command::new("verify")
+                    .about("Check existing artifacts against the SHA256SUMS file of the output folder")
+            )
+            .subcommand(
+                Command::new("keygen")
+                    .about("Create a minisign keypair for signing artifacts")
+                    .arg(
+                        Arg::new("secret-key")
+                            .long("secret-key")
+                            .value_name("FILE")
+                            .default_value("minisign.key")
+                            .help("Secret key file to write")
+                    )
+                    .arg(
+                        Arg::new("public-key")
+                            .long("public-key")
+                            .value_name("FILE")
+                            .default_value("minisign.pub")
+                            .help("Public key file to write")
+                    )
+                    .arg(
+                        Arg::new("force")
+                            .short('f')
+                            .long("force")
+                            .action(ArgAction::SetTrue)
+                            .help("Overwrite existing key files")
+                    )
+            )
             .get_matches_from(args_to_parse);
 
         Self {
+            verify: matches.subcommand_matches("verify").is_some(),
+            keygen: matches.subcommand_matches("keygen").map(|keygen| Keygen {
+                secret_key: PathBuf::from(keygen.get_one::<String>("secret-key").unwrap()),
+                public_key: PathBuf::from(keygen.get_one::<String>("public-key").unwrap()),
+                force: keygen.get_flag("force"),
+            }),
             verbose: matches.get_flag("verbose"),
+            json: matches
+                .get_one::<String>("message-format")
+                .is_some_and(|f| f == "json"),
             archive: matches.get_flag("archive"),
+            archive_format: matches
+                .get_one::<String>("archive-format")
+                .and_then(|f| ArchiveFormat::parse(f)),
+            archive_level: matches.get_one::<u32>("archive-level").copied(),
+            archive_threads: matches.get_one::<u32>("archive-threads").copied(),
             dmg: matches.get_flag("dmg"),
+            nsis: matches.get_flag("nsis"),
+            inno: matches.get_flag("inno"),
+            run: matches.get_flag("run"),
+            pkg: matches.get_flag("pkg"),
             no_build: matches.get_flag("no-build"),
             path: matches.get_one::<String>("path").map(PathBuf::from),
             manifest: matches.get_one::<String>("manifest").map(PathBuf::from),
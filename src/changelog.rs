@@ -0,0 +1,242 @@
+//! Release notes from a [Keep a Changelog](https://keepachangelog.com/) file
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::result::Result;
+use crate::utils::xml_escape;
+use std::fs;
+use std::path::PathBuf;
+
+/// Release notes written to the output folder and copied into the packages
+pub const RELEASE_NOTES_FILE: &str = "RELEASE_NOTES.md";
+
+/// The changelog section of one version
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleaseNotes {
+    /// Markdown body of the section, without its heading
+    pub text: String,
+    /// Release date from the heading (`## [1.0.0] - 2026-01-02`)
+    pub date: Option<String>,
+}
+
+/// Read the release notes of `manifest.version` from the configured changelog. A missing
+/// entry is a warning, or an error in strict mode.
+pub fn load(ctx: &Context, manifest: &Manifest) -> Result<Option<ReleaseNotes>> {
+    let Some(config) = &manifest.changelog else {
+        return Ok(None);
+    };
+    let path = ctx.base_dir.join(config.file());
+    let text = fs::read_to_string(&path).map_err(|e| {
+        Error::InvalidManifest(format!("Cannot read changelog {}: {}", path.display(), e))
+    })?;
+
+    match section(&text, &manifest.version) {
+        Some(notes) => Ok(Some(notes)),
+        None if config.strict() => Err(Error::InvalidManifest(format!(
+            "{} has no entry for version {}",
+            path.display(),
+            manifest.version
+        ))),
+        None => {
+            status!(
+                "Warning: {} has no entry for version {}",
+                path.display(),
+                manifest.version
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Write `RELEASE_NOTES.md` to the output folder if the manifest has release notes
+pub fn write(manifest: &Manifest) -> Result<Option<PathBuf>> {
+    let Some(notes) = &manifest.release_notes else {
+        return Ok(None);
+    };
+    fs::create_dir_all(&manifest.output_folder)?;
+    let path = manifest.output_folder.join(RELEASE_NOTES_FILE);
+    let heading = match &notes.date {
+        Some(date) => format!("# {} {} - {}", manifest.title, manifest.version, date),
+        None => format!("# {} {}", manifest.title, manifest.version),
+    };
+    fs::write(&path, format!("{}\n\n{}\n", heading, notes.text))?;
+    Ok(Some(path))
+}
+
+/// The section of `version`: from its `## [version]` heading to the next `##` heading.
+/// Empty sections count as missing.
+pub fn section(changelog: &str, version: &str) -> Option<ReleaseNotes> {
+    let mut lines = changelog.lines();
+    let date = lines.by_ref().find_map(|line| {
+        let (heading_version, date) = heading(line)?;
+        (heading_version == version).then_some(date)
+    })?;
+
+    let body = lines
+        .take_while(|line| heading(line).is_none())
+        // Link reference definitions of the versions follow the last section
+        .filter(|line| !is_link_definition(line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = body.trim().to_string();
+    (!text.is_empty()).then_some(ReleaseNotes { text, date })
+}
+
+/// Version and date of a `## [1.0.0] - 2026-01-02` heading; `## 1.0.0` and a leading
+/// `v` are accepted as well
+fn heading(line: &str) -> Option<(&str, Option<String>)> {
+    let rest = line.strip_prefix("## ")?.trim();
+    let (version, rest) = match rest.strip_prefix('[') {
+        Some(rest) => rest.split_once(']')?,
+        None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+    };
+    let date = rest
+        .trim()
+        .trim_start_matches(['-', '–', '—'])
+        .split_whitespace()
+        .next()
+        .map(str::to_string);
+    Some((version.trim().trim_start_matches('v'), date))
+}
+
+fn is_link_definition(line: &str) -> bool {
+    line.starts_with('[') && line.contains("]: ")
+}
+
+/// Markdown notes as an AppStream description: paragraphs and lists, with `###` headings
+/// as paragraphs of their own
+pub fn appstream_description(markdown: &str) -> String {
+    let mut xml = String::new();
+    let mut paragraph = Vec::new();
+    let mut items: Vec<String> = Vec::new();
+
+    fn flush(xml: &mut String, paragraph: &mut Vec<&str>, items: &mut Vec<String>) {
+        if !paragraph.is_empty() {
+            xml.push_str(&format!("<p>{}</p>", xml_escape(&paragraph.join(" "))));
+            paragraph.clear();
+        }
+        if !items.is_empty() {
+            xml.push_str("<ul>");
+            for item in items.drain(..) {
+                xml.push_str(&format!("<li>{}</li>", xml_escape(&item)));
+            }
+            xml.push_str("</ul>");
+        }
+    }
+
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+        if let Some(item) = trimmed
+            .strip_prefix("- ")
+            .or_else(|| trimmed.strip_prefix("* "))
+        {
+            if !paragraph.is_empty() {
+                flush(&mut xml, &mut paragraph, &mut Vec::new());
+            }
+            items.push(item.trim().to_string());
+        } else if trimmed.is_empty() {
+            flush(&mut xml, &mut paragraph, &mut items);
+        } else if let Some(title) = trimmed.strip_prefix('#') {
+            flush(&mut xml, &mut paragraph, &mut items);
+            paragraph.push(title.trim_start_matches('#').trim());
+            flush(&mut xml, &mut paragraph, &mut items);
+        } else if line.starts_with([' ', '\t'])
+            && let Some(item) = items.last_mut()
+        {
+            // Continuation of a list item
+            item.push(' ');
+            item.push_str(trimmed);
+        } else {
+            if !items.is_empty() {
+                flush(&mut xml, &mut Vec::new(), &mut items);
+            }
+            paragraph.push(trimmed);
+        }
+    }
+    flush(&mut xml, &mut paragraph, &mut items);
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANGELOG: &str = "# Changelog
+
+All notable changes to this project will be documented in this file.
+
+## [Unreleased]
+
+- Work in progress
+
+## [1.2.3] - 2026-01-02
+
+### Added
+
+- Dark mode
+- Export to PDF,
+  including bookmarks
+
+### Fixed
+
+- Crash on startup
+
+## [1.2.2] - 2025-12-01
+
+- First release
+
+[Unreleased]: https://example.com/compare/v1.2.3...HEAD
+[1.2.3]: https://example.com/compare/v1.2.2...v1.2.3
+[1.2.2]: https://example.com/releases/v1.2.2
+";
+
+    #[test]
+    fn test_section() {
+        let notes = section(CHANGELOG, "1.2.3").unwrap();
+        assert_eq!(notes.date.as_deref(), Some("2026-01-02"));
+        assert_eq!(
+            notes.text,
+            "### Added\n\n- Dark mode\n- Export to PDF,\n  including bookmarks\n\n### Fixed\n\n- Crash on startup"
+        );
+
+        // The last section stops before the link definitions
+        let notes = section(CHANGELOG, "1.2.2").unwrap();
+        assert_eq!(notes.text, "- First release");
+
+        assert!(section(CHANGELOG, "1.2").is_none());
+        assert!(section(CHANGELOG, "2.0.0").is_none());
+    }
+
+    #[test]
+    fn test_headings() {
+        assert_eq!(
+            heading("## [1.0.0] - 2026-01-02"),
+            Some(("1.0.0", Some("2026-01-02".to_string())))
+        );
+        assert_eq!(
+            heading("## v1.0.0 — 2026-01-02"),
+            Some(("1.0.0", Some("2026-01-02".to_string())))
+        );
+        assert_eq!(heading("## 1.0.0"), Some(("1.0.0", None)));
+        assert_eq!(heading("### Added"), None);
+        assert_eq!(heading("# Changelog"), None);
+
+        // Empty sections are missing
+        assert!(section("## [1.0.0]\n\n## [0.9.0]\n- x\n", "1.0.0").is_none());
+    }
+
+    #[test]
+    fn test_appstream_description() {
+        let notes = section(CHANGELOG, "1.2.3").unwrap();
+        assert_eq!(
+            appstream_description(&notes.text),
+            "<p>Added</p><ul><li>Dark mode</li><li>Export to PDF, including bookmarks</li></ul>\
+             <p>Fixed</p><ul><li>Crash on startup</li></ul>"
+        );
+        assert_eq!(
+            appstream_description("Faster & smaller.\nNo changes needed.\n- one"),
+            "<p>Faster &amp; smaller. No changes needed.</p><ul><li>one</li></ul>"
+        );
+    }
+}
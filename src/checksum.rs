@@ -0,0 +1,279 @@
+//! SHA-256 and BLAKE3 checksums of the produced artifacts, kept in a `SHA256SUMS` file
+
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::result::Result;
+use crate::utils;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Combined checksum file of the output folder, in `sha256sum` format
+pub const SUMS_FILE: &str = "SHA256SUMS";
+
+/// Checksums of one artifact, as lowercase hex
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksums {
+    pub sha256: String,
+    pub blake3: String,
+}
+
+/// Compute both checksums of `path` in a single read
+pub fn compute(path: &Path) -> Result<Checksums> {
+    let mut file = File::open(path)?;
+    let mut sha256 = Sha256::new();
+    let mut blake3 = blake3::Hasher::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        sha256.update(&buffer[..read]);
+        blake3.update(&buffer[..read]);
+    }
+    Ok(Checksums {
+        sha256: utils::hex(&sha256.finalize()),
+        blake3: blake3.finalize().to_hex().to_string(),
+    })
+}
+
+/// Checksum `artifacts`, write their sidecars if configured and update the `SHA256SUMS` file
+pub fn record(manifest: &Manifest, artifacts: &[PathBuf]) -> Result<Vec<(PathBuf, Checksums)>> {
+    let sidecar = manifest.checksums.as_ref().is_some_and(|c| c.sidecar);
+    let mut recorded = Vec::new();
+    let mut entries = Vec::new();
+    for artifact in artifacts {
+        let checksums = compute(artifact)?;
+        let name = entry_name(&manifest.output_folder, artifact);
+        status!("SHA-256 {}  {}", checksums.sha256, name);
+        status!("BLAKE3  {}  {}", checksums.blake3, name);
+
+        if sidecar {
+            let file_name = artifact.file_name().unwrap_or_default().to_string_lossy();
+            let mut sidecar_path = artifact.clone().into_os_string();
+            sidecar_path.push(".sha256");
+            fs::write(&sidecar_path, format_line(&checksums.sha256, &file_name))?;
+        }
+
+        entries.push((name, checksums.sha256.clone()));
+        recorded.push((artifact.clone(), checksums));
+    }
+
+    if !entries.is_empty() {
+        update_sums(&manifest.output_folder.join(SUMS_FILE), &entries)?;
+    }
+    Ok(recorded)
+}
+
+/// Add or replace `entries` (name, SHA-256) in the sums file, keeping the other lines
+pub fn update_sums(path: &Path, entries: &[(String, String)]) -> Result<()> {
+    let mut sums = if path.exists() {
+        parse_sums(&fs::read_to_string(path)?)?
+    } else {
+        BTreeMap::new()
+    };
+    for (name, sha256) in entries {
+        sums.insert(name.clone(), sha256.clone());
+    }
+
+    let text = sums
+        .iter()
+        .map(|(name, sha256)| format_line(sha256, name))
+        .collect::<String>();
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// Parse a `sha256sum` listing into checksums by file name
+pub fn parse_sums(text: &str) -> Result<BTreeMap<String, String>> {
+    let mut sums = BTreeMap::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parsed = line.split_once(' ').and_then(|(sha256, name)| {
+            // Text (" ") and binary ("*") mode markers are both accepted
+            let name = name.strip_prefix(' ').or_else(|| name.strip_prefix('*'))?;
+            let valid = sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit());
+            (valid && !name.is_empty()).then(|| (name.to_string(), sha256.to_ascii_lowercase()))
+        });
+        let Some((name, sha256)) = parsed else {
+            return Err(Error::Custom(format!(
+                "Invalid line {} in {}: {}",
+                number + 1,
+                SUMS_FILE,
+                line
+            )));
+        };
+        sums.insert(name, sha256);
+    }
+    Ok(sums)
+}
+
+/// Outcome of checking one entry of the sums file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Mismatch,
+    Missing,
+}
+
+/// Check the artifacts listed in the `SHA256SUMS` file of `folder`
+pub fn verify(folder: &Path) -> Result<Vec<(String, Status)>> {
+    let sums_path = folder.join(SUMS_FILE);
+    if !sums_path.exists() {
+        return Err(Error::Custom(format!(
+            "{} not found, nothing to verify",
+            sums_path.display()
+        )));
+    }
+
+    let mut results = Vec::new();
+    for (name, sha256) in parse_sums(&fs::read_to_string(&sums_path)?)? {
+        let path = folder.join(&name);
+        let status = if !path.is_file() {
+            Status::Missing
+        } else if compute(&path)?.sha256 == sha256 {
+            Status::Ok
+        } else {
+            Status::Mismatch
+        };
+        results.push((name, status));
+    }
+    Ok(results)
+}
+
+/// Name of `artifact` in the sums file, relative to the output folder
+pub fn entry_name(folder: &Path, artifact: &Path) -> String {
+    let relative = artifact
+        .strip_prefix(folder)
+        .ok()
+        .filter(|p| !p.as_os_str().is_empty())
+        .or_else(|| artifact.file_name().map(Path::new))
+        .unwrap_or(artifact);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn format_line(sha256: &str, name: &str) -> String {
+    format!("{}  {}\n", sha256, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::tests::sample_manifest;
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_compute() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.tar.gz");
+        fs::write(&path, b"").unwrap();
+        let checksums = compute(&path).unwrap();
+        assert_eq!(checksums.sha256, EMPTY_SHA256);
+        assert_eq!(
+            checksums.blake3,
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+
+        fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            compute(&path).unwrap().sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_parse_sums() {
+        let text = format!(
+            "{}  sample.zip\n{} *sample.run\n\n",
+            EMPTY_SHA256,
+            EMPTY_SHA256.to_uppercase()
+        );
+        let sums = parse_sums(&text).unwrap();
+        assert_eq!(sums.len(), 2);
+        assert_eq!(sums["sample.zip"], EMPTY_SHA256);
+        assert_eq!(sums["sample.run"], EMPTY_SHA256);
+
+        assert!(parse_sums("not a checksum  sample.zip\n").is_err());
+        assert!(parse_sums(&format!("{}\n", EMPTY_SHA256)).is_err());
+    }
+
+    #[test]
+    fn test_record_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, mut manifest) = sample_manifest("checksums = { sidecar = true }");
+        manifest.output_folder = dir.path().to_path_buf();
+
+        // Entries of earlier runs are kept, entries of the same name replaced
+        let sums_path = dir.path().join(SUMS_FILE);
+        fs::write(
+            &sums_path,
+            format!(
+                "{}  sample-1.0.0.zip\n{}  sample.run\n",
+                EMPTY_SHA256,
+                "0".repeat(64)
+            ),
+        )
+        .unwrap();
+        fs::write(dir.path().join("sample-1.0.0.zip"), b"").unwrap();
+
+        let run = dir.path().join("sample.run");
+        fs::write(&run, b"abc").unwrap();
+        let recorded = record(&manifest, std::slice::from_ref(&run)).unwrap();
+        let sha256 = &recorded[0].1.sha256;
+        assert_eq!(
+            fs::read_to_string(&sums_path).unwrap(),
+            format!(
+                "{}  sample-1.0.0.zip\n{}  sample.run\n",
+                EMPTY_SHA256, sha256
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("sample.run.sha256")).unwrap(),
+            format!("{}  sample.run\n", sha256)
+        );
+
+        assert_eq!(
+            verify(dir.path()).unwrap(),
+            [
+                ("sample-1.0.0.zip".to_string(), Status::Ok),
+                ("sample.run".to_string(), Status::Ok),
+            ]
+        );
+
+        fs::write(&run, b"abd").unwrap();
+        fs::remove_file(dir.path().join("sample-1.0.0.zip")).unwrap();
+        assert_eq!(
+            verify(dir.path()).unwrap(),
+            [
+                ("sample-1.0.0.zip".to_string(), Status::Missing),
+                ("sample.run".to_string(), Status::Mismatch),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entry_name() {
+        let folder = Path::new("/work/dist");
+        assert_eq!(entry_name(folder, &folder.join("sample.dmg")), "sample.dmg");
+        assert_eq!(
+            entry_name(folder, &folder.join("win/sample.exe")),
+            "win/sample.exe"
+        );
+        assert_eq!(
+            entry_name(folder, Path::new("/elsewhere/sample.pkg")),
+            "sample.pkg"
+        );
+    }
+}
@@ -7,7 +7,7 @@ use std::process::{Command, Stdio};
 /// Execute a command and stream output to stdout if verbose mode is enabled
 pub fn execute(ctx: &Context, program: &str, args: &[&str]) -> Result<()> {
     if ctx.verbose {
-        println!("Executing: {} {}", program, args.join(" "));
+        status!("Executing: {} {}", program, args.join(" "));
     }
 
     let mut child = Command::new(program)
@@ -21,7 +21,7 @@ pub fn execute(ctx: &Context, program: &str, args: &[&str]) -> Result<()> {
         let reader = BufReader::new(stdout);
         for line in reader.lines().map_while(|l| l.ok()) {
             if ctx.verbose {
-                println!("{}", line);
+                status!("{}", line);
             }
         }
     }
@@ -53,7 +53,7 @@ pub fn execute(ctx: &Context, program: &str, args: &[&str]) -> Result<()> {
 /// Execute a command and capture its output
 pub fn execute_with_output(ctx: &Context, program: &str, args: &[&str]) -> Result<String> {
     if ctx.verbose {
-        println!("Executing: {} {}", program, args.join(" "));
+        status!("Executing: {} {}", program, args.join(" "));
     }
 
     let output = Command::new(program).args(args).output()?;
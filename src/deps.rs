@@ -0,0 +1,37 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// How a dependency of a staged binary was resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Provided by the target system, not bundled
+    System,
+    /// Found on the build machine and copied into the bundle
+    Bundled(PathBuf),
+    /// Already shipped as part of the staged files
+    Staged,
+    /// Could not be found in any search path
+    Missing,
+}
+
+/// Print the resolved dependency set
+pub fn report(title: &str, dependencies: &BTreeMap<String, Resolution>) {
+    status!("{}:", title);
+    for (name, resolution) in dependencies {
+        match resolution {
+            Resolution::System => status!("  {} (system)", name),
+            Resolution::Staged => status!("  {} (staged)", name),
+            Resolution::Bundled(path) => status!("  {} => {}", name, path.display()),
+            Resolution::Missing => status!("  {} (not found)", name),
+        }
+    }
+}
+
+/// Names of the dependencies that could not be resolved
+pub fn missing(dependencies: &BTreeMap<String, Resolution>) -> Vec<&str> {
+    dependencies
+        .iter()
+        .filter(|(_, r)| **r == Resolution::Missing)
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
@@ -15,15 +15,27 @@ pub enum Error {
     #[error("TOML serialize error: {0}")]
     TomlSerialize(#[from] toml::ser::Error),
 
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
     #[error("Image error: {0}")]
     Image(#[from] image::ImageError),
 
-    #[error("Zip error: {0}")]
-    Zip(#[from] zip::result::ZipError),
-
     #[error("Walkdir error: {0}")]
     Walkdir(#[from] walkdir::Error),
 
+    #[error("Binary parse error: {0}")]
+    Binary(#[from] goblin::error::Error),
+
+    #[error("Signing error: {0}")]
+    Signing(String),
+
+    #[error("License policy violation: {0}")]
+    LicensePolicy(String),
+
+    #[error("DER error: {0}")]
+    Der(#[from] der::Error),
+
     #[error("Command execution failed: {0}")]
     CommandFailed(String),
 
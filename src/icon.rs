@@ -0,0 +1,342 @@
+//! Application icon set shared by the ICNS, ICO and hicolor outputs
+//!
+//! The icon is a single image, an SVG rendered at every size, or hand-tuned PNGs by
+//! pixel size. Rendered sizes are cached by the content hash of the sources in
+//! `target/emerge/icons`, so repeated runs do not render them again.
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::manifest::{IconSource, Manifest};
+use crate::result::Result;
+use crate::utils;
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader, RgbaImage};
+use resvg::{tiny_skia, usvg};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Largest size used by any output (the 512@2x ICNS entry)
+pub const MAX_SIZE: u32 = 1024;
+
+/// Bump when the rendering changes, to invalidate cached icon sets
+const CACHE_VERSION: &[u8] = b"emerge-icons-1";
+
+enum Source {
+    Svg(Box<usvg::Tree>),
+    /// Images by pixel size, ascending
+    Raster(Vec<(u32, RgbaImage)>),
+}
+
+/// Validated icon sources with cached rendering at any size
+pub struct IconSet {
+    source: Source,
+    /// Original SVG document, installed as the scalable icon
+    svg: Option<Vec<u8>>,
+    hash: String,
+    cache_dir: Option<PathBuf>,
+}
+
+impl IconSet {
+    /// Load `manifest.icon`, caching renders below the target directory.
+    /// A missing single icon file is skipped.
+    pub fn load(ctx: &Context, manifest: &Manifest) -> Result<Option<Self>> {
+        let Some(source) = &manifest.icon else {
+            return Ok(None);
+        };
+        if let IconSource::File(path) = source
+            && !path.exists()
+        {
+            return Ok(None);
+        }
+
+        let mut set = Self::from_source(source)?;
+        set.cache_dir = Some(utils::target_dir(ctx).join("emerge/icons").join(&set.hash));
+        Ok(Some(set))
+    }
+
+    /// Read and validate the icon sources
+    pub fn from_source(source: &IconSource) -> Result<Self> {
+        let mut hasher = Sha256::new();
+        hasher.update(CACHE_VERSION);
+
+        let files = match source {
+            IconSource::File(path) => vec![(None, path)],
+            IconSource::Sizes(sizes) => sizes.iter().map(|(s, p)| (Some(*s), p)).collect(),
+        };
+
+        let mut svg = None;
+        let mut images = Vec::new();
+        for (size, path) in files {
+            let data = fs::read(path).map_err(|e| {
+                Error::Custom(format!("Unable to read icon {}: {}", path.display(), e))
+            })?;
+            hasher.update(size.unwrap_or(0).to_be_bytes());
+            hasher.update((data.len() as u64).to_be_bytes());
+            hasher.update(&data);
+
+            let is_svg = path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("svg"));
+            if is_svg && size.is_none() {
+                svg = Some(data);
+                continue;
+            }
+
+            let image = ImageReader::new(Cursor::new(&data))
+                .with_guessed_format()?
+                .decode()
+                .map_err(|e| Error::Custom(format!("Invalid icon {}: {}", path.display(), e)))?
+                .to_rgba8();
+            let (width, height) = image.dimensions();
+            if width != height {
+                return Err(Error::Custom(format!(
+                    "Icon {} must be square, it is {}x{}",
+                    path.display(),
+                    width,
+                    height
+                )));
+            }
+            if let Some(size) = size
+                && width != size
+            {
+                return Err(Error::Custom(format!(
+                    "Icon {} is {}x{}, expected {}x{}",
+                    path.display(),
+                    width,
+                    height,
+                    size,
+                    size
+                )));
+            }
+            images.push((width, image));
+        }
+
+        let hash = utils::hex(&hasher.finalize()[..16]);
+
+        let source = match &svg {
+            Some(data) => {
+                let tree = usvg::Tree::from_data(data, &usvg::Options::default())
+                    .map_err(|e| Error::Custom(format!("Invalid SVG icon: {}", e)))?;
+                let size = tree.size();
+                if (size.width() - size.height()).abs() > 0.01 {
+                    return Err(Error::Custom(format!(
+                        "SVG icon must be square, it is {}x{}",
+                        size.width(),
+                        size.height()
+                    )));
+                }
+                Source::Svg(Box::new(tree))
+            }
+            None => {
+                images.sort_by_key(|(size, _)| *size);
+                if let Some((largest, _)) = images.last()
+                    && *largest < MAX_SIZE
+                {
+                    status!(
+                        "Warning: the largest icon is {largest}x{largest}; larger sizes are \
+                         upscaled and may look blurry (use {MAX_SIZE}x{MAX_SIZE} or an SVG)"
+                    );
+                }
+                Source::Raster(images)
+            }
+        };
+
+        Ok(Self {
+            source,
+            svg,
+            hash,
+            cache_dir: None,
+        })
+    }
+
+    /// SVG document of a vector icon
+    pub fn svg(&self) -> Option<&[u8]> {
+        self.svg.as_deref()
+    }
+
+    /// The icon at `size` x `size` pixels
+    pub fn image(&self, size: u32) -> Result<RgbaImage> {
+        let cached = self
+            .cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{size}.png")));
+        if let Some(path) = &cached
+            && let Ok(image) = image::open(path)
+            && image.width() == size
+        {
+            return Ok(image.to_rgba8());
+        }
+
+        let image = self.render(size)?;
+        if let Some(path) = &cached {
+            if let Some(parent) = path.parent() {
+                utils::ensure_dir(parent)?;
+            }
+            image.save_with_format(path, ImageFormat::Png)?;
+        }
+        Ok(image)
+    }
+
+    fn render(&self, size: u32) -> Result<RgbaImage> {
+        match &self.source {
+            Source::Svg(tree) => {
+                let mut pixmap = tiny_skia::Pixmap::new(size, size)
+                    .ok_or_else(|| Error::Custom(format!("Invalid icon size {}", size)))?;
+                let scale = size as f32 / tree.size().width();
+                resvg::render(
+                    tree,
+                    tiny_skia::Transform::from_scale(scale, scale),
+                    &mut pixmap.as_mut(),
+                );
+                let pixels = pixmap
+                    .pixels()
+                    .iter()
+                    .flat_map(|p| {
+                        let c = p.demultiply();
+                        [c.red(), c.green(), c.blue(), c.alpha()]
+                    })
+                    .collect();
+                Ok(RgbaImage::from_raw(size, size, pixels).expect("pixmap size"))
+            }
+            Source::Raster(images) => {
+                // The exact size, else the smallest larger image, else the largest one
+                let (source_size, image) = images
+                    .iter()
+                    .find(|(s, _)| *s >= size)
+                    .or_else(|| images.last())
+                    .ok_or_else(|| Error::Custom("Empty icon set".to_string()))?;
+                if *source_size == size {
+                    Ok(image.clone())
+                } else {
+                    Ok(image::imageops::resize(
+                        image,
+                        size,
+                        size,
+                        FilterType::Lanczos3,
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Path of the icon file if it is a single file of the given format, used as is
+pub fn passthrough<'a>(manifest: &'a Manifest, extension: &str) -> Option<&'a Path> {
+    manifest
+        .icon
+        .as_ref()
+        .and_then(|icon| icon.file_with_extension(extension))
+        .filter(|path| path.exists())
+        .map(|path| path.as_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use std::collections::BTreeMap;
+
+    const SVG: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 10 10">
+        <rect width="10" height="5" fill="#ff0000"/></svg>"##;
+
+    fn solid(dir: &Path, name: &str, size: u32, color: [u8; 4]) -> PathBuf {
+        let path = dir.join(name);
+        RgbaImage::from_pixel(size, size, Rgba(color))
+            .save(&path)
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_svg() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("icon.svg");
+        fs::write(&path, SVG).unwrap();
+
+        let set = IconSet::from_source(&IconSource::File(path)).unwrap();
+        assert_eq!(set.svg(), Some(SVG.as_bytes()));
+        for size in [16, 1024] {
+            let image = set.image(size).unwrap();
+            assert_eq!(image.dimensions(), (size, size));
+            assert_eq!(image.get_pixel(1, 1), &Rgba([255, 0, 0, 255]));
+            assert_eq!(image.get_pixel(1, size - 2)[3], 0);
+        }
+
+        fs::write(
+            dir.path().join("wide.svg"),
+            SVG.replace("0 0 10 10", "0 0 20 10"),
+        )
+        .unwrap();
+        let wide = IconSet::from_source(&IconSource::File(dir.path().join("wide.svg")));
+        assert!(wide.is_err());
+    }
+
+    #[test]
+    fn test_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = solid(dir.path(), "16.png", 16, [0, 0, 255, 255]);
+        let large = solid(dir.path(), "256.png", 256, [255, 0, 0, 255]);
+        let source = IconSource::Sizes(BTreeMap::from([(16, small.clone()), (256, large)]));
+        let set = IconSet::from_source(&source).unwrap();
+
+        // Hand-tuned sizes are used as they are, others come from the next larger image
+        assert_eq!(
+            set.image(16).unwrap().get_pixel(0, 0),
+            &Rgba([0, 0, 255, 255])
+        );
+        assert_eq!(
+            set.image(24).unwrap().get_pixel(0, 0),
+            &Rgba([255, 0, 0, 255])
+        );
+        assert_eq!(set.image(512).unwrap().dimensions(), (512, 512));
+
+        // Declared sizes must match
+        let source = IconSource::Sizes(BTreeMap::from([(32, small)]));
+        let err = IconSet::from_source(&source).err().unwrap();
+        assert!(err.to_string().contains("expected 32x32"));
+    }
+
+    #[test]
+    fn test_non_square() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("icon.png");
+        RgbaImage::new(64, 32).save(&path).unwrap();
+        let err = IconSet::from_source(&IconSource::File(path)).err().unwrap();
+        assert!(err.to_string().contains("must be square"));
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = solid(dir.path(), "icon.png", 64, [0, 255, 0, 255]);
+        let mut ctx = Context::new(dir.path().join("Cargo.toml"), false);
+        ctx.base_dir = dir.path().to_path_buf();
+        let (_, mut manifest) = crate::manifest::tests::sample_manifest("");
+        manifest.icon = Some(IconSource::File(path.clone()));
+
+        let set = IconSet::load(&ctx, &manifest).unwrap().unwrap();
+        set.image(32).unwrap();
+        let cached = utils::target_dir(&ctx)
+            .join("emerge/icons")
+            .join(&set.hash)
+            .join("32.png");
+        assert!(cached.exists());
+
+        // The cached render is used; changed sources get a new hash
+        solid(cached.parent().unwrap(), "32.png", 32, [9, 9, 9, 255]);
+        let again = IconSet::load(&ctx, &manifest).unwrap().unwrap();
+        assert_eq!(
+            again.image(32).unwrap().get_pixel(0, 0),
+            &Rgba([9, 9, 9, 255])
+        );
+
+        solid(dir.path(), "icon.png", 64, [0, 0, 0, 255]);
+        let changed = IconSet::load(&ctx, &manifest).unwrap().unwrap();
+        assert_ne!(changed.hash, set.hash);
+
+        manifest.icon = Some(IconSource::File(dir.path().join("missing.png")));
+        assert!(IconSet::load(&ctx, &manifest).unwrap().is_none());
+    }
+}
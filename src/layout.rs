@@ -0,0 +1,218 @@
+//! Placement of copied files in the package layouts
+//!
+//! Every copy operation has a [`Location`], either from the `location` field of its
+//! copy rule or detected from the kind of file. An `.app` bundle maps the locations
+//! to its `Contents` folders; Windows and Linux packages use one application directory.
+
+use crate::manifest::{Location, Manifest};
+use std::path::{Path, PathBuf};
+
+/// Documentation files without an extension that stay next to the application
+const DOCUMENTS: &[&str] = &[
+    "LICENSE",
+    "LICENCE",
+    "COPYING",
+    "README",
+    "CHANGELOG",
+    "NOTICE",
+    "AUTHORS",
+];
+
+/// Default location of a file by its kind
+pub fn detect(source: &Path, destination: &Path) -> Location {
+    let extension = destination
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let name = destination
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+
+    if source.is_dir() {
+        return match extension.as_deref() {
+            Some("framework") => Location::Frameworks,
+            Some("plugin" | "bundle" | "appex") => Location::Plugins,
+            Some("app") => Location::Macos,
+            _ => Location::Resources,
+        };
+    }
+
+    match extension.as_deref() {
+        _ if name.contains(".so.") => Location::Frameworks,
+        Some("dylib" | "so" | "dll") => Location::Frameworks,
+        Some("md" | "txt" | "pdf" | "html" | "rtf") => Location::Volume,
+        None if DOCUMENTS.contains(&name.to_ascii_uppercase().as_str()) => Location::Volume,
+        None | Some("exe" | "sh" | "command") => Location::Macos,
+        _ => Location::Resources,
+    }
+}
+
+/// A copy operation with its resolved location
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub source: PathBuf,
+    /// Destination path within the layout
+    pub destination: PathBuf,
+    pub location: Location,
+}
+
+/// Target directory of each location
+#[derive(Debug, Clone)]
+pub struct Layout {
+    macos: PathBuf,
+    resources: PathBuf,
+    frameworks: PathBuf,
+    plugins: PathBuf,
+    volume: PathBuf,
+}
+
+impl Layout {
+    /// An `.app` bundle inside `volume_root`
+    pub fn bundle(app_path: &Path, volume_root: &Path) -> Self {
+        let contents = app_path.join("Contents");
+        Self {
+            macos: contents.join("MacOS"),
+            resources: contents.join("Resources"),
+            frameworks: contents.join("Frameworks"),
+            plugins: contents.join("PlugIns"),
+            volume: volume_root.to_path_buf(),
+        }
+    }
+
+    /// A single application directory holding every location
+    pub fn flat(app_dir: &Path) -> Self {
+        Self {
+            macos: app_dir.to_path_buf(),
+            resources: app_dir.to_path_buf(),
+            frameworks: app_dir.to_path_buf(),
+            plugins: app_dir.to_path_buf(),
+            volume: app_dir.to_path_buf(),
+        }
+    }
+
+    pub fn dir(&self, location: Location) -> &Path {
+        match location {
+            Location::Macos => &self.macos,
+            Location::Resources => &self.resources,
+            Location::Frameworks => &self.frameworks,
+            Location::Plugins => &self.plugins,
+            Location::Volume => &self.volume,
+        }
+    }
+
+    /// Place copy operations, honouring the explicit locations of the manifest
+    pub fn place<'a>(
+        &self,
+        manifest: &Manifest,
+        operations: impl IntoIterator<Item = &'a (PathBuf, PathBuf)>,
+    ) -> Vec<Placement> {
+        operations
+            .into_iter()
+            .map(|(src, dst)| {
+                let location = manifest
+                    .copy_locations
+                    .get(dst)
+                    .copied()
+                    .unwrap_or_else(|| detect(src, dst));
+                Placement {
+                    source: src.clone(),
+                    destination: self.dir(location).join(dst),
+                    location,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::tests::sample_manifest;
+
+    #[test]
+    fn test_detect() {
+        let dir = tempfile::tempdir().unwrap();
+        let cases = [
+            ("app", Location::Macos),
+            ("bin/tool.sh", Location::Macos),
+            ("app.exe", Location::Macos),
+            ("libfoo.dylib", Location::Frameworks),
+            ("libfoo.so.1", Location::Frameworks),
+            ("config.json", Location::Resources),
+            ("settings.toml", Location::Resources),
+            ("icon.png", Location::Resources),
+            ("README.md", Location::Volume),
+            ("LICENSE", Location::Volume),
+            ("Manual.pdf", Location::Volume),
+        ];
+        for (name, location) in cases {
+            assert_eq!(
+                detect(&dir.path().join("missing"), Path::new(name)),
+                location,
+                "{}",
+                name
+            );
+        }
+
+        for (name, location) in [
+            ("assets", Location::Resources),
+            ("Sparkle.framework", Location::Frameworks),
+            ("Effects.plugin", Location::Plugins),
+            ("Helper.app", Location::Macos),
+        ] {
+            assert_eq!(detect(dir.path(), Path::new(name)), location, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_place() {
+        let (_, manifest) = sample_manifest(
+            r#"
+            copy = [
+                { "target/release/sample" = "sample" },
+                { "README.md" = "README.md" },
+                { from = "notes.txt", location = "resources" },
+                { from = "lib/libfoo.dylib", to = "libfoo.dylib" },
+            ]
+            "#,
+        );
+        let mut operations = manifest.copy_operations.clone();
+        operations.sort();
+
+        let layout = Layout::bundle(Path::new("/vol/Sample.app"), Path::new("/vol"));
+        let placed = layout
+            .place(&manifest, &operations)
+            .into_iter()
+            .map(|p| (p.destination, p.location))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            placed,
+            [
+                (PathBuf::from("/vol/README.md"), Location::Volume),
+                (
+                    PathBuf::from("/vol/Sample.app/Contents/Frameworks/libfoo.dylib"),
+                    Location::Frameworks
+                ),
+                (
+                    PathBuf::from("/vol/Sample.app/Contents/Resources/notes.txt"),
+                    Location::Resources
+                ),
+                (
+                    PathBuf::from("/vol/Sample.app/Contents/MacOS/sample"),
+                    Location::Macos
+                ),
+            ]
+        );
+
+        // Flat layouts keep the destination, whatever the location
+        let flat = Layout::flat(Path::new("/app"));
+        for placement in flat.place(&manifest, &operations) {
+            assert!(placement.destination.starts_with("/app"));
+            assert_eq!(
+                placement.destination.file_name(),
+                placement.source.file_name()
+            );
+        }
+    }
+}
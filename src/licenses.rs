@@ -0,0 +1,919 @@
+//! Licenses of the third-party packages shipped with the application, collected offline
+//! from a vendor directory or the cargo registry, rendered as `THIRD_PARTY_LICENSES.html`
+//! or `.md`
+
+use crate::cmd;
+use crate::context::Context;
+use crate::error::Error;
+use crate::manifest::{LicensesConfig, LicensesFormat, Manifest};
+use crate::result::Result;
+use crate::utils::xml_escape;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Name of the license file without its extension
+pub const LICENSES_FILE: &str = "THIRD_PARTY_LICENSES";
+
+/// Beginnings of the names of license and notice files, upper case
+const LICENSE_FILES: &[&str] = &[
+    "LICENSE",
+    "LICENCE",
+    "COPYING",
+    "COPYRIGHT",
+    "NOTICE",
+    "UNLICENSE",
+];
+
+#[derive(Debug, Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    /// `registry+...` or `git+...`; workspace members and path dependencies have none
+    source: Option<String>,
+}
+
+/// The parts of `cargo metadata` needed to walk the dependency graph
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    packages: Vec<MetadataPackage>,
+    resolve: Option<Resolve>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataPackage {
+    id: String,
+    name: String,
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Resolve {
+    nodes: Vec<Node>,
+    root: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    id: String,
+    #[serde(default)]
+    deps: Vec<NodeDep>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeDep {
+    pkg: String,
+    #[serde(default)]
+    dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DepKind {
+    /// `dev` or `build`; normal dependencies have none
+    kind: Option<String>,
+}
+
+/// Name and version of a package
+type PackageId = (String, String);
+
+/// License information of one package
+#[derive(Debug, Clone, PartialEq)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// SPDX expression of the `license` field
+    pub license: Option<String>,
+    pub repository: Option<String>,
+    /// License and notice files: name relative to the package root, text
+    pub files: Vec<(String, String)>,
+    /// Whether the source of the package was found
+    pub found: bool,
+}
+
+/// Collect the licenses of the packages the application depends on, check them against the
+/// policy and write the license file to the output folder. Returns `None` without a
+/// `licenses` table.
+pub fn write(ctx: &Context, manifest: &Manifest) -> Result<Option<PathBuf>> {
+    let Some(config) = &manifest.licenses else {
+        return Ok(None);
+    };
+
+    let lockfile = match &config.lockfile {
+        Some(path) => ctx.base_dir.join(path),
+        None => find_lockfile(&ctx.base_dir).ok_or_else(|| {
+            Error::InvalidManifest(format!(
+                "No Cargo.lock found in {} or its parents, run `cargo generate-lockfile`",
+                ctx.base_dir.display()
+            ))
+        })?,
+    };
+    let shipped = shipped(&metadata(ctx, config)?)?;
+    let sources = Sources::new(config.vendor.as_ref().map(|v| ctx.base_dir.join(v)));
+    let packages = collect(&fs::read_to_string(&lockfile)?, &shipped, config, &sources)?;
+
+    for package in packages.iter().filter(|p| !p.found) {
+        status!(
+            "Warning: source of {} {} not found, run `cargo fetch` or `cargo vendor`",
+            package.name,
+            package.version
+        );
+    }
+    check(config, &packages)?;
+
+    fs::create_dir_all(&manifest.output_folder)?;
+    let path = manifest.output_folder.join(file_name(config));
+    let text = match config.format {
+        LicensesFormat::Html => render_html(&manifest.title, &packages),
+        LicensesFormat::Md => render_markdown(&manifest.title, &packages),
+    };
+    fs::write(&path, text)?;
+    Ok(Some(path))
+}
+
+/// `THIRD_PARTY_LICENSES.html` or `.md`
+pub fn file_name(config: &LicensesConfig) -> String {
+    format!("{}.{}", LICENSES_FILE, config.format.extension())
+}
+
+/// Cargo.lock next to the manifest, or of the workspace in a parent directory
+fn find_lockfile(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.is_file())
+}
+
+/// `cargo metadata` of the packaged crate for the configured target or the host
+fn metadata(ctx: &Context, config: &LicensesConfig) -> Result<String> {
+    let target = match &config.target {
+        Some(target) => target.clone(),
+        None => host_target(ctx)?,
+    };
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let manifest_path = ctx.manifest_path.to_string_lossy();
+    cmd::execute_with_output(
+        ctx,
+        &cargo,
+        &[
+            "metadata",
+            "--offline",
+            "--format-version",
+            "1",
+            "--filter-platform",
+            &target,
+            "--manifest-path",
+            &manifest_path,
+        ],
+    )
+}
+
+/// Target triple of the host, from `rustc -vV`
+fn host_target(ctx: &Context) -> Result<String> {
+    let version = cmd::execute_with_output(ctx, "rustc", &["-vV"])?;
+    version
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(|host| host.trim().to_string())
+        .ok_or_else(|| Error::Custom("Cannot determine the host target from rustc -vV".into()))
+}
+
+/// Packages reachable from the root package of `cargo metadata` through normal
+/// dependencies. Build and dev-dependencies are not shipped, and neither is anything only
+/// they depend on.
+fn shipped(metadata: &str) -> Result<HashSet<PackageId>> {
+    let metadata: Metadata = serde_json::from_str(metadata)?;
+    let resolve = metadata
+        .resolve
+        .ok_or_else(|| Error::Custom("cargo metadata has no dependency graph".into()))?;
+    let root = resolve.root.ok_or_else(|| {
+        Error::InvalidManifest("Third-party licenses need a package, not a virtual manifest".into())
+    })?;
+    let nodes = resolve
+        .nodes
+        .iter()
+        .map(|node| (node.id.as_str(), node))
+        .collect::<HashMap<_, _>>();
+
+    let mut reached = HashSet::new();
+    let mut pending = vec![root.as_str()];
+    while let Some(id) = pending.pop() {
+        if !reached.insert(id) {
+            continue;
+        }
+        let Some(node) = nodes.get(id) else {
+            continue;
+        };
+        pending.extend(
+            node.deps
+                .iter()
+                .filter(|dep| dep.dep_kinds.iter().any(|k| k.kind.is_none()))
+                .map(|dep| dep.pkg.as_str()),
+        );
+    }
+
+    Ok(metadata
+        .packages
+        .into_iter()
+        .filter(|p| reached.contains(p.id.as_str()))
+        .map(|p| (p.name, p.version))
+        .collect())
+}
+
+/// Third-party packages of a Cargo.lock that are `shipped`, sorted by name and version
+fn collect(
+    lockfile: &str,
+    shipped: &HashSet<PackageId>,
+    config: &LicensesConfig,
+    sources: &Sources,
+) -> Result<Vec<Package>> {
+    let lockfile: Lockfile = toml::from_str(lockfile)
+        .map_err(|e| Error::InvalidManifest(format!("Invalid Cargo.lock: {}", e)))?;
+
+    let mut packages = lockfile
+        .package
+        .into_iter()
+        .filter(|p| p.source.is_some() && !config.exclude.contains(&p.name))
+        .filter(|p| shipped.contains(&(p.name.clone(), p.version.clone())))
+        .map(|p| {
+            sources.read(&p.name, &p.version).unwrap_or(Package {
+                name: p.name,
+                version: p.version,
+                license: None,
+                repository: None,
+                files: Vec::new(),
+                found: false,
+            })
+        })
+        .collect::<Vec<_>>();
+    packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    Ok(packages)
+}
+
+/// Places where package sources are looked up, in order
+struct Sources {
+    vendor: Option<PathBuf>,
+    /// Unpacked crates, `$CARGO_HOME/registry/src/<index>`
+    registry_src: Vec<PathBuf>,
+    /// Downloaded `.crate` files, `$CARGO_HOME/registry/cache/<index>`
+    registry_cache: Vec<PathBuf>,
+}
+
+impl Sources {
+    fn new(vendor: Option<PathBuf>) -> Self {
+        let registry = cargo_home().map(|home| home.join("registry"));
+        let indexes = |kind: &str| -> Vec<PathBuf> {
+            registry
+                .as_ref()
+                .and_then(|r| fs::read_dir(r.join(kind)).ok())
+                .map(|entries| {
+                    let mut dirs = entries
+                        .filter_map(|e| e.ok().map(|e| e.path()))
+                        .filter(|p| p.is_dir())
+                        .collect::<Vec<_>>();
+                    dirs.sort();
+                    dirs
+                })
+                .unwrap_or_default()
+        };
+        Self {
+            vendor,
+            registry_src: indexes("src"),
+            registry_cache: indexes("cache"),
+        }
+    }
+
+    fn read(&self, name: &str, version: &str) -> Option<Package> {
+        let full_name = format!("{}-{}", name, version);
+
+        // `cargo vendor` uses the bare name unless several versions are vendored
+        if let Some(vendor) = &self.vendor {
+            for dir in [vendor.join(&full_name), vendor.join(name)] {
+                if let Some(package) = read_dir(&dir)
+                    && package.name == name
+                    && package.version == version
+                {
+                    return Some(package);
+                }
+            }
+        }
+        self.registry_src
+            .iter()
+            .find_map(|index| read_dir(&index.join(&full_name)))
+            .or_else(|| {
+                self.registry_cache.iter().find_map(|index| {
+                    read_crate(&index.join(format!("{}.crate", full_name)), &full_name)
+                })
+            })
+    }
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    if let Some(home) = std::env::var_os("CARGO_HOME") {
+        return Some(PathBuf::from(home));
+    }
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".cargo"))
+}
+
+/// Whether a file in the package root is a license or notice file
+fn is_license_file(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    LICENSE_FILES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// Read an unpacked package
+fn read_dir(dir: &Path) -> Option<Package> {
+    let cargo_toml = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let license_file = license_file(&cargo_toml);
+
+    let mut names = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| is_license_file(name))
+        .collect::<Vec<_>>();
+    names.extend(license_file.filter(|file| !names.contains(file)));
+
+    let files = names
+        .into_iter()
+        .filter_map(|name| {
+            let data = fs::read(dir.join(&name)).ok()?;
+            Some((name, String::from_utf8_lossy(&data).to_string()))
+        })
+        .collect();
+    package(&cargo_toml, files)
+}
+
+/// Read a downloaded `.crate` file: a tar.gz of `<name>-<version>/`
+fn read_crate(path: &Path, root: &str) -> Option<Package> {
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(path).ok()?));
+    let mut contents = HashMap::new();
+    for entry in archive.entries().ok()? {
+        let mut entry = entry.ok()?;
+        let path = entry.path().ok()?.into_owned();
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        // A license-file of Cargo.toml may be in a subdirectory
+        let name = crate::utils::unix_path(relative);
+        let file_name = name.rsplit('/').next().unwrap_or_default();
+        if name == "Cargo.toml" || is_license_file(file_name) {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).ok()?;
+            contents.insert(name, String::from_utf8_lossy(&data).to_string());
+        }
+    }
+
+    let cargo_toml = contents.get("Cargo.toml")?.clone();
+    let license_file = license_file(&cargo_toml);
+    let mut files = contents
+        .into_iter()
+        .filter(|(name, _)| {
+            (!name.contains('/') && is_license_file(name)) || Some(name) == license_file.as_ref()
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    package(&cargo_toml, files)
+}
+
+/// `package.license-file` of a Cargo.toml, relative to the package root
+fn license_file(cargo_toml: &str) -> Option<String> {
+    let value: toml::Value = toml::from_str(cargo_toml).ok()?;
+    let file = value.get("package")?.get("license-file")?.as_str()?;
+    Some(file.trim_start_matches("./").replace('\\', "/"))
+}
+
+fn package(cargo_toml: &str, mut files: Vec<(String, String)>) -> Option<Package> {
+    let value: toml::Value = toml::from_str(cargo_toml).ok()?;
+    let package = value.get("package")?;
+    let field = |key: &str| {
+        package
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+    };
+    files.sort();
+    Some(Package {
+        name: field("name")?,
+        version: field("version")?,
+        license: field("license"),
+        repository: field("repository"),
+        files,
+        found: true,
+    })
+}
+
+/// An SPDX license expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    /// A license identifier, with the exception of `WITH` if any
+    License(String, Option<String>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Parse an expression such as `(MIT OR Apache-2.0) AND Unicode-3.0`; the legacy
+    /// `MIT/Apache-2.0` form of old crates means `OR`
+    pub fn parse(text: &str) -> Option<Self> {
+        let spaced = text
+            .replace('(', " ( ")
+            .replace(')', " ) ")
+            .replace('/', " OR ");
+        let tokens = spaced.split_whitespace().collect::<Vec<_>>();
+        let mut position = 0;
+        let expression = Self::parse_or(&tokens, &mut position)?;
+        (position == tokens.len()).then_some(expression)
+    }
+
+    fn parse_or(tokens: &[&str], position: &mut usize) -> Option<Self> {
+        let mut left = Self::parse_and(tokens, position)?;
+        while tokens
+            .get(*position)
+            .is_some_and(|t| t.eq_ignore_ascii_case("OR"))
+        {
+            *position += 1;
+            let right = Self::parse_and(tokens, position)?;
+            left = Expression::Or(Box::new(left), Box::new(right));
+        }
+        Some(left)
+    }
+
+    fn parse_and(tokens: &[&str], position: &mut usize) -> Option<Self> {
+        let mut left = Self::parse_term(tokens, position)?;
+        while tokens
+            .get(*position)
+            .is_some_and(|t| t.eq_ignore_ascii_case("AND"))
+        {
+            *position += 1;
+            let right = Self::parse_term(tokens, position)?;
+            left = Expression::And(Box::new(left), Box::new(right));
+        }
+        Some(left)
+    }
+
+    fn parse_term(tokens: &[&str], position: &mut usize) -> Option<Self> {
+        let token = *tokens.get(*position)?;
+        *position += 1;
+        if token == "(" {
+            let expression = Self::parse_or(tokens, position)?;
+            if tokens.get(*position) != Some(&")") {
+                return None;
+            }
+            *position += 1;
+            return Some(expression);
+        }
+        if [")", "AND", "OR", "WITH"]
+            .iter()
+            .any(|t| token.eq_ignore_ascii_case(t))
+        {
+            return None;
+        }
+        let exception = match tokens.get(*position) {
+            Some(t) if t.eq_ignore_ascii_case("WITH") => {
+                let exception = tokens.get(*position + 1)?;
+                *position += 2;
+                Some(exception.to_string())
+            }
+            _ => None,
+        };
+        Some(Expression::License(token.to_string(), exception))
+    }
+
+    /// Whether the licenses can be complied with when only licenses for which `allowed`
+    /// holds may be chosen
+    pub fn satisfies(&self, allowed: &impl Fn(&str) -> bool) -> bool {
+        match self {
+            Expression::License(id, _) => allowed(id),
+            Expression::And(a, b) => a.satisfies(allowed) && b.satisfies(allowed),
+            Expression::Or(a, b) => a.satisfies(allowed) || b.satisfies(allowed),
+        }
+    }
+}
+
+/// Fail if a package cannot be used under the `allow` and `deny` lists. With `allow`,
+/// packages without a license expression fail as well.
+fn check(config: &LicensesConfig, packages: &[Package]) -> Result<()> {
+    if config.allow.is_empty() && config.deny.is_empty() {
+        return Ok(());
+    }
+    let contains = |list: &[String], id: &str| list.iter().any(|l| l.eq_ignore_ascii_case(id));
+    let allowed = |id: &str| {
+        !contains(&config.deny, id) && (config.allow.is_empty() || contains(&config.allow, id))
+    };
+
+    let mut violations = Vec::new();
+    for package in packages {
+        let problem = match package
+            .license
+            .as_deref()
+            .map(|l| (l, Expression::parse(l)))
+        {
+            Some((_, Some(expression))) if expression.satisfies(&allowed) => continue,
+            Some((license, Some(_))) => format!("{} is not allowed", license),
+            Some((license, None)) => format!("invalid license expression {:?}", license),
+            None if config.allow.is_empty() => continue,
+            None if package.found => "no license expression".to_string(),
+            None => "source not found".to_string(),
+        };
+        violations.push(format!("{} {}: {}", package.name, package.version, problem));
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::LicensePolicy(violations.join("; ")))
+    }
+}
+
+/// Packages by license expression, for the overview
+fn by_license(packages: &[Package]) -> BTreeMap<&str, Vec<&Package>> {
+    let mut licenses = BTreeMap::<&str, Vec<&Package>>::new();
+    for package in packages {
+        licenses
+            .entry(package.license.as_deref().unwrap_or("Unknown"))
+            .or_default()
+            .push(package);
+    }
+    licenses
+}
+
+fn render_markdown(title: &str, packages: &[Package]) -> String {
+    let mut md = format!(
+        "# Third-Party Licenses\n\n{} includes the following third-party packages.\n\n",
+        title
+    );
+    md.push_str("| License | Packages |\n|---------|----------|\n");
+    for (license, packages) in by_license(packages) {
+        let names = packages
+            .iter()
+            .map(|p| format!("{} {}", p.name, p.version))
+            .collect::<Vec<_>>();
+        md.push_str(&format!("| {} | {} |\n", license, names.join(", ")));
+    }
+
+    for package in packages {
+        md.push_str(&format!("\n## {} {}\n\n", package.name, package.version));
+        md.push_str(&format!(
+            "License: {}\n",
+            package.license.as_deref().unwrap_or("Unknown")
+        ));
+        if let Some(repository) = &package.repository {
+            md.push_str(&format!("\nRepository: <{}>\n", repository));
+        }
+        for (name, text) in &package.files {
+            // A fence longer than any backtick run of the text
+            let mut fence = "```".to_string();
+            while text.contains(&fence) {
+                fence.push('`');
+            }
+            md.push_str(&format!(
+                "\n### {}\n\n{}text\n{}\n{}\n",
+                name,
+                fence,
+                text.trim_end(),
+                fence
+            ));
+        }
+    }
+    md
+}
+
+fn render_html(title: &str, packages: &[Package]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>Third-Party Licenses</title>\n\
+         <style>body{font-family:sans-serif;margin:2em;max-width:60em}pre{white-space:pre-wrap;background:#f5f5f5;padding:1em}</style>\n\
+         </head>\n<body>\n<h1>Third-Party Licenses</h1>\n",
+    );
+    html.push_str(&format!(
+        "<p>{} includes the following third-party packages.</p>\n",
+        xml_escape(title)
+    ));
+    html.push_str("<table>\n<tr><th>License</th><th>Packages</th></tr>\n");
+    for (license, packages) in by_license(packages) {
+        let links = packages
+            .iter()
+            .map(|p| {
+                format!(
+                    "<a href=\"#{0}-{1}\">{0} {1}</a>",
+                    xml_escape(&p.name),
+                    xml_escape(&p.version)
+                )
+            })
+            .collect::<Vec<_>>();
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td></tr>\n",
+            xml_escape(license),
+            links.join(", ")
+        ));
+    }
+    html.push_str("</table>\n");
+
+    for package in packages {
+        html.push_str(&format!(
+            "<h2 id=\"{0}-{1}\">{0} {1}</h2>\n<p>License: {2}</p>\n",
+            xml_escape(&package.name),
+            xml_escape(&package.version),
+            xml_escape(package.license.as_deref().unwrap_or("Unknown"))
+        ));
+        if let Some(repository) = &package.repository {
+            html.push_str(&format!(
+                "<p>Repository: <a href=\"{0}\">{0}</a></p>\n",
+                xml_escape(repository)
+            ));
+        }
+        for (name, text) in &package.files {
+            html.push_str(&format!(
+                "<h3>{}</h3>\n<pre>{}</pre>\n",
+                xml_escape(name),
+                xml_escape(text.trim_end())
+            ));
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    fn config(allow: &[&str], deny: &[&str]) -> LicensesConfig {
+        LicensesConfig {
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn package(name: &str, license: Option<&str>) -> Package {
+        Package {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            license: license.map(str::to_string),
+            repository: None,
+            files: Vec::new(),
+            found: true,
+        }
+    }
+
+    #[test]
+    fn test_expression() {
+        let license = |id: &str| Expression::License(id.to_string(), None);
+        assert_eq!(
+            Expression::parse("(MIT OR Apache-2.0) AND Unicode-3.0"),
+            Some(Expression::And(
+                Box::new(Expression::Or(
+                    Box::new(license("MIT")),
+                    Box::new(license("Apache-2.0"))
+                )),
+                Box::new(license("Unicode-3.0"))
+            ))
+        );
+        // AND binds tighter than OR
+        assert_eq!(
+            Expression::parse("MIT OR Apache-2.0 AND BSD-3-Clause"),
+            Some(Expression::Or(
+                Box::new(license("MIT")),
+                Box::new(Expression::And(
+                    Box::new(license("Apache-2.0")),
+                    Box::new(license("BSD-3-Clause"))
+                ))
+            ))
+        );
+        assert_eq!(
+            Expression::parse("Apache-2.0 WITH LLVM-exception"),
+            Some(Expression::License(
+                "Apache-2.0".to_string(),
+                Some("LLVM-exception".to_string())
+            ))
+        );
+        assert_eq!(
+            Expression::parse("MIT/Apache-2.0"),
+            Expression::parse("MIT OR Apache-2.0")
+        );
+        for invalid in ["", "MIT OR", "(MIT", "MIT Apache-2.0", "AND MIT"] {
+            assert_eq!(Expression::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let packages = [
+            package("dual", Some("MIT OR GPL-3.0-only")),
+            package("both", Some("MIT AND Zlib")),
+        ];
+        check(&config(&[], &[]), &packages).unwrap();
+        // One allowed alternative is enough, every license of AND is needed
+        check(&config(&["MIT", "zlib"], &[]), &packages).unwrap();
+        let err = check(&config(&["MIT"], &[]), &packages).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "License policy violation: both 1.0.0: MIT AND Zlib is not allowed"
+        );
+        check(&config(&[], &["GPL-3.0-only"]), &packages).unwrap();
+        assert!(check(&config(&[], &["MIT"]), &packages).is_err());
+
+        // Unknown licenses only fail an allow list
+        let unknown = [package("unknown", None)];
+        check(&config(&[], &["GPL-3.0-only"]), &unknown).unwrap();
+        let err = check(&config(&["MIT"], &[]), &unknown).unwrap_err();
+        assert!(err.to_string().contains("no license expression"));
+        let invalid = [package("invalid", Some("MIT OR"))];
+        assert!(check(&config(&["MIT"], &[]), &invalid).is_err());
+    }
+
+    #[test]
+    fn test_collect() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // A vendored package under its bare name
+        let vendored = dir.path().join("vendor/vendored");
+        fs::create_dir_all(&vendored).unwrap();
+        fs::write(
+            vendored.join("Cargo.toml"),
+            "[package]\nname = \"vendored\"\nversion = \"1.0.0\"\nlicense = \"MIT\"\nrepository = \"https://example.com/vendored\"\n",
+        )
+        .unwrap();
+        fs::write(vendored.join("LICENSE-MIT"), "MIT text").unwrap();
+        fs::write(vendored.join("README.md"), "readme").unwrap();
+
+        // A downloaded .crate with a license-file in a subdirectory
+        let cache = dir.path().join("cache/index");
+        fs::create_dir_all(&cache).unwrap();
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(cache.join("cached-2.0.0.crate")).unwrap(),
+            Compression::default(),
+        ));
+        for (path, data) in [
+            (
+                "cached-2.0.0/Cargo.toml",
+                "[package]\nname = \"cached\"\nversion = \"2.0.0\"\nlicense-file = \"legal/LICENSE.txt\"\n",
+            ),
+            ("cached-2.0.0/legal/LICENSE.txt", "Custom terms"),
+            ("cached-2.0.0/NOTICE", "Notice text"),
+            ("cached-2.0.0/src/lib.rs", ""),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, data.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let lockfile = r#"
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+
+[[package]]
+name = "cached"
+version = "2.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "dev-only"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "internal"
+version = "0.3.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "missing"
+version = "0.1.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "vendored"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#;
+        let sources = Sources {
+            vendor: Some(dir.path().join("vendor")),
+            registry_src: Vec::new(),
+            registry_cache: vec![cache],
+        };
+        let config = LicensesConfig {
+            exclude: vec!["internal".to_string()],
+            ..Default::default()
+        };
+        let shipped = [
+            ("app", "0.1.0"),
+            ("cached", "2.0.0"),
+            ("internal", "0.3.0"),
+            ("missing", "0.1.0"),
+            ("vendored", "1.0.0"),
+        ]
+        .map(|(name, version)| (name.to_string(), version.to_string()))
+        .into();
+        let packages = collect(lockfile, &shipped, &config, &sources).unwrap();
+
+        assert_eq!(
+            packages.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+            ["cached", "missing", "vendored"]
+        );
+        assert_eq!(packages[0].license, None);
+        assert_eq!(
+            packages[0].files,
+            [
+                ("NOTICE".to_string(), "Notice text".to_string()),
+                ("legal/LICENSE.txt".to_string(), "Custom terms".to_string()),
+            ]
+        );
+        assert!(!packages[1].found);
+        assert_eq!(packages[2].license.as_deref(), Some("MIT"));
+        assert_eq!(
+            packages[2].files,
+            [("LICENSE-MIT".to_string(), "MIT text".to_string())]
+        );
+
+        let md = render_markdown("Sample", &packages);
+        assert!(md.contains("| MIT | vendored 1.0.0 |"));
+        assert!(md.contains("| Unknown | cached 2.0.0, missing 0.1.0 |"));
+        assert!(md.contains("### LICENSE-MIT\n\n```text\nMIT text\n```\n"));
+        let html = render_html("Sample & Co", &packages);
+        assert!(html.contains("<p>Sample &amp; Co includes"));
+        assert!(html.contains("<a href=\"#vendored-1.0.0\">vendored 1.0.0</a>"));
+        assert!(html.contains("<h3>LICENSE-MIT</h3>\n<pre>MIT text</pre>"));
+    }
+
+    #[test]
+    fn test_shipped() {
+        let metadata = r#"{
+            "packages": [
+                {"id": "path+file:///app#0.1.0", "name": "app", "version": "0.1.0"},
+                {"id": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0", "name": "serde", "version": "1.0.0"},
+                {"id": "registry+https://github.com/rust-lang/crates.io-index#itoa@1.0.0", "name": "itoa", "version": "1.0.0"},
+                {"id": "registry+https://github.com/rust-lang/crates.io-index#cc@1.2.0", "name": "cc", "version": "1.2.0"},
+                {"id": "registry+https://github.com/rust-lang/crates.io-index#shlex@1.3.0", "name": "shlex", "version": "1.3.0"},
+                {"id": "registry+https://github.com/rust-lang/crates.io-index#tempfile@3.0.0", "name": "tempfile", "version": "3.0.0"},
+                {"id": "path+file:///app/tool#0.1.0", "name": "tool", "version": "0.1.0"},
+                {"id": "registry+https://github.com/rust-lang/crates.io-index#clap@4.0.0", "name": "clap", "version": "4.0.0"}
+            ],
+            "resolve": {
+                "nodes": [
+                    {"id": "path+file:///app#0.1.0", "deps": [
+                        {"pkg": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0", "dep_kinds": [{"kind": null, "target": null}]},
+                        {"pkg": "registry+https://github.com/rust-lang/crates.io-index#cc@1.2.0", "dep_kinds": [{"kind": "build", "target": null}]},
+                        {"pkg": "registry+https://github.com/rust-lang/crates.io-index#tempfile@3.0.0", "dep_kinds": [{"kind": "dev", "target": null}]}
+                    ]},
+                    {"id": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0", "deps": [
+                        {"pkg": "registry+https://github.com/rust-lang/crates.io-index#itoa@1.0.0", "dep_kinds": [{"kind": null, "target": null}]}
+                    ]},
+                    {"id": "registry+https://github.com/rust-lang/crates.io-index#itoa@1.0.0", "deps": []},
+                    {"id": "registry+https://github.com/rust-lang/crates.io-index#cc@1.2.0", "deps": [
+                        {"pkg": "registry+https://github.com/rust-lang/crates.io-index#shlex@1.3.0", "dep_kinds": [{"kind": null, "target": null}]}
+                    ]},
+                    {"id": "registry+https://github.com/rust-lang/crates.io-index#shlex@1.3.0", "deps": []},
+                    {"id": "registry+https://github.com/rust-lang/crates.io-index#tempfile@3.0.0", "deps": []},
+                    {"id": "path+file:///app/tool#0.1.0", "deps": [
+                        {"pkg": "registry+https://github.com/rust-lang/crates.io-index#clap@4.0.0", "dep_kinds": [{"kind": null, "target": null}]}
+                    ]},
+                    {"id": "registry+https://github.com/rust-lang/crates.io-index#clap@4.0.0", "deps": []}
+                ],
+                "root": "path+file:///app#0.1.0"
+            }
+        }"#;
+        // Build and dev-dependencies, their dependencies and other workspace members are
+        // not shipped
+        let mut names = shipped(metadata)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["app", "itoa", "serde"]);
+
+        let virtual_manifest =
+            metadata.replace(r#""root": "path+file:///app#0.1.0""#, r#""root": null"#);
+        assert!(shipped(&virtual_manifest).is_err());
+    }
+
+    #[test]
+    fn test_markdown_fence() {
+        let mut package = package("fenced", Some("MIT"));
+        package.files = vec![("LICENSE".to_string(), "a ``` b".to_string())];
+        let md = render_markdown("Sample", &[package]);
+        assert!(md.contains("````text\na ``` b\n````"));
+    }
+}
@@ -1,86 +1,88 @@
+use crate::archive::{self, Contents};
 use crate::context::Context;
-use crate::manifest::Manifest;
+use crate::layout::Layout;
+use crate::linux::{desktop, libs};
+use crate::macos;
+use crate::manifest::{ArchiveFormat, Location, Manifest};
 use crate::result::Result;
 use crate::utils;
-use flate2::Compression;
-use flate2::write::GzEncoder;
-use std::fs::{self, File};
-use std::path::Path;
-use tar::Builder;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-pub fn create_tar_gz(ctx: &Context, manifest: &Manifest) -> Result<()> {
-    println!("Creating tar.gz archive for Linux...");
+/// Create the archive of `--archive` on Linux and macOS (tar.gz unless configured otherwise)
+pub fn create(ctx: &Context, manifest: &Manifest) -> Result<PathBuf> {
+    let options = archive::Options::from_manifest(manifest, ArchiveFormat::TarGz)?;
+    status!("Creating {} archive...", options.format.as_str());
 
     // Ensure output folder exists
     utils::ensure_dir(&manifest.output_folder)?;
 
-    // Create temporary directory for archive contents
+    // Generated and modified files are staged; copied files are read in place
     let temp_dir = std::env::temp_dir().join(format!("emerge-{}", manifest.name));
     if temp_dir.exists() {
         fs::remove_dir_all(&temp_dir)?;
     }
     fs::create_dir_all(&temp_dir)?;
 
-    // Create application directory
-    let app_dir = temp_dir.join(&manifest.name);
-    fs::create_dir_all(&app_dir)?;
+    let contents = contents(ctx, manifest, &temp_dir, &manifest.name)?;
 
-    // Copy files according to copy operations
-    for (src, dst) in &manifest.copy_operations {
-        let dest_path = app_dir.join(dst);
+    // Create the archive
+    let archive_filename = options.file_name(&manifest.filename);
+    let archive_path = manifest.output_folder.join(&archive_filename);
 
-        if ctx.verbose {
-            println!("Copying {} to {}", src.display(), dest_path.display());
-        }
+    archive::write_with_progress(&options, &contents, &archive_path)?;
 
-        // Ensure parent directory exists
-        if let Some(parent) = dest_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
+    // Clean up temp directory
+    fs::remove_dir_all(&temp_dir)?;
 
-        utils::copy_recursively(src, &dest_path)?;
+    status!("Archive created successfully: {}", archive_path.display());
+    Ok(archive_path)
+}
 
-        // Set executable permissions for binary files (files without common document extensions)
-        #[cfg(unix)]
-        {
-            let dst_extension = dst.extension().and_then(|e| e.to_str());
-            let is_documentation = matches!(
-                dst_extension,
-                Some("md" | "txt" | "pdf" | "html" | "toml" | "json" | "yml" | "yaml")
-            );
+/// Entries of the Linux layout below `root` (`<name>` in archives, empty in the `.run`
+/// payload). Copied files are read from their sources; universal binaries, bundled
+/// libraries and desktop files are generated in `temp_dir`, where ELF files are also
+/// copied when their RUNPATH is rewritten.
+pub fn contents(
+    ctx: &Context,
+    manifest: &Manifest,
+    temp_dir: &Path,
+    root: &str,
+) -> Result<Contents> {
+    let mut contents = Contents::default();
+    let layout = Layout::flat(Path::new(root));
+    for placement in layout.place(manifest, &manifest.copy_operations) {
+        let path = utils::unix_path(&placement.destination);
+        if ctx.verbose {
+            status!("Adding {} as {}", placement.source.display(), path);
+        }
+        contents.add(&placement.source, &path)?;
 
-            if !is_documentation
-                && let Ok(metadata) = fs::metadata(&dest_path)
-                && metadata.is_file()
-            {
-                use std::os::unix::fs::PermissionsExt;
-                let mut perms = metadata.permissions();
-                perms.set_mode(0o755);
-                fs::set_permissions(&dest_path, perms)?;
-            }
+        // Executables are executable
+        if placement.location == Location::Macos && placement.source.is_file() {
+            contents.set_mode(&path, 0o755);
         }
     }
 
-    // Create tar.gz archive
-    let archive_filename = format!("{}.tar.gz", manifest.filename);
-    let archive_path = manifest.output_folder.join(&archive_filename);
-
-    create_tar_gz_file(&temp_dir, &archive_path)?;
-
-    // Clean up temp directory
-    fs::remove_dir_all(&temp_dir)?;
+    let app_dir = temp_dir.join(root);
+    fs::create_dir_all(&app_dir)?;
 
-    println!("Archive created successfully: {}", archive_path.display());
-    Ok(())
-}
+    // Universal macOS binaries (tar.gz archives are also used on macOS)
+    macos::universal::install(ctx, manifest, &app_dir)?;
 
-fn create_tar_gz_file(source_dir: &Path, output_path: &Path) -> Result<()> {
-    let tar_gz = File::create(output_path)?;
-    let enc = GzEncoder::new(tar_gz, Compression::default());
-    let mut tar = Builder::new(enc);
+    // Bundle non-system shared libraries (opt-in)
+    if manifest.linux.as_ref().is_some_and(|l| l.bundle_libraries) {
+        contents.stage(temp_dir, libs::is_elf)?;
+    }
+    libs::bundle(ctx, manifest, &app_dir)?;
 
-    tar.append_dir_all(".", source_dir)?;
-    tar.finish()?;
+    // Desktop integration (.desktop entry, hicolor icons, AppStream metainfo)
+    for file in desktop::install(ctx, manifest, &app_dir)? {
+        if ctx.verbose {
+            status!("Generated {}", file.display());
+        }
+    }
 
-    Ok(())
+    contents.add(temp_dir, "")?;
+    Ok(contents)
 }
@@ -0,0 +1,516 @@
+use crate::context::Context;
+use crate::error::Error;
+use crate::icon::IconSet;
+use crate::manifest::{DesktopConfig, Manifest};
+use crate::result::Result;
+use crate::utils::{self, xml_escape};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Standard hicolor theme sizes generated from `manifest.icon`
+pub const HICOLOR_SIZES: [u32; 9] = [16, 22, 24, 32, 48, 64, 128, 256, 512];
+
+/// Main categories from the freedesktop.org Desktop Menu Specification
+const MAIN_CATEGORIES: [&str; 13] = [
+    "AudioVideo",
+    "Audio",
+    "Video",
+    "Development",
+    "Education",
+    "Game",
+    "Graphics",
+    "Network",
+    "Office",
+    "Science",
+    "Settings",
+    "System",
+    "Utility",
+];
+
+/// Field codes allowed in the `Exec` key
+const EXEC_FIELD_CODES: [char; 8] = ['f', 'F', 'u', 'U', 'i', 'c', 'k', '%'];
+
+/// Resolved desktop integration settings
+#[derive(Debug, Clone)]
+pub struct DesktopSpec {
+    pub app_id: String,
+    pub title: String,
+    pub summary: String,
+    pub description: String,
+    pub version: String,
+    pub release_date: String,
+    /// Changelog section of the version (Markdown)
+    pub release_notes: Option<String>,
+    pub executable: String,
+    pub project_license: Option<String>,
+    pub homepage: Option<String>,
+    pub has_icon: bool,
+    pub desktop: DesktopConfig,
+}
+
+impl DesktopSpec {
+    /// Build the spec from the manifest; `None` if desktop integration is not configured
+    pub fn from_manifest(manifest: &Manifest) -> Option<Self> {
+        let linux = manifest.linux.as_ref()?;
+        let desktop = linux.desktop.clone()?;
+
+        let summary = desktop
+            .comment
+            .clone()
+            .filter(|c| !c.is_empty())
+            .or_else(|| Some(manifest.description.clone()).filter(|d| !d.is_empty()))
+            .unwrap_or_else(|| manifest.title.clone());
+
+        Some(Self {
+            app_id: app_id(manifest),
+            title: manifest.title.clone(),
+            summary,
+            description: manifest.description.clone(),
+            version: manifest.version.clone(),
+            release_date: manifest
+                .release_notes
+                .as_ref()
+                .and_then(|n| n.date.clone())
+                .unwrap_or_else(|| time::OffsetDateTime::now_utc().date().to_string()),
+            release_notes: manifest.release_notes.as_ref().map(|n| n.text.clone()),
+            executable: linux
+                .executable
+                .clone()
+                .unwrap_or_else(|| manifest.name.clone()),
+            project_license: linux.project_license.clone(),
+            homepage: linux.homepage.clone(),
+            has_icon: manifest.icon.as_ref().is_some_and(|i| i.exists()),
+            desktop,
+        })
+    }
+}
+
+/// Application id used for desktop integration file names
+pub fn app_id(manifest: &Manifest) -> String {
+    manifest
+        .linux
+        .as_ref()
+        .and_then(|l| l.app_id.clone())
+        .unwrap_or_else(|| format!("com.{}.{}", manifest.name, manifest.name))
+}
+
+/// Write the .desktop entry, hicolor icons and AppStream metainfo below `root`
+/// using the FHS `share/` layout. Returns the written files relative to `root`.
+pub fn install(ctx: &Context, manifest: &Manifest, root: &Path) -> Result<Vec<PathBuf>> {
+    let Some(spec) = DesktopSpec::from_manifest(manifest) else {
+        return Ok(Vec::new());
+    };
+
+    let mut written = Vec::new();
+
+    let desktop_file = PathBuf::from("share/applications").join(format!("{}.desktop", spec.app_id));
+    write_file(root, &desktop_file, &desktop_entry(&spec)?)?;
+    written.push(desktop_file);
+
+    let metainfo_file =
+        PathBuf::from("share/metainfo").join(format!("{}.metainfo.xml", spec.app_id));
+    write_file(root, &metainfo_file, &metainfo(&spec))?;
+    written.push(metainfo_file);
+
+    if let Some(icon_set) = IconSet::load(ctx, manifest)? {
+        if ctx.verbose {
+            status!("Generating hicolor icons");
+        }
+        written.extend(generate_hicolor_icons(&icon_set, root, &spec.app_id)?);
+    }
+
+    Ok(written)
+}
+
+fn write_file(root: &Path, relative: &Path, content: &str) -> Result<()> {
+    let path = root.join(relative);
+    if let Some(parent) = path.parent() {
+        utils::ensure_dir(parent)?;
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Render PNG icons at the standard hicolor sizes below `root/share/icons/hicolor`,
+/// plus the scalable icon of an SVG source
+pub fn generate_hicolor_icons(
+    icon_set: &IconSet,
+    root: &Path,
+    app_id: &str,
+) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for size in HICOLOR_SIZES {
+        let relative = PathBuf::from(format!("share/icons/hicolor/{size}x{size}/apps"))
+            .join(format!("{}.png", app_id));
+        let path = root.join(&relative);
+        if let Some(parent) = path.parent() {
+            utils::ensure_dir(parent)?;
+        }
+        icon_set
+            .image(size)?
+            .save_with_format(&path, image::ImageFormat::Png)?;
+        written.push(relative);
+    }
+
+    if let Some(svg) = icon_set.svg() {
+        let relative =
+            PathBuf::from("share/icons/hicolor/scalable/apps").join(format!("{}.svg", app_id));
+        let path = root.join(&relative);
+        if let Some(parent) = path.parent() {
+            utils::ensure_dir(parent)?;
+        }
+        fs::write(&path, svg)?;
+        written.push(relative);
+    }
+
+    Ok(written)
+}
+
+/// Render and validate a .desktop entry
+pub fn desktop_entry(spec: &DesktopSpec) -> Result<String> {
+    validate(spec)?;
+
+    let desktop = &spec.desktop;
+    let mut exec = vec![escape_exec_arg(&spec.executable)];
+    exec.extend(desktop.exec_args.iter().cloned());
+
+    let mut s = String::new();
+    // Writing to a String is infallible, hence the unwraps below
+    writeln!(s, "[Desktop Entry]").unwrap();
+    writeln!(s, "Type=Application").unwrap();
+    writeln!(s, "Version=1.5").unwrap();
+    writeln!(s, "Name={}", escape_value(&spec.title)).unwrap();
+    writeln!(s, "Comment={}", escape_value(&spec.summary)).unwrap();
+    writeln!(s, "Exec={}", exec.join(" ")).unwrap();
+    if spec.has_icon {
+        writeln!(s, "Icon={}", spec.app_id).unwrap();
+    }
+    writeln!(s, "Terminal={}", desktop.terminal).unwrap();
+    if !desktop.categories.is_empty() {
+        writeln!(s, "Categories={}", list(&desktop.categories)).unwrap();
+    }
+    if !desktop.mime_types.is_empty() {
+        writeln!(s, "MimeType={}", list(&desktop.mime_types)).unwrap();
+    }
+    if !desktop.keywords.is_empty() {
+        writeln!(s, "Keywords={}", list(&desktop.keywords)).unwrap();
+    }
+    if let Some(wm_class) = &desktop.startup_wm_class {
+        writeln!(s, "StartupWMClass={}", escape_value(wm_class)).unwrap();
+    }
+
+    Ok(s)
+}
+
+/// Check the desktop configuration against the Desktop Entry Specification
+pub fn validate(spec: &DesktopSpec) -> Result<()> {
+    let desktop = &spec.desktop;
+    let invalid = |msg: String| Err(Error::InvalidManifest(format!("linux.desktop: {}", msg)));
+
+    if spec.title.trim().is_empty() {
+        return invalid("application name must not be empty".to_string());
+    }
+
+    if spec.executable.trim().is_empty() {
+        return invalid("executable must not be empty".to_string());
+    }
+
+    if !spec
+        .app_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return invalid(format!("invalid application id '{}'", spec.app_id));
+    }
+
+    for category in &desktop.categories {
+        if category.is_empty()
+            || !category
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return invalid(format!("invalid category '{}'", category));
+        }
+    }
+    if !desktop.categories.is_empty()
+        && !desktop
+            .categories
+            .iter()
+            .any(|c| MAIN_CATEGORIES.contains(&c.as_str()))
+    {
+        return invalid(format!(
+            "categories must include one of the main categories: {}",
+            MAIN_CATEGORIES.join(", ")
+        ));
+    }
+
+    for mime in &desktop.mime_types {
+        let valid = mime
+            .split_once('/')
+            .is_some_and(|(t, s)| !t.is_empty() && !s.is_empty() && !s.contains('/'));
+        if !valid || mime.contains(';') || mime.contains(char::is_whitespace) {
+            return invalid(format!("invalid MIME type '{}'", mime));
+        }
+    }
+
+    for arg in &desktop.exec_args {
+        let mut chars = arg.chars();
+        while let Some(c) = chars.next() {
+            if c == '%' {
+                match chars.next() {
+                    Some(code) if EXEC_FIELD_CODES.contains(&code) => {}
+                    Some(code) => {
+                        return invalid(format!("unknown Exec field code '%{}'", code));
+                    }
+                    None => return invalid("dangling '%' in Exec arguments".to_string()),
+                }
+            }
+        }
+    }
+
+    if let Some(wm_class) = &desktop.startup_wm_class
+        && (wm_class.is_empty() || wm_class.contains(char::is_whitespace))
+    {
+        return invalid(format!("invalid StartupWMClass '{}'", wm_class));
+    }
+
+    for value in [&spec.title, &spec.summary]
+        .into_iter()
+        .chain(desktop.keywords.iter())
+    {
+        if value
+            .chars()
+            .any(|c| c.is_control() && c != '\n' && c != '\t')
+        {
+            return invalid(format!("control characters are not allowed in '{}'", value));
+        }
+    }
+
+    Ok(())
+}
+
+/// Render the AppStream metainfo document
+pub fn metainfo(spec: &DesktopSpec) -> String {
+    let mut s = String::new();
+    // Writing to a String is infallible, hence the unwraps below
+    writeln!(s, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(s, r#"<component type="desktop-application">"#).unwrap();
+    writeln!(s, "  <id>{}</id>", xml_escape(&spec.app_id)).unwrap();
+    writeln!(s, "  <metadata_license>CC0-1.0</metadata_license>").unwrap();
+    if let Some(license) = &spec.project_license {
+        writeln!(
+            s,
+            "  <project_license>{}</project_license>",
+            xml_escape(license)
+        )
+        .unwrap();
+    }
+    writeln!(s, "  <name>{}</name>", xml_escape(&spec.title)).unwrap();
+    writeln!(s, "  <summary>{}</summary>", xml_escape(&spec.summary)).unwrap();
+    if !spec.description.is_empty() {
+        writeln!(s, "  <description>").unwrap();
+        writeln!(s, "    <p>{}</p>", xml_escape(&spec.description)).unwrap();
+        writeln!(s, "  </description>").unwrap();
+    }
+    writeln!(
+        s,
+        r#"  <launchable type="desktop-id">{}.desktop</launchable>"#,
+        xml_escape(&spec.app_id)
+    )
+    .unwrap();
+    if let Some(homepage) = &spec.homepage {
+        writeln!(
+            s,
+            r#"  <url type="homepage">{}</url>"#,
+            xml_escape(homepage)
+        )
+        .unwrap();
+    }
+    writeln!(s, "  <provides>").unwrap();
+    writeln!(
+        s,
+        "    <binary>{}</binary>",
+        xml_escape(&binary_name(&spec.executable))
+    )
+    .unwrap();
+    for mime in &spec.desktop.mime_types {
+        writeln!(s, "    <mediatype>{}</mediatype>", xml_escape(mime)).unwrap();
+    }
+    writeln!(s, "  </provides>").unwrap();
+    if !spec.desktop.keywords.is_empty() {
+        writeln!(s, "  <keywords>").unwrap();
+        for keyword in &spec.desktop.keywords {
+            writeln!(s, "    <keyword>{}</keyword>", xml_escape(keyword)).unwrap();
+        }
+        writeln!(s, "  </keywords>").unwrap();
+    }
+    writeln!(s, "  <releases>").unwrap();
+    match &spec.release_notes {
+        Some(notes) => {
+            writeln!(
+                s,
+                r#"    <release version="{}" date="{}">"#,
+                xml_escape(&spec.version),
+                xml_escape(&spec.release_date)
+            )
+            .unwrap();
+            writeln!(
+                s,
+                "      <description>{}</description>",
+                crate::changelog::appstream_description(notes)
+            )
+            .unwrap();
+            writeln!(s, "    </release>").unwrap();
+        }
+        None => writeln!(
+            s,
+            r#"    <release version="{}" date="{}"/>"#,
+            xml_escape(&spec.version),
+            xml_escape(&spec.release_date)
+        )
+        .unwrap(),
+    }
+    writeln!(s, "  </releases>").unwrap();
+    writeln!(s, r#"  <content_rating type="oars-1.1"/>"#).unwrap();
+    writeln!(s, "</component>").unwrap();
+    s
+}
+
+fn binary_name(executable: &str) -> String {
+    Path::new(executable)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| executable.to_string())
+}
+
+/// Escape a string value (`\s`, `\n`, `\t`, `\r`, `\\`)
+fn escape_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+/// Escape a list value, where `;` separates items
+fn list(values: &[String]) -> String {
+    let mut s = String::new();
+    for value in values {
+        s.push_str(&escape_value(value).replace(';', "\\;"));
+        s.push(';');
+    }
+    s
+}
+
+/// Quote an Exec argument if it contains reserved characters
+fn escape_exec_arg(arg: &str) -> String {
+    let reserved = |c: char| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c);
+    if arg.chars().any(reserved) {
+        let quoted = arg
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('`', "\\`")
+            .replace('$', "\\$");
+        // The whole value is itself a string, so backslashes are escaped once more
+        escape_value(&format!("\"{}\"", quoted))
+    } else {
+        arg.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> DesktopSpec {
+        DesktopSpec {
+            app_id: "org.example.Viewer".to_string(),
+            title: "Example Viewer".to_string(),
+            summary: "View example files".to_string(),
+            description: "Views <example> files & more".to_string(),
+            version: "2.0.1".to_string(),
+            release_date: "2026-01-02".to_string(),
+            release_notes: None,
+            executable: "viewer".to_string(),
+            project_license: Some("MIT".to_string()),
+            homepage: None,
+            has_icon: true,
+            desktop: DesktopConfig {
+                categories: vec!["Graphics".to_string(), "Viewer".to_string()],
+                mime_types: vec!["image/x-example".to_string()],
+                exec_args: vec!["%F".to_string()],
+                keywords: vec!["picture".to_string(), "a;b".to_string()],
+                startup_wm_class: Some("viewer".to_string()),
+                comment: None,
+                terminal: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_desktop_entry() {
+        let entry = desktop_entry(&spec()).unwrap();
+        assert_eq!(
+            entry,
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Version=1.5\n\
+             Name=Example Viewer\n\
+             Comment=View example files\n\
+             Exec=viewer %F\n\
+             Icon=org.example.Viewer\n\
+             Terminal=false\n\
+             Categories=Graphics;Viewer;\n\
+             MimeType=image/x-example;\n\
+             Keywords=picture;a\\;b;\n\
+             StartupWMClass=viewer\n"
+        );
+    }
+
+    #[test]
+    fn test_desktop_validation() {
+        let mut s = spec();
+        s.desktop.exec_args = vec!["%x".to_string()];
+        assert!(desktop_entry(&s).is_err());
+
+        let mut s = spec();
+        s.desktop.mime_types = vec!["not-a-mime".to_string()];
+        assert!(desktop_entry(&s).is_err());
+
+        let mut s = spec();
+        s.desktop.categories = vec!["Viewer".to_string()];
+        assert!(desktop_entry(&s).is_err());
+
+        let mut s = spec();
+        s.desktop.startup_wm_class = Some("has space".to_string());
+        assert!(desktop_entry(&s).is_err());
+    }
+
+    #[test]
+    fn test_exec_quoting() {
+        assert_eq!(escape_exec_arg("viewer"), "viewer");
+        assert_eq!(
+            escape_exec_arg("/opt/my app/viewer"),
+            "\"/opt/my app/viewer\""
+        );
+    }
+
+    #[test]
+    fn test_metainfo() {
+        let xml = metainfo(&spec());
+        assert!(xml.contains("<id>org.example.Viewer</id>"));
+        assert!(xml.contains("<p>Views &lt;example&gt; files &amp; more</p>"));
+        assert!(xml.contains(r#"<release version="2.0.1" date="2026-01-02"/>"#));
+        assert!(
+            xml.contains("<launchable type=\"desktop-id\">org.example.Viewer.desktop</launchable>")
+        );
+
+        let mut spec = spec();
+        spec.release_notes = Some("### Fixed\n\n- Crash on <open>".to_string());
+        let xml = metainfo(&spec);
+        assert!(xml.contains(
+            "    <release version=\"2.0.1\" date=\"2026-01-02\">\n      <description><p>Fixed</p><ul><li>Crash on &lt;open&gt;</li></ul></description>\n    </release>"
+        ));
+    }
+}
@@ -0,0 +1,771 @@
+use crate::cmd;
+use crate::context::Context;
+use crate::deps::{self, Resolution};
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::result::Result;
+use crate::utils;
+use goblin::elf::Elf;
+use goblin::elf::dynamic::{DT_NULL, DT_RPATH, DT_RUNPATH, DT_STRSZ, DT_STRTAB};
+use goblin::elf::program_header::{PT_DYNAMIC, PT_LOAD};
+use goblin::elf::section_header::{SHT_NOBITS, SHT_STRTAB};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Libraries that are part of every supported base system and must never be bundled.
+/// Entries match a library name exactly or as the prefix before its `.so` version suffix;
+/// a trailing `*` matches any suffix.
+pub const SYSTEM_LIBRARIES: &[&str] = &[
+    // glibc
+    "ld-linux.so",
+    "ld-linux-x86-64.so",
+    "ld-linux-aarch64.so",
+    "ld-linux-armhf.so",
+    "libc.so",
+    "libm.so",
+    "libdl.so",
+    "libpthread.so",
+    "librt.so",
+    "libresolv.so",
+    "libutil.so",
+    "libnsl.so",
+    "libanl.so",
+    "libBrokenLocale.so",
+    "libmvec.so",
+    "libgcc_s.so",
+    // Graphics drivers are provided by the system
+    "libGL.so",
+    "libGLX.so",
+    "libGLdispatch.so",
+    "libEGL.so",
+    "libGLESv2.so",
+    "libOpenGL.so",
+    "libvulkan.so",
+    "libdrm.so",
+    "libgbm.so",
+    // Display server and session
+    "libX11.so",
+    "libX11-xcb.so",
+    "libxcb*",
+    "libXext.so",
+    "libXau.so",
+    "libXdmcp.so",
+    "libwayland-client.so",
+    "libwayland-cursor.so",
+    "libwayland-egl.so",
+    "libdbus-1.so",
+    "libasound.so",
+    "libfontconfig.so",
+    "libfreetype.so",
+    "libexpat.so",
+    "libz.so",
+];
+
+#[derive(Debug, Clone)]
+pub struct BundleOptions {
+    /// Library directory, relative to the staging root
+    pub lib_dir: PathBuf,
+    /// Directories searched before the system library paths
+    pub search_paths: Vec<PathBuf>,
+    /// Additional patterns treated as system libraries
+    pub system_libraries: Vec<String>,
+}
+
+/// Dynamic section information of an ELF file relevant for bundling
+#[derive(Debug, Clone)]
+pub struct ElfInfo {
+    pub needed: Vec<String>,
+    pub runpaths: Vec<String>,
+    pub machine: u16,
+    pub is_64: bool,
+}
+
+impl ElfInfo {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let elf = Elf::parse(bytes)?;
+        Ok(Self {
+            needed: elf.libraries.iter().map(|l| l.to_string()).collect(),
+            runpaths: elf
+                .runpaths
+                .iter()
+                .chain(elf.rpaths.iter())
+                .flat_map(|r| r.split(':'))
+                .filter(|r| !r.is_empty())
+                .map(|r| r.to_string())
+                .collect(),
+            machine: elf.header.e_machine,
+            is_64: elf.is_64,
+        })
+    }
+}
+
+/// Bundle the non-system shared libraries of the staged files in `app_dir`
+/// if `linux.bundle-libraries` is enabled
+pub fn bundle(
+    ctx: &Context,
+    manifest: &Manifest,
+    app_dir: &Path,
+) -> Result<BTreeMap<String, Resolution>> {
+    let Some(linux) = manifest
+        .linux
+        .as_ref()
+        .filter(|linux| linux.bundle_libraries)
+    else {
+        return Ok(BTreeMap::new());
+    };
+
+    let options = BundleOptions {
+        lib_dir: PathBuf::from(linux.lib_dir.as_deref().unwrap_or("lib")),
+        search_paths: linux
+            .library_paths
+            .iter()
+            .map(|p| ctx.base_dir.join(p))
+            .collect(),
+        system_libraries: linux.system_libraries.clone(),
+    };
+
+    let dependencies = bundle_dir(ctx, app_dir, &options)?;
+
+    deps::report("Shared library dependencies", &dependencies);
+
+    Ok(dependencies)
+}
+
+/// Resolve the dependencies of every ELF file below `root`, copy non-system libraries
+/// into `root/<lib_dir>` and point the RUNPATH of each file at that directory
+pub fn bundle_dir(
+    ctx: &Context,
+    root: &Path,
+    options: &BundleOptions,
+) -> Result<BTreeMap<String, Resolution>> {
+    let lib_dir = root.join(&options.lib_dir);
+    let search_paths = [options.search_paths.clone(), system_search_paths()].concat();
+
+    // Staged ELF files are the roots of the dependency walk; each file is resolved against
+    // the directory it was found in
+    let mut pending = Vec::new();
+    let mut staged_names = Vec::new();
+    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() && is_elf(entry.path()) {
+            staged_names.push(entry.file_name().to_string_lossy().to_string());
+            let origin = entry.path().parent().unwrap_or(root).to_path_buf();
+            pending.push((entry.path().to_path_buf(), origin, false));
+        }
+    }
+
+    let mut dependencies = BTreeMap::new();
+    let mut visited = Vec::new();
+
+    while let Some((path, origin, bundled)) = pending.pop() {
+        let info = ElfInfo::parse(&fs::read(&path)?)?;
+
+        for name in &info.needed {
+            if dependencies.contains_key(name) {
+                continue;
+            }
+
+            let resolution = if is_system_library(name, &options.system_libraries) {
+                Resolution::System
+            } else if staged_names.contains(name) {
+                Resolution::Staged
+            } else if let Some(found) = resolve(name, &origin, &info, &search_paths) {
+                let dest = lib_dir.join(name);
+                if ctx.verbose {
+                    status!("Bundling {} from {}", name, found.display());
+                }
+                utils::ensure_dir(&lib_dir)?;
+                fs::copy(&found, &dest)?;
+                utils::make_writable(&dest)?;
+                let origin = found.parent().unwrap_or(root).to_path_buf();
+                pending.push((dest, origin, true));
+                Resolution::Bundled(found)
+            } else {
+                Resolution::Missing
+            };
+
+            dependencies.insert(name.clone(), resolution);
+        }
+
+        visited.push((path, info.needed, bundled));
+    }
+
+    let missing = deps::missing(&dependencies);
+    if !missing.is_empty() {
+        return Err(Error::Custom(format!(
+            "Unable to resolve shared libraries: {} (add their directories to linux.library-paths \
+             or list them in linux.system-libraries)",
+            missing.join(", ")
+        )));
+    }
+
+    // Point every file that loads a bundled library at the library directory
+    for (path, needed, bundled) in visited {
+        if !needed
+            .iter()
+            .any(|n| matches!(dependencies.get(n), Some(Resolution::Bundled(_))))
+        {
+            continue;
+        }
+
+        let dir = path.parent().unwrap_or(root);
+        let runpath = origin_relative(dir.strip_prefix(root).unwrap_or(dir), &options.lib_dir);
+        if set_runpath(ctx, &path, &runpath)? {
+            continue;
+        }
+
+        let hint = format!(
+            "{} has no room for the RUNPATH and patchelf is not installed",
+            path.display()
+        );
+        if bundled {
+            // Only the bundled dependencies of this library are affected
+            status!("Warning: {}; its dependencies may not resolve", hint);
+        } else {
+            return Err(Error::Custom(format!(
+                "Unable to set RUNPATH: {}. Link with a placeholder such as \
+                 -C link-arg=-Wl,-rpath,$ORIGIN/{} or install patchelf.",
+                hint,
+                "x".repeat(runpath.len())
+            )));
+        }
+    }
+
+    Ok(dependencies)
+}
+
+/// Check for the ELF magic number
+pub fn is_elf(path: &Path) -> bool {
+    use std::io::Read;
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+/// Whether `name` matches the default system allowlist or one of the `extra` patterns
+pub fn is_system_library(name: &str, extra: &[String]) -> bool {
+    SYSTEM_LIBRARIES
+        .iter()
+        .copied()
+        .chain(extra.iter().map(|s| s.as_str()))
+        .any(|pattern| {
+            if let Some(prefix) = pattern.strip_suffix('*') {
+                name.starts_with(prefix)
+            } else {
+                name == pattern
+                    || name
+                        .strip_prefix(pattern)
+                        .is_some_and(|rest| rest.starts_with('.'))
+            }
+        })
+}
+
+/// Library search paths of the build machine
+pub fn system_search_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::env::var_os("LD_LIBRARY_PATH")
+        .map(|p| std::env::split_paths(&p).collect())
+        .unwrap_or_default();
+
+    let mut conf_files = vec![PathBuf::from("/etc/ld.so.conf")];
+    if let Ok(entries) = fs::read_dir("/etc/ld.so.conf.d") {
+        let mut entries: Vec<_> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e == "conf"))
+            .collect();
+        entries.sort();
+        conf_files.extend(entries);
+    }
+    for conf in conf_files {
+        if let Ok(content) = fs::read_to_string(conf) {
+            paths.extend(
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| l.starts_with('/'))
+                    .map(PathBuf::from),
+            );
+        }
+    }
+
+    paths.extend(
+        [
+            "/lib",
+            "/lib64",
+            "/usr/lib",
+            "/usr/lib64",
+            "/usr/local/lib",
+            "/lib/x86_64-linux-gnu",
+            "/usr/lib/x86_64-linux-gnu",
+            "/lib/aarch64-linux-gnu",
+            "/usr/lib/aarch64-linux-gnu",
+        ]
+        .map(PathBuf::from),
+    );
+    paths
+}
+
+/// Find `name` in the RUNPATH of the requesting file or in `search_paths`,
+/// skipping candidates built for a different architecture
+fn resolve(name: &str, origin: &Path, info: &ElfInfo, search_paths: &[PathBuf]) -> Option<PathBuf> {
+    let origin = origin.to_string_lossy();
+    let runpaths = info
+        .runpaths
+        .iter()
+        .map(|r| PathBuf::from(r.replace("$ORIGIN", &origin).replace("${ORIGIN}", &origin)));
+
+    runpaths
+        .chain(search_paths.iter().cloned())
+        .map(|dir| dir.join(name))
+        .find(|candidate| {
+            candidate.is_file()
+                && fs::read(candidate)
+                    .ok()
+                    .and_then(|bytes| ElfInfo::parse(&bytes).ok())
+                    .is_some_and(|c| c.machine == info.machine && c.is_64 == info.is_64)
+        })
+        .and_then(|found| fs::canonicalize(found).ok())
+}
+
+/// `$ORIGIN`-relative path from `dir` to `lib_dir` (both relative to the staging root)
+pub fn origin_relative(dir: &Path, lib_dir: &Path) -> String {
+    let depth = dir
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .count();
+    let mut runpath = String::from("$ORIGIN");
+    for _ in 0..depth {
+        runpath.push_str("/..");
+    }
+    for component in lib_dir.components() {
+        if let Component::Normal(part) = component {
+            runpath.push('/');
+            runpath.push_str(&part.to_string_lossy());
+        }
+    }
+    runpath
+}
+
+/// Set the RUNPATH of an ELF file, in place when possible and with patchelf otherwise.
+/// Returns `false` if neither is possible.
+fn set_runpath(ctx: &Context, path: &Path, runpath: &str) -> Result<bool> {
+    let mut bytes = fs::read(path)?;
+    if rewrite_runpath(&mut bytes, runpath)? {
+        fs::write(path, bytes)?;
+        return Ok(true);
+    }
+
+    if utils::find_program("patchelf").is_some() {
+        cmd::execute(
+            ctx,
+            "patchelf",
+            &["--set-rpath", runpath, utils::path_str(path)?],
+        )?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Set the RUNPATH without moving any data. An existing RUNPATH (or RPATH, which is
+/// converted to RUNPATH) string is overwritten if the new value fits. Otherwise the dynamic
+/// string table is copied, with the new value appended, into the zero padding behind its
+/// segment, and a spare `DT_NULL` slot becomes the RUNPATH entry if there is none yet.
+/// Returns `false` if the file has no room for that.
+pub fn rewrite_runpath(bytes: &mut [u8], runpath: &str) -> Result<bool> {
+    let elf = Elf::parse(bytes)?;
+    let Some(dynamic) = &elf.dynamic else {
+        return Ok(false);
+    };
+    let Some(dynamic_header) = elf
+        .program_headers
+        .iter()
+        .find(|ph| ph.p_type == PT_DYNAMIC)
+    else {
+        return Ok(false);
+    };
+    let (is_64, little_endian) = (elf.is_64, elf.little_endian);
+    let word = if is_64 { 8 } else { 4 };
+    let dynamic_offset = dynamic_header.p_offset as usize;
+    let existing = dynamic
+        .dyns
+        .iter()
+        .position(|d| d.d_tag == DT_RUNPATH)
+        .or_else(|| dynamic.dyns.iter().position(|d| d.d_tag == DT_RPATH));
+    let strtab = dynamic.info.strtab as u64;
+
+    let vaddr_to_offset = |vaddr: u64| {
+        elf.program_headers
+            .iter()
+            .find(|ph| {
+                ph.p_type == PT_LOAD && vaddr >= ph.p_vaddr && vaddr < ph.p_vaddr + ph.p_filesz
+            })
+            .map(|ph| (vaddr - ph.p_vaddr + ph.p_offset) as usize)
+    };
+
+    // Overwrite the existing string if the new value fits
+    if let Some(index) = existing {
+        let entry = &dynamic.dyns[index];
+        if let Some(string_offset) = vaddr_to_offset(strtab + entry.d_val)
+            && let Some(old_len) = bytes[string_offset..].iter().position(|b| *b == 0)
+            && runpath.len() <= old_len
+        {
+            let is_rpath = entry.d_tag == DT_RPATH;
+            let target = &mut bytes[string_offset..string_offset + old_len];
+            target.fill(0);
+            target[..runpath.len()].copy_from_slice(runpath.as_bytes());
+            // DT_RPATH is searched before LD_LIBRARY_PATH; switch to DT_RUNPATH semantics
+            if is_rpath {
+                let tag_offset = dynamic_offset + index * 2 * word;
+                write_word(bytes, tag_offset, DT_RUNPATH, is_64, little_endian);
+            }
+            return Ok(true);
+        }
+    }
+
+    // Otherwise a string table with the new value appended and, without an existing entry,
+    // a spare slot for it
+    let slot = match existing {
+        Some(index) => index,
+        None => {
+            let count = dynamic_header.p_filesz as usize / (2 * word);
+            let tag = |index: usize| {
+                read_word(
+                    bytes,
+                    dynamic_offset + index * 2 * word,
+                    is_64,
+                    little_endian,
+                )
+            };
+            // The first DT_NULL ends the table, so another one must follow it
+            let Some(end) = (0..count).find(|i| tag(*i) == Some(DT_NULL)) else {
+                return Ok(false);
+            };
+            if end + 1 >= count || tag(end + 1) != Some(DT_NULL) {
+                return Ok(false);
+            }
+            end
+        }
+    };
+    let position = |tag: u64| dynamic.dyns.iter().position(|d| d.d_tag == tag);
+    let (Some(strtab_entry), Some(strsz_entry), Some(strtab_offset)) = (
+        position(DT_STRTAB),
+        position(DT_STRSZ),
+        vaddr_to_offset(strtab),
+    ) else {
+        return Ok(false);
+    };
+    let strsz = dynamic.info.strsz;
+    let Some(old_table) = bytes.get(strtab_offset..strtab_offset + strsz) else {
+        return Ok(false);
+    };
+    let table = [old_table, runpath.as_bytes(), &[0]].concat();
+    let Some((header, table_offset, table_vaddr)) = table_space(&elf, bytes, strtab, table.len())
+    else {
+        return Ok(false);
+    };
+    let dynstr = elf
+        .section_headers
+        .iter()
+        .position(|sh| sh.sh_type == SHT_STRTAB && sh.sh_addr == strtab);
+
+    let (phdr, shdr) = (&elf.header, elf.header.e_shoff as usize);
+    let (shentsize, segment) = (
+        phdr.e_shentsize as usize,
+        phdr.e_phoff as usize + header * phdr.e_phentsize as usize,
+    );
+    let size = table_vaddr + table.len() as u64 - elf.program_headers[header].p_vaddr;
+    let (filesz, memsz) = if is_64 { (32, 40) } else { (16, 20) };
+    let (sh_addr, sh_offset, sh_size) = if is_64 { (16, 24, 32) } else { (12, 16, 20) };
+    let entry = |index: usize| dynamic_offset + index * 2 * word;
+
+    bytes[table_offset..table_offset + table.len()].copy_from_slice(&table);
+    write_word(bytes, segment + filesz, size, is_64, little_endian);
+    write_word(bytes, segment + memsz, size, is_64, little_endian);
+    write_word(
+        bytes,
+        entry(strtab_entry) + word,
+        table_vaddr,
+        is_64,
+        little_endian,
+    );
+    write_word(
+        bytes,
+        entry(strsz_entry) + word,
+        table.len() as u64,
+        is_64,
+        little_endian,
+    );
+    write_word(bytes, entry(slot), DT_RUNPATH, is_64, little_endian);
+    write_word(
+        bytes,
+        entry(slot) + word,
+        strsz as u64,
+        is_64,
+        little_endian,
+    );
+    // Keep `.dynstr` pointing at the table the loader uses
+    if let Some(index) = dynstr {
+        let section = shdr + index * shentsize;
+        write_word(bytes, section + sh_addr, table_vaddr, is_64, little_endian);
+        write_word(
+            bytes,
+            section + sh_offset,
+            table_offset as u64,
+            is_64,
+            little_endian,
+        );
+        write_word(
+            bytes,
+            section + sh_size,
+            table.len() as u64,
+            is_64,
+            little_endian,
+        );
+    }
+    Ok(true)
+}
+
+/// Room for `len` bytes behind the loaded segment holding the string table: zero padding in
+/// the file that no other segment, section or header uses, and that stays within the last
+/// page of the segment so the memory mapping does not change. Returns the index of the
+/// program header, and the file offset and address of the room.
+fn table_space(elf: &Elf, bytes: &[u8], strtab: u64, len: usize) -> Option<(usize, usize, u64)> {
+    let (index, segment) = elf.program_headers.iter().enumerate().find(|(_, ph)| {
+        ph.p_type == PT_LOAD && strtab >= ph.p_vaddr && strtab < ph.p_vaddr + ph.p_filesz
+    })?;
+    if segment.p_filesz != segment.p_memsz {
+        return None;
+    }
+    let start = (segment.p_offset + segment.p_filesz) as usize;
+    let end = start + len;
+    let vaddr = segment.p_vaddr + segment.p_filesz;
+    if vaddr + len as u64 > vaddr.next_multiple_of(segment.p_align.max(1)) {
+        return None;
+    }
+    if bytes.get(start..end)?.iter().any(|b| *b != 0) {
+        return None;
+    }
+
+    let overlaps =
+        |offset: u64, size: u64| size > 0 && offset < end as u64 && offset + size > start as u64;
+    let header = &elf.header;
+    let used = overlaps(
+        header.e_shoff,
+        header.e_shnum as u64 * header.e_shentsize as u64,
+    ) || overlaps(
+        header.e_phoff,
+        header.e_phnum as u64 * header.e_phentsize as u64,
+    ) || elf
+        .section_headers
+        .iter()
+        .any(|sh| sh.sh_type != SHT_NOBITS && overlaps(sh.sh_offset, sh.sh_size))
+        || elf.program_headers.iter().enumerate().any(|(i, ph)| {
+            i != index
+                && (overlaps(ph.p_offset, ph.p_filesz)
+                    || (ph.p_type == PT_LOAD
+                        && ph.p_vaddr < vaddr + len as u64
+                        && ph.p_vaddr + ph.p_memsz > vaddr))
+        });
+    (!used).then_some((index, start, vaddr))
+}
+
+fn read_word(bytes: &[u8], offset: usize, is_64: bool, little_endian: bool) -> Option<u64> {
+    Some(match (is_64, little_endian) {
+        (true, true) => u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?),
+        (true, false) => u64::from_be_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?),
+        (false, true) => u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as u64,
+        (false, false) => {
+            u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as u64
+        }
+    })
+}
+
+fn write_word(bytes: &mut [u8], offset: usize, value: u64, is_64: bool, little_endian: bool) {
+    let word = match (is_64, little_endian) {
+        (true, true) => value.to_le_bytes().to_vec(),
+        (true, false) => value.to_be_bytes().to_vec(),
+        (false, true) => (value as u32).to_le_bytes().to_vec(),
+        (false, false) => (value as u32).to_be_bytes().to_vec(),
+    };
+    bytes[offset..offset + word.len()].copy_from_slice(&word);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/elf")
+            .join(name)
+    }
+
+    #[test]
+    fn test_parse_needed() {
+        let info = ElfInfo::parse(&fs::read(fixture("app")).unwrap()).unwrap();
+        assert_eq!(info.needed, vec!["libfoo.so", "libm.so.6", "libc.so.6"]);
+        assert_eq!(info.runpaths, vec![format!("$ORIGIN/{}", "x".repeat(40))]);
+        assert!(info.is_64);
+    }
+
+    #[test]
+    fn test_system_libraries() {
+        assert!(is_system_library("libc.so.6", &[]));
+        assert!(is_system_library("libxcb-shm.so.0", &[]));
+        assert!(is_system_library("libGL.so.1", &[]));
+        assert!(!is_system_library("libcrypto.so.3", &[]));
+        assert!(!is_system_library("libcairo.so.2", &[]));
+        assert!(is_system_library(
+            "libcairo.so.2",
+            &["libcairo.so".to_string()]
+        ));
+    }
+
+    #[test]
+    fn test_origin_relative() {
+        assert_eq!(
+            origin_relative(Path::new("bin"), Path::new("lib")),
+            "$ORIGIN/../lib"
+        );
+        assert_eq!(
+            origin_relative(Path::new(""), Path::new("lib")),
+            "$ORIGIN/lib"
+        );
+        assert_eq!(
+            origin_relative(Path::new("lib"), Path::new("lib")),
+            "$ORIGIN/../lib"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_runpath() {
+        let mut bytes = fs::read(fixture("app")).unwrap();
+        assert!(rewrite_runpath(&mut bytes, "$ORIGIN/../lib").unwrap());
+        let info = ElfInfo::parse(&bytes).unwrap();
+        assert_eq!(info.runpaths, vec!["$ORIGIN/../lib"]);
+
+        // A longer value moves into the padding behind the string table
+        let long = format!("$ORIGIN/{}", "y".repeat(60));
+        assert!(rewrite_runpath(&mut bytes, &long).unwrap());
+        let info = ElfInfo::parse(&bytes).unwrap();
+        assert_eq!(info.runpaths, vec![long]);
+
+        // Without a RUNPATH, a spare DT_NULL slot becomes one
+        let original = fs::read(fixture("app-no-runpath")).unwrap();
+        let mut bytes = original.clone();
+        assert!(rewrite_runpath(&mut bytes, "$ORIGIN/../lib").unwrap());
+        assert_eq!(bytes.len(), original.len());
+        let info = ElfInfo::parse(&bytes).unwrap();
+        assert_eq!(info.runpaths, vec!["$ORIGIN/../lib"]);
+        assert_eq!(info.needed, ElfInfo::parse(&original).unwrap().needed);
+
+        // No room: the padding ends at the next page
+        let mut bytes = original.clone();
+        assert!(!rewrite_runpath(&mut bytes, &"z".repeat(0x1000)).unwrap());
+        assert_eq!(bytes, original);
+    }
+
+    #[test]
+    fn test_bundle_dir() {
+        let ctx = Context::new(PathBuf::from("Cargo.toml"), false);
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp.path().join("bin")).unwrap();
+        fs::copy(fixture("app"), temp.path().join("bin/app")).unwrap();
+
+        let options = BundleOptions {
+            lib_dir: PathBuf::from("lib"),
+            search_paths: vec![fixture("")],
+            system_libraries: Vec::new(),
+        };
+        let deps = bundle_dir(&ctx, temp.path(), &options).unwrap();
+
+        assert_eq!(deps["libm.so.6"], Resolution::System);
+        assert_eq!(deps["libc.so.6"], Resolution::System);
+        assert!(matches!(deps["libfoo.so"], Resolution::Bundled(_)));
+        assert!(matches!(deps["libbar.so"], Resolution::Bundled(_)));
+        assert!(temp.path().join("lib/libfoo.so").is_file());
+        assert!(temp.path().join("lib/libbar.so").is_file());
+
+        let info = ElfInfo::parse(&fs::read(temp.path().join("bin/app")).unwrap()).unwrap();
+        assert_eq!(info.runpaths, vec!["$ORIGIN/../lib"]);
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn test_bundle_dir_adds_runpath() {
+        let ctx = Context::new(PathBuf::from("Cargo.toml"), false);
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp.path().join("bin")).unwrap();
+        fs::copy(fixture("app-no-runpath"), temp.path().join("bin/app")).unwrap();
+
+        let options = BundleOptions {
+            lib_dir: PathBuf::from("lib"),
+            search_paths: vec![fixture("")],
+            system_libraries: Vec::new(),
+        };
+        bundle_dir(&ctx, temp.path(), &options).unwrap();
+
+        // The loader finds libfoo through the new RUNPATH of app, and libbar through libfoo's
+        let status = std::process::Command::new(temp.path().join("bin/app"))
+            .env_remove("LD_LIBRARY_PATH")
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bundle_dir_vendor_libraries() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let ctx = Context::new(PathBuf::from("Cargo.toml"), false);
+        let temp = tempfile::tempdir().unwrap();
+        let app_dir = temp.path().join("app");
+        fs::create_dir_all(app_dir.join("bin")).unwrap();
+        fs::copy(fixture("app"), app_dir.join("bin/app")).unwrap();
+
+        // A read-only vendor directory where libfoo finds libbar next to it via $ORIGIN
+        let vendor = temp.path().join("vendor");
+        fs::create_dir_all(vendor.join("deps")).unwrap();
+        let mut libfoo = fs::read(fixture("libfoo.so")).unwrap();
+        assert!(rewrite_runpath(&mut libfoo, "$ORIGIN/deps").unwrap());
+        fs::write(vendor.join("libfoo.so"), libfoo).unwrap();
+        fs::copy(fixture("libbar.so"), vendor.join("deps/libbar.so")).unwrap();
+        for file in ["libfoo.so", "deps/libbar.so"] {
+            fs::set_permissions(vendor.join(file), fs::Permissions::from_mode(0o444)).unwrap();
+        }
+
+        let options = BundleOptions {
+            lib_dir: PathBuf::from("lib"),
+            search_paths: vec![vendor.clone()],
+            system_libraries: Vec::new(),
+        };
+        let deps = bundle_dir(&ctx, &app_dir, &options).unwrap();
+
+        assert_eq!(
+            deps["libbar.so"],
+            Resolution::Bundled(fs::canonicalize(vendor.join("deps/libbar.so")).unwrap())
+        );
+        let info = ElfInfo::parse(&fs::read(app_dir.join("lib/libfoo.so")).unwrap()).unwrap();
+        assert_eq!(info.runpaths, vec!["$ORIGIN/../lib"]);
+    }
+
+    #[test]
+    fn test_bundle_dir_missing() {
+        let ctx = Context::new(PathBuf::from("Cargo.toml"), false);
+        let temp = tempfile::tempdir().unwrap();
+        fs::copy(fixture("app-no-runpath"), temp.path().join("app")).unwrap();
+
+        let options = BundleOptions {
+            lib_dir: PathBuf::from("lib"),
+            search_paths: Vec::new(),
+            system_libraries: Vec::new(),
+        };
+        let err = bundle_dir(&ctx, temp.path(), &options).unwrap_err();
+        assert!(err.to_string().contains("libfoo.so"));
+    }
+}
@@ -1,10 +1,14 @@
 pub mod archive;
+pub mod desktop;
+pub mod libs;
+pub mod run;
 
 use crate::context::Context;
 use crate::manifest::Manifest;
 use crate::result::Result;
+use std::path::PathBuf;
 
 #[allow(dead_code)]
-pub fn build(ctx: &Context, manifest: &Manifest) -> Result<()> {
-    archive::create_tar_gz(ctx, manifest)
+pub fn build(ctx: &Context, manifest: &Manifest) -> Result<PathBuf> {
+    archive::create(ctx, manifest)
 }
@@ -0,0 +1,547 @@
+use crate::archive::Contents;
+use crate::context::Context;
+use crate::linux::archive;
+use crate::manifest::{ArchiveFormat, Manifest};
+use crate::result::Result;
+use crate::utils;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Shell header prepended to the payload; `@@KEY@@` placeholders are substituted
+const HEADER_TEMPLATE: &str = r#"#!/bin/sh
+# @@TITLE@@ @@VERSION@@ self-extracting installer
+# Generated by cargo-emerge. The compressed payload follows the header.
+set -e
+
+NAME=@@NAME@@
+TITLE=@@TITLE_QUOTED@@
+VERSION=@@VERSION_QUOTED@@
+EXECUTABLE=@@EXECUTABLE@@
+PAYLOAD_LINE=@@PAYLOAD_LINE@@
+PAYLOAD_SHA256=@@PAYLOAD_SHA256@@
+
+usage() {
+    cat <<EOF
+Usage: $0 [options]
+
+Installs $TITLE $VERSION.
+
+Options:
+  --prefix DIR    Install into DIR
+  --user          Install for the current user only (default prefix: ~/.local/opt/$NAME)
+  --no-desktop    Skip desktop integration (menu entry, icons, metainfo)
+  --check         Verify the payload checksum and exit
+  --extract DIR   Extract the payload into DIR without installing
+  -h, --help      Show this help
+EOF
+}
+
+die() {
+    echo "Error: $*" >&2
+    exit 1
+}
+
+payload() {
+    tail -n +"$PAYLOAD_LINE" "$0"
+}
+
+verify() {
+    if command -v sha256sum >/dev/null 2>&1; then
+        actual=$(payload | sha256sum | cut -d ' ' -f 1)
+    elif command -v shasum >/dev/null 2>&1; then
+        actual=$(payload | shasum -a 256 | cut -d ' ' -f 1)
+    else
+        echo "Warning: sha256sum not found, skipping payload verification" >&2
+        return 0
+    fi
+    [ "$actual" = "$PAYLOAD_SHA256" ] || die "payload checksum mismatch, the installer is corrupted"
+}
+
+# Single-quote a value for embedding in the generated uninstall script
+quote() {
+    printf "'%s'" "$(printf '%s' "$1" | sed "s/'/'\\\\''/g")"
+}
+
+sed_escape() {
+    printf '%s' "$1" | sed -e 's/[\\|&]/\\&/g'
+}
+
+PREFIX=
+USER_INSTALL=0
+DESKTOP=1
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --prefix)
+            [ $# -ge 2 ] || die "--prefix requires a directory"
+            PREFIX=$2
+            shift
+            ;;
+        --prefix=*) PREFIX=${1#--prefix=} ;;
+        --user) USER_INSTALL=1 ;;
+        --no-desktop) DESKTOP=0 ;;
+        --check)
+            verify
+            echo "Payload checksum OK"
+            exit 0
+            ;;
+        --extract)
+            [ $# -ge 2 ] || die "--extract requires a directory"
+            verify
+            mkdir -p "$2"
+            payload | tar -xzf - -C "$2"
+            echo "Extracted to $2"
+            exit 0
+            ;;
+        -h | --help)
+            usage
+            exit 0
+            ;;
+        *)
+            usage >&2
+            die "unknown option: $1"
+            ;;
+    esac
+    shift
+done
+
+if [ "$USER_INSTALL" = 1 ]; then
+    DATA_DIR=${XDG_DATA_HOME:-$HOME/.local/share}
+    BIN_DIR=$HOME/.local/bin
+    [ -n "$PREFIX" ] || PREFIX=$HOME/.local/opt/$NAME
+else
+    [ "$(id -u)" = 0 ] || die "system install requires root, re-run with sudo or use --user"
+    DATA_DIR=/usr/local/share
+    BIN_DIR=/usr/local/bin
+    [ -n "$PREFIX" ] || PREFIX=/opt/$NAME
+fi
+
+verify
+
+# Only an uninstaller of this package is run; the prefix may be shared with other files
+MARKER='# emerge-package: '$NAME
+if [ -e "$PREFIX/uninstall.sh" ]; then
+    grep -qxF "$MARKER" "$PREFIX/uninstall.sh" ||
+        die "$PREFIX/uninstall.sh belongs to another installation, choose another --prefix"
+    echo "Removing previous installation from $PREFIX"
+    sh "$PREFIX/uninstall.sh" >/dev/null
+fi
+
+echo "Installing $TITLE $VERSION to $PREFIX"
+mkdir -p "$PREFIX"
+payload | tar -xzf - -C "$PREFIX"
+
+# Installed files, and directories with a trailing slash, removed by uninstall.sh
+INSTALLED=$PREFIX/.installed-files
+: >"$INSTALLED"
+record() {
+    echo "$1" >>"$INSTALLED"
+}
+payload | tar -tzf - | while IFS= read -r entry; do
+    entry=${entry#./}
+    entry=${entry%/}
+    case "$entry" in "" | .) continue ;; esac
+    if [ -d "$PREFIX/$entry" ] && [ ! -L "$PREFIX/$entry" ]; then
+        record "$PREFIX/$entry/"
+    else
+        record "$PREFIX/$entry"
+    fi
+done
+
+mkdir -p "$BIN_DIR"
+LINK=$BIN_DIR/$(basename "$EXECUTABLE")
+ln -sf "$PREFIX/$EXECUTABLE" "$LINK"
+record "$LINK"
+
+if [ "$DESKTOP" = 1 ] && [ -d "$PREFIX/share" ]; then
+    EXEC_PATH=$PREFIX/$EXECUTABLE
+    case "$EXEC_PATH" in
+        *[[:space:]]*) EXEC_PATH="\"$EXEC_PATH\"" ;;
+    esac
+    for src in "$PREFIX"/share/applications/*.desktop; do
+        [ -f "$src" ] || continue
+        mkdir -p "$DATA_DIR/applications"
+        dst=$DATA_DIR/applications/$(basename "$src")
+        sed "s|^Exec=[^ ]*|Exec=$(sed_escape "$EXEC_PATH")|" "$src" >"$dst"
+        record "$dst"
+    done
+    for src in "$PREFIX"/share/metainfo/*.xml; do
+        [ -f "$src" ] || continue
+        mkdir -p "$DATA_DIR/metainfo"
+        dst=$DATA_DIR/metainfo/$(basename "$src")
+        cp "$src" "$dst"
+        record "$dst"
+    done
+    if [ -d "$PREFIX/share/icons" ]; then
+        (cd "$PREFIX/share" && find icons -type f) | while IFS= read -r icon; do
+            mkdir -p "$DATA_DIR/$(dirname "$icon")"
+            cp "$PREFIX/share/$icon" "$DATA_DIR/$icon"
+            record "$DATA_DIR/$icon"
+        done
+    fi
+    if command -v update-desktop-database >/dev/null 2>&1; then
+        update-desktop-database -q "$DATA_DIR/applications" || true
+    fi
+    if command -v gtk-update-icon-cache >/dev/null 2>&1; then
+        gtk-update-icon-cache -q -t "$DATA_DIR/icons/hicolor" || true
+    fi
+fi
+
+cat >"$PREFIX/uninstall.sh" <<EOF
+#!/bin/sh
+# Uninstalls $TITLE $VERSION from $PREFIX
+$MARKER
+set -e
+INSTALLED=$(quote "$INSTALLED")
+grep -v '/\$' "\$INSTALLED" | while IFS= read -r file; do
+    rm -f "\$file"
+done
+# Deepest directories first; directories holding other files are kept
+grep '/\$' "\$INSTALLED" | sort -r | while IFS= read -r dir; do
+    rmdir "\$dir" 2>/dev/null || true
+done
+rm -f "\$INSTALLED" $(quote "$PREFIX/uninstall.sh")
+rmdir $(quote "$PREFIX") 2>/dev/null || true
+echo $(quote "$TITLE has been uninstalled")
+EOF
+chmod 755 "$PREFIX/uninstall.sh"
+
+echo "$TITLE $VERSION has been installed"
+echo "Run $PREFIX/uninstall.sh to uninstall"
+exit 0
+"#;
+
+/// Values embedded into the installer header
+#[derive(Debug, Clone)]
+pub struct RunSpec {
+    pub name: String,
+    pub title: String,
+    pub version: String,
+    /// Executable relative to the install prefix
+    pub executable: String,
+}
+
+impl RunSpec {
+    pub fn from_manifest(manifest: &Manifest) -> Self {
+        Self {
+            name: manifest.name.clone(),
+            title: manifest.title.clone(),
+            version: manifest.version.clone(),
+            executable: manifest
+                .linux
+                .as_ref()
+                .and_then(|l| l.executable.clone())
+                .unwrap_or_else(|| manifest.name.clone()),
+        }
+    }
+}
+
+/// Create the self-extracting installer and return its path
+pub fn create_run(ctx: &Context, manifest: &Manifest) -> Result<PathBuf> {
+    status!("Creating self-extracting installer for Linux...");
+
+    // Ensure output folder exists
+    utils::ensure_dir(&manifest.output_folder)?;
+
+    // Generated and modified files are staged; copied files are read in place
+    let temp_dir = std::env::temp_dir().join(format!("emerge-{}", manifest.name));
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir)?;
+    }
+    fs::create_dir_all(&temp_dir)?;
+
+    // The payload holds the archive layout without the `<name>` directory
+    let contents = archive::contents(ctx, manifest, &temp_dir, "")?;
+
+    let run_path = manifest
+        .output_folder
+        .join(format!("{}.run", manifest.filename));
+    let payload_path = temp_dir.join("payload.tar.gz");
+
+    build_installer(
+        &RunSpec::from_manifest(manifest),
+        &contents,
+        &payload_path,
+        &run_path,
+    )?;
+
+    // Clean up temp directory
+    fs::remove_dir_all(&temp_dir)?;
+
+    status!("Installer created successfully: {}", run_path.display());
+    Ok(run_path)
+}
+
+/// Compress `contents` into `payload_path` and write the self-extracting installer to `output_path`
+pub fn build_installer(
+    spec: &RunSpec,
+    contents: &Contents,
+    payload_path: &Path,
+    output_path: &Path,
+) -> Result<()> {
+    let options = crate::archive::Options {
+        format: ArchiveFormat::TarGz,
+        level: flate2::Compression::default().level(),
+        threads: 1,
+        zip_method: Default::default(),
+        store: Vec::new(),
+    };
+    crate::archive::tar::write_stream(&options, contents, File::create(payload_path)?, &|_| {})?;
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(payload_path)?, &mut hasher)?;
+    let checksum = format!("{:x}", hasher.finalize());
+
+    let mut output = BufWriter::new(File::create(output_path)?);
+    output.write_all(header(spec, &checksum).as_bytes())?;
+    io::copy(&mut File::open(payload_path)?, &mut output)?;
+    output.flush()?;
+    drop(output);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(output_path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
+
+/// Render the shell header for a payload with the given SHA-256 checksum
+pub fn header(spec: &RunSpec, payload_sha256: &str) -> String {
+    let render = |payload_line: usize| {
+        HEADER_TEMPLATE
+            .replace("@@TITLE_QUOTED@@", &shell_quote(&spec.title))
+            .replace("@@VERSION_QUOTED@@", &shell_quote(&spec.version))
+            .replace("@@TITLE@@", &comment(&spec.title))
+            .replace("@@VERSION@@", &comment(&spec.version))
+            .replace("@@NAME@@", &shell_quote(&spec.name))
+            .replace("@@EXECUTABLE@@", &shell_quote(&spec.executable))
+            .replace("@@PAYLOAD_LINE@@", &payload_line.to_string())
+            .replace("@@PAYLOAD_SHA256@@", payload_sha256)
+    };
+
+    // The payload starts on the line following the header
+    let lines = render(0).lines().count();
+    render(lines + 1)
+}
+
+/// Single-quote a value for POSIX shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Keep a value on a single comment line
+fn comment(value: &str) -> String {
+    value.replace(['\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn contents(source: &Path) -> Contents {
+        let mut contents = Contents::default();
+        contents.add(source, "").unwrap();
+        contents
+    }
+
+    fn spec() -> RunSpec {
+        RunSpec {
+            name: "sample".to_string(),
+            title: "Sample's App".to_string(),
+            version: "1.0.0".to_string(),
+            executable: "bin/sample".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_header() {
+        let header = header(&spec(), "abc123");
+        let payload_line = header.lines().count() + 1;
+        assert!(header.starts_with("#!/bin/sh\n"));
+        assert!(header.ends_with("exit 0\n"));
+        assert!(header.contains(&format!("PAYLOAD_LINE={}\n", payload_line)));
+        assert!(header.contains("PAYLOAD_SHA256=abc123\n"));
+        assert!(header.contains("TITLE='Sample'\\''s App'\n"));
+        assert!(header.contains("EXECUTABLE='bin/sample'\n"));
+        assert!(!header.contains("@@"));
+    }
+
+    #[test]
+    fn test_check_and_extract() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::write(source.join("bin/sample"), "#!/bin/sh\necho sample\n").unwrap();
+
+        let run = temp.path().join("sample.run");
+        build_installer(
+            &spec(),
+            &contents(&source),
+            &temp.path().join("payload.tar.gz"),
+            &run,
+        )
+        .unwrap();
+
+        let status = Command::new("sh")
+            .arg(&run)
+            .arg("--check")
+            .output()
+            .unwrap();
+        assert!(status.status.success());
+
+        let extracted = temp.path().join("extracted");
+        let status = Command::new("sh")
+            .arg(&run)
+            .arg("--extract")
+            .arg(&extracted)
+            .output()
+            .unwrap();
+        assert!(status.status.success());
+        assert_eq!(
+            fs::read_to_string(extracted.join("bin/sample")).unwrap(),
+            "#!/bin/sh\necho sample\n"
+        );
+
+        // Corrupt the last payload byte
+        let mut bytes = fs::read(&run).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&run, bytes).unwrap();
+        let status = Command::new("sh")
+            .arg(&run)
+            .arg("--check")
+            .output()
+            .unwrap();
+        assert!(!status.status.success());
+    }
+
+    #[test]
+    fn test_user_install_and_uninstall() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::create_dir_all(source.join("share/applications")).unwrap();
+        fs::write(source.join("bin/sample"), "#!/bin/sh\n").unwrap();
+        fs::write(
+            source.join("share/applications/com.sample.sample.desktop"),
+            "[Desktop Entry]\nExec=bin/sample %F\n",
+        )
+        .unwrap();
+
+        let run = temp.path().join("sample.run");
+        build_installer(
+            &spec(),
+            &contents(&source),
+            &temp.path().join("payload.tar.gz"),
+            &run,
+        )
+        .unwrap();
+
+        let home = temp.path().join("home");
+        let prefix = temp.path().join("opt/sample");
+        let output = Command::new("sh")
+            .arg(&run)
+            .arg("--user")
+            .arg("--prefix")
+            .arg(&prefix)
+            .env("HOME", &home)
+            .env_remove("XDG_DATA_HOME")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+
+        assert!(prefix.join("bin/sample").is_file());
+        assert!(home.join(".local/bin/sample").exists());
+        let entry =
+            fs::read_to_string(home.join(".local/share/applications/com.sample.sample.desktop"))
+                .unwrap();
+        assert!(entry.contains(&format!("Exec={}/bin/sample %F", prefix.display())));
+
+        let output = Command::new(prefix.join("uninstall.sh")).output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert!(!prefix.exists());
+        assert!(!home.join(".local/bin/sample").exists());
+        assert!(
+            !home
+                .join(".local/share/applications/com.sample.sample.desktop")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn test_shared_prefix() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::create_dir_all(source.join("lib")).unwrap();
+        fs::write(source.join("bin/sample"), "#!/bin/sh\n").unwrap();
+        fs::write(source.join("lib/data.txt"), "data").unwrap();
+
+        let run = temp.path().join("sample.run");
+        build_installer(
+            &spec(),
+            &contents(&source),
+            &temp.path().join("payload.tar.gz"),
+            &run,
+        )
+        .unwrap();
+
+        // A prefix such as /usr/local holding files of other software
+        let home = temp.path().join("home");
+        let prefix = temp.path().join("usr/local");
+        fs::create_dir_all(prefix.join("bin")).unwrap();
+        fs::write(prefix.join("bin/other"), "other").unwrap();
+        let install = || {
+            Command::new("sh")
+                .arg(&run)
+                .arg("--user")
+                .arg("--prefix")
+                .arg(&prefix)
+                .env("HOME", &home)
+                .env_remove("XDG_DATA_HOME")
+                .output()
+                .unwrap()
+        };
+        let output = install();
+        assert!(output.status.success(), "{:?}", output);
+
+        // Reinstalling runs the uninstaller of the same package first
+        let output = install();
+        assert!(output.status.success(), "{:?}", output);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("Removing previous installation"));
+        assert!(prefix.join("lib/data.txt").is_file());
+
+        // Only the installed files and the directories left empty are removed
+        let output = Command::new("sh")
+            .arg(prefix.join("uninstall.sh"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(
+            fs::read_to_string(prefix.join("bin/other")).unwrap(),
+            "other"
+        );
+        assert!(!prefix.join("bin/sample").exists());
+        assert!(!prefix.join("lib").exists());
+        assert!(!prefix.join("uninstall.sh").exists());
+        assert!(!prefix.join(".installed-files").exists());
+
+        // The uninstaller of another package is neither run nor replaced
+        let foreign = "#!/bin/sh\n# emerge-package: other\ntouch ran\n";
+        fs::write(prefix.join("uninstall.sh"), foreign).unwrap();
+        let output = install();
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("belongs to another installation")
+        );
+        assert_eq!(
+            fs::read_to_string(prefix.join("uninstall.sh")).unwrap(),
+            foreign
+        );
+        assert!(!prefix.join("bin/sample").exists());
+    }
+}
@@ -0,0 +1,549 @@
+//! DMG window backgrounds: picture files, Retina pairs and generated backgrounds
+//!
+//! A picture with a `background_2x` companion, and every generated background, is
+//! stored as a multi-resolution TIFF (72 and 144 dpi pages), the format produced by
+//! `tiffutil -cathidpicheck`. Finder picks the page matching the display.
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::macos::dsstore::Layout;
+use crate::manifest::{DmgBackground, GeneratedBackground, Manifest};
+use crate::result::Result;
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont, point};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use image::{Rgba, RgbaImage};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Folder of the volume holding the background picture
+pub const DIRECTORY: &str = ".background";
+
+/// Fonts tried when a generated background has text but no `font`
+const SYSTEM_FONTS: &[&str] = &[
+    "/System/Library/Fonts/Helvetica.ttc",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu-sans-fonts/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "C:\\Windows\\Fonts\\segoeui.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+/// Space between an icon and the arrow, in points
+const ARROW_GAP: f32 = 20.0;
+
+/// File name of the background inside [`DIRECTORY`]
+pub fn file_name(manifest: &Manifest) -> Option<String> {
+    let dmg = manifest.dmg.as_ref()?;
+    match dmg.background.as_ref()? {
+        DmgBackground::File(path) if dmg.background_2x.is_none() => {
+            let extension = Path::new(path)
+                .extension()
+                .map(|e| e.to_string_lossy().to_string())
+                .unwrap_or_else(|| "png".to_string());
+            Some(format!("background.{}", extension))
+        }
+        _ => Some("background.tiff".to_string()),
+    }
+}
+
+/// Contents of the background file for the window `layout`
+pub fn render(ctx: &Context, manifest: &Manifest, layout: &Layout) -> Result<Option<Vec<u8>>> {
+    let Some(dmg) = manifest.dmg.as_ref() else {
+        return Ok(None);
+    };
+    let retina = dmg.background_2x.as_ref().map(|p| ctx.base_dir.join(p));
+
+    match (&dmg.background, retina) {
+        (None, None) => Ok(None),
+        (None, Some(_)) | (Some(DmgBackground::Generated(_)), Some(_)) => Err(Error::Custom(
+            "dmg.background_2x needs a background picture in dmg.background".to_string(),
+        )),
+        (Some(DmgBackground::File(path)), None) => Ok(Some(read(&ctx.base_dir.join(path))?)),
+        (Some(DmgBackground::File(path)), Some(retina)) => {
+            let normal = load(&ctx.base_dir.join(path))?;
+            let retina = load(&retina)?;
+            if retina.dimensions() != (normal.width() * 2, normal.height() * 2) {
+                return Err(Error::Custom(format!(
+                    "dmg.background_2x must be twice the size of dmg.background \
+                     ({}x{} instead of {}x{})",
+                    retina.width(),
+                    retina.height(),
+                    normal.width() * 2,
+                    normal.height() * 2
+                )));
+            }
+            Ok(Some(tiff(&[(&normal, 72), (&retina, 144)])?))
+        }
+        (Some(DmgBackground::Generated(config)), None) => {
+            let font = match &config.text {
+                Some(_) => Some(load_font(ctx, config)?),
+                None => None,
+            };
+            let normal = generate(config, layout, font.as_ref(), 1)?;
+            let retina = generate(config, layout, font.as_ref(), 2)?;
+            Ok(Some(tiff(&[(&normal, 72), (&retina, 144)])?))
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| {
+        Error::Custom(format!(
+            "DMG background not found: {} ({})",
+            path.display(),
+            e
+        ))
+    })
+}
+
+fn load(path: &Path) -> Result<RgbaImage> {
+    let data = read(path)?;
+    Ok(image::load_from_memory(&data)
+        .map_err(|e| Error::Custom(format!("Invalid image {}: {}", path.display(), e)))?
+        .to_rgba8())
+}
+
+fn load_font(ctx: &Context, config: &GeneratedBackground) -> Result<FontVec> {
+    let path = match &config.font {
+        Some(font) => ctx.base_dir.join(font),
+        None => SYSTEM_FONTS
+            .iter()
+            .map(PathBuf::from)
+            .find(|p| p.exists())
+            .ok_or_else(|| {
+                Error::Custom(
+                    "No system font found for the DMG background text, set dmg.background.font"
+                        .to_string(),
+                )
+            })?,
+    };
+    let data = fs::read(&path)
+        .map_err(|e| Error::Custom(format!("Unable to read font {}: {}", path.display(), e)))?;
+    FontVec::try_from_vec_and_index(data, 0)
+        .map_err(|e| Error::Custom(format!("Invalid font {}: {}", path.display(), e)))
+}
+
+/// Parse `#rgb`, `#rrggbb` or `#rrggbbaa`
+pub fn parse_color(value: &str) -> Result<Rgba<u8>> {
+    let invalid = || Error::Custom(format!("Invalid colour '{}', expected #rrggbb", value));
+    let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+    if !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let digits = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect::<String>(),
+        6 | 8 => hex.to_string(),
+        _ => return Err(invalid()),
+    };
+    let mut channels = [255u8; 4];
+    for (i, channel) in channels.iter_mut().enumerate().take(digits.len() / 2) {
+        *channel = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(Rgba(channels))
+}
+
+/// Draw a background at `scale` times the window size of `layout`
+pub fn generate(
+    config: &GeneratedBackground,
+    layout: &Layout,
+    font: Option<&FontVec>,
+    scale: u32,
+) -> Result<RgbaImage> {
+    let (width, height) = layout.window_size;
+    if width <= 0 || height <= 0 {
+        return Err(Error::Custom("Invalid DMG window size".to_string()));
+    }
+    let (width, height) = (width as u32 * scale, height as u32 * scale);
+    let top = parse_color(&config.color)?;
+    let bottom = match &config.gradient {
+        Some(color) => parse_color(color)?,
+        None => top,
+    };
+
+    let mut image = RgbaImage::from_fn(width, height, |_, y| {
+        let t = if height > 1 {
+            y as f32 / (height - 1) as f32
+        } else {
+            0.0
+        };
+        Rgba(std::array::from_fn(|i| {
+            (top[i] as f32 + (bottom[i] as f32 - top[i] as f32) * t).round() as u8
+        }))
+    });
+
+    let scale = scale as f32;
+    let icon = layout.icon_size as f32;
+    let app = layout.items.first().map(|(_, p)| *p);
+    let applications = layout.items.get(1).map(|(_, p)| *p);
+
+    if config.arrow
+        && let (Some(from), Some(to)) = (app, applications)
+    {
+        let from = (from.0 as f32 * scale, from.1 as f32 * scale);
+        let to = (to.0 as f32 * scale, to.1 as f32 * scale);
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let length = (dx * dx + dy * dy).sqrt();
+        let inset = (icon / 2.0 + ARROW_GAP) * scale;
+        if length > 2.0 * inset {
+            let direction = (dx / length, dy / length);
+            let start = (from.0 + direction.0 * inset, from.1 + direction.1 * inset);
+            let end = (to.0 - direction.0 * inset, to.1 - direction.1 * inset);
+            draw_arrow(
+                &mut image,
+                start,
+                end,
+                scale,
+                parse_color(&config.arrow_color)?,
+            );
+        }
+    }
+
+    if let Some(text) = &config.text {
+        let font = font.ok_or_else(|| Error::Custom("No font for the background text".into()))?;
+        let lowest = layout
+            .items
+            .iter()
+            .take(2)
+            .map(|(_, p)| p.1)
+            .max()
+            .unwrap_or(0) as f32;
+        // Below the icons and their labels
+        let top = (lowest + icon / 2.0 + 36.0) * scale;
+        let size = config.text_size * scale;
+        let top = top.min(height as f32 - size * 1.5).max(0.0);
+        draw_text(
+            &mut image,
+            font,
+            text,
+            size,
+            (width as f32 / 2.0, top),
+            parse_color(&config.text_color)?,
+        );
+    }
+
+    Ok(image)
+}
+
+/// Blend `color` with the given coverage (0-1) into a pixel
+fn blend(image: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>, coverage: f32) {
+    if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+        return;
+    }
+    let alpha = coverage.clamp(0.0, 1.0) * color[3] as f32 / 255.0;
+    let pixel = image.get_pixel_mut(x as u32, y as u32);
+    for i in 0..3 {
+        pixel[i] = (pixel[i] as f32 * (1.0 - alpha) + color[i] as f32 * alpha).round() as u8;
+    }
+    pixel[3] = (pixel[3] as f32 + (255.0 - pixel[3] as f32) * alpha).round() as u8;
+}
+
+/// Arrow with a rounded shaft and a triangular head ending at `end`
+fn draw_arrow(
+    image: &mut RgbaImage,
+    start: (f32, f32),
+    end: (f32, f32),
+    scale: f32,
+    color: Rgba<u8>,
+) {
+    let thickness = 6.0 * scale;
+    let head_length = 22.0 * scale;
+    let head_width = 26.0 * scale;
+
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    let (ux, uy) = (dx / length, dy / length);
+    let head_length = head_length.min(length);
+
+    let margin = head_width;
+    let (x0, x1) = (start.0.min(end.0) - margin, start.0.max(end.0) + margin);
+    let (y0, y1) = (start.1.min(end.1) - margin, start.1.max(end.1) + margin);
+
+    for y in y0.floor() as i64..=y1.ceil() as i64 {
+        for x in x0.floor() as i64..=x1.ceil() as i64 {
+            let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+            // Position along and across the arrow, relative to the start
+            let along = (px - start.0) * ux + (py - start.1) * uy;
+            let across = (px - start.0) * -uy + (py - start.1) * ux;
+
+            let shaft_length = length - head_length;
+            let clamped = along.clamp(0.0, shaft_length);
+            let distance = ((along - clamped).powi(2) + across.powi(2)).sqrt();
+            let shaft = thickness / 2.0 + 0.5 - distance;
+
+            // Inside the head when behind the tip's two edges and in front of its base
+            let from_tip = length - along;
+            let half_width = head_width / 2.0 * from_tip / head_length;
+            let edge = (half_width - across.abs()) * head_length
+                / (head_length.powi(2) + (head_width / 2.0).powi(2)).sqrt();
+            let head = (edge + 0.5)
+                .min(from_tip + 0.5)
+                .min(along - shaft_length + 0.5);
+
+            let coverage = shaft.max(head).clamp(0.0, 1.0);
+            if coverage > 0.0 {
+                blend(image, x, y, color, coverage);
+            }
+        }
+    }
+}
+
+/// Draw a line of text horizontally centred on `center.0`, with its top at `center.1`
+fn draw_text(
+    image: &mut RgbaImage,
+    font: &FontVec,
+    text: &str,
+    size: f32,
+    center: (f32, f32),
+    color: Rgba<u8>,
+) {
+    let scaled = font.as_scaled(PxScale::from(size));
+
+    let mut glyphs = Vec::new();
+    let mut x = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            x += scaled.kern(previous, id);
+        }
+        glyphs.push((id, x));
+        x += scaled.h_advance(id);
+        previous = Some(id);
+    }
+
+    let left = center.0 - x / 2.0;
+    let baseline = center.1 + scaled.ascent();
+    for (id, offset) in glyphs {
+        let glyph = id.with_scale_and_position(scaled.scale(), point(left + offset, baseline));
+        if let Some(outline) = scaled.outline_glyph(glyph) {
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, coverage| {
+                blend(
+                    image,
+                    bounds.min.x as i64 + gx as i64,
+                    bounds.min.y as i64 + gy as i64,
+                    color,
+                    coverage,
+                );
+            });
+        }
+    }
+}
+
+/// TIFF tag types
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+
+/// Encode RGBA images with their resolution (dpi) as pages of a deflate-compressed TIFF
+pub fn tiff(pages: &[(&RgbaImage, u32)]) -> Result<Vec<u8>> {
+    // Little-endian header, the first IFD offset is patched below
+    let mut output = b"II*\0\0\0\0\0".to_vec();
+    let mut next_pointer = 4;
+
+    for (image, dpi) in pages {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(image.as_raw())?;
+        let strip = encoder.finish()?;
+
+        let strip_offset = output.len() as u32;
+        output.extend_from_slice(&strip);
+        if output.len() % 2 == 1 {
+            output.push(0);
+        }
+
+        let bits_offset = output.len() as u32;
+        for _ in 0..4 {
+            output.extend_from_slice(&8u16.to_le_bytes());
+        }
+        let resolution_offset = output.len() as u32;
+        output.extend_from_slice(&dpi.to_le_bytes());
+        output.extend_from_slice(&1u32.to_le_bytes());
+
+        let entries: [(u16, u16, u32, u32); 14] = [
+            (256, LONG, 1, image.width()),
+            (257, LONG, 1, image.height()),
+            (258, SHORT, 4, bits_offset),
+            // Adobe deflate
+            (259, SHORT, 1, 8),
+            // RGB
+            (262, SHORT, 1, 2),
+            (273, LONG, 1, strip_offset),
+            (277, SHORT, 1, 4),
+            (278, LONG, 1, image.height()),
+            (279, LONG, 1, strip.len() as u32),
+            (282, RATIONAL, 1, resolution_offset),
+            (283, RATIONAL, 1, resolution_offset),
+            // Chunky pixels
+            (284, SHORT, 1, 1),
+            // Inches
+            (296, SHORT, 1, 2),
+            // Unassociated alpha
+            (338, SHORT, 1, 2),
+        ];
+
+        let ifd = output.len() as u32;
+        output[next_pointer..next_pointer + 4].copy_from_slice(&ifd.to_le_bytes());
+        output.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, kind, count, value) in entries {
+            output.extend_from_slice(&tag.to_le_bytes());
+            output.extend_from_slice(&kind.to_le_bytes());
+            output.extend_from_slice(&count.to_le_bytes());
+            if kind == SHORT && count == 1 {
+                // Short values are left-justified in the value field
+                output.extend_from_slice(&(value as u16).to_le_bytes());
+                output.extend_from_slice(&[0, 0]);
+            } else {
+                output.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        next_pointer = output.len();
+        output.extend_from_slice(&[0; 4]);
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macos::dmg::finder_layout;
+    use crate::manifest::tests::sample_manifest;
+    use std::io::Cursor;
+    use tiff::decoder::{Decoder, DecodingResult};
+    use tiff::tags::Tag;
+
+    fn generated(emerge: &str) -> (GeneratedBackground, Layout) {
+        let (_, manifest) = sample_manifest(emerge);
+        let Some(DmgBackground::Generated(config)) =
+            manifest.dmg.as_ref().and_then(|d| d.background.clone())
+        else {
+            panic!("no generated background");
+        };
+        (config, finder_layout(&manifest))
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#ff8000").unwrap(), Rgba([255, 128, 0, 255]));
+        assert_eq!(parse_color("#fff").unwrap(), Rgba([255, 255, 255, 255]));
+        assert_eq!(parse_color("#00000080").unwrap(), Rgba([0, 0, 0, 128]));
+        assert!(parse_color("red").is_err());
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("#gggggg").is_err());
+    }
+
+    #[test]
+    fn test_file_name() {
+        let name = |emerge: &str| file_name(&sample_manifest(emerge).1);
+        assert_eq!(name(""), None);
+        assert_eq!(
+            name("[dmg]\nbackground = \"bg.jpg\"").as_deref(),
+            Some("background.jpg")
+        );
+        assert_eq!(
+            name("[dmg]\nbackground = \"bg.png\"\nbackground_2x = \"bg@2x.png\"").as_deref(),
+            Some("background.tiff")
+        );
+        assert_eq!(
+            name("[dmg]\nbackground = { color = \"#eeeeee\" }").as_deref(),
+            Some("background.tiff")
+        );
+    }
+
+    #[test]
+    fn test_tiff() {
+        let normal = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 7, 255]));
+        let retina = RgbaImage::from_pixel(6, 4, Rgba([1, 2, 3, 4]));
+        let data = tiff(&[(&normal, 72), (&retina, 144)]).unwrap();
+
+        let mut decoder = Decoder::new(Cursor::new(data)).unwrap();
+        for (image, dpi) in [(&normal, 72), (&retina, 144)] {
+            assert_eq!(decoder.dimensions().unwrap(), image.dimensions());
+            assert_eq!(decoder.get_tag_u32_vec(Tag::XResolution).unwrap(), [dpi, 1]);
+            match decoder.read_image().unwrap() {
+                DecodingResult::U8(pixels) => assert_eq!(&pixels, image.as_raw()),
+                _ => panic!("unexpected sample format"),
+            }
+            if dpi == 72 {
+                decoder.next_image().unwrap();
+            }
+        }
+        assert!(!decoder.more_images());
+    }
+
+    #[test]
+    fn test_retina_pair() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut ctx, manifest) =
+            sample_manifest("[dmg]\nbackground = \"bg.png\"\nbackground_2x = \"bg@2x.png\"");
+        ctx.base_dir = dir.path().to_path_buf();
+        let layout = finder_layout(&manifest);
+
+        RgbaImage::new(60, 40)
+            .save(dir.path().join("bg.png"))
+            .unwrap();
+        RgbaImage::new(100, 80)
+            .save(dir.path().join("bg@2x.png"))
+            .unwrap();
+        let err = render(&ctx, &manifest, &layout).unwrap_err();
+        assert!(err.to_string().contains("twice the size"));
+
+        RgbaImage::new(120, 80)
+            .save(dir.path().join("bg@2x.png"))
+            .unwrap();
+        let data = render(&ctx, &manifest, &layout).unwrap().unwrap();
+        let mut decoder = Decoder::new(Cursor::new(data)).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (60, 40));
+        decoder.next_image().unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (120, 80));
+    }
+
+    #[test]
+    fn test_generate() {
+        let (config, layout) = generated(
+            "[dmg]\nwindow_size = [600, 400]\nbackground = { color = \"#ffffff\", gradient = \"#000000\", arrow_color = \"#ff0000\" }",
+        );
+        let image = generate(&config, &layout, None, 1).unwrap();
+        assert_eq!(image.dimensions(), (600, 400));
+        assert_eq!(image.get_pixel(5, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(5, 399), &Rgba([0, 0, 0, 255]));
+
+        // Arrow between the default icon positions (150, 200) and (450, 200)
+        assert_eq!(image.get_pixel(300, 200), &Rgba([255, 0, 0, 255]));
+        assert_ne!(image.get_pixel(200, 200), &Rgba([255, 0, 0, 255]));
+
+        let retina = generate(&config, &layout, None, 2).unwrap();
+        assert_eq!(retina.dimensions(), (1200, 800));
+        assert_eq!(retina.get_pixel(600, 400), &Rgba([255, 0, 0, 255]));
+
+        let (config, layout) = generated("[dmg]\nbackground = { arrow = false }");
+        let image = generate(&config, &layout, None, 1).unwrap();
+        assert!(image.pixels().all(|p| *p == Rgba([255, 255, 255, 255])));
+    }
+
+    #[test]
+    fn test_generate_text() {
+        let (config, layout) = generated(
+            "[dmg]\nbackground = { arrow = false, text = \"Drag to install\", text_color = \"#000000\" }",
+        );
+        assert!(generate(&config, &layout, None, 1).is_err());
+
+        // Needs a TrueType font; DejaVu is found on most Linux systems
+        let Some(path) = SYSTEM_FONTS.iter().map(Path::new).find(|p| p.exists()) else {
+            return;
+        };
+        let font = FontVec::try_from_vec_and_index(fs::read(path).unwrap(), 0).unwrap();
+        let image = generate(&config, &layout, Some(&font), 1).unwrap();
+
+        // Text is centred below the icons (y = 200 + 36 + 36)
+        let dark =
+            |x0: u32, x1: u32| (x0..x1).any(|x| (272..295).any(|y| image.get_pixel(x, y)[0] < 128));
+        assert!(dark(280, 320));
+        assert!(!dark(0, 150) && !dark(450, 600));
+        assert!((0..600).all(|x| (0..260).all(|y| image.get_pixel(x, y)[0] == 255)));
+    }
+}
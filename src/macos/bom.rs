@@ -0,0 +1,387 @@
+//! Bill of materials (`Bom`) of installer packages
+//!
+//! A BOM store is a header, numbered blocks, named variables pointing at blocks and the
+//! block table. Packages need the `BomInfo` and `Paths` variables plus the empty `HLIndex`,
+//! `VIndex` and `Size64` trees. `Paths` is a B+ tree of leaves linking each path key
+//! (parent id and name) to its type, mode, owner, size and checksum. All integers are
+//! big-endian.
+
+use crate::archive::Kind;
+use crate::error::Error;
+use crate::macos::cpio::Entry;
+use crate::result::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+const MAGIC: &[u8; 8] = b"BOMStore";
+const HEADER_SIZE: usize = 512;
+/// Block size of the `Paths` and `HLIndex` trees
+const PATHS_BLOCK_SIZE: u32 = 4096;
+/// Block size of the `VIndex` and `Size64` trees
+const SMALL_BLOCK_SIZE: u32 = 128;
+/// Size of the `isLeaf`, `count`, `forward` and `backward` fields of a tree node
+const NODE_HEADER: usize = 12;
+/// Paths per leaf: each one takes two block indices
+const LEAF_CAPACITY: usize = (PATHS_BLOCK_SIZE as usize - NODE_HEADER) / 8;
+
+const TYPE_FILE: u8 = 1;
+const TYPE_DIRECTORY: u8 = 2;
+const TYPE_LINK: u8 = 3;
+
+/// Blocks and variables of a BOM store being built
+struct Store {
+    /// Block 0 is the null block
+    blocks: Vec<Vec<u8>>,
+    vars: Vec<(&'static str, u32)>,
+}
+
+impl Store {
+    fn new() -> Self {
+        Self {
+            blocks: vec![Vec::new()],
+            vars: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, data: Vec<u8>) -> u32 {
+        self.blocks.push(data);
+        self.blocks.len() as u32 - 1
+    }
+
+    /// A tree header pointing at `child`
+    fn add_tree(&mut self, child: u32, block_size: u32, path_count: u32) -> u32 {
+        let mut tree = b"tree".to_vec();
+        tree.extend(1u32.to_be_bytes());
+        tree.extend(child.to_be_bytes());
+        tree.extend(block_size.to_be_bytes());
+        tree.extend(path_count.to_be_bytes());
+        tree.push(0);
+        self.add(tree)
+    }
+
+    /// A tree with a single empty leaf
+    fn add_empty_tree(&mut self, block_size: u32) -> u32 {
+        let leaf = node(true, &[], 0, 0, block_size as usize);
+        let child = self.add(leaf);
+        self.add_tree(child, block_size, 0)
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut output = vec![0u8; HEADER_SIZE];
+        let mut pointers = vec![(0u32, 0u32)];
+        for block in &self.blocks[1..] {
+            pointers.push((output.len() as u32, block.len() as u32));
+            output.extend(block);
+        }
+
+        let vars_offset = output.len();
+        output.extend((self.vars.len() as u32).to_be_bytes());
+        for (name, index) in &self.vars {
+            output.extend(index.to_be_bytes());
+            output.push(name.len() as u8);
+            output.extend(name.as_bytes());
+        }
+        let vars_length = output.len() - vars_offset;
+
+        let index_offset = output.len();
+        output.extend((pointers.len() as u32).to_be_bytes());
+        for (address, length) in &pointers {
+            output.extend(address.to_be_bytes());
+            output.extend(length.to_be_bytes());
+        }
+        // Empty free list
+        output.extend(0u32.to_be_bytes());
+        let index_length = output.len() - index_offset;
+
+        let mut header = MAGIC.to_vec();
+        header.extend(1u32.to_be_bytes());
+        header.extend((self.blocks.len() as u32 - 1).to_be_bytes());
+        for value in [index_offset, index_length, vars_offset, vars_length] {
+            header.extend((value as u32).to_be_bytes());
+        }
+        output[..header.len()].copy_from_slice(&header);
+        output
+    }
+}
+
+/// A tree node of `size` bytes with (index0, index1) pairs
+fn node(leaf: bool, indices: &[(u32, u32)], forward: u32, backward: u32, size: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(size);
+    data.extend((leaf as u16).to_be_bytes());
+    data.extend((indices.len() as u16).to_be_bytes());
+    data.extend(forward.to_be_bytes());
+    data.extend(backward.to_be_bytes());
+    for (index0, index1) in indices {
+        data.extend(index0.to_be_bytes());
+        data.extend(index1.to_be_bytes());
+    }
+    data.resize(data.len().max(size), 0);
+    data
+}
+
+/// BOM of the entries of a payload, in payload order (parents first)
+pub fn write(entries: &[Entry]) -> Result<Vec<u8>> {
+    let mut store = Store::new();
+
+    let mut info = Vec::new();
+    info.extend(1u32.to_be_bytes());
+    info.extend((entries.len() as u32).to_be_bytes());
+    // One empty architecture entry
+    info.extend(1u32.to_be_bytes());
+    info.extend([0u8; 16]);
+    let info = store.add(info);
+
+    // Path ids start at 1; the root has parent 0
+    let mut ids = HashMap::new();
+    let mut keys = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.iter().enumerate() {
+        let id = index as u32 + 1;
+        ids.insert(entry.path.as_str(), id);
+        let parent = match entry.parent() {
+            Some(parent) => *ids
+                .get(parent)
+                .ok_or_else(|| Error::Custom(format!("BOM parent of {} is missing", entry.path)))?,
+            None => 0,
+        };
+
+        let attributes = store.add(path_attributes(entry)?);
+        let mut path_info = id.to_be_bytes().to_vec();
+        path_info.extend(attributes.to_be_bytes());
+        let path_info = store.add(path_info);
+
+        let mut file = parent.to_be_bytes().to_vec();
+        file.extend(entry.name().as_bytes());
+        file.push(0);
+        let file = store.add(file);
+
+        keys.push((path_info, file));
+    }
+
+    // Leaves linked in both directions, under a branch node when there are several
+    let chunks = keys.chunks(LEAF_CAPACITY).collect::<Vec<_>>();
+    let first_leaf = store.blocks.len() as u32;
+    let mut leaves = Vec::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let leaf = first_leaf + index as u32;
+        let forward = if index + 1 < chunks.len() {
+            leaf + 1
+        } else {
+            0
+        };
+        let backward = if index > 0 { leaf - 1 } else { 0 };
+        store.add(node(
+            true,
+            chunk,
+            forward,
+            backward,
+            PATHS_BLOCK_SIZE as usize,
+        ));
+        leaves.push((leaf, chunk.last().map_or(0, |(_, file)| *file)));
+    }
+    let root = if leaves.len() == 1 {
+        first_leaf
+    } else {
+        store.add(node(false, &leaves, 0, 0, PATHS_BLOCK_SIZE as usize))
+    };
+    let paths = store.add_tree(root, PATHS_BLOCK_SIZE, entries.len() as u32);
+
+    let hard_links = store.add_empty_tree(PATHS_BLOCK_SIZE);
+
+    let vtree = store.add_empty_tree(SMALL_BLOCK_SIZE);
+    let mut vindex = 1u32.to_be_bytes().to_vec();
+    vindex.extend(vtree.to_be_bytes());
+    vindex.extend(0u32.to_be_bytes());
+    vindex.push(0);
+    let vindex = store.add(vindex);
+
+    let size64 = store.add_empty_tree(SMALL_BLOCK_SIZE);
+
+    store.vars = vec![
+        ("BomInfo", info),
+        ("Paths", paths),
+        ("HLIndex", hard_links),
+        ("VIndex", vindex),
+        ("Size64", size64),
+    ];
+    Ok(store.into_bytes())
+}
+
+/// Type, mode, owner, modification time, size and checksum of a path
+fn path_attributes(entry: &Entry) -> Result<Vec<u8>> {
+    let (kind, checksum, link) = match &entry.kind {
+        Kind::Directory => (TYPE_DIRECTORY, 0, None),
+        Kind::File => (TYPE_FILE, cksum(File::open(&entry.source)?)?, None),
+        Kind::Symlink(target) => (TYPE_LINK, cksum(target.as_bytes())?, Some(target)),
+    };
+
+    let mut data = vec![kind, 1];
+    // Architecture
+    data.extend(0u16.to_be_bytes());
+    data.extend((entry.st_mode() as u16).to_be_bytes());
+    data.extend(entry.uid.to_be_bytes());
+    data.extend(entry.gid.to_be_bytes());
+    data.extend((entry.mtime as u32).to_be_bytes());
+    data.extend((entry.size as u32).to_be_bytes());
+    data.push(1);
+    data.extend(checksum.to_be_bytes());
+    match link {
+        Some(target) => {
+            data.extend((target.len() as u32 + 1).to_be_bytes());
+            data.extend(target.as_bytes());
+            data.push(0);
+        }
+        None => data.extend(0u32.to_be_bytes()),
+    }
+    Ok(data)
+}
+
+/// POSIX `cksum` CRC of a stream
+pub fn cksum<R: Read>(mut reader: R) -> Result<u32> {
+    let table: Vec<u32> = (0..256u32)
+        .map(|byte| {
+            (0..8).fold(byte << 24, |crc, _| {
+                if crc & 0x8000_0000 != 0 {
+                    (crc << 1) ^ 0x04c1_1db7
+                } else {
+                    crc << 1
+                }
+            })
+        })
+        .collect();
+    let update = |crc: u32, byte: u8| (crc << 8) ^ table[((crc >> 24) as u8 ^ byte) as usize];
+
+    let mut crc = 0u32;
+    let mut length = 0u64;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        crc = buffer[..read]
+            .iter()
+            .fold(crc, |crc, byte| update(crc, *byte));
+        length += read as u64;
+    }
+
+    // The length follows the data, least significant byte first
+    while length > 0 {
+        crc = update(crc, length as u8);
+        length >>= 8;
+    }
+    Ok(!crc)
+}
+
+/// Paths of a BOM store as (path, mode, size, checksum), following the leaf links
+#[cfg(test)]
+pub fn read_paths(bom: &[u8]) -> Vec<(String, u16, u32, u32)> {
+    let u32_at = |data: &[u8], offset: usize| {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    };
+    assert_eq!(&bom[..8], MAGIC);
+    let index_offset = u32_at(bom, 16) as usize;
+    let vars_offset = u32_at(bom, 24) as usize;
+    let block = |index: u32| {
+        let pointer = index_offset + 4 + index as usize * 8;
+        let address = u32_at(bom, pointer) as usize;
+        &bom[address..address + u32_at(bom, pointer + 4) as usize]
+    };
+
+    let mut vars = HashMap::new();
+    let mut offset = vars_offset + 4;
+    for _ in 0..u32_at(bom, vars_offset) {
+        let length = bom[offset + 4] as usize;
+        let name = String::from_utf8(bom[offset + 5..offset + 5 + length].to_vec()).unwrap();
+        vars.insert(name, u32_at(bom, offset));
+        offset += 5 + length;
+    }
+    for name in ["BomInfo", "HLIndex", "VIndex", "Size64"] {
+        assert!(vars.contains_key(name), "{}", name);
+    }
+
+    let tree = block(vars["Paths"]);
+    assert_eq!(&tree[..4], b"tree");
+    let mut node = block(u32_at(tree, 8));
+    while node[1] == 0 {
+        node = block(u32_at(node, NODE_HEADER));
+    }
+
+    let mut names: HashMap<u32, String> = HashMap::new();
+    let mut paths = Vec::new();
+    loop {
+        let count = u16::from_be_bytes([node[2], node[3]]) as usize;
+        for i in 0..count {
+            let path_info = block(u32_at(node, NODE_HEADER + i * 8));
+            let file = block(u32_at(node, NODE_HEADER + i * 8 + 4));
+            let attributes = block(u32_at(path_info, 4));
+            let parent = u32_at(file, 0);
+            let name = std::str::from_utf8(&file[4..file.len() - 1]).unwrap();
+            let path = match names.get(&parent) {
+                Some(parent) => format!("{}/{}", parent, name),
+                None => name.to_string(),
+            };
+            names.insert(u32_at(path_info, 0), path.clone());
+            let mode = u16::from_be_bytes([attributes[4], attributes[5]]);
+            paths.push((path, mode, u32_at(attributes, 18), u32_at(attributes, 23)));
+        }
+        match u32_at(node, 4) {
+            0 => return paths,
+            next => node = block(next),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macos::cpio;
+    use std::fs;
+
+    #[test]
+    fn test_cksum() {
+        assert_eq!(cksum(&b"123456789"[..]).unwrap(), 930766865);
+        assert_eq!(cksum(&b""[..]).unwrap(), 4294967295);
+    }
+
+    #[test]
+    fn test_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("Sample.app/Contents/MacOS")).unwrap();
+        fs::write(
+            dir.path().join("Sample.app/Contents/MacOS/sample"),
+            "123456789",
+        )
+        .unwrap();
+
+        let bom = write(&cpio::entries(dir.path()).unwrap()).unwrap();
+        let paths = read_paths(&bom);
+        assert_eq!(
+            paths.iter().map(|p| p.0.as_str()).collect::<Vec<_>>(),
+            [
+                ".",
+                "./Sample.app",
+                "./Sample.app/Contents",
+                "./Sample.app/Contents/MacOS",
+                "./Sample.app/Contents/MacOS/sample",
+            ]
+        );
+        let (_, mode, size, checksum) = paths[4];
+        assert_eq!(mode & 0o170000, 0o100000);
+        assert_eq!((size, checksum), (9, 930766865));
+        assert_eq!(paths[0].1 & 0o170000, 0o040000);
+    }
+
+    #[test]
+    fn test_many_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..LEAF_CAPACITY + 10 {
+            fs::write(dir.path().join(format!("file{:04}", i)), "").unwrap();
+        }
+
+        let entries = cpio::entries(dir.path()).unwrap();
+        let paths = read_paths(&write(&entries).unwrap());
+        assert_eq!(paths.len(), entries.len());
+        assert_eq!(paths.last().unwrap().0, entries.last().unwrap().path);
+    }
+}
@@ -0,0 +1,172 @@
+//! Binary property list (`bplist00`) encoder, as used by `.DS_Store` view settings
+
+/// A property list value; dictionaries keep their insertion order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Integer(i64),
+    Real(f64),
+    String(String),
+    Data(Vec<u8>),
+    Dict(Vec<(String, Value)>),
+}
+
+/// Object table entry: encoded scalars or a dictionary of object references
+enum Object {
+    Scalar(Vec<u8>),
+    Dict(Vec<usize>, Vec<usize>),
+}
+
+/// Encode a value as a binary property list
+pub fn write(value: &Value) -> Vec<u8> {
+    let mut objects = Vec::new();
+    flatten(value, &mut objects);
+
+    let ref_size = int_size(objects.len() as u64);
+    let mut output = b"bplist00".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for object in &objects {
+        offsets.push(output.len() as u64);
+        match object {
+            Object::Scalar(bytes) => output.extend_from_slice(bytes),
+            Object::Dict(keys, values) => {
+                push_marker(&mut output, 0xd, keys.len());
+                for reference in keys.iter().chain(values) {
+                    push_uint(&mut output, *reference as u64, ref_size);
+                }
+            }
+        }
+    }
+
+    let offset_table = output.len() as u64;
+    let offset_size = int_size(offset_table);
+    for offset in offsets {
+        push_uint(&mut output, offset, offset_size);
+    }
+
+    // Trailer: 6 unused bytes, sizes, object count, top object, offset table position
+    output.extend_from_slice(&[0; 6]);
+    output.push(offset_size as u8);
+    output.push(ref_size as u8);
+    output.extend_from_slice(&(objects.len() as u64).to_be_bytes());
+    output.extend_from_slice(&0u64.to_be_bytes());
+    output.extend_from_slice(&offset_table.to_be_bytes());
+    output
+}
+
+/// Append `value` and its children to the object table, returning its reference
+fn flatten(value: &Value, objects: &mut Vec<Object>) -> usize {
+    let index = objects.len();
+    objects.push(Object::Scalar(Vec::new()));
+
+    let object = match value {
+        Value::Dict(entries) => {
+            let keys = entries
+                .iter()
+                .map(|(key, _)| {
+                    objects.push(Object::Scalar(encode_string(key)));
+                    objects.len() - 1
+                })
+                .collect();
+            let values = entries
+                .iter()
+                .map(|(_, value)| flatten(value, objects))
+                .collect();
+            Object::Dict(keys, values)
+        }
+        Value::Bool(value) => Object::Scalar(vec![if *value { 0x09 } else { 0x08 }]),
+        Value::Integer(value) => Object::Scalar(encode_integer(*value)),
+        Value::Real(value) => {
+            let mut bytes = vec![0x23];
+            bytes.extend_from_slice(&value.to_be_bytes());
+            Object::Scalar(bytes)
+        }
+        Value::String(value) => Object::Scalar(encode_string(value)),
+        Value::Data(data) => {
+            let mut bytes = Vec::new();
+            push_marker(&mut bytes, 0x4, data.len());
+            bytes.extend_from_slice(data);
+            Object::Scalar(bytes)
+        }
+    };
+
+    objects[index] = object;
+    index
+}
+
+fn encode_integer(value: i64) -> Vec<u8> {
+    // Negative integers are always stored in 8 bytes
+    let size = if value < 0 { 8 } else { int_size(value as u64) };
+    let mut bytes = vec![0x10 | size.trailing_zeros() as u8];
+    push_uint(&mut bytes, value as u64, size);
+    bytes
+}
+
+/// ASCII strings are stored as bytes, everything else as UTF-16BE
+fn encode_string(value: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    if value.is_ascii() {
+        push_marker(&mut bytes, 0x5, value.len());
+        bytes.extend_from_slice(value.as_bytes());
+    } else {
+        let units = value.encode_utf16().collect::<Vec<_>>();
+        push_marker(&mut bytes, 0x6, units.len());
+        bytes.extend(units.iter().flat_map(|unit| unit.to_be_bytes()));
+    }
+    bytes
+}
+
+/// Object marker with the length in the low nibble or in a following integer
+fn push_marker(output: &mut Vec<u8>, kind: u8, length: usize) {
+    if length < 15 {
+        output.push(kind << 4 | length as u8);
+    } else {
+        output.push(kind << 4 | 0xf);
+        output.extend(encode_integer(length as i64));
+    }
+}
+
+fn push_uint(output: &mut Vec<u8>, value: u64, size: usize) {
+    output.extend_from_slice(&value.to_be_bytes()[8 - size..]);
+}
+
+/// Smallest power-of-two byte count able to hold `value`
+fn int_size(value: u64) -> usize {
+    match value {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x1_0000..=0xffff_ffff => 4,
+        _ => 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let value = Value::Dict(vec![
+            ("flag".to_string(), Value::Bool(true)),
+            ("size".to_string(), Value::Integer(300)),
+            ("scale".to_string(), Value::Real(1.5)),
+            ("name".to_string(), Value::String("Ünïcode".to_string())),
+            ("data".to_string(), Value::Data(vec![0xaa; 16])),
+        ]);
+
+        // Reference encoding produced by Python's plistlib
+        let expected = [
+            "62706c6973743030d50102030405060708090a54666c61675473697a65557363616c6554",
+            "6e616d6554646174610911012c233ff80000000000006700dc006e00ef0063006f006400",
+            "654f1010aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa0813181d23282d2e313a490000000000",
+            "000101000000000000000b0000000000000000000000000000005c",
+        ]
+        .concat();
+        let expected = (0..expected.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&expected[i..i + 2], 16).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(write(&value), expected);
+    }
+}
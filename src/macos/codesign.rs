@@ -0,0 +1,959 @@
+use crate::context::Context;
+use crate::error::Error;
+use crate::macos::macho::{
+    self, LC_CODE_SIGNATURE, MachO, invalid, read_u32, read_u64, write_u32, write_u64,
+};
+use crate::macos::universal;
+use crate::manifest::Manifest;
+use crate::result::Result;
+use crate::signing::{self, Identity};
+use crate::utils;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use der::Any;
+use der::asn1::OctetString;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+pub const CSMAGIC_REQUIREMENT: u32 = 0xfade_0c00;
+pub const CSMAGIC_REQUIREMENTS: u32 = 0xfade_0c01;
+pub const CSMAGIC_CODEDIRECTORY: u32 = 0xfade_0c02;
+pub const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade_0cc0;
+pub const CSMAGIC_EMBEDDED_ENTITLEMENTS: u32 = 0xfade_7171;
+pub const CSMAGIC_BLOBWRAPPER: u32 = 0xfade_0b01;
+
+pub const CSSLOT_CODEDIRECTORY: u32 = 0;
+pub const CSSLOT_INFOSLOT: u32 = 1;
+pub const CSSLOT_REQUIREMENTS: u32 = 2;
+pub const CSSLOT_RESOURCEDIR: u32 = 3;
+pub const CSSLOT_ENTITLEMENTS: u32 = 5;
+pub const CSSLOT_SIGNATURESLOT: u32 = 0x10000;
+
+pub const CS_ADHOC: u32 = 0x2;
+pub const CS_RUNTIME: u32 = 0x10000;
+pub const CS_EXECSEG_MAIN_BINARY: u64 = 0x1;
+pub const CS_HASHTYPE_SHA256: u8 = 2;
+
+const CODEDIRECTORY_VERSION: u32 = 0x20400;
+const CODEDIRECTORY_HEADER_SIZE: usize = 88;
+const PAGE_SIZE_LOG2: u8 = 12;
+const PAGE_SIZE: usize = 1 << PAGE_SIZE_LOG2;
+const HASH_SIZE: usize = 32;
+/// Length of a cdhash (truncated SHA-256 of the CodeDirectory)
+pub const CDHASH_SIZE: usize = 20;
+
+/// Apple's `CDHashes` signed attribute
+const APPLE_CDHASHES_OID: der::oid::ObjectIdentifier =
+    der::oid::ObjectIdentifier::new_unwrap("1.2.840.113635.100.9.1");
+
+// Requirement language opcodes and types
+const REQUIREMENT_EXPRESSION: u32 = 1;
+const DESIGNATED_REQUIREMENT: u32 = 3;
+const OP_IDENT: u32 = 2;
+const OP_ANCHOR_HASH: u32 = 4;
+const OP_AND: u32 = 6;
+
+/// Default resource rules of `codesign`, keys in plist order
+const RESOURCE_RULES: &str = r#"	<key>rules</key>
+	<dict>
+		<key>^Resources/</key>
+		<true/>
+		<key>^Resources/.*\.lproj/</key>
+		<dict>
+			<key>optional</key>
+			<true/>
+			<key>weight</key>
+			<real>1000</real>
+		</dict>
+		<key>^Resources/.*\.lproj/locversion.plist$</key>
+		<dict>
+			<key>omit</key>
+			<true/>
+			<key>weight</key>
+			<real>1100</real>
+		</dict>
+		<key>^Resources/Base\.lproj/</key>
+		<dict>
+			<key>weight</key>
+			<real>1010</real>
+		</dict>
+		<key>^version.plist$</key>
+		<true/>
+	</dict>
+	<key>rules2</key>
+	<dict>
+		<key>.*\.dSYM($|/)</key>
+		<dict>
+			<key>weight</key>
+			<real>11</real>
+		</dict>
+		<key>^(.*/)?\.DS_Store$</key>
+		<dict>
+			<key>omit</key>
+			<true/>
+			<key>weight</key>
+			<real>2000</real>
+		</dict>
+		<key>^.*</key>
+		<true/>
+		<key>^.*\.lproj/</key>
+		<dict>
+			<key>optional</key>
+			<true/>
+			<key>weight</key>
+			<real>1000</real>
+		</dict>
+		<key>^.*\.lproj/locversion.plist$</key>
+		<dict>
+			<key>omit</key>
+			<true/>
+			<key>weight</key>
+			<real>1100</real>
+		</dict>
+		<key>^Base\.lproj/</key>
+		<dict>
+			<key>weight</key>
+			<real>1010</real>
+		</dict>
+		<key>^Info\.plist$</key>
+		<dict>
+			<key>omit</key>
+			<true/>
+			<key>weight</key>
+			<real>20</real>
+		</dict>
+		<key>^PkgInfo$</key>
+		<dict>
+			<key>omit</key>
+			<true/>
+			<key>weight</key>
+			<real>20</real>
+		</dict>
+		<key>^embedded\.provisionprofile$</key>
+		<dict>
+			<key>weight</key>
+			<real>20</real>
+		</dict>
+		<key>^version\.plist$</key>
+		<dict>
+			<key>weight</key>
+			<real>20</real>
+		</dict>
+	</dict>
+"#;
+
+/// Options for signing a single Mach-O file
+#[derive(Default)]
+pub struct SignOptions<'a> {
+    pub identifier: String,
+    /// Certificate identity, ad hoc signature if `None`
+    pub identity: Option<&'a Identity>,
+    pub team_id: Option<String>,
+    /// Contents of `Info.plist` (main executable only)
+    pub info_plist: Option<&'a [u8]>,
+    /// Contents of `_CodeSignature/CodeResources` (main executable only)
+    pub code_resources: Option<&'a [u8]>,
+    /// Entitlements property list (XML)
+    pub entitlements: Option<&'a [u8]>,
+    pub hardened_runtime: bool,
+    pub main_binary: bool,
+}
+
+/// Sign the app bundle: nested binaries, resources and the main executable
+pub fn sign_bundle(ctx: &Context, manifest: &Manifest, app_path: &Path) -> Result<()> {
+    let config = manifest
+        .macos
+        .as_ref()
+        .and_then(|m| m.signing.clone())
+        .unwrap_or_default();
+
+    let identity = config
+        .identity
+        .as_ref()
+        .map(|path| Identity::load(&ctx.base_dir.join(path), config.password_env.as_deref()))
+        .transpose()?;
+
+    let entitlements = match &config.entitlements {
+        Some(path) => {
+            let path = ctx.base_dir.join(path);
+            let data = fs::read(&path)?;
+            validate_entitlements(&data)
+                .map_err(|e| Error::Signing(format!("{}: {}", path.display(), e)))?;
+            Some(data)
+        }
+        None => None,
+    };
+
+    let options = SignOptions {
+        identifier: crate::macos::dmg::bundle_identifier(manifest),
+        team_id: identity.as_ref().and_then(|i| i.organizational_unit()),
+        identity: identity.as_ref(),
+        entitlements: entitlements.as_deref(),
+        hardened_runtime: config.hardened_runtime,
+        ..Default::default()
+    };
+
+    let signed = sign_app(app_path, &manifest.name, &options)?;
+    if ctx.verbose {
+        for path in &signed {
+            status!("Signed {}", path.display());
+        }
+    }
+
+    match &identity {
+        Some(identity) => status!(
+            "Signed {} with {}",
+            app_path.display(),
+            identity.common_name().unwrap_or_default()
+        ),
+        None => status!("Signed {} ad hoc", app_path.display()),
+    }
+
+    Ok(())
+}
+
+/// Sign every Mach-O file in `Contents/`, write `_CodeSignature/CodeResources` and sign
+/// the main executable last. `options` apply to the main executable; nested binaries
+/// use their file name as identifier. Returns the signed files.
+pub fn sign_app(app_path: &Path, executable: &str, options: &SignOptions) -> Result<Vec<PathBuf>> {
+    let contents = app_path.join("Contents");
+    let main_path = contents.join("MacOS").join(executable);
+    if !main_path.is_file() {
+        return Err(Error::Signing(format!(
+            "Main executable not found: {}",
+            main_path.display()
+        )));
+    }
+
+    let mut signed = Vec::new();
+    let mut nested = BTreeMap::new();
+    for entry in WalkDir::new(&contents)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !entry.file_type().is_file()
+            || path == main_path
+            || path.starts_with(contents.join("_CodeSignature"))
+            || !macho::is_macho(&fs::read(path)?)
+        {
+            continue;
+        }
+
+        let identifier = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let nested_options = SignOptions {
+            identifier,
+            identity: options.identity,
+            team_id: options.team_id.clone(),
+            hardened_runtime: options.hardened_runtime,
+            ..Default::default()
+        };
+        let data = sign_macho(&fs::read(path)?, &nested_options)?;
+        fs::write(path, &data)?;
+
+        let relative = path.strip_prefix(&contents).unwrap();
+        nested.insert(utils::unix_path(relative), cdhash(&data)?);
+        signed.push(path.to_path_buf());
+    }
+
+    let resources = code_resources(&contents, &main_path, &nested)?;
+    let signature_dir = contents.join("_CodeSignature");
+    utils::ensure_dir(&signature_dir)?;
+    fs::write(signature_dir.join("CodeResources"), &resources)?;
+
+    let info_plist = fs::read(contents.join("Info.plist")).ok();
+    let main_options = SignOptions {
+        identifier: options.identifier.clone(),
+        identity: options.identity,
+        team_id: options.team_id.clone(),
+        info_plist: info_plist.as_deref(),
+        code_resources: Some(resources.as_bytes()),
+        entitlements: options.entitlements,
+        hardened_runtime: options.hardened_runtime,
+        main_binary: true,
+    };
+    let data = sign_macho(&fs::read(&main_path)?, &main_options)?;
+    fs::write(&main_path, data)?;
+    signed.push(main_path);
+
+    Ok(signed)
+}
+
+/// Embed a code signature into every architecture of a thin or fat Mach-O file
+pub fn sign_macho(data: &[u8], options: &SignOptions) -> Result<Vec<u8>> {
+    let ranges = macho::slices(data)?;
+    if ranges.len() == 1 && ranges[0] == (0..data.len()) {
+        return sign_slice(data, options);
+    }
+
+    let slices = ranges
+        .into_iter()
+        .map(|range| sign_slice(&data[range], options))
+        .collect::<Result<Vec<_>>>()?;
+    universal::merge(&slices)
+}
+
+fn sign_slice(slice: &[u8], options: &SignOptions) -> Result<Vec<u8>> {
+    let image = MachO::parse(slice)?;
+    if !image.is_64 {
+        return Err(invalid("signing 32-bit images is not supported"));
+    }
+    let linkedit = image
+        .segment(slice, "__LINKEDIT")
+        .ok_or_else(|| invalid("no __LINKEDIT segment"))?;
+    let (exec_base, exec_limit) = match image.segment(slice, "__TEXT") {
+        Some(text) => (
+            read_u64(slice, text.offset + 40)?,
+            read_u64(slice, text.offset + 48)?,
+        ),
+        None => (0, 0),
+    };
+
+    let mut data = slice.to_vec();
+    let linkedit_offset = read_u64(&data, linkedit.offset + 40)? as usize;
+
+    // Reuse an existing signature slot, otherwise add LC_CODE_SIGNATURE
+    let (code_limit, command_offset) = match image
+        .commands
+        .iter()
+        .find(|c| c.cmd == LC_CODE_SIGNATURE)
+    {
+        Some(command) => (
+            read_u32(&data, command.offset + 8)? as usize,
+            command.offset,
+        ),
+        None => {
+            let end = image.header_size + image.sizeofcmds;
+            if end + 16 > image.load_command_limit(&data) {
+                return Err(invalid(
+                    "not enough header space for LC_CODE_SIGNATURE (link with -headerpad_max_install_names)",
+                ));
+            }
+            let linkedit_end = linkedit_offset + read_u64(&data, linkedit.offset + 48)? as usize;
+            if data.len() > linkedit_end {
+                return Err(invalid("unexpected data after __LINKEDIT"));
+            }
+            write_u32(&mut data, end, LC_CODE_SIGNATURE);
+            write_u32(&mut data, end + 4, 16);
+            write_u32(&mut data, 16, image.commands.len() as u32 + 1);
+            write_u32(&mut data, 20, (image.sizeofcmds + 16) as u32);
+            (linkedit_end.next_multiple_of(16), end)
+        }
+    };
+    data.resize(code_limit, 0);
+
+    let requirements = requirements(options)?;
+    let entitlements = options
+        .entitlements
+        .map(|xml| blob(CSMAGIC_EMBEDDED_ENTITLEMENTS, xml));
+
+    let special_slots = if entitlements.is_some() {
+        CSSLOT_ENTITLEMENTS
+    } else if options.main_binary {
+        CSSLOT_RESOURCEDIR
+    } else {
+        CSSLOT_REQUIREMENTS
+    } as usize;
+    let code_slots = code_limit.div_ceil(PAGE_SIZE);
+    let strings =
+        options.identifier.len() + 1 + options.team_id.as_ref().map_or(0, |t| t.len() + 1);
+    let directory_size =
+        CODEDIRECTORY_HEADER_SIZE + strings + (special_slots + code_slots) * HASH_SIZE;
+    let signature_reserve = 8 + options
+        .identity
+        .map_or(0, |identity| identity.signature_size_estimate());
+    let blob_count = 3 + entitlements.is_some() as usize;
+    let signature_size = (12
+        + 8 * blob_count
+        + directory_size
+        + requirements.len()
+        + entitlements.as_ref().map_or(0, |e| e.len())
+        + signature_reserve)
+        .next_multiple_of(16);
+
+    // Header changes must be in place before the pages are hashed
+    write_u32(&mut data, command_offset + 8, code_limit as u32);
+    write_u32(&mut data, command_offset + 12, signature_size as u32);
+    let linkedit_size = (code_limit + signature_size - linkedit_offset) as u64;
+    let linkedit_vmsize =
+        read_u64(&data, linkedit.offset + 32)?.max(linkedit_size.next_multiple_of(0x4000));
+    write_u64(&mut data, linkedit.offset + 32, linkedit_vmsize);
+    write_u64(&mut data, linkedit.offset + 48, linkedit_size);
+
+    let mut special = vec![[0u8; HASH_SIZE]; special_slots];
+    if let Some(info) = options.info_plist {
+        special[CSSLOT_INFOSLOT as usize - 1] = Sha256::digest(info).into();
+    }
+    special[CSSLOT_REQUIREMENTS as usize - 1] = Sha256::digest(&requirements).into();
+    if let Some(resources) = options.code_resources {
+        special[CSSLOT_RESOURCEDIR as usize - 1] = Sha256::digest(resources).into();
+    }
+    if let Some(entitlements) = &entitlements {
+        special[CSSLOT_ENTITLEMENTS as usize - 1] = Sha256::digest(entitlements).into();
+    }
+
+    let exec_flags = if options.main_binary {
+        CS_EXECSEG_MAIN_BINARY
+    } else {
+        0
+    };
+    let directory = code_directory(
+        options,
+        &data,
+        &special,
+        (exec_base, exec_limit, exec_flags),
+    )?;
+
+    let cms = match options.identity {
+        Some(identity) => {
+            let plist = cdhashes_plist(&directory);
+            let attribute = signing::attribute(
+                APPLE_CDHASHES_OID,
+                Any::encode_from(&OctetString::new(plist.into_bytes())?)?,
+            )?;
+            identity.sign(
+                const_oid::db::rfc5911::ID_DATA,
+                None,
+                Some(&Sha256::digest(&directory)),
+                vec![attribute],
+            )?
+        }
+        None => Vec::new(),
+    };
+    let cms = blob(CSMAGIC_BLOBWRAPPER, &cms);
+    if cms.len() > signature_reserve {
+        return Err(Error::Signing(
+            "CMS signature exceeds the reserved space".to_string(),
+        ));
+    }
+
+    let mut blobs = vec![
+        (CSSLOT_CODEDIRECTORY, directory),
+        (CSSLOT_REQUIREMENTS, requirements),
+    ];
+    if let Some(entitlements) = entitlements {
+        blobs.push((CSSLOT_ENTITLEMENTS, entitlements));
+    }
+    blobs.push((CSSLOT_SIGNATURESLOT, cms));
+
+    let mut superblob = superblob(CSMAGIC_EMBEDDED_SIGNATURE, &blobs);
+    superblob.resize(signature_size, 0);
+    data.extend_from_slice(&superblob);
+
+    Ok(data)
+}
+
+fn code_directory(
+    options: &SignOptions,
+    code: &[u8],
+    special: &[[u8; HASH_SIZE]],
+    (exec_base, exec_limit, exec_flags): (u64, u64, u64),
+) -> Result<Vec<u8>> {
+    let mut flags = 0;
+    if options.identity.is_none() {
+        flags |= CS_ADHOC;
+    }
+    if options.hardened_runtime {
+        flags |= CS_RUNTIME;
+    }
+
+    let ident_offset = CODEDIRECTORY_HEADER_SIZE;
+    let team_offset = ident_offset + options.identifier.len() + 1;
+    let hash_offset = team_offset
+        + options.team_id.as_ref().map_or(0, |t| t.len() + 1)
+        + special.len() * HASH_SIZE;
+    let code_slots = code.len().div_ceil(PAGE_SIZE);
+    let length = hash_offset + code_slots * HASH_SIZE;
+    let code_limit =
+        u32::try_from(code.len()).map_err(|_| invalid("images over 4 GiB are not supported"))?;
+
+    let mut directory = Vec::with_capacity(length);
+    for value in [
+        CSMAGIC_CODEDIRECTORY,
+        length as u32,
+        CODEDIRECTORY_VERSION,
+        flags,
+        hash_offset as u32,
+        ident_offset as u32,
+        special.len() as u32,
+        code_slots as u32,
+        code_limit,
+    ] {
+        directory.extend_from_slice(&value.to_be_bytes());
+    }
+    directory.extend_from_slice(&[HASH_SIZE as u8, CS_HASHTYPE_SHA256, 0, PAGE_SIZE_LOG2]);
+    let team_field = if options.team_id.is_some() {
+        team_offset as u32
+    } else {
+        0
+    };
+    for value in [0u32, 0, team_field, 0] {
+        directory.extend_from_slice(&value.to_be_bytes());
+    }
+    for value in [0u64, exec_base, exec_limit, exec_flags] {
+        directory.extend_from_slice(&value.to_be_bytes());
+    }
+
+    directory.extend_from_slice(options.identifier.as_bytes());
+    directory.push(0);
+    if let Some(team) = &options.team_id {
+        directory.extend_from_slice(team.as_bytes());
+        directory.push(0);
+    }
+
+    // Special slots are stored in reverse order in front of the code slots
+    for hash in special.iter().rev() {
+        directory.extend_from_slice(hash);
+    }
+    for page in code.chunks(PAGE_SIZE) {
+        directory.extend_from_slice(&Sha256::digest(page));
+    }
+
+    Ok(directory)
+}
+
+/// Requirement set: empty for ad hoc signatures, otherwise the designated requirement
+/// `identifier "<id>" and certificate leaf = H"<sha1 of the certificate>"`
+fn requirements(options: &SignOptions) -> Result<Vec<u8>> {
+    let Some(identity) = options.identity else {
+        return Ok(superblob(CSMAGIC_REQUIREMENTS, &[]));
+    };
+
+    let certificate = der::Encode::to_der(&identity.certificate)?;
+    let mut expression = Vec::new();
+    expression.extend_from_slice(&REQUIREMENT_EXPRESSION.to_be_bytes());
+    expression.extend_from_slice(&OP_AND.to_be_bytes());
+    expression.extend_from_slice(&OP_IDENT.to_be_bytes());
+    push_data(&mut expression, options.identifier.as_bytes());
+    expression.extend_from_slice(&OP_ANCHOR_HASH.to_be_bytes());
+    expression.extend_from_slice(&0i32.to_be_bytes());
+    push_data(&mut expression, &Sha1::digest(&certificate));
+
+    let requirement = blob(CSMAGIC_REQUIREMENT, &expression);
+    Ok(superblob(
+        CSMAGIC_REQUIREMENTS,
+        &[(DESIGNATED_REQUIREMENT, requirement)],
+    ))
+}
+
+/// Length-prefixed data padded to 4 bytes, as used by the requirement language
+fn push_data(buffer: &mut Vec<u8>, data: &[u8]) {
+    buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(data);
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+}
+
+fn blob(magic: u32, payload: &[u8]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(8 + payload.len());
+    blob.extend_from_slice(&magic.to_be_bytes());
+    blob.extend_from_slice(&(8 + payload.len() as u32).to_be_bytes());
+    blob.extend_from_slice(payload);
+    blob
+}
+
+fn superblob(magic: u32, blobs: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let header = 12 + 8 * blobs.len();
+    let length = header + blobs.iter().map(|(_, b)| b.len()).sum::<usize>();
+
+    let mut superblob = Vec::with_capacity(length);
+    superblob.extend_from_slice(&magic.to_be_bytes());
+    superblob.extend_from_slice(&(length as u32).to_be_bytes());
+    superblob.extend_from_slice(&(blobs.len() as u32).to_be_bytes());
+    let mut offset = header;
+    for (slot, blob) in blobs {
+        superblob.extend_from_slice(&slot.to_be_bytes());
+        superblob.extend_from_slice(&(offset as u32).to_be_bytes());
+        offset += blob.len();
+    }
+    for (_, blob) in blobs {
+        superblob.extend_from_slice(blob);
+    }
+    superblob
+}
+
+/// Embedded signature superblob of a single-architecture image
+pub fn embedded_signature(slice: &[u8]) -> Result<Option<&[u8]>> {
+    let image = MachO::parse(slice)?;
+    let Some(command) = image.commands.iter().find(|c| c.cmd == LC_CODE_SIGNATURE) else {
+        return Ok(None);
+    };
+    let offset = read_u32(slice, command.offset + 8)? as usize;
+    let size = read_u32(slice, command.offset + 12)? as usize;
+    slice
+        .get(offset..offset + size)
+        .map(Some)
+        .ok_or_else(|| invalid("code signature exceeds the file size"))
+}
+
+/// Blob stored under `slot` in a superblob
+pub fn superblob_entry(superblob: &[u8], slot: u32) -> Option<&[u8]> {
+    let be = |offset: usize| -> Option<usize> {
+        superblob
+            .get(offset..offset + 4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize)
+    };
+    let count = be(8)?;
+    (0..count).find_map(|i| {
+        if be(12 + 8 * i)? as u32 != slot {
+            return None;
+        }
+        let offset = be(16 + 8 * i)?;
+        let length = be(offset + 4)?;
+        superblob.get(offset..offset + length)
+    })
+}
+
+/// cdhash of the first architecture of a signed file
+pub fn cdhash(data: &[u8]) -> Result<Vec<u8>> {
+    let range = macho::slices(data)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("no architectures"))?;
+    let directory = embedded_signature(&data[range])?
+        .and_then(|signature| superblob_entry(signature, CSSLOT_CODEDIRECTORY))
+        .ok_or_else(|| invalid("image is not signed"))?;
+    Ok(Sha256::digest(directory)[..CDHASH_SIZE].to_vec())
+}
+
+fn cdhashes_plist(directory: &[u8]) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n\t<key>cdhashes</key>\n\t<array>\n\t\t<data>{}</data>\n\t</array>\n</dict>\n</plist>\n",
+        BASE64.encode(&Sha256::digest(directory)[..CDHASH_SIZE])
+    )
+}
+
+/// Generate `_CodeSignature/CodeResources` for the bundle contents. Nested code is
+/// recorded by cdhash, all other files by SHA-1 and SHA-256 digests.
+pub fn code_resources(
+    contents: &Path,
+    main_executable: &Path,
+    nested: &BTreeMap<String, Vec<u8>>,
+) -> Result<String> {
+    let mut files = String::new();
+    let mut files2 = String::new();
+
+    let mut entries: Vec<_> = WalkDir::new(contents)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir())
+        .collect();
+    entries.sort_by_key(|e| utils::unix_path(e.path().strip_prefix(contents).unwrap()));
+
+    for entry in entries {
+        let path = entry.path();
+        let key = utils::unix_path(path.strip_prefix(contents).unwrap());
+        if path == main_executable
+            || key.starts_with("_CodeSignature/")
+            || key == "Info.plist"
+            || key == "PkgInfo"
+            || path.file_name().is_some_and(|n| n == ".DS_Store")
+        {
+            continue;
+        }
+        let key_xml = utils::xml_escape(&key);
+
+        if entry.path_is_symlink() {
+            let target = fs::read_link(path)?;
+            files2.push_str(&format!(
+                "\t\t<key>{}</key>\n\t\t<dict>\n\t\t\t<key>symlink</key>\n\t\t\t<string>{}</string>\n\t\t</dict>\n",
+                key_xml,
+                utils::xml_escape(&utils::unix_path(&target))
+            ));
+        } else if let Some(cdhash) = nested.get(&key) {
+            files2.push_str(&format!(
+                "\t\t<key>{}</key>\n\t\t<dict>\n\t\t\t<key>cdhash</key>\n\t\t\t<data>{}</data>\n\t\t\t<key>requirement</key>\n\t\t\t<string>cdhash H\"{}\"</string>\n\t\t</dict>\n",
+                key_xml,
+                BASE64.encode(cdhash),
+                utils::hex(cdhash)
+            ));
+        } else {
+            let data = fs::read(path)?;
+            let sha1 = BASE64.encode(Sha1::digest(&data));
+            let sha256 = BASE64.encode(Sha256::digest(&data));
+            if key.starts_with("Resources/") {
+                files.push_str(&format!(
+                    "\t\t<key>{}</key>\n\t\t<data>{}</data>\n",
+                    key_xml, sha1
+                ));
+            }
+            files2.push_str(&format!(
+                "\t\t<key>{}</key>\n\t\t<dict>\n\t\t\t<key>hash</key>\n\t\t\t<data>{}</data>\n\t\t\t<key>hash2</key>\n\t\t\t<data>{}</data>\n\t\t</dict>\n",
+                key_xml, sha1, sha256
+            ));
+        }
+    }
+
+    Ok(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n\
+         \t<key>files</key>\n\t<dict>\n{}\t</dict>\n\
+         \t<key>files2</key>\n\t<dict>\n{}\t</dict>\n\
+         {}</dict>\n</plist>\n",
+        files, files2, RESOURCE_RULES
+    ))
+}
+
+/// Minimal check that the entitlements are an XML property list with a dictionary
+pub fn validate_entitlements(data: &[u8]) -> std::result::Result<(), String> {
+    let text =
+        std::str::from_utf8(data).map_err(|_| "entitlements must be UTF-8 XML".to_string())?;
+    if !text.contains("<plist") || !text.contains("<dict") {
+        return Err("entitlements must be an XML property list containing a <dict>".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macos::macho::tests::fixture;
+    use crate::signing::tests::{test_identity, verify_signed_data};
+
+    const ENTITLEMENTS: &[u8] = br#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict><key>com.apple.security.cs.allow-jit</key><true/></dict></plist>
+"#;
+
+    fn be32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn be64(data: &[u8], offset: usize) -> u64 {
+        u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// Parse the signature of a thin image back and check every hash
+    fn verify(slice: &[u8], special: &[(u32, &[u8])]) -> Vec<u8> {
+        let image = MachO::parse(slice).unwrap();
+        let command = image
+            .commands
+            .iter()
+            .find(|c| c.cmd == LC_CODE_SIGNATURE)
+            .unwrap();
+        let code_limit = read_u32(slice, command.offset + 8).unwrap() as usize;
+        let linkedit = image.segment(slice, "__LINKEDIT").unwrap();
+        let linkedit_end = read_u64(slice, linkedit.offset + 40).unwrap()
+            + read_u64(slice, linkedit.offset + 48).unwrap();
+        assert_eq!(linkedit_end as usize, slice.len());
+
+        let signature = embedded_signature(slice).unwrap().unwrap();
+        assert_eq!(be32(signature, 0), CSMAGIC_EMBEDDED_SIGNATURE);
+
+        let directory = superblob_entry(signature, CSSLOT_CODEDIRECTORY).unwrap();
+        assert_eq!(be32(directory, 0), CSMAGIC_CODEDIRECTORY);
+        assert_eq!(be32(directory, 8), CODEDIRECTORY_VERSION);
+        assert_eq!(be32(directory, 32) as usize, code_limit);
+        assert_eq!(directory[37], CS_HASHTYPE_SHA256);
+        assert_eq!(directory[39], PAGE_SIZE_LOG2);
+
+        let hash_offset = be32(directory, 16) as usize;
+        let special_slots = be32(directory, 24) as usize;
+        let code_slots = be32(directory, 28) as usize;
+        assert_eq!(code_slots, code_limit.div_ceil(PAGE_SIZE));
+        for (i, page) in slice[..code_limit].chunks(PAGE_SIZE).enumerate() {
+            let at = hash_offset + i * HASH_SIZE;
+            assert_eq!(&directory[at..at + HASH_SIZE], &Sha256::digest(page)[..]);
+        }
+
+        let requirements = superblob_entry(signature, CSSLOT_REQUIREMENTS).unwrap();
+        let mut expected: Vec<(u32, Vec<u8>)> = special
+            .iter()
+            .map(|(slot, data)| (*slot, Sha256::digest(data).to_vec()))
+            .collect();
+        expected.push((CSSLOT_REQUIREMENTS, Sha256::digest(requirements).to_vec()));
+        if let Some(entitlements) = superblob_entry(signature, CSSLOT_ENTITLEMENTS) {
+            assert_eq!(be32(entitlements, 0), CSMAGIC_EMBEDDED_ENTITLEMENTS);
+            expected.push((CSSLOT_ENTITLEMENTS, Sha256::digest(entitlements).to_vec()));
+        }
+        for (slot, hash) in expected {
+            assert!(slot as usize <= special_slots);
+            let at = hash_offset - slot as usize * HASH_SIZE;
+            assert_eq!(
+                &directory[at..at + HASH_SIZE],
+                &hash[..],
+                "special slot {}",
+                slot
+            );
+        }
+
+        directory.to_vec()
+    }
+
+    fn identifier(directory: &[u8]) -> String {
+        let offset = be32(directory, 20) as usize;
+        let end = directory[offset..].iter().position(|b| *b == 0).unwrap();
+        String::from_utf8(directory[offset..offset + end].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_sign_adhoc() {
+        let data = fs::read(fixture("app")).unwrap();
+        let options = SignOptions {
+            identifier: "com.example.app".to_string(),
+            main_binary: true,
+            ..Default::default()
+        };
+        let signed = sign_macho(&data, &options).unwrap();
+
+        let directory = verify(&signed, &[]);
+        assert_eq!(identifier(&directory), "com.example.app");
+        assert_eq!(be32(&directory, 12), CS_ADHOC);
+        assert_eq!(be64(&directory, 80), CS_EXECSEG_MAIN_BINARY);
+
+        let signature = embedded_signature(&signed).unwrap().unwrap();
+        assert_eq!(
+            superblob_entry(signature, CSSLOT_REQUIREMENTS).unwrap(),
+            &superblob(CSMAGIC_REQUIREMENTS, &[])[..]
+        );
+        assert_eq!(
+            superblob_entry(signature, CSSLOT_SIGNATURESLOT).unwrap(),
+            &blob(CSMAGIC_BLOBWRAPPER, &[])[..]
+        );
+
+        // The original content is unchanged apart from the load commands
+        assert_eq!(
+            macho::dylibs(&signed).unwrap(),
+            macho::dylibs(&data).unwrap()
+        );
+        assert_eq!(&signed[0x800..0x1040], &data[0x800..0x1040]);
+
+        // Re-signing reuses the signature slot
+        let resigned = sign_macho(&signed, &options).unwrap();
+        assert_eq!(resigned, signed);
+    }
+
+    #[test]
+    fn test_sign_identity() {
+        let identity = test_identity();
+        let data = fs::read(fixture("libfoo.dylib")).unwrap();
+        let options = SignOptions {
+            identifier: "libfoo".to_string(),
+            identity: Some(&identity),
+            team_id: identity.organizational_unit(),
+            entitlements: Some(ENTITLEMENTS),
+            hardened_runtime: true,
+            ..Default::default()
+        };
+        let signed = sign_macho(&data, &options).unwrap();
+
+        let directory = verify(&signed, &[]);
+        assert_eq!(identifier(&directory), "libfoo");
+        assert_eq!(be32(&directory, 12), CS_RUNTIME);
+        let team_offset = be32(&directory, 48) as usize;
+        assert_eq!(&directory[team_offset..team_offset + 11], b"EMERGETEST\0");
+
+        let signature = embedded_signature(&signed).unwrap().unwrap();
+        let entitlements = superblob_entry(signature, CSSLOT_ENTITLEMENTS).unwrap();
+        assert_eq!(&entitlements[8..], ENTITLEMENTS);
+
+        let requirements = superblob_entry(signature, CSSLOT_REQUIREMENTS).unwrap();
+        assert_eq!(be32(requirements, 8), 1);
+        assert_eq!(be32(requirements, 12), DESIGNATED_REQUIREMENT);
+
+        let wrapper = superblob_entry(signature, CSSLOT_SIGNATURESLOT).unwrap();
+        assert_eq!(be32(wrapper, 0), CSMAGIC_BLOBWRAPPER);
+        let signed_data = verify_signed_data(&wrapper[8..], &identity);
+        let signer = signed_data.signer_infos.0.get(0).unwrap();
+        let digest = signer
+            .signed_attrs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|a| a.oid == const_oid::db::rfc5911::ID_MESSAGE_DIGEST)
+            .unwrap();
+        let digest: OctetString = digest.values.get(0).unwrap().decode_as().unwrap();
+        assert_eq!(digest.as_bytes(), &Sha256::digest(&directory)[..]);
+    }
+
+    #[test]
+    fn test_sign_universal() {
+        let x86_64 = fs::read(fixture("app")).unwrap();
+        let arm64 = fs::read(fixture("app-arm64")).unwrap();
+        let fat = universal::merge(&[x86_64, arm64]).unwrap();
+
+        let signed = sign_macho(
+            &fat,
+            &SignOptions {
+                identifier: "app".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let ranges = macho::slices(&signed).unwrap();
+        assert_eq!(ranges.len(), 2);
+        for range in ranges {
+            verify(&signed[range], &[]);
+        }
+    }
+
+    #[test]
+    fn test_sign_app() {
+        let temp = tempfile::tempdir().unwrap();
+        let app = temp.path().join("Sample.app");
+        let contents = app.join("Contents");
+        fs::create_dir_all(contents.join("MacOS")).unwrap();
+        fs::create_dir_all(contents.join("Frameworks")).unwrap();
+        fs::create_dir_all(contents.join("Resources")).unwrap();
+        fs::copy(fixture("app"), contents.join("MacOS/sample")).unwrap();
+        fs::copy(
+            fixture("libfoo.dylib"),
+            contents.join("Frameworks/libfoo.dylib"),
+        )
+        .unwrap();
+        fs::write(contents.join("Info.plist"), "<plist/>").unwrap();
+        fs::write(contents.join("Resources/icon.icns"), "icon").unwrap();
+
+        let signed = sign_app(
+            &app,
+            "sample",
+            &SignOptions {
+                identifier: "com.sample.sample".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(signed.len(), 2);
+
+        let resources = fs::read(contents.join("_CodeSignature/CodeResources")).unwrap();
+        let text = String::from_utf8(resources.clone()).unwrap();
+        let lib = fs::read(contents.join("Frameworks/libfoo.dylib")).unwrap();
+        let lib_cdhash = cdhash(&lib).unwrap();
+        assert!(text.contains("<key>Frameworks/libfoo.dylib</key>"));
+        assert!(text.contains(&format!("cdhash H\"{}\"", utils::hex(&lib_cdhash))));
+        assert!(text.contains(&format!(
+            "<key>Resources/icon.icns</key>\n\t\t<data>{}</data>",
+            BASE64.encode(Sha1::digest(b"icon"))
+        )));
+        assert!(!text.contains("MacOS/sample"));
+        assert!(!text.contains("<key>Info.plist</key>"));
+
+        let main = fs::read(contents.join("MacOS/sample")).unwrap();
+        let directory = verify(
+            &main,
+            &[
+                (CSSLOT_INFOSLOT, b"<plist/>"),
+                (CSSLOT_RESOURCEDIR, &resources),
+            ],
+        );
+        assert_eq!(identifier(&directory), "com.sample.sample");
+        verify(&lib, &[]);
+    }
+
+    #[test]
+    fn test_validate_entitlements() {
+        assert!(validate_entitlements(ENTITLEMENTS).is_ok());
+        assert!(validate_entitlements(b"com.apple.security.cs.allow-jit = true").is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Portable (odc) cpio archives, the payload format of installer packages
+
+use crate::archive::{Kind, permissions};
+use crate::error::Error;
+use crate::result::Result;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+const MAGIC: &str = "070707";
+const TRAILER: &str = "TRAILER!!!";
+/// Largest value of the 11-digit octal fields (size and mtime)
+const MAX_LARGE: u64 = 0o77777777777;
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// A file system entry below the archive root
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Path in the archive: `.` for the root, else starting with `./`
+    pub path: String,
+    pub source: PathBuf,
+    pub kind: Kind,
+    /// Permission bits
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+impl Entry {
+    /// File type and permission bits
+    pub fn st_mode(&self) -> u32 {
+        let kind = match self.kind {
+            Kind::Directory => S_IFDIR,
+            Kind::File => S_IFREG,
+            Kind::Symlink(_) => S_IFLNK,
+        };
+        kind | self.mode
+    }
+
+    /// Last path component (`.` for the root)
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Path of the parent directory, `None` for the root
+    pub fn parent(&self) -> Option<&str> {
+        self.path.rsplit_once('/').map(|(parent, _)| parent)
+    }
+}
+
+/// Entries of `root` and everything below it, parents first, owned by root:wheel
+pub fn entries(root: &Path) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for item in WalkDir::new(root).sort_by_file_name() {
+        let item = item.map_err(|e| Error::Custom(e.to_string()))?;
+        let relative = item.path().strip_prefix(root).unwrap_or(item.path());
+        let path = relative
+            .components()
+            .fold(".".to_string(), |path, component| {
+                format!("{}/{}", path, component.as_os_str().to_string_lossy())
+            });
+
+        let metadata = item
+            .path()
+            .symlink_metadata()
+            .map_err(|e| Error::Custom(format!("{}: {}", item.path().display(), e)))?;
+        let (kind, size) = if metadata.is_dir() {
+            (Kind::Directory, 0)
+        } else if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(item.path())?
+                .to_string_lossy()
+                .to_string();
+            let size = target.len() as u64;
+            (Kind::Symlink(target), size)
+        } else {
+            (Kind::File, metadata.len())
+        };
+
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+
+        entries.push(Entry {
+            path,
+            source: item.path().to_path_buf(),
+            mode: permissions(&metadata, &kind),
+            kind,
+            uid: 0,
+            gid: 0,
+            size,
+            mtime,
+        });
+    }
+    Ok(entries)
+}
+
+/// Write the entries and the trailer, streaming file contents from their sources
+pub fn write<W: Write>(entries: &[Entry], mut writer: W) -> Result<W> {
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.size > MAX_LARGE {
+            return Err(Error::Custom(format!(
+                "{} is too large for a cpio payload ({} bytes)",
+                entry.source.display(),
+                entry.size
+            )));
+        }
+        let header = Header {
+            ino: index as u64 + 1,
+            mode: entry.st_mode(),
+            uid: entry.uid,
+            gid: entry.gid,
+            nlink: 1,
+            mtime: entry.mtime.min(MAX_LARGE),
+            size: entry.size,
+        };
+        header.write(&mut writer, &entry.path)?;
+
+        match &entry.kind {
+            Kind::Directory => {}
+            Kind::Symlink(target) => writer.write_all(target.as_bytes())?,
+            Kind::File => {
+                let copied = io::copy(&mut File::open(&entry.source)?, &mut writer)?;
+                if copied != entry.size {
+                    return Err(Error::Custom(format!(
+                        "{} changed while it was archived",
+                        entry.source.display()
+                    )));
+                }
+            }
+        }
+    }
+
+    Header::default().write(&mut writer, TRAILER)?;
+    Ok(writer)
+}
+
+#[derive(Default)]
+struct Header {
+    ino: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    mtime: u64,
+    size: u64,
+}
+
+impl Header {
+    fn write<W: Write>(&self, writer: &mut W, name: &str) -> Result<()> {
+        // Six-digit fields wrap like other odc writers; only the inode can get that large
+        write!(
+            writer,
+            "{}{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:011o}{:06o}{:011o}{}\0",
+            MAGIC,
+            0,
+            self.ino & 0o777777,
+            self.mode & 0o777777,
+            self.uid & 0o777777,
+            self.gid & 0o777777,
+            self.nlink,
+            0,
+            self.mtime,
+            name.len() + 1,
+            self.size,
+            name
+        )?;
+        Ok(())
+    }
+}
+
+/// Entries of an odc archive as (path, mode, uid, gid, contents)
+#[cfg(test)]
+pub fn read(mut data: &[u8]) -> Vec<(String, u32, u32, u32, Vec<u8>)> {
+    let field = |header: &[u8], start: usize, len: usize| {
+        let text = std::str::from_utf8(&header[start..start + len]).unwrap();
+        u64::from_str_radix(text, 8).unwrap()
+    };
+
+    let mut entries = Vec::new();
+    loop {
+        assert_eq!(&data[..6], MAGIC.as_bytes());
+        let mode = field(data, 18, 6) as u32;
+        let uid = field(data, 24, 6) as u32;
+        let gid = field(data, 30, 6) as u32;
+        let name_size = field(data, 59, 6) as usize;
+        let size = field(data, 65, 11) as usize;
+        let name = std::str::from_utf8(&data[76..76 + name_size - 1])
+            .unwrap()
+            .to_string();
+        assert_eq!(data[76 + name_size - 1], 0);
+        data = &data[76 + name_size..];
+        if name == TRAILER {
+            assert!(data.is_empty());
+            return entries;
+        }
+        entries.push((name, mode, uid, gid, data[..size].to_vec()));
+        data = &data[size..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("Sample.app/Contents/MacOS")).unwrap();
+        fs::write(root.join("Sample.app/Contents/Info.plist"), "<plist/>").unwrap();
+        fs::write(root.join("Sample.app/Contents/MacOS/sample"), "binary").unwrap();
+
+        let entries = entries(root).unwrap();
+        let paths = entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                ".",
+                "./Sample.app",
+                "./Sample.app/Contents",
+                "./Sample.app/Contents/Info.plist",
+                "./Sample.app/Contents/MacOS",
+                "./Sample.app/Contents/MacOS/sample",
+            ]
+        );
+        assert_eq!(entries[0].parent(), None);
+        assert_eq!(entries[0].name(), ".");
+        assert_eq!(entries[3].parent(), Some("./Sample.app/Contents"));
+        assert_eq!(entries[3].name(), "Info.plist");
+        assert_eq!(entries[3].size, 8);
+        assert_eq!(entries[3].st_mode() & 0o170000, S_IFREG);
+        assert_eq!(entries[4].st_mode() & 0o170000, S_IFDIR);
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data"), "contents").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("data", dir.path().join("link")).unwrap();
+
+        let mut entries = entries(dir.path()).unwrap();
+        entries[1].mode = 0o755;
+        let archive = write(&entries, Vec::new()).unwrap();
+
+        let read = read(&archive);
+        assert_eq!(read[0].0, ".");
+        assert_eq!(read[0].1 & 0o170000, S_IFDIR);
+        assert_eq!(
+            read[1],
+            (
+                "./data".to_string(),
+                S_IFREG | 0o755,
+                0,
+                0,
+                b"contents".to_vec()
+            )
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            read[2],
+            (
+                "./link".to_string(),
+                S_IFLNK | 0o777,
+                0,
+                0,
+                b"data".to_vec()
+            )
+        );
+
+        // Fixed-width header: magic and 70 octal digits
+        assert_eq!(&archive[..6], b"070707");
+        assert_eq!(&archive[76..78], b".\0");
+    }
+}
@@ -1,16 +1,30 @@
 use crate::cmd;
 use crate::context::Context;
 use crate::error::Error;
-use crate::manifest::Manifest;
+use crate::icon::{self, IconSet};
+use crate::layout;
+use crate::macos::dsstore::{self, Layout};
+use crate::macos::{background, codesign, dylibs, sla, universal};
+use crate::manifest::{DmgFilesystem, DmgFormat, Location, Manifest};
 use crate::result::Result;
 use crate::utils;
 use icns::{IconFamily, IconType};
-use image::ImageReader;
 use std::fs;
 use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
 
-pub fn create(ctx: &Context, manifest: &Manifest) -> Result<()> {
-    println!("Creating DMG for macOS...");
+/// Icon file of a volume, shown when the root folder has the custom icon flag
+const VOLUME_ICON: &str = ".VolumeIcon.icns";
+/// Extended attribute holding the classic Finder info
+#[cfg(unix)]
+const FINDER_INFO: &str = "com.apple.FinderInfo";
+/// `kHasCustomIcon` Finder flag
+const HAS_CUSTOM_ICON: u16 = 0x0400;
+/// `LSMinimumSystemVersion` of the app bundle
+pub const MINIMUM_SYSTEM_VERSION: &str = "10.13";
+
+pub fn create(ctx: &Context, manifest: &Manifest) -> Result<PathBuf> {
+    status!("Creating DMG for macOS...");
 
     // Ensure output folder exists
     utils::ensure_dir(&manifest.output_folder)?;
@@ -22,68 +36,125 @@ pub fn create(ctx: &Context, manifest: &Manifest) -> Result<()> {
     }
     fs::create_dir_all(&temp_dir)?;
 
-    // Pre-process icon to ICNS if needed (before any file operations)
-    let processed_icon_path = if let Some(icon_path) = &manifest.icon {
-        if icon_path.exists() {
-            let icon_temp_dir = std::env::temp_dir().join(format!("emerge-icon-{}", manifest.name));
-            if icon_temp_dir.exists() {
-                fs::remove_dir_all(&icon_temp_dir)?;
-            }
-            fs::create_dir_all(&icon_temp_dir)?;
+    // Assemble the signed app bundle; volume files go next to it
+    let app_path = temp_dir.join(format!("{}.app", manifest.title));
+    let icns_path = assemble_app(ctx, manifest, &app_path, &temp_dir)?;
 
-            let processed_icon = icon_temp_dir.join("icon.icns");
+    // Create symbolic link to /Applications
+    let applications_link = temp_dir.join("Applications");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::symlink;
+        symlink("/Applications", &applications_link)?;
+    }
 
-            if icon_path.extension().and_then(|e| e.to_str()) == Some("icns") {
-                fs::copy(icon_path, &processed_icon)?;
-            } else {
-                if ctx.verbose {
-                    println!("Converting icon to ICNS format...");
-                }
-                generate_icns_from_image(icon_path, &processed_icon)?;
-            }
+    // Window layout: background picture and .DS_Store (unless Finder arranges it)
+    let layout = finder_layout(manifest);
+    if let Some(name) = &layout.background
+        && let Some(data) = background::render(ctx, manifest, &layout)?
+    {
+        let background_dir = temp_dir.join(background::DIRECTORY);
+        fs::create_dir_all(&background_dir)?;
+        fs::write(background_dir.join(name), data)?;
+    }
+    let applescript = manifest.dmg.as_ref().is_some_and(|d| d.applescript);
+    if !applescript {
+        fs::write(temp_dir.join(".DS_Store"), dsstore::write(&layout)?)?;
+    }
 
-            Some(processed_icon)
-        } else {
-            None
+    // Additional files and the volume icon go to the volume root
+    let root_files = volume_files(ctx, manifest, icns_path.as_deref());
+    for (src, dst) in &root_files {
+        if !src.exists() {
+            return Err(Error::Custom(format!(
+                "DMG file not found: {}",
+                src.display()
+            )));
         }
-    } else {
+        if ctx.verbose {
+            status!("Copying {} to volume root", src.display());
+        }
+        utils::copy_recursively(src, &temp_dir.join(dst))?;
+    }
+    let volume_icon = root_files
+        .iter()
+        .any(|(_, dst)| dst.as_os_str() == VOLUME_ICON);
+
+    // License texts, converted to RTF
+    let licenses = license_files(ctx, manifest)
+        .iter()
+        .map(|(language, path)| sla::License::from_file(language, path))
+        .collect::<Result<Vec<_>>>()?;
+    let license_resources = if licenses.is_empty() {
         None
+    } else {
+        Some(sla::resources(&licenses)?)
     };
 
-    // Create the .app bundle structure
-    let app_name = format!("{}.app", manifest.title);
-    let app_path = temp_dir.join(&app_name);
-    let (macos_dir, resources_dir) = create_app_bundle_structure(ctx, manifest, &app_path)?;
+    // Create DMG
+    let dmg_filename = format!("{}.dmg", manifest.filename);
+    let dmg_path = manifest.output_folder.join(&dmg_filename);
 
-    // Copy the processed ICNS icon first (if available)
-    if let Some(ref processed_icon) = processed_icon_path {
-        let icon_dest = resources_dir.join("icon.icns");
+    if dmg_path.exists() {
+        fs::remove_file(&dmg_path)?;
+    }
+
+    let options = ImageOptions::from_manifest(manifest, &temp_dir)?;
+    if applescript || volume_icon {
+        let layout = applescript.then_some(&layout);
+        create_dmg_image(ctx, &options, layout, volume_icon, &temp_dir, &dmg_path)?;
+    } else {
+        let args = create_args(&options, &temp_dir, &dmg_path, false);
+        cmd::execute(ctx, "hdiutil", &as_strs(&args))?;
+    }
+
+    // License agreement shown before the image mounts
+    if let Some(resources) = &license_resources {
         if ctx.verbose {
-            println!("Copying processed icon to {}", icon_dest.display());
+            status!("Attaching license agreement ({} languages)", licenses.len());
         }
-        fs::copy(processed_icon, icon_dest)?;
+        sla::attach_file(&dmg_path, resources)?;
     }
 
-    // Copy files according to copy operations
-    // For macOS DMG, files are copied into the .app bundle's MacOS folder
-    // unless they have specific extensions (like .md, .txt, etc.) which go to DMG root
-    for (src, dst) in &manifest.copy_operations {
-        let dst_extension = dst.extension().and_then(|e| e.to_str());
+    // Clean up temp directory
+    fs::remove_dir_all(&temp_dir)?;
 
-        // Determine if file should go to DMG root or app bundle
-        let is_documentation =
-            matches!(dst_extension, Some("md" | "txt" | "pdf" | "html" | "toml"));
+    status!("DMG created successfully: {}", dmg_path.display());
+    Ok(dmg_path)
+}
 
-        let dest_path = if is_documentation {
-            // Documentation files go to DMG root alongside the .app
-            temp_dir.join(dst)
-        } else {
-            // Executable and other files go into the app bundle's MacOS folder
-            macos_dir.join(dst)
-        };
+/// Assemble the signed `.app` bundle at `app_path`. Files of the volume location are
+/// copied to `volume_root`. Returns the ICNS icon of the bundle, if any.
+pub fn assemble_app(
+    ctx: &Context,
+    manifest: &Manifest,
+    app_path: &Path,
+    volume_root: &Path,
+) -> Result<Option<PathBuf>> {
+    // Create the .app bundle structure
+    let (macos_dir, resources_dir) = create_app_bundle_structure(ctx, manifest, app_path)?;
+
+    // Write the ICNS icon first, so that copied files can replace it
+    let icns_path = resources_dir.join("icon.icns");
+    if let Some(icns) = icon::passthrough(manifest, "icns") {
+        fs::copy(icns, &icns_path)?;
+    } else if let Some(set) = IconSet::load(ctx, manifest)? {
+        if ctx.verbose {
+            status!("Converting icon to ICNS format...");
+        }
+        generate_icns(&set, &icns_path)?;
+    }
 
+    // Copy files according to copy operations, into the bundle folder of their location
+    let bundle = layout::Layout::bundle(app_path, volume_root);
+    for placement in bundle.place(manifest, &manifest.copy_operations) {
+        let dest_path = &placement.destination;
         if ctx.verbose {
-            println!("Copying {} to {}", src.display(), dest_path.display());
+            status!(
+                "Copying {} to {}",
+                placement.source.display(),
+                dest_path.display()
+            );
         }
 
         // Ensure parent directory exists
@@ -91,55 +162,31 @@ pub fn create(ctx: &Context, manifest: &Manifest) -> Result<()> {
             fs::create_dir_all(parent)?;
         }
 
-        utils::copy_recursively(src, &dest_path)?;
+        utils::copy_recursively(&placement.source, dest_path)?;
 
         // Set executable permissions for files in MacOS folder
         #[cfg(unix)]
-        if dest_path.starts_with(&macos_dir)
-            && let Ok(metadata) = fs::metadata(&dest_path)
+        if placement.location == Location::Macos
+            && let Ok(metadata) = fs::metadata(dest_path)
             && metadata.is_file()
         {
             use std::os::unix::fs::PermissionsExt;
             let mut perms = metadata.permissions();
             perms.set_mode(0o755);
-            fs::set_permissions(&dest_path, perms)?;
+            fs::set_permissions(dest_path, perms)?;
         }
     }
 
-    // Create symbolic link to /Applications
-    let applications_link = temp_dir.join("Applications");
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::symlink;
-        symlink("/Applications", &applications_link)?;
-    }
-
-    // Create DMG
-    let dmg_filename = format!("{}.dmg", manifest.filename);
-    let dmg_path = manifest.output_folder.join(&dmg_filename);
+    // Merge per-architecture binaries into universal ones
+    universal::install(ctx, manifest, &macos_dir)?;
 
-    if dmg_path.exists() {
-        fs::remove_file(&dmg_path)?;
-    }
+    // Bundle non-system dylibs into Contents/Frameworks (opt-in)
+    dylibs::bundle(ctx, manifest, app_path)?;
 
-    create_dmg_image(
-        ctx,
-        manifest,
-        &temp_dir,
-        &dmg_path,
-        processed_icon_path.as_ref(),
-    )?;
+    // Sign nested binaries and the main executable, seal Contents/
+    codesign::sign_bundle(ctx, manifest, app_path)?;
 
-    // Clean up temp directory
-    fs::remove_dir_all(&temp_dir)?;
-
-    // Clean up icon temp directory if it was created
-    if let Some(ref icon_dir) = processed_icon_path.as_ref().and_then(|p| p.parent()) {
-        let _ = fs::remove_dir_all(icon_dir);
-    }
-
-    println!("DMG created successfully: {}", dmg_path.display());
-    Ok(())
+    Ok(icns_path.exists().then_some(icns_path))
 }
 
 fn create_app_bundle_structure(
@@ -156,14 +203,12 @@ fn create_app_bundle_structure(
     fs::create_dir_all(&resources_dir)?;
 
     if ctx.verbose {
-        println!("Created app bundle structure at {}", app_path.display());
+        status!("Created app bundle structure at {}", app_path.display());
     }
 
     // Create Info.plist
     create_info_plist(manifest, &contents_dir)?;
 
-    // Note: Icon is now pre-processed and copied separately before other file operations
-
     Ok((macos_dir, resources_dir))
 }
 
@@ -178,7 +223,7 @@ fn create_info_plist(manifest: &Manifest, contents_dir: &Path) -> Result<()> {
     <key>CFBundleExecutable</key>
     <string>{}</string>
     <key>CFBundleIdentifier</key>
-    <string>com.{}.{}</string>
+    <string>{}</string>
     <key>CFBundleInfoDictionaryVersion</key>
     <string>6.0</string>
     <key>CFBundleName</key>
@@ -192,7 +237,7 @@ fn create_info_plist(manifest: &Manifest, contents_dir: &Path) -> Result<()> {
     <key>CFBundleVersion</key>
     <string>{}</string>
     <key>LSMinimumSystemVersion</key>
-    <string>10.13</string>
+    <string>{}</string>
     <key>NSHighResolutionCapable</key>
     <true/>
     <key>CFBundleIconFile</key>
@@ -201,12 +246,12 @@ fn create_info_plist(manifest: &Manifest, contents_dir: &Path) -> Result<()> {
 </plist>
 "#,
         manifest.name,
-        manifest.name,
-        manifest.name,
+        bundle_identifier(manifest),
         manifest.title,
         manifest.title,
         manifest.version,
         manifest.version,
+        MINIMUM_SYSTEM_VERSION,
     );
 
     let plist_path = contents_dir.join("Info.plist");
@@ -215,14 +260,223 @@ fn create_info_plist(manifest: &Manifest, contents_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Generate an ICNS file from a source image (PNG, JPEG, etc.)
-/// Supports multiple icon sizes as required by macOS
-fn generate_icns_from_image(source_path: &Path, output_path: &Path) -> Result<()> {
-    // Load the source image
-    let img = ImageReader::open(source_path)?
-        .with_guessed_format()?
-        .decode()?;
+/// Finder window layout from the `dmg` configuration
+pub fn finder_layout(manifest: &Manifest) -> Layout {
+    let dmg = manifest.dmg.as_ref();
+
+    let mut items = vec![
+        (
+            format!("{}.app", manifest.title),
+            dmg.and_then(|d| d.app_position).unwrap_or((150, 200)),
+        ),
+        (
+            "Applications".to_string(),
+            dmg.and_then(|d| d.applications_position)
+                .unwrap_or((450, 200)),
+        ),
+    ];
+    for file in dmg
+        .map(|d| d.additional_files.as_slice())
+        .unwrap_or_default()
+    {
+        if let Some(name) = Path::new(&file.source).file_name() {
+            items.push((name.to_string_lossy().to_string(), file.position));
+        }
+    }
+
+    Layout {
+        volume_name: volume_name(manifest),
+        window_position: dmg.and_then(|d| d.window_position).unwrap_or((100, 100)),
+        window_size: dmg.and_then(|d| d.window_size).unwrap_or((600, 400)),
+        icon_size: 72,
+        text_size: 12,
+        background: background::file_name(manifest),
+        items,
+    }
+}
+
+/// Volume name from `dmg.volume_name`, defaulting to the title
+pub fn volume_name(manifest: &Manifest) -> String {
+    manifest
+        .dmg
+        .as_ref()
+        .and_then(|d| d.volume_name.clone())
+        .unwrap_or_else(|| manifest.title.clone())
+}
+
+/// hdiutil settings resolved from the `dmg` configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageOptions {
+    pub volume_name: String,
+    pub filesystem: DmgFilesystem,
+    pub format: DmgFormat,
+    pub compression_level: Option<u32>,
+    /// `-size` argument; hdiutil sizes the image to the contents if unset
+    pub size: Option<String>,
+}
+
+impl ImageOptions {
+    /// Validate the configuration; `headroom` is added to the size of `source_dir`
+    pub fn from_manifest(manifest: &Manifest, source_dir: &Path) -> Result<Self> {
+        let dmg = manifest.dmg.as_ref();
+        let format = dmg.map(|d| d.format).unwrap_or_default();
+
+        let compression_level = dmg.and_then(|d| d.compression_level);
+        if let Some(level) = compression_level {
+            if format != DmgFormat::Udzo {
+                return Err(Error::Custom(format!(
+                    "dmg.compression_level is only supported by the UDZO format, not {}",
+                    format.as_str()
+                )));
+            }
+            if !(1..=9).contains(&level) {
+                return Err(Error::Custom(format!(
+                    "dmg.compression_level must be between 1 and 9, got {}",
+                    level
+                )));
+            }
+        }
+
+        let size = match (
+            dmg.and_then(|d| d.size.clone()),
+            dmg.and_then(|d| d.headroom),
+        ) {
+            (Some(_), Some(_)) => {
+                return Err(Error::Custom(
+                    "Set either dmg.size or dmg.headroom, not both".to_string(),
+                ));
+            }
+            (Some(size), None) => Some(size),
+            (None, Some(headroom)) => {
+                let megabytes = directory_size(source_dir)?.div_ceil(1024 * 1024);
+                Some(format!("{}m", megabytes + headroom))
+            }
+            (None, None) => None,
+        };
+
+        Ok(Self {
+            volume_name: volume_name(manifest),
+            filesystem: dmg.map(|d| d.filesystem).unwrap_or_default(),
+            format,
+            compression_level,
+            size,
+        })
+    }
+}
+
+/// Arguments of `hdiutil create`: a writable image for customization or the final image
+pub fn create_args(
+    options: &ImageOptions,
+    source_dir: &Path,
+    output: &Path,
+    writable: bool,
+) -> Vec<String> {
+    let mut args = vec![
+        "create".to_string(),
+        "-srcfolder".to_string(),
+        source_dir.to_string_lossy().to_string(),
+        "-volname".to_string(),
+        options.volume_name.clone(),
+        "-fs".to_string(),
+        options.filesystem.as_str().to_string(),
+    ];
+    if options.filesystem == DmgFilesystem::HfsPlus {
+        // Catalog, attribute and extents file clump sizes suited to small volumes
+        args.extend(["-fsargs".to_string(), "-c c=64,a=16,e=16".to_string()]);
+    }
+    if let Some(size) = &options.size {
+        args.extend(["-size".to_string(), size.clone()]);
+    }
+    if writable {
+        args.extend(["-format".to_string(), "UDRW".to_string()]);
+    } else {
+        args.extend(format_args(options));
+    }
+    args.push(output.to_string_lossy().to_string());
+    args
+}
+
+/// Arguments of `hdiutil convert` from the writable image to the final format
+pub fn convert_args(options: &ImageOptions, input: &Path, output: &Path) -> Vec<String> {
+    let mut args = vec!["convert".to_string(), input.to_string_lossy().to_string()];
+    args.extend(format_args(options));
+    args.extend(["-o".to_string(), output.to_string_lossy().to_string()]);
+    args
+}
+
+fn format_args(options: &ImageOptions) -> Vec<String> {
+    let mut args = vec!["-format".to_string(), options.format.as_str().to_string()];
+    if options.format == DmgFormat::Udzo {
+        let level = options.compression_level.unwrap_or(9);
+        args.extend(["-imagekey".to_string(), format!("zlib-level={}", level)]);
+    }
+    args
+}
+
+fn as_strs(args: &[String]) -> Vec<&str> {
+    args.iter().map(|a| a.as_str()).collect()
+}
+
+/// Total size of the files below `path`
+fn directory_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in WalkDir::new(path) {
+        let entry = entry.map_err(|e| Error::Custom(e.to_string()))?;
+        if entry.file_type().is_file() {
+            size += entry
+                .metadata()
+                .map_err(|e| Error::Custom(e.to_string()))?
+                .len();
+        }
+    }
+    Ok(size)
+}
+
+/// License files of `dmg.license` by language code, relative to the manifest directory
+pub fn license_files(ctx: &Context, manifest: &Manifest) -> Vec<(String, PathBuf)> {
+    manifest
+        .dmg
+        .iter()
+        .flat_map(|dmg| &dmg.license)
+        .map(|(language, path)| (language.clone(), ctx.base_dir.join(path)))
+        .collect()
+}
 
+/// Files copied to the volume root besides the app bundle and the Applications link:
+/// `dmg.additional_files` and the volume icon (unless `dmg.volume_icon = false`)
+pub fn volume_files(
+    ctx: &Context,
+    manifest: &Manifest,
+    icon: Option<&Path>,
+) -> Vec<(PathBuf, PathBuf)> {
+    let dmg = manifest.dmg.as_ref();
+
+    let mut files = dmg
+        .map(|d| d.additional_files.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|file| {
+            let name = Path::new(&file.source).file_name()?;
+            Some((ctx.base_dir.join(&file.source), PathBuf::from(name)))
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(icon) = icon
+        && dmg.is_none_or(|d| d.volume_icon)
+    {
+        files.push((icon.to_path_buf(), PathBuf::from(VOLUME_ICON)));
+    }
+
+    files
+}
+
+/// `CFBundleIdentifier` of the app bundle, also used as the code signing identifier
+pub fn bundle_identifier(manifest: &Manifest) -> String {
+    format!("com.{}.{}", manifest.name, manifest.name)
+}
+
+/// Write an ICNS file with every standard size and its @2x variant
+fn generate_icns(icon_set: &IconSet, output_path: &Path) -> Result<()> {
     // Create a new IconFamily
     let mut icon_family = IconFamily::new();
 
@@ -242,12 +496,7 @@ fn generate_icns_from_image(source_path: &Path, output_path: &Path) -> Result<()
     ];
 
     for (icon_type, size) in icon_types {
-        // Resize the image
-        let resized = img.resize_exact(size, size, image::imageops::FilterType::Lanczos3);
-
-        // Convert to RGBA8
-        let rgba = resized.to_rgba8();
-        let raw_data = rgba.into_raw();
+        let raw_data = icon_set.image(size)?.into_raw();
 
         // Create ICNS image using the encode method
         let icns_image = icns::Image::from_data(icns::PixelFormat::RGBA, size, size, raw_data)?;
@@ -263,42 +512,29 @@ fn generate_icns_from_image(source_path: &Path, output_path: &Path) -> Result<()
     Ok(())
 }
 
+/// Create a writable image and mount it to set the volume icon flag and/or arrange
+/// the window with Finder, then compress it
 fn create_dmg_image(
     ctx: &Context,
-    manifest: &Manifest,
+    options: &ImageOptions,
+    finder_layout: Option<&Layout>,
+    volume_icon: bool,
     source_dir: &Path,
     output_path: &Path,
-    processed_icon: Option<&PathBuf>,
 ) -> Result<()> {
     // Create initial DMG using hdiutil
     let temp_dmg = output_path.with_extension("temp.dmg");
 
     if ctx.verbose {
-        println!("Creating temporary DMG...");
+        status!("Creating temporary DMG...");
     }
 
-    cmd::execute(
-        ctx,
-        "hdiutil",
-        &[
-            "create",
-            "-srcfolder",
-            source_dir.to_str().unwrap(),
-            "-volname",
-            &manifest.title,
-            "-fs",
-            "HFS+",
-            "-fsargs",
-            "-c c=64,a=16,e=16",
-            "-format",
-            "UDRW",
-            temp_dmg.to_str().unwrap(),
-        ],
-    )?;
+    let args = create_args(options, source_dir, &temp_dmg, true);
+    cmd::execute(ctx, "hdiutil", &as_strs(&args))?;
 
     // Mount the DMG
     if ctx.verbose {
-        println!("Mounting DMG for customization...");
+        status!("Mounting DMG for customization...");
     }
 
     let mount_output = cmd::execute_with_output(
@@ -328,21 +564,24 @@ fn create_dmg_image(
         })?;
 
     if ctx.verbose {
-        println!("Mounted at: {}", mount_point);
+        status!("Mounted at: {}", mount_point);
     }
 
-    // Customize DMG appearance
-    customize_dmg_appearance(ctx, manifest, mount_point)?;
+    // The root folder of the volume only shows .VolumeIcon.icns with the custom icon flag
+    if volume_icon {
+        set_custom_icon_flag(Path::new(mount_point))?;
+    }
 
-    // Note: Icon is already copied to the .app bundle's Resources folder
-    // Volume icon for DMG itself is optional and not set here to avoid space issues
-    let _ = processed_icon; // Suppress unused variable warning
+    // Customize DMG appearance
+    if let Some(layout) = finder_layout {
+        customize_dmg_appearance(ctx, layout, mount_point)?;
+    }
 
     // Sync to ensure all data is flushed to disk before unmounting
     // This is critical to prevent corruption and ensure the DMG is properly unmountable
     // Reference: cargo-nw dmg.rs implementation
     if ctx.verbose {
-        println!("Syncing filesystem...");
+        status!("Syncing filesystem...");
     }
     cmd::execute(ctx, "sync", &[])?;
 
@@ -351,30 +590,18 @@ fn create_dmg_image(
 
     // Detach the DMG
     if ctx.verbose {
-        println!("Detaching DMG...");
+        status!("Detaching DMG...");
     }
 
     cmd::execute(ctx, "hdiutil", &["detach", mount_point])?;
 
     // Convert to compressed read-only DMG
     if ctx.verbose {
-        println!("Compressing DMG...");
+        status!("Compressing DMG...");
     }
 
-    cmd::execute(
-        ctx,
-        "hdiutil",
-        &[
-            "convert",
-            temp_dmg.to_str().unwrap(),
-            "-format",
-            "UDZO",
-            "-imagekey",
-            "zlib-level=9",
-            "-o",
-            output_path.to_str().unwrap(),
-        ],
-    )?;
+    let args = convert_args(options, &temp_dmg, output_path);
+    cmd::execute(ctx, "hdiutil", &as_strs(&args))?;
 
     // Remove temporary DMG
     fs::remove_file(temp_dmg)?;
@@ -382,49 +609,39 @@ fn create_dmg_image(
     Ok(())
 }
 
-fn customize_dmg_appearance(ctx: &Context, manifest: &Manifest, mount_point: &str) -> Result<()> {
-    let mount_path = Path::new(mount_point);
-
-    // Get DMG configuration or use defaults
-    let window_pos = manifest
-        .dmg
-        .as_ref()
-        .and_then(|d| d.window_position)
-        .unwrap_or((100, 100));
+/// Set `kHasCustomIcon` in the Finder flags of a folder
+#[cfg(unix)]
+fn set_custom_icon_flag(path: &Path) -> Result<()> {
+    let info = xattr::get(path, FINDER_INFO)?.unwrap_or_default();
+    xattr::set(path, FINDER_INFO, &with_custom_icon(info))?;
+    Ok(())
+}
 
-    let window_size = manifest
-        .dmg
-        .as_ref()
-        .and_then(|d| d.window_size)
-        .unwrap_or((600, 400));
+#[cfg(not(unix))]
+fn set_custom_icon_flag(_path: &Path) -> Result<()> {
+    Ok(())
+}
 
-    let app_pos = manifest
-        .dmg
-        .as_ref()
-        .and_then(|d| d.app_position)
-        .unwrap_or((150, 200));
+/// FinderInfo (32 bytes) with `kHasCustomIcon` set in the Finder flags at offset 8
+fn with_custom_icon(mut info: Vec<u8>) -> Vec<u8> {
+    info.resize(32, 0);
+    info[8] |= (HAS_CUSTOM_ICON >> 8) as u8;
+    info
+}
 
-    let apps_pos = manifest
-        .dmg
-        .as_ref()
-        .and_then(|d| d.applications_position)
-        .unwrap_or((450, 200));
+fn customize_dmg_appearance(ctx: &Context, layout: &Layout, mount_point: &str) -> Result<()> {
+    let mount_path = Path::new(mount_point);
+    let (x, y) = layout.window_position;
+    let (width, height) = layout.window_size;
 
-    // Copy background image if provided
-    if let Some(dmg_config) = &manifest.dmg
-        && let Some(bg_path) = &dmg_config.background
-    {
-        let bg_src = ctx.base_dir.join(bg_path);
-        if bg_src.exists() {
-            let background_dir = mount_path.join(".background");
-            fs::create_dir_all(&background_dir)?;
-            let bg_dst = background_dir.join("background.png");
-            fs::copy(&bg_src, &bg_dst)?;
-        }
-    }
+    let positions = layout
+        .items
+        .iter()
+        .map(|(name, (x, y))| format!("set position of item \"{}\" to {{{}, {}}}", name, x, y))
+        .collect::<Vec<_>>()
+        .join("\n                ");
 
     // Create AppleScript to set window properties
-    let app_name = format!("{}.app", manifest.title);
     let applescript = format!(
         r#"
         tell application "Finder"
@@ -436,10 +653,9 @@ fn customize_dmg_appearance(ctx: &Context, manifest: &Manifest, mount_point: &st
                 set the bounds of container window to {{{}, {}, {}, {}}}
                 set viewOptions to the icon view options of container window
                 set arrangement of viewOptions to not arranged
-                set icon size of viewOptions to 72
+                set icon size of viewOptions to {}
+                {}
                 {}
-                set position of item "{}" to {{{}, {}}}
-                set position of item "Applications" to {{{}, {}}}
                 close
                 open
                 update without registering applications
@@ -447,26 +663,20 @@ fn customize_dmg_appearance(ctx: &Context, manifest: &Manifest, mount_point: &st
             end tell
         end tell
     "#,
-        manifest.title,
-        window_pos.0,
-        window_pos.1,
-        window_pos.0 + window_size.0,
-        window_pos.1 + window_size.1,
-        if manifest
-            .dmg
-            .as_ref()
-            .and_then(|d| d.background.as_ref())
-            .is_some()
-        {
-            "set background picture of viewOptions to file \".background:background.png\""
-        } else {
-            ""
+        layout.volume_name,
+        x,
+        y,
+        x + width,
+        y + height,
+        layout.icon_size,
+        match &layout.background {
+            Some(background) => format!(
+                "set background picture of viewOptions to file \".background:{}\"",
+                background
+            ),
+            None => String::new(),
         },
-        app_name,
-        app_pos.0,
-        app_pos.1,
-        apps_pos.0,
-        apps_pos.1,
+        positions,
     );
 
     // Execute AppleScript
@@ -480,3 +690,204 @@ fn customize_dmg_appearance(ctx: &Context, manifest: &Manifest, mount_point: &st
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::tests::sample_manifest;
+
+    const DMG_CONFIG: &str = r#"
+        title = "Sample App"
+
+        [dmg]
+        background = "assets/background.jpg"
+        window_position = [200, 120]
+        window_size = [640, 480]
+        app_position = [160, 220]
+        additional_files = [
+            { source = "docs/README.md", position = [320, 380] },
+            { source = "LICENSE", position = [480, 380] },
+        ]
+    "#;
+
+    #[test]
+    fn test_finder_layout() {
+        let (_, manifest) = sample_manifest(DMG_CONFIG);
+        let layout = finder_layout(&manifest);
+
+        assert_eq!(layout.volume_name, "Sample App");
+        assert_eq!(layout.window_position, (200, 120));
+        assert_eq!(layout.window_size, (640, 480));
+        assert_eq!(layout.background.as_deref(), Some("background.jpg"));
+        assert_eq!(
+            layout.items,
+            vec![
+                ("Sample App.app".to_string(), (160, 220)),
+                ("Applications".to_string(), (450, 200)),
+                ("README.md".to_string(), (320, 380)),
+                ("LICENSE".to_string(), (480, 380)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_volume_files() {
+        let (ctx, manifest) = sample_manifest(DMG_CONFIG);
+        let icon = Path::new("/tmp/icon.icns");
+
+        assert_eq!(
+            volume_files(&ctx, &manifest, Some(icon)),
+            vec![
+                (
+                    PathBuf::from("/work/docs/README.md"),
+                    PathBuf::from("README.md")
+                ),
+                (PathBuf::from("/work/LICENSE"), PathBuf::from("LICENSE")),
+                (icon.to_path_buf(), PathBuf::from(VOLUME_ICON)),
+            ]
+        );
+        assert_eq!(volume_files(&ctx, &manifest, None).len(), 2);
+
+        let (ctx, manifest) = sample_manifest("[dmg]\nvolume_icon = false");
+        assert!(volume_files(&ctx, &manifest, Some(icon)).is_empty());
+
+        // Without a [dmg] section the volume icon is still installed
+        let (ctx, manifest) = sample_manifest("");
+        assert_eq!(volume_files(&ctx, &manifest, Some(icon)).len(), 1);
+    }
+
+    #[test]
+    fn test_license_files() {
+        let (ctx, manifest) =
+            sample_manifest("[dmg.license]\nen = \"LICENSE.md\"\nde = \"legal/LIZENZ.rtf\"");
+        assert_eq!(
+            license_files(&ctx, &manifest),
+            vec![
+                ("de".to_string(), PathBuf::from("/work/legal/LIZENZ.rtf")),
+                ("en".to_string(), PathBuf::from("/work/LICENSE.md")),
+            ]
+        );
+        assert!(license_files(&ctx, &sample_manifest("").1).is_empty());
+    }
+
+    fn image_options(emerge: &str) -> Result<ImageOptions> {
+        let (_, manifest) = sample_manifest(emerge);
+        ImageOptions::from_manifest(&manifest, Path::new("/nonexistent"))
+    }
+
+    #[test]
+    fn test_default_hdiutil_args() {
+        let options = image_options("title = \"Sample App\"").unwrap();
+        let source = Path::new("/tmp/src");
+
+        assert_eq!(
+            create_args(&options, source, Path::new("/out/sample.dmg"), false),
+            [
+                "create",
+                "-srcfolder",
+                "/tmp/src",
+                "-volname",
+                "Sample App",
+                "-fs",
+                "HFS+",
+                "-fsargs",
+                "-c c=64,a=16,e=16",
+                "-format",
+                "UDZO",
+                "-imagekey",
+                "zlib-level=9",
+                "/out/sample.dmg"
+            ]
+        );
+        assert_eq!(
+            create_args(&options, source, Path::new("/out/sample.temp.dmg"), true)[9..],
+            ["-format", "UDRW", "/out/sample.temp.dmg"]
+        );
+        assert_eq!(
+            convert_args(
+                &options,
+                Path::new("/out/sample.temp.dmg"),
+                Path::new("/out/sample.dmg")
+            ),
+            [
+                "convert",
+                "/out/sample.temp.dmg",
+                "-format",
+                "UDZO",
+                "-imagekey",
+                "zlib-level=9",
+                "-o",
+                "/out/sample.dmg"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_configured_hdiutil_args() {
+        let options = image_options(
+            r#"
+            [dmg]
+            volume_name = "Sample $VERSION"
+            filesystem = "APFS"
+            format = "ULMO"
+            size = "200m"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            create_args(&options, Path::new("/src"), Path::new("/out.dmg"), false),
+            [
+                "create",
+                "-srcfolder",
+                "/src",
+                "-volname",
+                "Sample 1.2.3",
+                "-fs",
+                "APFS",
+                "-size",
+                "200m",
+                "-format",
+                "ULMO",
+                "/out.dmg"
+            ]
+        );
+
+        let options = image_options("[dmg]\ncompression_level = 5").unwrap();
+        assert_eq!(
+            convert_args(&options, Path::new("/in.dmg"), Path::new("/out.dmg"))[2..6],
+            ["-format", "UDZO", "-imagekey", "zlib-level=5"]
+        );
+    }
+
+    #[test]
+    fn test_image_options_validation() {
+        assert!(image_options("[dmg]\nformat = \"ULFO\"\ncompression_level = 5").is_err());
+        assert!(image_options("[dmg]\ncompression_level = 10").is_err());
+        assert!(image_options("[dmg]\nsize = \"1g\"\nheadroom = 20").is_err());
+    }
+
+    #[test]
+    fn test_headroom() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("payload"), vec![0u8; 3 * 1024 * 1024 + 1]).unwrap();
+
+        let (_, manifest) = sample_manifest("[dmg]\nheadroom = 20");
+        let options = ImageOptions::from_manifest(&manifest, dir.path()).unwrap();
+        assert_eq!(options.size.as_deref(), Some("24m"));
+    }
+
+    #[test]
+    fn test_custom_icon_flag() {
+        let info = with_custom_icon(Vec::new());
+        assert_eq!(info.len(), 32);
+        assert_eq!(&info[8..10], &[0x04, 0x00]);
+
+        // Existing Finder info is preserved
+        let mut existing = vec![0u8; 32];
+        existing[0] = 0x11;
+        existing[8] = 0x40;
+        let info = with_custom_icon(existing);
+        assert_eq!((info[0], info[8]), (0x11, 0x44));
+    }
+}
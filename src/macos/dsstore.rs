@@ -0,0 +1,653 @@
+//! `.DS_Store` writer for the Finder window of a disk image.
+//!
+//! The file is a buddy-allocated block store holding a B-tree of records keyed by file name
+//! and a four character code. Only the records Finder needs to lay out a DMG window are written.
+
+use crate::error::Error;
+use crate::macos::bplist;
+use crate::result::Result;
+use std::cmp::Ordering;
+
+/// B-tree page size
+const PAGE_SIZE: usize = 0x1000;
+/// Size of the allocator info block
+const INFO_BLOCK_SIZE: u32 = 0x800;
+/// Unknown header bytes, as written by Finder
+const HEADER_TRAILER: [u8; 16] = [
+    0x00, 0x00, 0x10, 0x0c, 0x00, 0x00, 0x00, 0x87, 0x00, 0x00, 0x20, 0x0b, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Finder window layout of a volume
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub volume_name: String,
+    /// Top-left corner of the window on screen
+    pub window_position: (i32, i32),
+    pub window_size: (i32, i32),
+    pub icon_size: u32,
+    pub text_size: u32,
+    /// Background picture, a file name inside `.background/`
+    pub background: Option<String>,
+    /// Item names with the position of their icon centre
+    pub items: Vec<(String, (i32, i32))>,
+}
+
+/// Record value types
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Long(u32),
+    Type([u8; 4]),
+    Blob(Vec<u8>),
+}
+
+/// A `.DS_Store` entry: file name, property code and value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub code: [u8; 4],
+    pub value: Value,
+}
+
+/// Encode the `.DS_Store` of a volume root
+pub fn write(layout: &Layout) -> Result<Vec<u8>> {
+    encode(records(layout))
+}
+
+/// Records describing the window of `.` and the position of each item
+pub fn records(layout: &Layout) -> Vec<Record> {
+    let (x, y) = layout.window_position;
+    let (width, height) = layout.window_size;
+
+    let window = bplist::Value::Dict(vec![
+        (
+            "WindowBounds".to_string(),
+            bplist::Value::String(format!("{{{{{}, {}}}, {{{}, {}}}}}", x, y, width, height)),
+        ),
+        ("ShowSidebar".to_string(), bplist::Value::Bool(false)),
+        (
+            "ContainerShowSidebar".to_string(),
+            bplist::Value::Bool(false),
+        ),
+        ("SidebarWidth".to_string(), bplist::Value::Integer(0)),
+        ("ShowToolbar".to_string(), bplist::Value::Bool(false)),
+        ("ShowTabView".to_string(), bplist::Value::Bool(false)),
+        ("ShowPathbar".to_string(), bplist::Value::Bool(false)),
+        ("ShowStatusBar".to_string(), bplist::Value::Bool(false)),
+        (
+            "PreviewPaneVisibility".to_string(),
+            bplist::Value::Bool(false),
+        ),
+    ]);
+
+    let mut view = vec![
+        ("viewOptionsVersion".to_string(), bplist::Value::Integer(1)),
+        (
+            "backgroundType".to_string(),
+            bplist::Value::Integer(if layout.background.is_some() { 2 } else { 0 }),
+        ),
+        ("backgroundColorRed".to_string(), bplist::Value::Real(1.0)),
+        ("backgroundColorGreen".to_string(), bplist::Value::Real(1.0)),
+        ("backgroundColorBlue".to_string(), bplist::Value::Real(1.0)),
+        ("gridOffsetX".to_string(), bplist::Value::Real(0.0)),
+        ("gridOffsetY".to_string(), bplist::Value::Real(0.0)),
+        ("gridSpacing".to_string(), bplist::Value::Real(100.0)),
+        (
+            "arrangeBy".to_string(),
+            bplist::Value::String("none".to_string()),
+        ),
+        ("showIconPreview".to_string(), bplist::Value::Bool(true)),
+        ("showItemInfo".to_string(), bplist::Value::Bool(false)),
+        ("labelOnBottom".to_string(), bplist::Value::Bool(true)),
+        (
+            "textSize".to_string(),
+            bplist::Value::Real(layout.text_size as f64),
+        ),
+        (
+            "iconSize".to_string(),
+            bplist::Value::Real(layout.icon_size as f64),
+        ),
+        ("scrollPositionX".to_string(), bplist::Value::Real(0.0)),
+        ("scrollPositionY".to_string(), bplist::Value::Real(0.0)),
+    ];
+    if let Some(background) = &layout.background {
+        view.push((
+            "backgroundImageAlias".to_string(),
+            bplist::Value::Data(alias(&layout.volume_name, &[".background", background])),
+        ));
+    }
+
+    let mut records = vec![
+        record(".", b"bwsp", Value::Blob(bplist::write(&window))),
+        record(
+            ".",
+            b"icvp",
+            Value::Blob(bplist::write(&bplist::Value::Dict(view))),
+        ),
+        record(".", b"vSrn", Value::Long(1)),
+        record(".", b"vstl", Value::Type(*b"icnv")),
+    ];
+
+    for (name, (x, y)) in &layout.items {
+        // Icon location: x, y and six bytes of 0xff followed by two zero bytes
+        let mut location = Vec::with_capacity(16);
+        location.extend_from_slice(&(*x as u32).to_be_bytes());
+        location.extend_from_slice(&(*y as u32).to_be_bytes());
+        location.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00]);
+        records.push(record(name, b"Iloc", Value::Blob(location)));
+    }
+
+    records
+}
+
+fn record(name: &str, code: &[u8; 4], value: Value) -> Record {
+    Record {
+        name: name.to_string(),
+        code: *code,
+        value,
+    }
+}
+
+/// Finder orders records by case-insensitive file name, then by code
+fn compare(a: &Record, b: &Record) -> Ordering {
+    let key = |name: &str| name.to_lowercase().encode_utf16().collect::<Vec<_>>();
+    key(&a.name)
+        .cmp(&key(&b.name))
+        .then_with(|| a.code.cmp(&b.code))
+}
+
+/// Serialize records into a complete `.DS_Store` file
+pub fn encode(mut records: Vec<Record>) -> Result<Vec<u8>> {
+    records.sort_by(compare);
+    let (nodes, levels) = tree(&records)?;
+
+    let mut allocator = Allocator::new();
+    // The file header occupies the first block, which is not listed in the block table
+    allocator.allocate(32)?;
+    let info_offset = allocator.allocate(INFO_BLOCK_SIZE)?;
+    let header_offset = allocator.allocate(32)?;
+    let node_offsets = nodes
+        .iter()
+        .map(|_| allocator.allocate(PAGE_SIZE as u32))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Block 0 is the allocator info, block 1 the B-tree header, then the nodes (root last)
+    let mut addresses = vec![
+        address(info_offset, INFO_BLOCK_SIZE),
+        address(header_offset, 32),
+    ];
+    addresses.extend(
+        node_offsets
+            .iter()
+            .map(|offset| address(*offset, PAGE_SIZE as u32)),
+    );
+
+    let root = (addresses.len() - 1) as u32;
+    let mut btree_header = Vec::with_capacity(20);
+    for value in [
+        root,
+        levels,
+        records.len() as u32,
+        nodes.len() as u32,
+        PAGE_SIZE as u32,
+    ] {
+        btree_header.extend_from_slice(&value.to_be_bytes());
+    }
+
+    let mut info = Vec::with_capacity(INFO_BLOCK_SIZE as usize);
+    info.extend_from_slice(&(addresses.len() as u32).to_be_bytes());
+    info.extend_from_slice(&0u32.to_be_bytes());
+    for index in 0..addresses.len().next_multiple_of(256) {
+        let address = addresses.get(index).copied().unwrap_or(0);
+        info.extend_from_slice(&address.to_be_bytes());
+    }
+    // Table of contents: the B-tree header is block 1
+    info.extend_from_slice(&1u32.to_be_bytes());
+    info.push(4);
+    info.extend_from_slice(b"DSDB");
+    info.extend_from_slice(&1u32.to_be_bytes());
+    for list in &allocator.free {
+        info.extend_from_slice(&(list.len() as u32).to_be_bytes());
+        for offset in list {
+            info.extend_from_slice(&offset.to_be_bytes());
+        }
+    }
+    if info.len() > INFO_BLOCK_SIZE as usize {
+        return Err(Error::Custom(
+            ".DS_Store allocator info exceeds its block".to_string(),
+        ));
+    }
+
+    let end = node_offsets
+        .iter()
+        .map(|offset| *offset as usize + PAGE_SIZE)
+        .chain([(info_offset + INFO_BLOCK_SIZE) as usize])
+        .max()
+        .unwrap_or_default();
+    let mut output = vec![0u8; 4 + end];
+
+    output[..4].copy_from_slice(&1u32.to_be_bytes());
+    output[4..8].copy_from_slice(b"Bud1");
+    output[8..12].copy_from_slice(&info_offset.to_be_bytes());
+    output[12..16].copy_from_slice(&INFO_BLOCK_SIZE.to_be_bytes());
+    output[16..20].copy_from_slice(&info_offset.to_be_bytes());
+    output[20..36].copy_from_slice(&HEADER_TRAILER);
+
+    let mut put = |offset: u32, data: &[u8]| {
+        let start = 4 + offset as usize;
+        output[start..start + data.len()].copy_from_slice(data);
+    };
+    put(info_offset, &info);
+    put(header_offset, &btree_header);
+    for (index, node) in nodes.iter().enumerate() {
+        put(node_offsets[index], &node.encode(2));
+    }
+
+    Ok(output)
+}
+
+/// A B-tree node; internal nodes pair each separator record with the child before it
+struct Node {
+    records: Vec<Vec<u8>>,
+    /// Block numbers relative to the first node, for internal nodes
+    children: Vec<u32>,
+}
+
+impl Node {
+    fn encode(&self, first_block: u32) -> Vec<u8> {
+        let mut data = Vec::with_capacity(PAGE_SIZE);
+        let last_child = self.children.last().map(|c| c + first_block).unwrap_or(0);
+        data.extend_from_slice(&last_child.to_be_bytes());
+        data.extend_from_slice(&(self.records.len() as u32).to_be_bytes());
+        for (index, record) in self.records.iter().enumerate() {
+            if let Some(child) = self.children.get(index) {
+                data.extend_from_slice(&(child + first_block).to_be_bytes());
+            }
+            data.extend_from_slice(record);
+        }
+        data
+    }
+}
+
+/// Split sorted records into leaves and, if needed, one internal root node.
+/// Returns the nodes (root last) and the number of internal levels.
+fn tree(records: &[Record]) -> Result<(Vec<Node>, u32)> {
+    let encoded = records.iter().map(encode_record).collect::<Vec<_>>();
+    let fits = |records: &[Vec<u8>], per_record: usize| {
+        8 + records.iter().map(|r| r.len() + per_record).sum::<usize>() <= PAGE_SIZE
+    };
+
+    if fits(&encoded, 0) {
+        let leaf = Node {
+            records: encoded,
+            children: Vec::new(),
+        };
+        return Ok((vec![leaf], 0));
+    }
+
+    // Fill leaves greedily; the record following a full leaf becomes its separator
+    let mut leaves: Vec<Node> = Vec::new();
+    let mut separators = Vec::new();
+    let mut current: Vec<Vec<u8>> = Vec::new();
+    let mut size = 8;
+    for record in encoded {
+        if size + record.len() > PAGE_SIZE {
+            leaves.push(Node {
+                records: std::mem::take(&mut current),
+                children: Vec::new(),
+            });
+            separators.push(record);
+            size = 8;
+            continue;
+        }
+        size += record.len();
+        current.push(record);
+    }
+    leaves.push(Node {
+        records: current,
+        children: Vec::new(),
+    });
+
+    if !fits(&separators, 4) {
+        return Err(Error::Custom(
+            "Too many items for the .DS_Store of the disk image".to_string(),
+        ));
+    }
+
+    let root = Node {
+        records: separators,
+        children: (0..leaves.len() as u32).collect(),
+    };
+    leaves.push(root);
+    Ok((leaves, 1))
+}
+
+fn encode_record(record: &Record) -> Vec<u8> {
+    let name = record.name.encode_utf16().collect::<Vec<_>>();
+    let mut data = Vec::new();
+    data.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    data.extend(name.iter().flat_map(|unit| unit.to_be_bytes()));
+    data.extend_from_slice(&record.code);
+    match &record.value {
+        Value::Long(value) => {
+            data.extend_from_slice(b"long");
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        Value::Type(value) => {
+            data.extend_from_slice(b"type");
+            data.extend_from_slice(value);
+        }
+        Value::Blob(value) => {
+            data.extend_from_slice(b"blob");
+            data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            data.extend_from_slice(value);
+        }
+    }
+    data
+}
+
+/// Block address: offset with the log2 of the block size in the low five bits
+fn address(offset: u32, size: u32) -> u32 {
+    offset | size.trailing_zeros()
+}
+
+/// Buddy allocator over a 2 GiB address space, as used by the store format
+struct Allocator {
+    /// Free block offsets by log2 of their size
+    free: Vec<Vec<u32>>,
+}
+
+impl Allocator {
+    fn new() -> Self {
+        let mut free = vec![Vec::new(); 32];
+        free[31].push(0);
+        Self { free }
+    }
+
+    fn allocate(&mut self, size: u32) -> Result<u32> {
+        let width = size.next_power_of_two().max(32).trailing_zeros() as usize;
+        let level = (width..32)
+            .find(|level| !self.free[*level].is_empty())
+            .ok_or_else(|| Error::Custom(".DS_Store is out of space".to_string()))?;
+        let offset = self.free[level].remove(0);
+        // Split the block, returning the upper halves to the free lists
+        for level in (width..level).rev() {
+            self.free[level].push(offset + (1 << level));
+            self.free[level].sort_unstable();
+        }
+        Ok(offset)
+    }
+}
+
+/// Version 2 alias record of a file that will exist on the volume, resolved by path
+fn alias(volume_name: &str, components: &[&str]) -> Vec<u8> {
+    let file_name = components.last().copied().unwrap_or_default();
+    let parent_name = components
+        .len()
+        .checked_sub(2)
+        .map(|i| components[i])
+        .unwrap_or(volume_name);
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&[0; 4]); // application-specific type
+    data.extend_from_slice(&[0; 2]); // size, patched below
+    data.extend_from_slice(&2u16.to_be_bytes()); // version
+    data.extend_from_slice(&0u16.to_be_bytes()); // kind: file
+    data.extend_from_slice(&pascal_string(volume_name, 27));
+    data.extend_from_slice(&0u32.to_be_bytes()); // volume creation date
+    data.extend_from_slice(b"H+"); // file system
+    data.extend_from_slice(&5u16.to_be_bytes()); // ejectable disk
+    data.extend_from_slice(&0u32.to_be_bytes()); // parent directory ID
+    data.extend_from_slice(&pascal_string(file_name, 63));
+    data.extend_from_slice(&0u32.to_be_bytes()); // file ID
+    data.extend_from_slice(&0u32.to_be_bytes()); // file creation date
+    data.extend_from_slice(&[0; 8]); // file type and creator
+    data.extend_from_slice(&(-1i16).to_be_bytes()); // levels to the common ancestor
+    data.extend_from_slice(&(-1i16).to_be_bytes());
+    data.extend_from_slice(&0u32.to_be_bytes()); // volume attributes
+    data.extend_from_slice(&0u16.to_be_bytes()); // volume file system ID
+    data.extend_from_slice(&[0; 10]);
+
+    let mut tag = |tag: i16, value: &[u8]| {
+        data.extend_from_slice(&tag.to_be_bytes());
+        data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        data.extend_from_slice(value);
+        if value.len() % 2 == 1 {
+            data.push(0);
+        }
+    };
+    tag(0, &mac_roman(parent_name));
+    let carbon_path = std::iter::once(volume_name)
+        .chain(components.iter().copied())
+        .collect::<Vec<_>>()
+        .join(":");
+    tag(2, &mac_roman(&carbon_path));
+    tag(14, &unicode_string(file_name));
+    tag(15, &unicode_string(volume_name));
+    tag(18, format!("/{}", components.join("/")).as_bytes());
+    tag(19, format!("/Volumes/{}", volume_name).as_bytes());
+    tag(-1, &[]);
+
+    let size = data.len() as u16;
+    data[4..6].copy_from_slice(&size.to_be_bytes());
+    data
+}
+
+/// Length-prefixed string padded to `capacity` bytes
+fn pascal_string(value: &str, capacity: usize) -> Vec<u8> {
+    let mut bytes = mac_roman(value);
+    bytes.truncate(capacity);
+    let mut data = vec![bytes.len() as u8];
+    data.extend_from_slice(&bytes);
+    data.resize(capacity + 1, 0);
+    data
+}
+
+/// Classic Mac OS names: ASCII, with other characters replaced
+fn mac_roman(value: &str) -> Vec<u8> {
+    value
+        .chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+        .collect()
+}
+
+fn unicode_string(value: &str) -> Vec<u8> {
+    let units = value.encode_utf16().collect::<Vec<_>>();
+    let mut data = (units.len() as u16).to_be_bytes().to_vec();
+    data.extend(units.iter().flat_map(|unit| unit.to_be_bytes()));
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Parse a `.DS_Store` file back into its records, in tree order
+    fn read(data: &[u8]) -> Vec<Record> {
+        assert_eq!(read_u32(data, 0), 1);
+        assert_eq!(&data[4..8], b"Bud1");
+        let info = 4 + read_u32(data, 8) as usize;
+        assert_eq!(read_u32(data, 16) as usize + 4, info);
+
+        let count = read_u32(data, info) as usize;
+        let addresses = (0..count)
+            .map(|i| read_u32(data, info + 8 + 4 * i))
+            .collect::<Vec<_>>();
+        let toc = info + 8 + 4 * count.next_multiple_of(256);
+        assert_eq!(read_u32(data, toc), 1);
+        assert_eq!(&data[toc + 4..toc + 9], b"\x04DSDB");
+        let block = |id: u32| 4 + (addresses[id as usize] & !0x1f) as usize;
+
+        let header = block(read_u32(data, toc + 9));
+        let root = read_u32(data, header);
+        let record_count = read_u32(data, header + 8) as usize;
+        assert_eq!(read_u32(data, header + 16), PAGE_SIZE as u32);
+
+        let mut records = Vec::new();
+        read_node(data, block(root), &block, &mut records);
+        assert_eq!(records.len(), record_count);
+        records
+    }
+
+    fn read_node(data: &[u8], node: usize, block: &dyn Fn(u32) -> usize, out: &mut Vec<Record>) {
+        let last_child = read_u32(data, node);
+        let count = read_u32(data, node + 4) as usize;
+        let mut offset = node + 8;
+        for _ in 0..count {
+            if last_child != 0 {
+                read_node(data, block(read_u32(data, offset)), block, out);
+                offset += 4;
+            }
+            let (record, length) = read_record(&data[offset..]);
+            out.push(record);
+            offset += length;
+        }
+        if last_child != 0 {
+            read_node(data, block(last_child), block, out);
+        }
+    }
+
+    fn read_record(data: &[u8]) -> (Record, usize) {
+        let length = read_u32(data, 0) as usize;
+        let units = (0..length)
+            .map(|i| u16::from_be_bytes([data[4 + 2 * i], data[5 + 2 * i]]))
+            .collect::<Vec<_>>();
+        let mut offset = 4 + 2 * length;
+        let code: [u8; 4] = data[offset..offset + 4].try_into().unwrap();
+        let kind = &data[offset + 4..offset + 8];
+        offset += 8;
+        let value = match kind {
+            b"long" => {
+                offset += 4;
+                Value::Long(read_u32(data, offset - 4))
+            }
+            b"type" => {
+                offset += 4;
+                Value::Type(data[offset - 4..offset].try_into().unwrap())
+            }
+            b"blob" => {
+                let size = read_u32(data, offset) as usize;
+                offset += 4 + size;
+                Value::Blob(data[offset - size..offset].to_vec())
+            }
+            other => panic!("unexpected type {:?}", other),
+        };
+        let record = Record {
+            name: String::from_utf16(&units).unwrap(),
+            code,
+            value,
+        };
+        (record, offset)
+    }
+
+    fn sample_layout() -> Layout {
+        Layout {
+            volume_name: "Sample App".to_string(),
+            window_position: (100, 120),
+            window_size: (600, 400),
+            icon_size: 72,
+            text_size: 12,
+            background: Some("background.png".to_string()),
+            items: vec![
+                ("Sample App.app".to_string(), (150, 200)),
+                ("Applications".to_string(), (450, 200)),
+                ("README.md".to_string(), (300, 320)),
+            ],
+        }
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn test_write_layout() {
+        let layout = sample_layout();
+        let data = write(&layout).unwrap();
+
+        // Byte-stable
+        assert_eq!(write(&layout).unwrap(), data);
+
+        let records = read(&data);
+        let codes = records
+            .iter()
+            .map(|r| format!("{}:{}", r.name, String::from_utf8_lossy(&r.code)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [
+                ".:bwsp",
+                ".:icvp",
+                ".:vSrn",
+                ".:vstl",
+                "Applications:Iloc",
+                "README.md:Iloc",
+                "Sample App.app:Iloc"
+            ]
+        );
+
+        let location = |name: &str| match &records.iter().find(|r| r.name == name).unwrap().value {
+            Value::Blob(blob) => (read_u32(blob, 0), read_u32(blob, 4)),
+            other => panic!("unexpected value {:?}", other),
+        };
+        assert_eq!(location("Sample App.app"), (150, 200));
+        assert_eq!(location("Applications"), (450, 200));
+        assert_eq!(location("README.md"), (300, 320));
+
+        let Value::Blob(window) = &records[0].value else {
+            panic!("bwsp is not a blob");
+        };
+        assert!(window.starts_with(b"bplist00"));
+        assert!(contains(window, b"{{100, 120}, {600, 400}}"));
+
+        let Value::Blob(view) = &records[1].value else {
+            panic!("icvp is not a blob");
+        };
+        assert!(contains(view, b"backgroundImageAlias"));
+        assert!(contains(view, b"/.background/background.png"));
+        assert!(contains(view, b"/Volumes/Sample App"));
+    }
+
+    #[test]
+    fn test_write_without_background() {
+        let layout = Layout {
+            background: None,
+            ..sample_layout()
+        };
+        let records = read(&write(&layout).unwrap());
+        let Value::Blob(view) = &records[1].value else {
+            panic!("icvp is not a blob");
+        };
+        assert!(!contains(view, b"backgroundImageAlias"));
+    }
+
+    #[test]
+    fn test_write_many_items() {
+        let layout = Layout {
+            items: (0..300)
+                .map(|i| (format!("File {:03}.txt", i), (i, i * 2)))
+                .collect(),
+            ..sample_layout()
+        };
+        let data = write(&layout).unwrap();
+        let records = read(&data);
+        assert_eq!(records.len(), 304);
+
+        // Tree order is the sort order
+        let mut sorted = records.clone();
+        sorted.sort_by(compare);
+        assert_eq!(records, sorted);
+    }
+
+    #[test]
+    fn test_alias() {
+        let data = alias("Sample App", &[".background", "background.png"]);
+        assert_eq!(u16::from_be_bytes([data[4], data[5]]) as usize, data.len());
+        assert_eq!(&data[10..21], b"\x0aSample App");
+        assert_eq!(&data[50..65], b"\x0ebackground.png");
+        assert!(data.ends_with(&[0xff, 0xff, 0x00, 0x00]));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::context::Context;
+use crate::deps::{self, Resolution};
+use crate::error::Error;
+use crate::macos::macho;
+use crate::manifest::Manifest;
+use crate::result::Result;
+use crate::utils;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Install name prefix of dylibs bundled into `Contents/Frameworks`
+pub const FRAMEWORKS_PREFIX: &str = "@executable_path/../Frameworks";
+
+/// Install name prefixes of libraries that ship with macOS
+pub const SYSTEM_PREFIXES: &[&str] = &["/usr/lib/", "/System/Library/", "@rpath/libswift"];
+
+#[derive(Debug, Clone, Default)]
+pub struct BundleOptions {
+    /// Directories searched by file name when an install name does not resolve
+    pub search_paths: Vec<PathBuf>,
+    /// Additional install name prefixes treated as system libraries
+    pub system_libraries: Vec<String>,
+}
+
+/// Bundle non-system dylibs into the app if `macos.bundle-dylibs` is enabled
+pub fn bundle(
+    ctx: &Context,
+    manifest: &Manifest,
+    app_path: &Path,
+) -> Result<BTreeMap<String, Resolution>> {
+    let Some(macos) = manifest.macos.as_ref().filter(|m| m.bundle_dylibs) else {
+        return Ok(BTreeMap::new());
+    };
+
+    let options = BundleOptions {
+        search_paths: macos
+            .library_paths
+            .iter()
+            .map(|p| ctx.base_dir.join(p))
+            .collect(),
+        system_libraries: macos.system_libraries.clone(),
+    };
+
+    // Modified files are re-signed by the signing stage (`codesign::sign_bundle`)
+    let (dependencies, _) = bundle_app(ctx, app_path, &options)?;
+    deps::report("Dylib dependencies", &dependencies);
+
+    Ok(dependencies)
+}
+
+/// Copy the non-system dylibs loaded by the binaries in `Contents/MacOS` into
+/// `Contents/Frameworks` and rewrite their install names. Returns the resolved
+/// dependencies and the files that were modified and need to be re-signed.
+pub fn bundle_app(
+    ctx: &Context,
+    app_path: &Path,
+    options: &BundleOptions,
+) -> Result<(BTreeMap<String, Resolution>, Vec<PathBuf>)> {
+    let macos_dir = app_path.join("Contents/MacOS");
+    let frameworks_dir = app_path.join("Contents/Frameworks");
+
+    // (file in the bundle, directory it was loaded from on the build machine)
+    let mut pending = Vec::new();
+    let mut executable_rpaths = Vec::new();
+    for entry in WalkDir::new(&macos_dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() && is_macho_file(entry.path()) {
+            let data = fs::read(entry.path())?;
+            executable_rpaths.extend(macho::rpaths(&data)?);
+            pending.push((entry.path().to_path_buf(), macos_dir.clone()));
+        }
+    }
+
+    let mut dependencies = BTreeMap::new();
+    let mut renames: BTreeMap<String, String> = BTreeMap::new();
+    let mut files = Vec::new();
+
+    while let Some((path, loader_dir)) = pending.pop() {
+        let data = fs::read(&path)?;
+        let mut rpaths = macho::rpaths(&data)?;
+        rpaths.extend(executable_rpaths.iter().cloned());
+
+        for name in macho::dylibs(&data)? {
+            if dependencies.contains_key(&name) {
+                continue;
+            }
+
+            let resolution = if is_system_dylib(&name, &options.system_libraries) {
+                Resolution::System
+            } else if name.starts_with(FRAMEWORKS_PREFIX) {
+                Resolution::Staged
+            } else if let Some(found) = resolve(
+                &name,
+                &loader_dir,
+                &macos_dir,
+                &rpaths,
+                &options.search_paths,
+            ) {
+                let file_name = found.file_name().unwrap().to_string_lossy().to_string();
+                let dest = frameworks_dir.join(&file_name);
+                let new_name = format!("{}/{}", FRAMEWORKS_PREFIX, file_name);
+
+                if !dest.exists() {
+                    if ctx.verbose {
+                        status!("Bundling {} from {}", name, found.display());
+                    }
+                    utils::ensure_dir(&frameworks_dir)?;
+                    fs::copy(&found, &dest)?;
+                    utils::make_writable(&dest)?;
+
+                    let mut dylib = fs::read(&dest)?;
+                    macho::set_install_id(&mut dylib, &new_name)?;
+                    fs::write(&dest, dylib)?;
+
+                    pending.push((dest, found.parent().unwrap().to_path_buf()));
+                }
+
+                renames.insert(name.clone(), new_name);
+                Resolution::Bundled(found)
+            } else {
+                Resolution::Missing
+            };
+
+            dependencies.insert(name, resolution);
+        }
+
+        files.push(path);
+    }
+
+    let missing = deps::missing(&dependencies);
+    if !missing.is_empty() {
+        return Err(Error::Custom(format!(
+            "Unable to resolve dylibs: {} (add their directories to macos.library-paths)",
+            missing.join(", ")
+        )));
+    }
+
+    // Rewrite references to bundled dylibs in every processed binary
+    let mut modified = Vec::new();
+    for path in files {
+        let mut data = fs::read(&path)?;
+        let mut changed = path.starts_with(&frameworks_dir);
+        for (old, new) in &renames {
+            changed |= macho::change_install_name(&mut data, old, new)?;
+        }
+        if changed {
+            fs::write(&path, data)?;
+            modified.push(path);
+        }
+    }
+
+    Ok((dependencies, modified))
+}
+
+/// Whether an install name refers to a library shipped with macOS
+pub fn is_system_dylib(name: &str, extra: &[String]) -> bool {
+    SYSTEM_PREFIXES
+        .iter()
+        .copied()
+        .chain(extra.iter().map(|s| s.as_str()))
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Resolve an install name (`@rpath`, `@loader_path`, `@executable_path` or absolute)
+/// on the build machine, falling back to a file name lookup in `search_paths`
+fn resolve(
+    name: &str,
+    loader_dir: &Path,
+    executable_dir: &Path,
+    rpaths: &[String],
+    search_paths: &[PathBuf],
+) -> Option<PathBuf> {
+    let expand = |path: &str| -> PathBuf {
+        if let Some(rest) = path.strip_prefix("@loader_path") {
+            loader_dir.join(rest.trim_start_matches('/'))
+        } else if let Some(rest) = path.strip_prefix("@executable_path") {
+            executable_dir.join(rest.trim_start_matches('/'))
+        } else {
+            PathBuf::from(path)
+        }
+    };
+
+    let mut candidates = Vec::new();
+    if let Some(rest) = name.strip_prefix("@rpath/") {
+        candidates.extend(rpaths.iter().map(|r| expand(r).join(rest)));
+    } else {
+        candidates.push(expand(name));
+    }
+
+    let file_name = Path::new(name).file_name()?;
+    candidates.extend(search_paths.iter().map(|dir| dir.join(file_name)));
+
+    candidates
+        .into_iter()
+        .find(|c| is_macho_file(c))
+        .and_then(|found| fs::canonicalize(found).ok())
+}
+
+fn is_macho_file(path: &Path) -> bool {
+    use std::io::Read;
+    let mut magic = [0u8; 4];
+    path.is_file()
+        && fs::File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .is_ok()
+        && macho::is_macho(&magic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macos::macho::tests::fixture;
+
+    #[test]
+    fn test_bundle_app() {
+        let ctx = Context::new(PathBuf::from("Cargo.toml"), false);
+        let temp = tempfile::tempdir().unwrap();
+        let app = temp.path().join("Sample.app");
+        fs::create_dir_all(app.join("Contents/MacOS")).unwrap();
+        fs::copy(fixture("app"), app.join("Contents/MacOS/app")).unwrap();
+
+        let options = BundleOptions {
+            search_paths: vec![fixture("")],
+            system_libraries: Vec::new(),
+        };
+        let (deps, modified) = bundle_app(&ctx, &app, &options).unwrap();
+
+        assert_eq!(deps["/usr/lib/libSystem.B.dylib"], Resolution::System);
+        assert!(matches!(
+            deps["@rpath/libfoo.dylib"],
+            Resolution::Bundled(_)
+        ));
+        assert!(matches!(
+            deps["/opt/homebrew/lib/libbar.dylib"],
+            Resolution::Bundled(_)
+        ));
+        assert_eq!(modified.len(), 3);
+
+        let exe = fs::read(app.join("Contents/MacOS/app")).unwrap();
+        assert_eq!(
+            macho::dylibs(&exe).unwrap(),
+            vec![
+                "/usr/lib/libSystem.B.dylib",
+                "@executable_path/../Frameworks/libfoo.dylib",
+                "@executable_path/../Frameworks/libbar.dylib",
+            ]
+        );
+
+        let foo = fs::read(app.join("Contents/Frameworks/libfoo.dylib")).unwrap();
+        let foo_macho = macho::MachO::parse(&foo).unwrap();
+        assert_eq!(
+            foo_macho.install_id(&foo).as_deref(),
+            Some("@executable_path/../Frameworks/libfoo.dylib")
+        );
+        assert!(
+            foo_macho
+                .dylibs(&foo)
+                .contains(&"@executable_path/../Frameworks/libbar.dylib".to_string())
+        );
+    }
+
+    #[test]
+    fn test_bundle_app_missing() {
+        let ctx = Context::new(PathBuf::from("Cargo.toml"), false);
+        let temp = tempfile::tempdir().unwrap();
+        let app = temp.path().join("Sample.app");
+        fs::create_dir_all(app.join("Contents/MacOS")).unwrap();
+        fs::copy(fixture("app"), app.join("Contents/MacOS/app")).unwrap();
+
+        let err = bundle_app(&ctx, &app, &BundleOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("@rpath/libfoo.dylib"));
+    }
+
+    #[test]
+    fn test_system_dylibs() {
+        assert!(is_system_dylib("/usr/lib/libSystem.B.dylib", &[]));
+        assert!(is_system_dylib(
+            "/System/Library/Frameworks/AppKit.framework/Versions/C/AppKit",
+            &[]
+        ));
+        assert!(!is_system_dylib("/opt/homebrew/lib/libbar.dylib", &[]));
+    }
+}
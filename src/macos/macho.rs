@@ -0,0 +1,427 @@
+use crate::error::Error;
+use crate::result::Result;
+use std::ops::Range;
+
+pub const MH_MAGIC: u32 = 0xfeedface;
+pub const MH_MAGIC_64: u32 = 0xfeedfacf;
+pub const FAT_MAGIC: u32 = 0xcafebabe;
+pub const FAT_MAGIC_64: u32 = 0xcafebabf;
+
+pub const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+pub const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+
+pub const LC_SEGMENT: u32 = 0x1;
+pub const LC_LOAD_DYLIB: u32 = 0xc;
+pub const LC_ID_DYLIB: u32 = 0xd;
+pub const LC_SEGMENT_64: u32 = 0x19;
+pub const LC_LAZY_LOAD_DYLIB: u32 = 0x20;
+pub const LC_LOAD_WEAK_DYLIB: u32 = 0x8000_0018;
+pub const LC_RPATH: u32 = 0x8000_001c;
+pub const LC_REEXPORT_DYLIB: u32 = 0x8000_001f;
+pub const LC_LOAD_UPWARD_DYLIB: u32 = 0x8000_0023;
+pub const LC_CODE_SIGNATURE: u32 = 0x1d;
+
+/// Load commands that make the image depend on another dylib
+const DYLIB_LOAD_COMMANDS: [u32; 5] = [
+    LC_LOAD_DYLIB,
+    LC_LOAD_WEAK_DYLIB,
+    LC_REEXPORT_DYLIB,
+    LC_LAZY_LOAD_DYLIB,
+    LC_LOAD_UPWARD_DYLIB,
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoadCommand {
+    pub cmd: u32,
+    /// Offset of the command within the slice
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Parsed header and load commands of a single-architecture Mach-O image
+#[derive(Debug, Clone)]
+pub struct MachO {
+    pub is_64: bool,
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    pub header_size: usize,
+    pub sizeofcmds: usize,
+    pub commands: Vec<LoadCommand>,
+}
+
+impl MachO {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let magic = read_u32(data, 0)?;
+        let (is_64, header_size) = match magic {
+            MH_MAGIC => (false, 28),
+            MH_MAGIC_64 => (true, 32),
+            _ => return Err(invalid("not a little-endian Mach-O image")),
+        };
+
+        let ncmds = read_u32(data, 16)? as usize;
+        let sizeofcmds = read_u32(data, 20)? as usize;
+        if header_size + sizeofcmds > data.len() {
+            return Err(invalid("load commands exceed the file size"));
+        }
+
+        let mut commands = Vec::with_capacity(ncmds);
+        let mut offset = header_size;
+        for _ in 0..ncmds {
+            let cmd = read_u32(data, offset)?;
+            let size = read_u32(data, offset + 4)? as usize;
+            if size < 8 || offset + size > header_size + sizeofcmds {
+                return Err(invalid("malformed load command"));
+            }
+            commands.push(LoadCommand { cmd, offset, size });
+            offset += size;
+        }
+
+        Ok(Self {
+            is_64,
+            cputype: read_u32(data, 4)?,
+            cpusubtype: read_u32(data, 8)?,
+            header_size,
+            sizeofcmds,
+            commands,
+        })
+    }
+
+    /// Install names of all dylibs this image loads
+    pub fn dylibs(&self, data: &[u8]) -> Vec<String> {
+        self.commands
+            .iter()
+            .filter(|c| DYLIB_LOAD_COMMANDS.contains(&c.cmd))
+            .filter_map(|c| command_string(data, c))
+            .collect()
+    }
+
+    /// Install name of a dylib (`LC_ID_DYLIB`)
+    pub fn install_id(&self, data: &[u8]) -> Option<String> {
+        self.commands
+            .iter()
+            .find(|c| c.cmd == LC_ID_DYLIB)
+            .and_then(|c| command_string(data, c))
+    }
+
+    pub fn rpaths(&self, data: &[u8]) -> Vec<String> {
+        self.commands
+            .iter()
+            .filter(|c| c.cmd == LC_RPATH)
+            .filter_map(|c| command_string(data, c))
+            .collect()
+    }
+
+    /// `LC_SEGMENT_64` command of the named segment
+    pub fn segment(&self, data: &[u8], name: &str) -> Option<LoadCommand> {
+        self.commands.iter().copied().find(|c| {
+            c.cmd == LC_SEGMENT_64
+                && data
+                    .get(c.offset + 8..c.offset + 24)
+                    .is_some_and(|n| n.split(|b| *b == 0).next() == Some(name.as_bytes()))
+        })
+    }
+
+    /// End of the space available for load commands: the file offset of the
+    /// first section (or segment) contents
+    pub fn load_command_limit(&self, data: &[u8]) -> usize {
+        let mut limit = data.len();
+        for command in &self.commands {
+            let (segment_size, section_size, fileoff_at, nsects_at, section_offset_at) =
+                match command.cmd {
+                    LC_SEGMENT_64 => (72, 80, 40, 64, 48),
+                    LC_SEGMENT => (56, 68, 32, 48, 40),
+                    _ => continue,
+                };
+
+            let read_field = |at: usize| -> usize {
+                if command.cmd == LC_SEGMENT_64 {
+                    read_u64(data, command.offset + at).unwrap_or(0) as usize
+                } else {
+                    read_u32(data, command.offset + at).unwrap_or(0) as usize
+                }
+            };
+
+            let nsects = read_u32(data, command.offset + nsects_at).unwrap_or(0) as usize;
+            for i in 0..nsects {
+                let section = command.offset + segment_size + i * section_size;
+                let offset = read_u32(data, section + section_offset_at).unwrap_or(0) as usize;
+                if offset != 0 {
+                    limit = limit.min(offset);
+                }
+            }
+
+            let fileoff = read_field(fileoff_at);
+            if nsects == 0 && fileoff != 0 {
+                limit = limit.min(fileoff);
+            }
+        }
+        limit
+    }
+}
+
+/// Byte ranges of the single-architecture images in a thin or fat (universal) file
+pub fn slices(data: &[u8]) -> Result<Vec<Range<usize>>> {
+    let magic = read_u32_be(data, 0)?;
+    let (is_64, entry_size) = match magic {
+        FAT_MAGIC => (false, 20),
+        FAT_MAGIC_64 => (true, 32),
+        _ => {
+            MachO::parse(data)?;
+            return Ok(std::iter::once(0..data.len()).collect());
+        }
+    };
+
+    let count = read_u32_be(data, 4)? as usize;
+    let mut ranges = Vec::with_capacity(count);
+    for i in 0..count {
+        let entry = 8 + i * entry_size;
+        let (offset, size) = if is_64 {
+            (
+                read_u64_be(data, entry + 8)? as usize,
+                read_u64_be(data, entry + 16)? as usize,
+            )
+        } else {
+            (
+                read_u32_be(data, entry + 8)? as usize,
+                read_u32_be(data, entry + 12)? as usize,
+            )
+        };
+        if offset + size > data.len() {
+            return Err(invalid("fat architecture exceeds the file size"));
+        }
+        ranges.push(offset..offset + size);
+    }
+    Ok(ranges)
+}
+
+/// Whether `data` starts with a thin or fat Mach-O magic number
+pub fn is_macho(data: &[u8]) -> bool {
+    matches!(read_u32(data, 0), Ok(MH_MAGIC | MH_MAGIC_64))
+        || matches!(read_u32_be(data, 0), Ok(FAT_MAGIC | FAT_MAGIC_64))
+}
+
+/// Dylibs loaded by any architecture of a thin or fat file
+pub fn dylibs(data: &[u8]) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for range in slices(data)? {
+        let slice = &data[range];
+        for name in MachO::parse(slice)?.dylibs(slice) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Run paths of any architecture of a thin or fat file
+pub fn rpaths(data: &[u8]) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for range in slices(data)? {
+        let slice = &data[range];
+        for path in MachO::parse(slice)?.rpaths(slice) {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Change a dependent install name in every architecture (`install_name_tool -change`).
+/// Returns whether any load command was changed.
+pub fn change_install_name(data: &mut [u8], old: &str, new: &str) -> Result<bool> {
+    let mut changed = false;
+    for range in slices(data)? {
+        let slice = &mut data[range];
+        loop {
+            let macho = MachO::parse(slice)?;
+            let Some(index) = macho.commands.iter().position(|c| {
+                DYLIB_LOAD_COMMANDS.contains(&c.cmd)
+                    && command_string(slice, c).as_deref() == Some(old)
+            }) else {
+                break;
+            };
+            replace_command_string(slice, &macho, index, new)?;
+            changed = true;
+        }
+    }
+    Ok(changed)
+}
+
+/// Set the install name of a dylib in every architecture (`install_name_tool -id`)
+pub fn set_install_id(data: &mut [u8], new: &str) -> Result<()> {
+    for range in slices(data)? {
+        let slice = &mut data[range];
+        let macho = MachO::parse(slice)?;
+        if macho.install_id(slice).as_deref() == Some(new) {
+            continue;
+        }
+        if let Some(index) = macho.commands.iter().position(|c| c.cmd == LC_ID_DYLIB) {
+            replace_command_string(slice, &macho, index, new)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read the `lc_str` of a dylib or rpath command
+fn command_string(data: &[u8], command: &LoadCommand) -> Option<String> {
+    let offset = read_u32(data, command.offset + 8).ok()? as usize;
+    let bytes = data.get(command.offset + offset..command.offset + command.size)?;
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8(bytes[..end].to_vec()).ok()
+}
+
+/// Replace the string of a dylib/rpath load command, growing the command into
+/// the header padding if needed
+fn replace_command_string(
+    slice: &mut [u8],
+    macho: &MachO,
+    index: usize,
+    value: &str,
+) -> Result<()> {
+    let command = macho.commands[index];
+    let string_offset = read_u32(slice, command.offset + 8)? as usize;
+    let align = if macho.is_64 { 8 } else { 4 };
+    let required = (string_offset + value.len() + 1).next_multiple_of(align);
+    let new_size = required.max(command.size);
+
+    if new_size > command.size {
+        let commands_end = macho.header_size + macho.sizeofcmds;
+        let delta = new_size - command.size;
+        if commands_end + delta > macho.load_command_limit(slice) {
+            return Err(invalid(&format!(
+                "not enough header space to change install name to '{}' \
+                 (link with -headerpad_max_install_names)",
+                value
+            )));
+        }
+
+        // Shift the following load commands into the padding
+        let tail = command.offset + command.size..commands_end;
+        slice.copy_within(tail, command.offset + new_size);
+        write_u32(slice, command.offset + 4, new_size as u32);
+        write_u32(slice, 20, (macho.sizeofcmds + delta) as u32);
+    }
+
+    let string = &mut slice[command.offset + string_offset..command.offset + new_size];
+    string.fill(0);
+    string[..value.len()].copy_from_slice(value.as_bytes());
+
+    Ok(())
+}
+
+pub(crate) fn invalid(msg: &str) -> Error {
+    Error::Custom(format!("Mach-O: {}", msg))
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| invalid("unexpected end of file"))
+}
+
+pub(crate) fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    data.get(offset..offset + 8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| invalid("unexpected end of file"))
+}
+
+pub(crate) fn read_u32_be(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| invalid("unexpected end of file"))
+}
+
+pub(crate) fn read_u64_be(data: &[u8], offset: usize) -> Result<u64> {
+    data.get(offset..offset + 8)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| invalid("unexpected end of file"))
+}
+
+pub(crate) fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    pub fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/macho")
+            .join(name)
+    }
+
+    #[test]
+    fn test_parse_fixture() {
+        let data = std::fs::read(fixture("app")).unwrap();
+        let macho = MachO::parse(&data).unwrap();
+        assert!(macho.is_64);
+        assert_eq!(
+            macho.dylibs(&data),
+            vec![
+                "/usr/lib/libSystem.B.dylib",
+                "@rpath/libfoo.dylib",
+                "/opt/homebrew/lib/libbar.dylib"
+            ]
+        );
+        assert_eq!(macho.rpaths(&data), vec!["/opt/homebrew/lib"]);
+        assert_eq!(macho.load_command_limit(&data), 0x800);
+
+        // Cross-check with an independent parser
+        let goblin = goblin::mach::MachO::parse(&data, 0).unwrap();
+        assert_eq!(
+            goblin.libs[1..],
+            [
+                "/usr/lib/libSystem.B.dylib",
+                "@rpath/libfoo.dylib",
+                "/opt/homebrew/lib/libbar.dylib"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_install_name() {
+        let mut data = std::fs::read(fixture("app")).unwrap();
+        let new = "@executable_path/../Frameworks/libfoo.dylib";
+        assert!(change_install_name(&mut data, "@rpath/libfoo.dylib", new).unwrap());
+        assert!(!change_install_name(&mut data, "@rpath/libfoo.dylib", new).unwrap());
+
+        let macho = MachO::parse(&data).unwrap();
+        assert_eq!(
+            macho.dylibs(&data),
+            vec![
+                "/usr/lib/libSystem.B.dylib",
+                new,
+                "/opt/homebrew/lib/libbar.dylib"
+            ]
+        );
+        // The following commands were shifted intact
+        assert_eq!(macho.rpaths(&data), vec!["/opt/homebrew/lib"]);
+        assert!(goblin::mach::MachO::parse(&data, 0).is_ok());
+    }
+
+    #[test]
+    fn test_set_install_id() {
+        let mut data = std::fs::read(fixture("libfoo.dylib")).unwrap();
+        set_install_id(&mut data, "@executable_path/../Frameworks/libfoo.dylib").unwrap();
+        let macho = MachO::parse(&data).unwrap();
+        assert_eq!(
+            macho.install_id(&data).as_deref(),
+            Some("@executable_path/../Frameworks/libfoo.dylib")
+        );
+    }
+
+    #[test]
+    fn test_header_space_exhausted() {
+        let mut data = std::fs::read(fixture("app")).unwrap();
+        let long = format!("/{}", "x".repeat(0x1000));
+        assert!(change_install_name(&mut data, "@rpath/libfoo.dylib", &long).is_err());
+    }
+}
@@ -1,10 +1,23 @@
+pub mod background;
+pub mod bom;
+pub mod bplist;
+pub mod codesign;
+pub mod cpio;
 pub mod dmg;
+pub mod dsstore;
+pub mod dylibs;
+pub mod macho;
+pub mod pkg;
+pub mod sla;
+pub mod universal;
+pub mod xar;
 
 use crate::context::Context;
 use crate::manifest::Manifest;
 use crate::result::Result;
+use std::path::PathBuf;
 
 #[allow(dead_code)]
-pub fn build(ctx: &Context, manifest: &Manifest) -> Result<()> {
+pub fn build(ctx: &Context, manifest: &Manifest) -> Result<PathBuf> {
     dmg::create(ctx, manifest)
 }
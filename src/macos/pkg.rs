@@ -0,0 +1,440 @@
+//! Flat installer packages (`.pkg`) for MDM and Installer deployment
+//!
+//! A product archive is a xar archive with a `Distribution` script and one component
+//! package holding `PackageInfo`, the gzip-compressed cpio `Payload`, its `Bom` and
+//! optional `Scripts`. Everything is written in Rust, so packages can be built on any host.
+
+use crate::archive::Kind;
+use crate::context::Context;
+use crate::error::Error;
+use crate::macos::{bom, cpio, dmg, xar};
+use crate::manifest::Manifest;
+use crate::result::Result;
+use crate::utils::{self, xml_escape};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Default install location of the app bundle
+const INSTALL_LOCATION: &str = "/Applications";
+/// Group of the install location root, matching `/Applications` (admin)
+const ADMIN_GID: u32 = 80;
+
+/// Package settings resolved from the manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct PkgSpec {
+    pub identifier: String,
+    pub version: String,
+    pub title: String,
+    pub install_location: String,
+    pub minimum_os_version: String,
+    /// App bundle folder name, such as `Sample.app`
+    pub bundle: String,
+    pub bundle_identifier: String,
+    /// Component package name inside the product archive
+    pub component: String,
+    pub preinstall: Option<PathBuf>,
+    pub postinstall: Option<PathBuf>,
+}
+
+impl PkgSpec {
+    pub fn from_manifest(ctx: &Context, manifest: &Manifest) -> Result<Self> {
+        let config = manifest
+            .macos
+            .as_ref()
+            .and_then(|m| m.pkg.clone())
+            .unwrap_or_default();
+
+        let install_location = config
+            .install_location
+            .unwrap_or_else(|| INSTALL_LOCATION.to_string());
+        if !install_location.starts_with('/') {
+            return Err(Error::InvalidManifest(format!(
+                "macos.pkg.install-location must be an absolute path, got {}",
+                install_location
+            )));
+        }
+
+        let minimum_os_version = config
+            .minimum_os_version
+            .unwrap_or_else(|| dmg::MINIMUM_SYSTEM_VERSION.to_string());
+        let parts = minimum_os_version.split('.').collect::<Vec<_>>();
+        if parts.len() > 3
+            || parts
+                .iter()
+                .any(|p| p.is_empty() || !p.bytes().all(|b| b.is_ascii_digit()))
+        {
+            return Err(Error::InvalidManifest(format!(
+                "macos.pkg.minimum-os-version must be a version such as \"11.0\", got {}",
+                minimum_os_version
+            )));
+        }
+
+        let bundle_identifier = dmg::bundle_identifier(manifest);
+        Ok(Self {
+            identifier: config
+                .identifier
+                .unwrap_or_else(|| bundle_identifier.clone()),
+            version: manifest.version.clone(),
+            title: manifest.title.clone(),
+            install_location,
+            minimum_os_version,
+            bundle: format!("{}.app", manifest.title),
+            bundle_identifier,
+            component: format!("{}.pkg", manifest.name),
+            preinstall: config.preinstall.map(|p| ctx.base_dir.join(p)),
+            postinstall: config.postinstall.map(|p| ctx.base_dir.join(p)),
+        })
+    }
+
+    fn scripts(&self) -> impl Iterator<Item = (&'static str, &PathBuf)> {
+        [
+            ("preinstall", &self.preinstall),
+            ("postinstall", &self.postinstall),
+        ]
+        .into_iter()
+        .filter_map(|(name, path)| path.as_ref().map(|p| (name, p)))
+    }
+}
+
+pub fn create(ctx: &Context, manifest: &Manifest) -> Result<PathBuf> {
+    status!("Creating installer package for macOS...");
+
+    let spec = PkgSpec::from_manifest(ctx, manifest)?;
+
+    // Ensure output folder exists
+    utils::ensure_dir(&manifest.output_folder)?;
+
+    // Create temporary directory for the payload root and package parts
+    let temp_dir = std::env::temp_dir().join(format!("emerge-pkg-{}", manifest.name));
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir)?;
+    }
+    let root = temp_dir.join("root");
+    fs::create_dir_all(&root)?;
+
+    // Files of the volume location have no volume to go to; keep them in the bundle
+    let app_path = root.join(&spec.bundle);
+    dmg::assemble_app(
+        ctx,
+        manifest,
+        &app_path,
+        &app_path.join("Contents/Resources"),
+    )?;
+
+    let pkg_path = manifest
+        .output_folder
+        .join(format!("{}.pkg", manifest.filename));
+    build(&spec, &root, &temp_dir, &pkg_path)?;
+
+    // Clean up temp directory
+    fs::remove_dir_all(&temp_dir)?;
+
+    status!("Package created successfully: {}", pkg_path.display());
+    Ok(pkg_path)
+}
+
+/// Write the product archive installing the contents of `root` to `output_path`;
+/// `work_dir` receives the intermediate payload and scripts archives
+pub fn build(spec: &PkgSpec, root: &Path, work_dir: &Path, output_path: &Path) -> Result<()> {
+    let mut entries = cpio::entries(root)?;
+    // The root stands for the install location
+    if let Some(root) = entries.first_mut() {
+        root.mode = 0o775;
+        root.gid = ADMIN_GID;
+    }
+
+    let payload_path = work_dir.join("Payload");
+    write_gzip_cpio(&entries, &payload_path)?;
+    let bom = bom::write(&entries)?;
+
+    let scripts_path = if spec.scripts().next().is_some() {
+        let scripts_dir = work_dir.join("scripts");
+        fs::create_dir_all(&scripts_dir)?;
+        for (name, source) in spec.scripts() {
+            let script = fs::read(source).map_err(|e| {
+                Error::Custom(format!(
+                    "Unable to read {} {}: {}",
+                    name,
+                    source.display(),
+                    e
+                ))
+            })?;
+            if !script.starts_with(b"#!") {
+                return Err(Error::Custom(format!(
+                    "The {} script {} must start with an interpreter line such as #!/bin/sh",
+                    name,
+                    source.display()
+                )));
+            }
+            fs::write(scripts_dir.join(name), script)?;
+        }
+
+        let mut scripts = cpio::entries(&scripts_dir)?;
+        for entry in &mut scripts {
+            entry.mode = 0o755;
+        }
+        let path = work_dir.join("Scripts");
+        write_gzip_cpio(&scripts, &path)?;
+        Some(path)
+    } else {
+        None
+    };
+
+    let install_kbytes = entries
+        .iter()
+        .filter(|e| e.kind == Kind::File)
+        .map(|e| e.size.div_ceil(1024))
+        .sum();
+
+    let mut archive = xar::Builder::new();
+    archive.add_data(
+        "Distribution",
+        distribution(spec, install_kbytes).into_bytes(),
+    );
+    let component = |name: &str| format!("{}/{}", spec.component, name);
+    archive.add_data(
+        &component("PackageInfo"),
+        package_info(spec, entries.len(), install_kbytes).into_bytes(),
+    );
+    archive.add_data(&component("Bom"), bom);
+    archive.add_file(&component("Payload"), &payload_path);
+    if let Some(scripts) = &scripts_path {
+        archive.add_file(&component("Scripts"), scripts);
+    }
+
+    let output = archive.write(BufWriter::new(File::create(output_path)?))?;
+    output
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    Ok(())
+}
+
+fn write_gzip_cpio(entries: &[cpio::Entry], path: &Path) -> Result<()> {
+    let encoder = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::best());
+    cpio::write(entries, encoder)?.finish()?.flush()?;
+    Ok(())
+}
+
+/// `PackageInfo` of the component package
+pub fn package_info(spec: &PkgSpec, files: usize, install_kbytes: u64) -> String {
+    let bundle_id = xml_escape(&spec.bundle_identifier);
+    let version = xml_escape(&spec.version);
+
+    let mut xml = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<pkg-info format-version="2" identifier="{}" version="{}" install-location="{}" auth="root" overwrite-permissions="false" relocatable="false">
+    <payload numberOfFiles="{}" installKBytes="{}"/>
+    <bundle path="./{}" id="{}" CFBundleShortVersionString="{}" CFBundleVersion="{}"/>
+    <bundle-version>
+        <bundle id="{}"/>
+    </bundle-version>
+"#,
+        xml_escape(&spec.identifier),
+        version,
+        xml_escape(&spec.install_location),
+        files,
+        install_kbytes,
+        xml_escape(&spec.bundle),
+        bundle_id,
+        version,
+        version,
+        bundle_id,
+    );
+    if spec.scripts().next().is_some() {
+        xml.push_str("    <scripts>\n");
+        for (name, _) in spec.scripts() {
+            xml.push_str(&format!("        <{} file=\"./{}\"/>\n", name, name));
+        }
+        xml.push_str("    </scripts>\n");
+    }
+    xml.push_str("</pkg-info>\n");
+    xml
+}
+
+/// `Distribution` script of the product archive
+pub fn distribution(spec: &PkgSpec, install_kbytes: u64) -> String {
+    let identifier = xml_escape(&spec.identifier);
+    format!(
+        r##"<?xml version="1.0" encoding="utf-8"?>
+<installer-gui-script minSpecVersion="2">
+    <title>{}</title>
+    <options customize="never" require-scripts="false" hostArchitectures="x86_64,arm64"/>
+    <domains enable_anywhere="false" enable_currentUserHome="false" enable_localSystem="true"/>
+    <volume-check>
+        <allowed-os-versions>
+            <os-version min="{}"/>
+        </allowed-os-versions>
+    </volume-check>
+    <choices-outline>
+        <line choice="default">
+            <line choice="{}"/>
+        </line>
+    </choices-outline>
+    <choice id="default"/>
+    <choice id="{}" visible="false">
+        <pkg-ref id="{}"/>
+    </choice>
+    <pkg-ref id="{}" version="{}" onConclusion="none" installKBytes="{}">#{}</pkg-ref>
+</installer-gui-script>
+"##,
+        xml_escape(&spec.title),
+        spec.minimum_os_version,
+        identifier,
+        identifier,
+        identifier,
+        identifier,
+        xml_escape(&spec.version),
+        install_kbytes,
+        xml_escape(&spec.component),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::tests::sample_manifest;
+    use std::io::Read;
+
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        flate2::read::GzDecoder::new(data)
+            .read_to_end(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_spec() {
+        let (ctx, manifest) = sample_manifest("title = \"Sample App\"");
+        let spec = PkgSpec::from_manifest(&ctx, &manifest).unwrap();
+        assert_eq!(spec.identifier, "com.sample.sample");
+        assert_eq!(spec.install_location, "/Applications");
+        assert_eq!(spec.minimum_os_version, "10.13");
+        assert_eq!(spec.bundle, "Sample App.app");
+        assert_eq!(spec.component, "sample.pkg");
+        assert_eq!(spec.preinstall, None);
+
+        let (ctx, manifest) = sample_manifest(
+            r#"
+            [macos.pkg]
+            identifier = "com.example.sample.pkg"
+            install-location = "/Applications/Utilities"
+            postinstall = "scripts/postinstall.sh"
+            minimum-os-version = "12.3"
+            "#,
+        );
+        let spec = PkgSpec::from_manifest(&ctx, &manifest).unwrap();
+        assert_eq!(spec.identifier, "com.example.sample.pkg");
+        assert_eq!(spec.install_location, "/Applications/Utilities");
+        assert_eq!(spec.minimum_os_version, "12.3");
+        assert_eq!(
+            spec.postinstall,
+            Some(PathBuf::from("/work/scripts/postinstall.sh"))
+        );
+
+        for invalid in [
+            "install-location = \"Applications\"",
+            "minimum-os-version = \"12.x\"",
+            "minimum-os-version = \"12..1\"",
+        ] {
+            let (ctx, manifest) = sample_manifest(&format!("[macos.pkg]\n{}", invalid));
+            assert!(
+                PkgSpec::from_manifest(&ctx, &manifest).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join("Sample.app/Contents/MacOS")).unwrap();
+        fs::write(root.join("Sample.app/Contents/Info.plist"), "<plist/>").unwrap();
+        fs::write(
+            root.join("Sample.app/Contents/MacOS/sample"),
+            vec![1u8; 3000],
+        )
+        .unwrap();
+        fs::write(dir.path().join("post.sh"), "#!/bin/sh\nexit 0\n").unwrap();
+
+        let (ctx, manifest) = sample_manifest(
+            "title = \"Sample\"\n[macos.pkg]\npostinstall = \"post.sh\"\nminimum-os-version = \"11.0\"",
+        );
+        let mut spec = PkgSpec::from_manifest(&ctx, &manifest).unwrap();
+        spec.postinstall = Some(dir.path().join("post.sh"));
+
+        let output = dir.path().join("sample.pkg");
+        build(&spec, &root, dir.path(), &output).unwrap();
+
+        let files = xar::read(&fs::read(&output).unwrap());
+        let names = files.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "Distribution",
+                "sample.pkg/PackageInfo",
+                "sample.pkg/Bom",
+                "sample.pkg/Payload",
+                "sample.pkg/Scripts",
+            ]
+        );
+        let file = |name: &str| &files.iter().find(|(n, _)| n == name).unwrap().1;
+
+        let distribution = String::from_utf8(file("Distribution").clone()).unwrap();
+        assert!(distribution.contains("<os-version min=\"11.0\"/>"));
+        assert!(distribution.contains("installKBytes=\"4\">#sample.pkg</pkg-ref>"));
+
+        let package_info = String::from_utf8(file("sample.pkg/PackageInfo").clone()).unwrap();
+        assert!(package_info.contains("install-location=\"/Applications\""));
+        assert!(package_info.contains("<payload numberOfFiles=\"6\" installKBytes=\"4\"/>"));
+        assert!(package_info.contains("<bundle path=\"./Sample.app\" id=\"com.sample.sample\""));
+        assert!(package_info.contains("<postinstall file=\"./postinstall\"/>"));
+        assert!(!package_info.contains("preinstall"));
+
+        // Payload and BOM list the same paths
+        let payload = cpio::read(&gunzip(file("sample.pkg/Payload")));
+        let bom = bom::read_paths(file("sample.pkg/Bom"));
+        assert_eq!(
+            payload.iter().map(|e| &e.0).collect::<Vec<_>>(),
+            bom.iter().map(|e| &e.0).collect::<Vec<_>>()
+        );
+        assert_eq!(payload.len(), 6);
+        let (path, mode, uid, gid, _) = &payload[0];
+        assert_eq!((path.as_str(), *mode, *uid, *gid), (".", 0o040775, 0, 80));
+        let binary = payload
+            .iter()
+            .find(|e| e.0 == "./Sample.app/Contents/MacOS/sample")
+            .unwrap();
+        assert_eq!(binary.4, vec![1u8; 3000]);
+
+        let scripts = cpio::read(&gunzip(file("sample.pkg/Scripts")));
+        assert_eq!(scripts[1].0, "./postinstall");
+        assert_eq!(scripts[1].1 & 0o777, 0o755);
+        assert_eq!(scripts[1].4, b"#!/bin/sh\nexit 0\n");
+    }
+
+    #[test]
+    fn test_script_without_interpreter() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("root")).unwrap();
+        fs::write(dir.path().join("pre.sh"), "echo missing shebang\n").unwrap();
+
+        let (ctx, manifest) = sample_manifest("");
+        let mut spec = PkgSpec::from_manifest(&ctx, &manifest).unwrap();
+        spec.preinstall = Some(dir.path().join("pre.sh"));
+        let err = build(
+            &spec,
+            &dir.path().join("root"),
+            dir.path(),
+            &dir.path().join("out.pkg"),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("interpreter line"));
+    }
+}
@@ -0,0 +1,732 @@
+//! Software license agreement (SLA) shown by Finder before a disk image mounts
+//!
+//! The agreement lives in the resource fork of the UDIF image: an `LPic` table maps
+//! languages to `STR#` button labels and `RTF ` license texts. The resources are
+//! written into the XML property list of the image and the `koly` trailer is updated
+//! to point at it, so no `hdiutil udifrez` (removed from recent macOS) is needed.
+
+use crate::error::Error;
+use crate::result::Result;
+use crate::utils::xml_escape;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::fs;
+use std::path::Path;
+
+/// First resource ID used for the SLA resources
+const RESOURCE_ID: i16 = 5000;
+
+/// Size of the UDIF trailer at the end of the image
+const KOLY_SIZE: usize = 512;
+
+/// Offsets of the XML property list position within the trailer
+const XML_OFFSET: usize = 216;
+const XML_LENGTH: usize = 224;
+
+/// A license text for one language
+#[derive(Debug, Clone)]
+pub struct License {
+    pub language: String,
+    pub rtf: String,
+}
+
+impl License {
+    /// Load a license file, converting Markdown and plain text to RTF
+    pub fn from_file(language: &str, path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            Error::Custom(format!("Failed to read license {}: {}", path.display(), e))
+        })?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let rtf = match extension.as_deref() {
+            Some("rtf") => content,
+            Some("md" | "markdown") => markdown_to_rtf(&content),
+            _ => text_to_rtf(&content),
+        };
+        Ok(Self {
+            language: language.to_string(),
+            rtf,
+        })
+    }
+}
+
+/// Localized strings of the license window for one language
+struct Language {
+    codes: &'static [&'static str],
+    /// Classic Mac OS region code (`verUS`, `verGermany`, ...)
+    region: i16,
+    name: &'static str,
+    agree: &'static str,
+    disagree: &'static str,
+    print: &'static str,
+    save: &'static str,
+    message: &'static str,
+}
+
+/// Supported languages; all of them use the Mac Roman encoding
+const LANGUAGES: &[Language] = &[
+    Language {
+        codes: &["en"],
+        region: 0,
+        name: "English",
+        agree: "Agree",
+        disagree: "Disagree",
+        print: "Print",
+        save: "Save...",
+        message: "If you agree with the terms of this license, click \"Agree\" to access the software. If you do not agree, click \"Disagree\".",
+    },
+    Language {
+        codes: &["fr"],
+        region: 1,
+        name: "Français",
+        agree: "Accepter",
+        disagree: "Refuser",
+        print: "Imprimer",
+        save: "Enregistrer...",
+        message: "Si vous acceptez les termes de la présente licence, cliquez sur \"Accepter\" afin d'accéder au logiciel. Si vous ne les acceptez pas, cliquez sur \"Refuser\".",
+    },
+    Language {
+        codes: &["de"],
+        region: 3,
+        name: "Deutsch",
+        agree: "Akzeptieren",
+        disagree: "Ablehnen",
+        print: "Drucken",
+        save: "Sichern...",
+        message: "Klicken Sie auf \"Akzeptieren\", wenn Sie mit den Bestimmungen dieser Lizenz einverstanden sind. Falls nicht, klicken Sie auf \"Ablehnen\".",
+    },
+    Language {
+        codes: &["it"],
+        region: 4,
+        name: "Italiano",
+        agree: "Accetto",
+        disagree: "Rifiuto",
+        print: "Stampa",
+        save: "Registra...",
+        message: "Se accetti le condizioni di questa licenza, fai clic su \"Accetto\" per accedere al software. Altrimenti fai clic su \"Rifiuto\".",
+    },
+    Language {
+        codes: &["nl"],
+        region: 5,
+        name: "Nederlands",
+        agree: "Ja",
+        disagree: "Nee",
+        print: "Print",
+        save: "Bewaar...",
+        message: "Klik op \"Ja\" als u akkoord gaat met de voorwaarden van deze licentie. Klik anders op \"Nee\".",
+    },
+    Language {
+        codes: &["sv"],
+        region: 7,
+        name: "Svenska",
+        agree: "Godkänns",
+        disagree: "Avböjs",
+        print: "Skriv ut",
+        save: "Spara...",
+        message: "Klicka på \"Godkänns\" om du godkänner villkoren i denna licens. Annars klickar du på \"Avböjs\".",
+    },
+    Language {
+        codes: &["es"],
+        region: 8,
+        name: "Español",
+        agree: "Aceptar",
+        disagree: "No aceptar",
+        print: "Imprimir",
+        save: "Guardar...",
+        message: "Si está de acuerdo con los términos de esta licencia, pulse \"Aceptar\" para acceder al software. En caso contrario, pulse \"No aceptar\".",
+    },
+    Language {
+        codes: &["da"],
+        region: 9,
+        name: "Dansk",
+        agree: "Enig",
+        disagree: "Uenig",
+        print: "Udskriv",
+        save: "Arkiver...",
+        message: "Klik på \"Enig\", hvis du accepterer betingelserne i denne licens. Ellers skal du klikke på \"Uenig\".",
+    },
+    Language {
+        codes: &["pt", "pt-pt"],
+        region: 10,
+        name: "Português",
+        agree: "Concordar",
+        disagree: "Discordar",
+        print: "Imprimir",
+        save: "Guardar...",
+        message: "Se concorda com os termos desta licença, clique em \"Concordar\" para aceder ao software. Caso contrário, clique em \"Discordar\".",
+    },
+    Language {
+        codes: &["nb", "no"],
+        region: 12,
+        name: "Norsk",
+        agree: "Enig",
+        disagree: "Ikke enig",
+        print: "Skriv ut",
+        save: "Arkiver...",
+        message: "Klikk på \"Enig\" hvis du godtar vilkårene i denne lisensen. Ellers klikker du på \"Ikke enig\".",
+    },
+    Language {
+        codes: &["fi"],
+        region: 17,
+        name: "Suomi",
+        agree: "Hyväksyn",
+        disagree: "En hyväksy",
+        print: "Tulosta",
+        save: "Tallenna...",
+        message: "Hyväksy tämän lisenssin ehdot osoittamalla \"Hyväksyn\". Jos et hyväksy ehtoja, osoita \"En hyväksy\".",
+    },
+    Language {
+        codes: &["pt-br"],
+        region: 71,
+        name: "Português do Brasil",
+        agree: "Concordar",
+        disagree: "Discordar",
+        print: "Imprimir",
+        save: "Salvar...",
+        message: "Se você concorda com os termos desta licença, clique em \"Concordar\" para acessar o software. Caso contrário, clique em \"Discordar\".",
+    },
+];
+
+fn language(code: &str) -> Result<&'static Language> {
+    let code = code.to_ascii_lowercase().replace('_', "-");
+    LANGUAGES
+        .iter()
+        .find(|l| l.codes.contains(&code.as_str()))
+        .ok_or_else(|| {
+            let supported = LANGUAGES
+                .iter()
+                .map(|l| l.codes[0])
+                .collect::<Vec<_>>()
+                .join(", ");
+            Error::Custom(format!(
+                "Unsupported DMG license language '{}' (supported: {})",
+                code, supported
+            ))
+        })
+}
+
+/// A resource of the image's resource fork
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resource {
+    pub kind: &'static str,
+    pub id: i16,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Build the `LPic`, `STR#` and `RTF ` resources; English is the default language if present
+pub fn resources(licenses: &[License]) -> Result<Vec<Resource>> {
+    if licenses.is_empty() {
+        return Err(Error::Custom("No DMG license given".to_string()));
+    }
+    let languages = licenses
+        .iter()
+        .map(|l| language(&l.language))
+        .collect::<Result<Vec<_>>>()?;
+    for (i, language) in languages.iter().enumerate() {
+        if languages[..i].iter().any(|l| l.region == language.region) {
+            return Err(Error::Custom(format!(
+                "DMG license language '{}' given twice",
+                language.codes[0]
+            )));
+        }
+    }
+    let default = languages
+        .iter()
+        .find(|l| l.region == 0)
+        .unwrap_or(&languages[0]);
+
+    let mut lpic = Vec::new();
+    lpic.extend_from_slice(&default.region.to_be_bytes());
+    lpic.extend_from_slice(&(languages.len() as i16).to_be_bytes());
+    for (index, language) in languages.iter().enumerate() {
+        lpic.extend_from_slice(&language.region.to_be_bytes());
+        lpic.extend_from_slice(&(index as i16).to_be_bytes());
+        // Two-byte script flag, always off for Mac Roman languages
+        lpic.extend_from_slice(&0i16.to_be_bytes());
+    }
+
+    let mut result = vec![Resource {
+        kind: "LPic",
+        id: RESOURCE_ID,
+        name: String::new(),
+        data: lpic,
+    }];
+    for (index, (language, license)) in languages.iter().zip(licenses).enumerate() {
+        let id = RESOURCE_ID + index as i16;
+        result.push(Resource {
+            kind: "STR#",
+            id,
+            name: language.name.to_string(),
+            data: string_list(&[
+                language.name,
+                language.agree,
+                language.disagree,
+                language.print,
+                language.save,
+                language.message,
+            ])?,
+        });
+        result.push(Resource {
+            kind: "RTF ",
+            id,
+            name: language.name.to_string(),
+            data: license.rtf.as_bytes().to_vec(),
+        });
+    }
+    Ok(result)
+}
+
+/// `STR#` resource: a count followed by Pascal strings
+fn string_list(strings: &[&str]) -> Result<Vec<u8>> {
+    let mut data = (strings.len() as u16).to_be_bytes().to_vec();
+    for string in strings {
+        let encoded = mac_roman(string)?;
+        if encoded.len() > 255 {
+            return Err(Error::Custom(format!("String too long: {}", string)));
+        }
+        data.push(encoded.len() as u8);
+        data.extend_from_slice(&encoded);
+    }
+    Ok(data)
+}
+
+/// Characters 0x80-0xFF of the Mac Roman encoding
+const MAC_ROMAN: &str = "ÄÅÇÉÑÖÜáàâäãåçéèêëíìîïñóòôöõúùûü†°¢£§•¶ß®©™´¨≠ÆØ∞±≤≥¥µ∂∑∏π∫ªºΩæø\
+    ¿¡¬√ƒ≈∆«»…\u{a0}ÀÃÕŒœ–—“”‘’÷◊ÿŸ⁄€‹›ﬁﬂ‡·‚„‰ÂÊÁËÈÍÎÏÌÓÔ\u{f8ff}ÒÚÛÙıˆ˜¯˘˙˚¸˝˛ˇ";
+
+fn mac_roman(value: &str) -> Result<Vec<u8>> {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii() {
+                Ok(c as u8)
+            } else {
+                MAC_ROMAN
+                    .chars()
+                    .position(|m| m == c)
+                    .map(|i| 0x80 + i as u8)
+                    .ok_or_else(|| {
+                        Error::Custom(format!("Character '{}' not representable in Mac Roman", c))
+                    })
+            }
+        })
+        .collect()
+}
+
+/// Resources as entries of the `resource-fork` dictionary of the UDIF property list
+fn resources_xml(resources: &[Resource]) -> String {
+    let mut xml = String::new();
+    let mut kinds = Vec::new();
+    for resource in resources {
+        if !kinds.contains(&resource.kind) {
+            kinds.push(resource.kind);
+        }
+    }
+    for kind in kinds {
+        xml.push_str(&format!("\t\t<key>{}</key>\n\t\t<array>\n", kind));
+        for resource in resources.iter().filter(|r| r.kind == kind) {
+            xml.push_str(&format!(
+                "\t\t\t<dict>\n\
+                 \t\t\t\t<key>Attributes</key>\n\t\t\t\t<string>0x0000</string>\n\
+                 \t\t\t\t<key>Data</key>\n\t\t\t\t<data>{}</data>\n\
+                 \t\t\t\t<key>ID</key>\n\t\t\t\t<string>{}</string>\n\
+                 \t\t\t\t<key>Name</key>\n\t\t\t\t<string>{}</string>\n\
+                 \t\t\t</dict>\n",
+                BASE64.encode(&resource.data),
+                resource.id,
+                xml_escape(&resource.name)
+            ));
+        }
+        xml.push_str("\t\t</array>\n");
+    }
+    xml
+}
+
+/// Add resources to a UDIF image, rewriting its property list and trailer
+pub fn attach(image: &[u8], resources: &[Resource]) -> Result<Vec<u8>> {
+    let invalid = || Error::Custom("Not a UDIF disk image".to_string());
+    if image.len() < KOLY_SIZE || &image[image.len() - KOLY_SIZE..][..4] != b"koly" {
+        return Err(invalid());
+    }
+    let trailer_start = image.len() - KOLY_SIZE;
+    let mut koly = image[trailer_start..].to_vec();
+    let read_u64 = |offset: usize| u64::from_be_bytes(koly[offset..offset + 8].try_into().unwrap());
+    let xml_offset = read_u64(XML_OFFSET) as usize;
+    let xml_length = read_u64(XML_LENGTH) as usize;
+    if xml_length == 0 || xml_offset.saturating_add(xml_length) > trailer_start {
+        return Err(invalid());
+    }
+
+    let plist =
+        std::str::from_utf8(&image[xml_offset..xml_offset + xml_length]).map_err(|_| invalid())?;
+    let fork = plist.find("<key>resource-fork</key>").ok_or_else(invalid)?;
+    let dict = fork + plist[fork..].find("<dict>").ok_or_else(invalid)? + "<dict>".len();
+    if plist[dict..].contains("<key>LPic</key>") {
+        return Err(Error::Custom(
+            "Disk image already has a license agreement".to_string(),
+        ));
+    }
+    let plist = format!(
+        "{}\n{}{}",
+        &plist[..dict],
+        resources_xml(resources),
+        plist[dict..].trim_start_matches('\n')
+    );
+
+    // Replace the property list if it directly precedes the trailer, append it otherwise
+    let data_end = if xml_offset + xml_length == trailer_start {
+        xml_offset
+    } else {
+        trailer_start
+    };
+    let mut output = image[..data_end].to_vec();
+    koly[XML_OFFSET..XML_OFFSET + 8].copy_from_slice(&(output.len() as u64).to_be_bytes());
+    koly[XML_LENGTH..XML_LENGTH + 8].copy_from_slice(&(plist.len() as u64).to_be_bytes());
+    output.extend_from_slice(plist.as_bytes());
+    output.extend_from_slice(&koly);
+    Ok(output)
+}
+
+/// Attach resources to the disk image at `path`
+pub fn attach_file(path: &Path, resources: &[Resource]) -> Result<()> {
+    let image = fs::read(path)?;
+    fs::write(path, attach(&image, resources)?)?;
+    Ok(())
+}
+
+const RTF_HEADER: &str = "{\\rtf1\\ansi\\ansicpg1252\\deff0\
+    {\\fonttbl{\\f0\\fswiss Helvetica;}{\\f1\\fmodern Courier;}}\n\\f0\\fs24\n";
+
+/// Escape text for RTF; non-ASCII characters become `\uN?` escapes
+fn rtf_escape(value: &str) -> String {
+    let mut output = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' | '{' | '}' => {
+                output.push('\\');
+                output.push(c);
+            }
+            '\t' => output.push_str("\\tab "),
+            c if c.is_ascii() => output.push(c),
+            c => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    output.push_str(&format!("\\u{}?", *unit as i16));
+                }
+            }
+        }
+    }
+    output
+}
+
+/// Convert plain text to RTF, keeping its line breaks
+pub fn text_to_rtf(text: &str) -> String {
+    let mut rtf = RTF_HEADER.to_string();
+    for line in text.lines() {
+        rtf.push_str(&rtf_escape(line));
+        rtf.push_str("\\par\n");
+    }
+    rtf.push('}');
+    rtf
+}
+
+/// Convert Markdown to RTF: headings, paragraphs, lists, code blocks and emphasis
+pub fn markdown_to_rtf(markdown: &str) -> String {
+    let mut rtf = RTF_HEADER.to_string();
+    let mut paragraph = Vec::new();
+    let mut code = false;
+
+    let flush = |rtf: &mut String, paragraph: &mut Vec<&str>| {
+        if !paragraph.is_empty() {
+            rtf.push_str(&inline_rtf(&paragraph.join(" ")));
+            rtf.push_str("\\par\\par\n");
+            paragraph.clear();
+        }
+    };
+
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            flush(&mut rtf, &mut paragraph);
+            if code {
+                rtf.push_str("\\f0\\par\n");
+            } else {
+                rtf.push_str("\\f1 ");
+            }
+            code = !code;
+        } else if code {
+            rtf.push_str(&rtf_escape(line));
+            rtf.push_str("\\line\n");
+        } else if trimmed.is_empty() {
+            flush(&mut rtf, &mut paragraph);
+        } else if let Some((level, title)) = heading(trimmed) {
+            flush(&mut rtf, &mut paragraph);
+            let size = match level {
+                1 => 32,
+                2 => 28,
+                _ => 24,
+            };
+            rtf.push_str(&format!(
+                "{{\\b\\fs{} {}}}\\par\\par\n",
+                size,
+                inline_rtf(title)
+            ));
+        } else if trimmed.chars().all(|c| matches!(c, '-' | '*' | '_' | ' ')) && trimmed.len() >= 3
+        {
+            flush(&mut rtf, &mut paragraph);
+        } else if let Some(item) = list_item(trimmed) {
+            flush(&mut rtf, &mut paragraph);
+            rtf.push_str(&format!(
+                "\\pard\\li360\\fi-240 {}\\tab {}\\par\\pard\n",
+                item.0,
+                inline_rtf(item.1)
+            ));
+        } else {
+            paragraph.push(trimmed);
+        }
+    }
+    flush(&mut rtf, &mut paragraph);
+    rtf.push('}');
+    rtf
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let title = line[level..].strip_prefix(' ')?;
+    (1..=6)
+        .contains(&level)
+        .then(|| (level, title.trim().trim_end_matches('#').trim_end()))
+}
+
+/// Bullet or numbered list item: (RTF marker, text)
+fn list_item(line: &str) -> Option<(String, &str)> {
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(text) = line.strip_prefix(bullet) {
+            return Some(("\\bullet".to_string(), text));
+        }
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    let text = line[digits..].strip_prefix(". ")?;
+    (digits > 0).then(|| (format!("{}.", &line[..digits]), text))
+}
+
+/// Inline Markdown: `**bold**`, `*italic*`, `` `code` `` and `[text](url)`
+fn inline_rtf(text: &str) -> String {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '\\'
+            && let Some(next) = rest[1..].chars().next()
+            && next.is_ascii_punctuation()
+        {
+            output.push_str(&rtf_escape(&next.to_string()));
+            rest = &rest[1 + next.len_utf8()..];
+            continue;
+        }
+        let delimited = |open: &str, close: &str| {
+            let inner = rest.strip_prefix(open)?;
+            let end = inner.find(close)?;
+            (end > 0).then(|| (&inner[..end], &inner[end + close.len()..]))
+        };
+        if let Some((inner, after)) = delimited("**", "**").or_else(|| delimited("__", "__")) {
+            output.push_str(&format!("{{\\b {}}}", inline_rtf(inner)));
+            rest = after;
+        } else if let Some((inner, after)) = delimited("*", "*").or_else(|| delimited("_", "_")) {
+            output.push_str(&format!("{{\\i {}}}", inline_rtf(inner)));
+            rest = after;
+        } else if let Some((inner, after)) = delimited("`", "`") {
+            output.push_str(&format!("{{\\f1 {}}}", rtf_escape(inner)));
+            rest = after;
+        } else if let Some((label, after)) = delimited("[", "](")
+            && let Some(end) = after.find(')')
+        {
+            let url = &after[..end];
+            output.push_str(&inline_rtf(label));
+            if url != label {
+                output.push_str(&format!(" ({})", rtf_escape(url)));
+            }
+            rest = &after[end + 1..];
+        } else {
+            output.push_str(&rtf_escape(&c.to_string()));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn license(language: &str) -> License {
+        License {
+            language: language.to_string(),
+            rtf: text_to_rtf("Terms"),
+        }
+    }
+
+    /// Parse a `STR#` resource back into strings (Mac Roman decoded)
+    fn parse_strings(data: &[u8]) -> Vec<String> {
+        let count = u16::from_be_bytes([data[0], data[1]]) as usize;
+        let mut offset = 2;
+        (0..count)
+            .map(|_| {
+                let len = data[offset] as usize;
+                let bytes = &data[offset + 1..offset + 1 + len];
+                offset += 1 + len;
+                bytes
+                    .iter()
+                    .map(|b| {
+                        if b.is_ascii() {
+                            *b as char
+                        } else {
+                            MAC_ROMAN.chars().nth((*b - 0x80) as usize).unwrap()
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mac_roman_table() {
+        assert_eq!(MAC_ROMAN.chars().count(), 128);
+        assert_eq!(mac_roman("Ä").unwrap(), [0x80]);
+        assert_eq!(mac_roman("é").unwrap(), [0x8e]);
+        assert_eq!(mac_roman("ß").unwrap(), [0xa7]);
+        assert_eq!(mac_roman("“").unwrap(), [0xd2]);
+        assert_eq!(mac_roman("ˇ").unwrap(), [0xff]);
+        assert!(mac_roman("日本").is_err());
+    }
+
+    #[test]
+    fn test_resources() {
+        let resources = resources(&[license("de"), license("en"), license("pt_BR")]).unwrap();
+
+        // LPic: default region (English), count, then (region, offset, two-byte) per language
+        assert_eq!(resources[0].kind, "LPic");
+        assert_eq!(resources[0].id, 5000);
+        assert_eq!(
+            resources[0].data,
+            [
+                0, 0, 0, 3, 0, 3, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 71, 0, 2, 0, 0
+            ]
+        );
+
+        let kinds = resources.iter().map(|r| (r.kind, r.id)).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                ("LPic", 5000),
+                ("STR#", 5000),
+                ("RTF ", 5000),
+                ("STR#", 5001),
+                ("RTF ", 5001),
+                ("STR#", 5002),
+                ("RTF ", 5002),
+            ]
+        );
+
+        let german = parse_strings(&resources[1].data);
+        assert_eq!(german.len(), 6);
+        assert_eq!(german[..3], ["Deutsch", "Akzeptieren", "Ablehnen"]);
+        assert_eq!(resources[1].name, "Deutsch");
+        assert_eq!(parse_strings(&resources[5].data)[0], "Português do Brasil");
+        assert!(resources[2].data.starts_with(b"{\\rtf1"));
+    }
+
+    #[test]
+    fn test_all_languages_encodable() {
+        for language in LANGUAGES {
+            let resources = resources(&[license(language.codes[0])]).unwrap();
+            assert_eq!(parse_strings(&resources[1].data)[5], language.message);
+        }
+    }
+
+    #[test]
+    fn test_invalid_languages() {
+        assert!(resources(&[]).is_err());
+        assert!(resources(&[license("tlh")]).is_err());
+        assert!(resources(&[license("nb"), license("no")]).is_err());
+    }
+
+    /// Minimal UDIF image: data, property list and trailer
+    fn image(data: &[u8], plist: &str) -> Vec<u8> {
+        let mut image = data.to_vec();
+        let mut koly = vec![0; KOLY_SIZE];
+        koly[..4].copy_from_slice(b"koly");
+        koly[XML_OFFSET..XML_OFFSET + 8].copy_from_slice(&(image.len() as u64).to_be_bytes());
+        koly[XML_LENGTH..XML_LENGTH + 8].copy_from_slice(&(plist.len() as u64).to_be_bytes());
+        koly[492..500].copy_from_slice(&42u64.to_be_bytes());
+        image.extend_from_slice(plist.as_bytes());
+        image.extend_from_slice(&koly);
+        image
+    }
+
+    const PLIST: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<plist version=\"1.0\">\n<dict>\n\
+        \t<key>resource-fork</key>\n\t<dict>\n\t\t<key>blkx</key>\n\t\t<array>\n\t\t</array>\n\t</dict>\n\
+        </dict>\n</plist>\n";
+
+    #[test]
+    fn test_attach() {
+        let data = vec![0xab; 1000];
+        let resources = resources(&[license("en")]).unwrap();
+        let output = attach(&image(&data, PLIST), &resources).unwrap();
+
+        assert_eq!(&output[..1000], &data[..]);
+        let koly = &output[output.len() - KOLY_SIZE..];
+        assert_eq!(&koly[..4], b"koly");
+        assert_eq!(&koly[492..500], &42u64.to_be_bytes());
+        let offset = u64::from_be_bytes(koly[XML_OFFSET..XML_OFFSET + 8].try_into().unwrap());
+        let length = u64::from_be_bytes(koly[XML_LENGTH..XML_LENGTH + 8].try_into().unwrap());
+        assert_eq!(offset, 1000);
+        assert_eq!(offset + length, (output.len() - KOLY_SIZE) as u64);
+
+        let plist = std::str::from_utf8(&output[1000..1000 + length as usize]).unwrap();
+        let fork = plist.find("<key>resource-fork</key>").unwrap();
+        let lpic = plist.find("<key>LPic</key>").unwrap();
+        assert!(lpic > fork && lpic < plist.find("<key>blkx</key>").unwrap());
+        assert!(plist.contains("<key>RTF </key>"));
+        assert!(plist.contains(&format!(
+            "<data>{}</data>",
+            BASE64.encode(&resources[0].data)
+        )));
+        assert!(plist.contains("<string>English</string>"));
+
+        // A second license is refused, garbage is not an image
+        assert!(attach(&output, &resources).is_err());
+        assert!(attach(&data, &resources).is_err());
+    }
+
+    #[test]
+    fn test_text_to_rtf() {
+        assert_eq!(
+            text_to_rtf("A {b}\\c\n\nÜ €"),
+            format!(
+                "{}A \\{{b\\}}\\\\c\\par\n\\par\n\\u220? \\u8364?\\par\n}}",
+                RTF_HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn test_markdown_to_rtf() {
+        let rtf = markdown_to_rtf(
+            "# License\n\nSome **bold** and *italic*\ntext with `code`.\n\n\
+             - First\n2. Second\n\nSee [site](https://example.com).\n\n```\nraw *x*\n```\n",
+        );
+        let body = rtf.strip_prefix(RTF_HEADER).unwrap();
+        assert_eq!(
+            body,
+            "{\\b\\fs32 License}\\par\\par\n\
+             Some {\\b bold} and {\\i italic} text with {\\f1 code}.\\par\\par\n\
+             \\pard\\li360\\fi-240 \\bullet\\tab First\\par\\pard\n\
+             \\pard\\li360\\fi-240 2.\\tab Second\\par\\pard\n\
+             See site (https://example.com).\\par\\par\n\
+             \\f1 raw *x*\\line\n\\f0\\par\n}"
+        );
+    }
+}
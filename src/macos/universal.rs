@@ -0,0 +1,188 @@
+use crate::context::Context;
+use crate::error::Error;
+use crate::macos::macho::{self, CPU_TYPE_ARM64, CPU_TYPE_X86_64, FAT_MAGIC, MachO};
+use crate::manifest::Manifest;
+use crate::result::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Capability bits in the upper byte of `cpusubtype`
+const CPU_SUBTYPE_MASK: u32 = 0xff00_0000;
+
+/// Merge the `universal` copy rules into fat Mach-O files below `dest_dir`
+pub fn install(ctx: &Context, manifest: &Manifest, dest_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+
+    for (sources, dst) in &manifest.universal_binaries {
+        let dest_path = dest_dir.join(dst);
+        if ctx.verbose {
+            status!(
+                "Merging {} into {}",
+                sources
+                    .iter()
+                    .map(|s| s.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                dest_path.display()
+            );
+        }
+
+        let inputs = sources
+            .iter()
+            .map(|src| {
+                fs::read(src)
+                    .map_err(|e| Error::Custom(format!("Unable to read {}: {}", src.display(), e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&dest_path, merge(&inputs)?)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&dest_path, fs::Permissions::from_mode(0o755))?;
+        }
+
+        written.push(dest_path);
+    }
+
+    Ok(written)
+}
+
+/// Combine thin or fat Mach-O files into one fat file, like `lipo -create`
+pub fn merge(inputs: &[Vec<u8>]) -> Result<Vec<u8>> {
+    // (cputype, cpusubtype, image)
+    let mut archs: Vec<(u32, u32, &[u8])> = Vec::new();
+    for input in inputs {
+        for range in macho::slices(input)? {
+            let slice = &input[range];
+            let header = MachO::parse(slice)?;
+            if archs.iter().any(|(cputype, cpusubtype, _)| {
+                *cputype == header.cputype
+                    && cpusubtype & !CPU_SUBTYPE_MASK == header.cpusubtype & !CPU_SUBTYPE_MASK
+            }) {
+                return Err(Error::Custom(format!(
+                    "Duplicate architecture {} in universal binary",
+                    arch_name(header.cputype)
+                )));
+            }
+            archs.push((header.cputype, header.cpusubtype, slice));
+        }
+    }
+
+    if archs.is_empty() {
+        return Err(Error::Custom(
+            "A universal binary needs at least one architecture".to_string(),
+        ));
+    }
+
+    // Same order as lipo: by alignment, then by CPU type
+    archs.sort_by_key(|(cputype, cpusubtype, _)| (alignment(*cputype), *cputype, *cpusubtype));
+
+    let mut output = Vec::new();
+    output.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+    output.extend_from_slice(&(archs.len() as u32).to_be_bytes());
+
+    let mut offset = 8 + 20 * archs.len();
+    let mut entries = Vec::with_capacity(archs.len());
+    for (cputype, cpusubtype, image) in &archs {
+        let align = alignment(*cputype);
+        offset = offset.next_multiple_of(1 << align);
+        entries.push(offset);
+        for value in [
+            *cputype,
+            *cpusubtype,
+            u32::try_from(offset).map_err(|_| too_large())?,
+            u32::try_from(image.len()).map_err(|_| too_large())?,
+            align,
+        ] {
+            output.extend_from_slice(&value.to_be_bytes());
+        }
+        offset += image.len();
+    }
+    u32::try_from(offset).map_err(|_| too_large())?;
+
+    for ((_, _, image), start) in archs.iter().zip(entries) {
+        output.resize(start, 0);
+        output.extend_from_slice(image);
+    }
+
+    Ok(output)
+}
+
+/// Slice alignment (power of two): the page size of the architecture
+fn alignment(cputype: u32) -> u32 {
+    if cputype == CPU_TYPE_ARM64 { 14 } else { 12 }
+}
+
+fn arch_name(cputype: u32) -> String {
+    match cputype {
+        CPU_TYPE_X86_64 => "x86_64".to_string(),
+        CPU_TYPE_ARM64 => "arm64".to_string(),
+        other => format!("cputype {:#x}", other),
+    }
+}
+
+fn too_large() -> Error {
+    Error::Custom("Universal binary exceeds 4 GiB".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macos::macho::tests::fixture;
+    use goblin::mach::{Mach, MultiArch};
+
+    #[test]
+    fn test_merge() {
+        let x86_64 = fs::read(fixture("app")).unwrap();
+        let arm64 = fs::read(fixture("app-arm64")).unwrap();
+
+        let fat = merge(&[arm64.clone(), x86_64.clone()]).unwrap();
+
+        let ranges = macho::slices(&fat).unwrap();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(&fat[ranges[0].clone()], &x86_64[..]);
+        assert_eq!(&fat[ranges[1].clone()], &arm64[..]);
+        assert_eq!(ranges[0].start % (1 << 12), 0);
+        assert_eq!(ranges[1].start % (1 << 14), 0);
+
+        let Mach::Fat(multi) = Mach::parse(&fat).unwrap() else {
+            panic!("expected a fat binary");
+        };
+        let arches = MultiArch::arches(&multi).unwrap();
+        assert_eq!(arches[0].cputype, CPU_TYPE_X86_64);
+        assert_eq!(arches[1].cputype, CPU_TYPE_ARM64);
+        assert_eq!(arches[1].align, 14);
+
+        // Dylib lookup keeps working on the merged file
+        assert_eq!(
+            macho::dylibs(&fat).unwrap(),
+            macho::dylibs(&x86_64).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_merge_fat_input() {
+        let x86_64 = fs::read(fixture("app")).unwrap();
+        let arm64 = fs::read(fixture("app-arm64")).unwrap();
+
+        let fat = merge(&[x86_64, arm64]).unwrap();
+        assert_eq!(merge(std::slice::from_ref(&fat)).unwrap(), fat);
+    }
+
+    #[test]
+    fn test_merge_duplicate_architecture() {
+        let x86_64 = fs::read(fixture("app")).unwrap();
+        let err = merge(&[x86_64.clone(), x86_64]).unwrap_err();
+        assert!(err.to_string().contains("x86_64"));
+    }
+
+    #[test]
+    fn test_merge_rejects_non_macho() {
+        assert!(merge(&[b"#!/bin/sh\n".to_vec()]).is_err());
+    }
+}
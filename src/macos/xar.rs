@@ -0,0 +1,294 @@
+//! xar archives, the container of flat installer packages
+//!
+//! A 28-byte header is followed by the zlib-compressed XML table of contents and the
+//! heap. The heap starts with the SHA-1 of the compressed table of contents; file data
+//! is stored as is and located by its offset in the heap.
+
+use crate::result::Result;
+use crate::utils;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"xar!";
+const HEADER_SIZE: u16 = 28;
+const VERSION: u16 = 1;
+const CHECKSUM_SHA1: u32 = 1;
+const SHA1_SIZE: u64 = 20;
+
+enum Data {
+    Bytes(Vec<u8>),
+    File(PathBuf),
+}
+
+impl Data {
+    fn copy_to<W: Write>(&self, writer: &mut W) -> Result<u64> {
+        Ok(match self {
+            Data::Bytes(bytes) => io::copy(&mut bytes.as_slice(), writer)?,
+            Data::File(path) => io::copy(&mut File::open(path)?, writer)?,
+        })
+    }
+}
+
+/// Directory tree of the table of contents, with indices of the stored files
+#[derive(Default)]
+struct Node {
+    name: String,
+    file: Option<usize>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn insert(&mut self, path: &str, file: usize) {
+        match path.split_once('/') {
+            Some((directory, rest)) => {
+                let position = match self.children.iter().position(|c| c.name == directory) {
+                    Some(position) => position,
+                    None => {
+                        self.children.push(Node {
+                            name: directory.to_string(),
+                            ..Default::default()
+                        });
+                        self.children.len() - 1
+                    }
+                };
+                self.children[position].insert(rest, file);
+            }
+            None => self.children.push(Node {
+                name: path.to_string(),
+                file: Some(file),
+                children: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Location and checksum of a file in the heap
+struct Stored {
+    offset: u64,
+    length: u64,
+    sha1: String,
+}
+
+/// Files added by path (`/`-separated); directories are created as needed
+#[derive(Default)]
+pub struct Builder {
+    files: Vec<(String, Data)>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_data(&mut self, path: &str, data: Vec<u8>) {
+        self.files.push((path.to_string(), Data::Bytes(data)));
+    }
+
+    /// Add a file, streamed from `source` when the archive is written
+    pub fn add_file(&mut self, path: &str, source: &Path) {
+        self.files
+            .push((path.to_string(), Data::File(source.to_path_buf())));
+    }
+
+    pub fn write<W: Write>(self, mut writer: W) -> Result<W> {
+        // File data follows the table of contents checksum in the heap
+        let mut stored = Vec::with_capacity(self.files.len());
+        let mut offset = SHA1_SIZE;
+        for (_, data) in &self.files {
+            let mut hasher = HashWriter(Sha1::new());
+            let length = data.copy_to(&mut hasher)?;
+            stored.push(Stored {
+                offset,
+                length,
+                sha1: format!("{:x}", hasher.0.finalize()),
+            });
+            offset += length;
+        }
+
+        let mut root = Node::default();
+        for (index, (path, _)) in self.files.iter().enumerate() {
+            root.insert(path, index);
+        }
+        let toc = toc(&root, &stored);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(toc.as_bytes())?;
+        let compressed = encoder.finish()?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&HEADER_SIZE.to_be_bytes())?;
+        writer.write_all(&VERSION.to_be_bytes())?;
+        writer.write_all(&(compressed.len() as u64).to_be_bytes())?;
+        writer.write_all(&(toc.len() as u64).to_be_bytes())?;
+        writer.write_all(&CHECKSUM_SHA1.to_be_bytes())?;
+        writer.write_all(&compressed)?;
+        writer.write_all(&Sha1::digest(&compressed))?;
+        for ((path, data), stored) in self.files.iter().zip(&stored) {
+            if data.copy_to(&mut writer)? != stored.length {
+                return Err(crate::error::Error::Custom(format!(
+                    "{} changed while it was archived",
+                    path
+                )));
+            }
+        }
+        Ok(writer)
+    }
+}
+
+/// Sink computing the SHA-1 of the written data
+struct HashWriter(Sha1);
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// XML table of contents, one element per line
+fn toc(root: &Node, stored: &[Stored]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<xar>\n<toc>\n");
+    xml.push_str("<checksum style=\"sha1\">\n<offset>0</offset>\n");
+    xml.push_str(&format!("<size>{}</size>\n</checksum>\n", SHA1_SIZE));
+
+    let mut id = 0;
+    for node in &root.children {
+        file_element(&mut xml, node, stored, &mut id);
+    }
+    xml.push_str("</toc>\n</xar>\n");
+    xml
+}
+
+fn file_element(xml: &mut String, node: &Node, stored: &[Stored], id: &mut usize) {
+    *id += 1;
+    xml.push_str(&format!("<file id=\"{}\">\n", id));
+    xml.push_str(&format!("<name>{}</name>\n", utils::xml_escape(&node.name)));
+    match node.file.map(|index| &stored[index]) {
+        Some(stored) => {
+            xml.push_str("<type>file</type>\n<mode>0644</mode>\n<data>\n");
+            xml.push_str(&format!("<offset>{}</offset>\n", stored.offset));
+            xml.push_str(&format!("<length>{}</length>\n", stored.length));
+            xml.push_str(&format!("<size>{}</size>\n", stored.length));
+            xml.push_str("<encoding style=\"application/octet-stream\"/>\n");
+            for kind in ["archived", "extracted"] {
+                xml.push_str(&format!(
+                    "<{}-checksum style=\"sha1\">{}</{}-checksum>\n",
+                    kind, stored.sha1, kind
+                ));
+            }
+            xml.push_str("</data>\n");
+        }
+        None => xml.push_str("<type>directory</type>\n<mode>0755</mode>\n"),
+    }
+    for child in &node.children {
+        file_element(xml, child, stored, id);
+    }
+    xml.push_str("</file>\n");
+}
+
+/// Files of an archive as (path, contents), after checking the header and checksums
+#[cfg(test)]
+pub fn read(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+    use std::io::Read;
+
+    assert_eq!(&archive[..4], MAGIC);
+    assert_eq!(u16::from_be_bytes([archive[4], archive[5]]), HEADER_SIZE);
+    let compressed_length = u64::from_be_bytes(archive[8..16].try_into().unwrap()) as usize;
+    let compressed = &archive[HEADER_SIZE as usize..HEADER_SIZE as usize + compressed_length];
+    let heap = &archive[HEADER_SIZE as usize + compressed_length..];
+    assert_eq!(
+        &heap[..SHA1_SIZE as usize],
+        Sha1::digest(compressed).as_slice()
+    );
+
+    let mut toc = String::new();
+    flate2::read::ZlibDecoder::new(compressed)
+        .read_to_string(&mut toc)
+        .unwrap();
+
+    let value = |line: &str, tag: &str| {
+        line.strip_prefix(&format!("<{}>", tag))
+            .and_then(|l| l.strip_suffix(&format!("</{}>", tag)))
+            .map(|v| v.to_string())
+    };
+
+    // Directory stack of (name, data offset, data length)
+    let mut stack: Vec<(String, Option<usize>, Option<usize>)> = Vec::new();
+    let mut files = Vec::new();
+    for line in toc.lines() {
+        if line.starts_with("<file ") {
+            stack.push(Default::default());
+        } else if line == "</file>" {
+            let (_, offset, length) = stack.last().unwrap().clone();
+            if let (Some(offset), Some(length)) = (offset, length) {
+                let path = stack
+                    .iter()
+                    .map(|(name, _, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let data = heap[offset..offset + length].to_vec();
+                files.push((path, data));
+            }
+            stack.pop();
+        } else if let Some(top) = stack.last_mut() {
+            if let Some(name) = value(line, "name") {
+                top.0 = name;
+            } else if let Some(offset) = value(line, "offset") {
+                top.1 = offset.parse().ok();
+            } else if let Some(length) = value(line, "length") {
+                top.2 = length.parse().ok();
+            } else if let Some(sha1) = line
+                .strip_prefix("<extracted-checksum style=\"sha1\">")
+                .and_then(|l| l.strip_suffix("</extracted-checksum>"))
+            {
+                let (offset, length) = (top.1.unwrap(), top.2.unwrap());
+                let digest = Sha1::digest(&heap[offset..offset + length]);
+                assert_eq!(format!("{:x}", digest), sha1);
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = dir.path().join("Payload");
+        std::fs::write(&payload, vec![7u8; 1000]).unwrap();
+
+        let mut builder = Builder::new();
+        builder.add_data("Distribution", b"<installer-gui-script/>".to_vec());
+        builder.add_data("sample.pkg/PackageInfo", b"<pkg-info/>".to_vec());
+        builder.add_file("sample.pkg/Payload", &payload);
+        let archive = builder.write(Vec::new()).unwrap();
+
+        assert_eq!(&archive[..8], b"xar!\0\x1c\0\x01");
+        assert_eq!(&archive[24..28], &CHECKSUM_SHA1.to_be_bytes());
+        assert_eq!(
+            read(&archive),
+            [
+                (
+                    "Distribution".to_string(),
+                    b"<installer-gui-script/>".to_vec()
+                ),
+                (
+                    "sample.pkg/PackageInfo".to_string(),
+                    b"<pkg-info/>".to_vec()
+                ),
+                ("sample.pkg/Payload".to_string(), vec![7u8; 1000]),
+            ]
+        );
+    }
+}
@@ -1,20 +1,32 @@
+// Declared first so that `status!` is available in every module
+#[macro_use]
+mod output;
+
+mod archive;
 mod args;
+mod changelog;
+mod checksum;
 mod cmd;
 mod context;
+mod deps;
 mod error;
+mod icon;
+mod layout;
+mod licenses;
 mod manifest;
+mod minisign;
 mod platform;
+mod release;
 mod result;
+mod signing;
 mod tpl;
+mod updates;
 mod utils;
 
-#[cfg(target_os = "macos")]
-mod macos;
-
-// Linux module is always included for tar.gz support on all platforms
+// Platform modules are always included so that packages and installer
+// scripts can be generated from any host; tools are checked at runtime
 mod linux;
-
-#[cfg(target_os = "windows")]
+mod macos;
 mod windows;
 
 use args::Args;
@@ -32,26 +44,50 @@ fn main() {
 fn run() -> result::Result<()> {
     // Parse command-line arguments
     let Args {
+        verify,
+        keygen,
         verbose,
+        json,
         archive: archive_flag,
+        archive_format,
+        archive_level,
+        archive_threads,
         dmg: dmg_flag,
+        nsis: nsis_flag,
+        inno: inno_flag,
+        run: run_flag,
+        pkg: pkg_flag,
         no_build,
         path,
         manifest: emerge_manifest,
     } = Args::parse();
 
+    output::set_json(json);
+
+    // `cargo emerge keygen` needs no project
+    if let Some(keygen) = keygen {
+        output::intro("emerge")?;
+        let public_key = minisign::keygen(&keygen.secret_key, &keygen.public_key, keygen.force)?;
+        status!("Secret key: {}", keygen.secret_key.display());
+        status!("Public key: {}", keygen.public_key.display());
+        status!("{}", public_key.to_base64());
+        status!("Sparkle SUPublicEDKey: {}", public_key.to_ed25519_base64());
+        output::outro(format!("Created minisign key {}", public_key.id()))?;
+        return Ok(());
+    }
+
     // Find Cargo.toml
     let manifest_path = utils::find_manifest(path.as_deref())?;
 
     // Create context
     let ctx = Context::new(manifest_path, verbose);
 
-    // Use cliclack for nice UI
-    cliclack::intro("emerge")?;
+    // Use cliclack for nice UI, unless JSON records are requested
+    output::intro("emerge")?;
 
     // Load manifest
-    let manifest = {
-        let spinner = cliclack::spinner();
+    let mut manifest = {
+        let spinner = output::Progress::spinner();
         spinner.start("Loading manifest...");
         let result = if let Some(emerge_path) = emerge_manifest {
             Manifest::load_with_emerge_manifest(&ctx, &emerge_path)
@@ -70,9 +106,32 @@ fn run() -> result::Result<()> {
         }
     };
 
+    // Archive settings given on the command line override the manifest
+    if archive_format.is_some() || archive_level.is_some() || archive_threads.is_some() {
+        let config = manifest.archive.get_or_insert_default();
+        config.format = archive_format.or(config.format);
+        config.level = archive_level.or(config.level);
+        config.threads = archive_threads.or(config.threads);
+    }
+    let archive_flag = archive_flag || archive_format.is_some();
+
+    // `cargo emerge verify` only checks what earlier runs produced
+    if verify {
+        return verify_artifacts(&ctx, &manifest);
+    }
+
+    // Release notes of this version, shipped in every package
+    manifest.release_notes = changelog::load(&ctx, &manifest)?;
+    if let Some(path) = changelog::write(&manifest)? {
+        manifest.include_file(path, changelog::RELEASE_NOTES_FILE.into());
+    }
+
+    // Load the signing key before building, so that a wrong password fails early
+    let minisign_key = minisign::SecretKey::from_manifest(&ctx, &manifest)?;
+
     // Execute build commands unless --no-build is specified
     if !no_build && !manifest.build_commands.is_empty() {
-        let spinner = cliclack::spinner();
+        let spinner = output::Progress::spinner();
         spinner.start("Building application...");
 
         for command in &manifest.build_commands {
@@ -94,67 +153,194 @@ fn run() -> result::Result<()> {
         spinner.stop("Build completed");
     }
 
+    // Licenses of the dependencies, read from Cargo.lock after the build updated it
+    if manifest.licenses.is_some() {
+        let spinner = output::Progress::spinner();
+        spinner.start("Collecting third-party licenses...");
+        match licenses::write(&ctx, &manifest) {
+            Ok(Some(path)) => {
+                spinner.stop(format!(
+                    "Third-party licenses written to {}",
+                    path.display()
+                ));
+                let name = path.file_name().unwrap_or_default().into();
+                manifest.include_file(path, name);
+            }
+            Ok(None) => spinner.stop("No third-party licenses"),
+            Err(e) => {
+                spinner.error("License check failed");
+                return Err(e);
+            }
+        }
+    }
+
     // Determine what to build
     let current_platform = Platform::current();
+    let mut outputs = Vec::new();
 
-    if archive_flag {
+    if nsis_flag || inno_flag {
+        // Windows installer scripts can be generated on any platform
+        if nsis_flag {
+            let spinner = output::Progress::spinner();
+            spinner.start("Creating NSIS installer...");
+            if let Some(path) = windows::installer::create_nsis(&ctx, &manifest)? {
+                outputs.push(release::Output::new(path, Platform::Windows, "nsis"));
+            }
+            spinner.stop("NSIS installer created");
+        }
+
+        if inno_flag {
+            let spinner = output::Progress::spinner();
+            spinner.start("Creating Inno Setup installer...");
+            if let Some(path) = windows::installer::create_inno(&ctx, &manifest)? {
+                outputs.push(release::Output::new(path, Platform::Windows, "inno"));
+            }
+            spinner.stop("Inno Setup installer created");
+        }
+    } else if run_flag {
+        // Self-extracting installers only need sh and tar on the target system
+        let spinner = output::Progress::spinner();
+        spinner.start("Creating self-extracting installer...");
+        let path = linux::run::create_run(&ctx, &manifest)?;
+        outputs.push(release::Output::new(path, Platform::Linux, "run"));
+        spinner.stop("Installer created successfully");
+    } else if pkg_flag {
+        // Packages are written without Apple tools, so they can be built on any platform
+        let spinner = output::Progress::spinner();
+        spinner.start("Creating installer package...");
+        let path = macos::pkg::create(&ctx, &manifest)?;
+        outputs.push(release::Output::new(path, Platform::MacOS, "pkg"));
+        spinner.stop("Package created successfully");
+    } else if archive_flag {
         // Create archive based on platform
-        create_archive(&ctx, &manifest, current_platform)?;
+        outputs.push(create_archive(&ctx, &manifest, current_platform)?);
     } else if dmg_flag || current_platform == Platform::MacOS {
         // Create DMG (default on macOS)
         if current_platform != Platform::MacOS {
-            cliclack::outro_cancel("DMG creation is only available on macOS")?;
+            output::outro_cancel("DMG creation is only available on macOS")?;
             return Ok(());
         }
 
-        let spinner = cliclack::spinner();
+        let spinner = output::Progress::spinner();
         spinner.start("Creating DMG...");
 
-        #[cfg(target_os = "macos")]
-        macos::dmg::create(&ctx, &manifest)?;
+        let path = macos::dmg::create(&ctx, &manifest)?;
+        outputs.push(release::Output::new(path, Platform::MacOS, "dmg"));
 
         spinner.stop("DMG created successfully");
     } else {
         // Default behavior based on platform
         match current_platform {
             Platform::MacOS => {
-                let spinner = cliclack::spinner();
+                let spinner = output::Progress::spinner();
                 spinner.start("Creating DMG...");
 
-                #[cfg(target_os = "macos")]
-                macos::dmg::create(&ctx, &manifest)?;
+                let path = macos::dmg::create(&ctx, &manifest)?;
+                outputs.push(release::Output::new(path, Platform::MacOS, "dmg"));
 
                 spinner.stop("DMG created successfully");
             }
             Platform::Linux => {
-                create_archive(&ctx, &manifest, current_platform)?;
+                outputs.push(create_archive(&ctx, &manifest, current_platform)?);
             }
             Platform::Windows => {
-                create_archive(&ctx, &manifest, current_platform)?;
+                outputs.push(create_archive(&ctx, &manifest, current_platform)?);
             }
         }
     }
 
-    cliclack::outro("Setup package created successfully!")?;
+    if !outputs.is_empty() {
+        let artifacts = outputs.iter().map(|o| o.path.clone()).collect::<Vec<_>>();
+        let signatures = match &minisign_key {
+            Some(key) => minisign::sign_artifacts(key, &manifest, &artifacts)?,
+            None => Vec::new(),
+        };
+        let checksums = checksum::record(&manifest, &artifacts)?;
+
+        let mut release =
+            release::Release::new(&ctx, &manifest, &outputs, &checksums, &signatures)?;
+        for artifact in &release.artifacts {
+            output::record(artifact)?;
+        }
+        let release_path = release.write(&manifest.output_folder)?;
+        output::info(format!(
+            "Checksums written to {}, release manifest to {}",
+            manifest.output_folder.join(checksum::SUMS_FILE).display(),
+            release_path.display()
+        ))?;
+        for feed in updates::write(&manifest, &release, minisign_key.as_ref())? {
+            output::info(format!("Update feed written to {}", feed.display()))?;
+        }
+    }
+
+    output::outro("Setup package created successfully!")?;
     Ok(())
 }
 
-fn create_archive(ctx: &Context, manifest: &Manifest, platform: Platform) -> result::Result<()> {
-    let spinner = cliclack::spinner();
+fn create_archive(
+    ctx: &Context,
+    manifest: &Manifest,
+    platform: Platform,
+) -> result::Result<release::Output> {
+    // The archivers report their progress in bytes
+    let path = match platform {
+        Platform::Linux | Platform::MacOS => linux::archive::create(ctx, manifest)?,
+        Platform::Windows => windows::archive::create(ctx, manifest)?,
+    };
+    Ok(release::Output::new(path, platform, "archive"))
+}
+
+/// Check the artifacts of the output folder against its `SHA256SUMS` file, and their
+/// signatures if a minisign public key is configured
+fn verify_artifacts(ctx: &Context, manifest: &Manifest) -> result::Result<()> {
+    let public_key = match manifest
+        .minisign
+        .as_ref()
+        .and_then(|m| m.public_key.as_ref())
+    {
+        Some(path) => Some(minisign::PublicKey::parse(&std::fs::read_to_string(
+            ctx.base_dir.join(path),
+        )?)?),
+        None => None,
+    };
 
-    match platform {
-        Platform::Linux | Platform::MacOS => {
-            spinner.start("Creating tar.gz archive...");
-            linux::archive::create_tar_gz(ctx, manifest)?;
-            spinner.stop("Archive created successfully");
+    let results = checksum::verify(&manifest.output_folder)?;
+    let mut failed = 0;
+    for (name, status) in &results {
+        match status {
+            checksum::Status::Ok => status!("{}: OK", name),
+            checksum::Status::Mismatch => status!("{}: FAILED", name),
+            checksum::Status::Missing => status!("{}: MISSING", name),
         }
-        Platform::Windows => {
-            spinner.start("Creating zip archive...");
-            #[cfg(target_os = "windows")]
-            windows::archive::create_zip(ctx, manifest)?;
-            spinner.stop("Archive created successfully");
+        if *status != checksum::Status::Ok {
+            failed += 1;
+            continue;
+        }
+
+        if let Some(public_key) = &public_key {
+            let artifact = manifest.output_folder.join(name);
+            let signature_path = minisign::signature_path(&artifact);
+            let verified = match std::fs::read_to_string(&signature_path) {
+                Ok(signature) => public_key.verify(std::fs::File::open(&artifact)?, &signature),
+                Err(e) => Err(e.into()),
+            };
+            match verified {
+                Ok(_) => status!("{}.minisig: OK", name),
+                Err(e) => {
+                    status!("{}.minisig: FAILED ({})", name, e);
+                    failed += 1;
+                }
+            }
         }
     }
 
+    if failed > 0 {
+        return Err(error::Error::Custom(format!(
+            "{} of {} artifacts failed verification",
+            failed,
+            results.len()
+        )));
+    }
+    output::outro(format!("{} artifacts verified", results.len()))?;
     Ok(())
 }
@@ -1,9 +1,10 @@
+use crate::changelog::ReleaseNotes;
 use crate::context::Context;
 use crate::error::Error;
 use crate::result::Result;
 use crate::tpl::Tpl;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::PathBuf;
 
@@ -109,28 +110,159 @@ pub struct EmergeConfig {
     pub build: Vec<String>,
 
     #[serde(default)]
-    pub copy: Vec<HashMap<String, String>>,
+    pub copy: Vec<CopyRule>,
 
     #[serde(rename = "output-folder", default)]
     pub output_folder: Option<String>,
 
     // Icon configuration
     #[serde(default)]
-    pub icon: Option<String>,
+    pub icon: Option<IconConfig>,
+
+    // Keep a Changelog file with the release notes of each version
+    #[serde(default)]
+    pub changelog: Option<ChangelogConfig>,
+
+    // Archive format and compression (`--archive`)
+    #[serde(default)]
+    pub archive: Option<ArchiveConfig>,
+
+    // Checksums of the produced artifacts
+    #[serde(default)]
+    pub checksums: Option<ChecksumsConfig>,
+
+    // Detached minisign signatures of the produced artifacts
+    #[serde(default)]
+    pub minisign: Option<MinisignConfig>,
+
+    // Sparkle appcast and JSON update feed
+    #[serde(default)]
+    pub updates: Option<UpdatesConfig>,
+
+    // Licenses of the dependencies in Cargo.lock
+    #[serde(default)]
+    pub licenses: Option<LicensesConfig>,
 
     // DMG-specific configuration
     #[serde(default)]
     pub dmg: Option<DmgConfig>,
 
+    // Windows installer (NSIS / Inno Setup) configuration
+    #[serde(default)]
+    pub installer: Option<InstallerConfig>,
+
+    // Linux-specific configuration
+    #[serde(default)]
+    pub linux: Option<LinuxConfig>,
+
+    // macOS-specific configuration
+    #[serde(default)]
+    pub macos: Option<MacosConfig>,
+
+    // Windows-specific configuration
+    #[serde(default)]
+    pub windows: Option<WindowsConfig>,
+
     // Path to external manifest file
     #[serde(default)]
     pub manifest: Option<String>,
 }
 
+/// An entry of the `copy` list
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum CopyRule {
+    /// Fat Mach-O merged from per-architecture binaries
+    Universal {
+        universal: Vec<String>,
+        /// Destination, defaults to the file name of the first source
+        #[serde(default)]
+        to: Option<String>,
+    },
+    /// A single source with an explicit placement
+    File(FileRule),
+    /// `source = destination` pairs
+    Paths(HashMap<String, String>),
+}
+
+/// `icon`: one image (PNG, JPEG, SVG, ICNS, ICO) or PNG files by pixel size
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum IconConfig {
+    File(String),
+    Sizes(BTreeMap<String, String>),
+}
+
+/// Resolved icon source
+#[derive(Debug, Clone, PartialEq)]
+pub enum IconSource {
+    File(PathBuf),
+    /// Hand-tuned PNG files by pixel size
+    Sizes(BTreeMap<u32, PathBuf>),
+}
+
+impl IconSource {
+    /// The icon file when it is a single file with the given extension
+    pub fn file_with_extension(&self, extension: &str) -> Option<&PathBuf> {
+        match self {
+            IconSource::File(path)
+                if path
+                    .extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case(extension)) =>
+            {
+                Some(path)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the source files exist; a missing icon file is skipped
+    pub fn exists(&self) -> bool {
+        match self {
+            IconSource::File(path) => path.exists(),
+            IconSource::Sizes(sizes) => sizes.values().all(|p| p.exists()),
+        }
+    }
+}
+
+/// `{ from = "...", to = "...", location = "..." }` copy rule
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FileRule {
+    pub from: String,
+    /// Destination within the location, defaults to the file name of the source
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Target location, detected from the kind of file when omitted
+    #[serde(default)]
+    pub location: Option<Location>,
+}
+
+/// Where a copied file is placed; Windows and Linux use a single application directory
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Location {
+    /// Executables, `Contents/MacOS`
+    Macos,
+    /// Data files and asset folders, `Contents/Resources`
+    Resources,
+    /// Shared libraries and frameworks, `Contents/Frameworks`
+    Frameworks,
+    /// Plug-in bundles, `Contents/PlugIns`
+    Plugins,
+    /// Next to the application, e.g. in the root of the disk image
+    Volume,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DmgConfig {
+    /// Background picture file, or a generated background
     #[serde(default)]
-    pub background: Option<String>,
+    pub background: Option<DmgBackground>,
+
+    /// Double-resolution background for Retina displays
+    #[serde(default, alias = "background-2x")]
+    pub background_2x: Option<String>,
 
     #[serde(default)]
     pub window_position: Option<(i32, i32)>,
@@ -146,30 +278,647 @@ pub struct DmgConfig {
 
     #[serde(default)]
     pub additional_files: Vec<DmgFile>,
+
+    /// Arrange the window by scripting Finder instead of writing `.DS_Store` directly
+    #[serde(default)]
+    pub applescript: bool,
+
+    /// Show the application icon as the icon of the mounted volume
+    #[serde(default = "default_true")]
+    pub volume_icon: bool,
+
+    /// Volume name (template), defaults to the title
+    #[serde(default)]
+    pub volume_name: Option<String>,
+
+    #[serde(default)]
+    pub filesystem: DmgFilesystem,
+
+    /// Format of the final image
+    #[serde(default)]
+    pub format: DmgFormat,
+
+    /// zlib compression level (1-9) of UDZO images
+    #[serde(default)]
+    pub compression_level: Option<u32>,
+
+    /// Explicit image size passed to hdiutil (e.g. "500m")
+    #[serde(default)]
+    pub size: Option<String>,
+
+    /// Free space in megabytes added to the content size
+    #[serde(default)]
+    pub headroom: Option<u64>,
+
+    /// License agreement shown before mounting, by language code (RTF, Markdown or text)
+    #[serde(default)]
+    pub license: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DmgFilesystem {
+    #[default]
+    #[serde(rename = "HFS+")]
+    HfsPlus,
+    #[serde(rename = "APFS")]
+    Apfs,
+}
+
+impl DmgFilesystem {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DmgFilesystem::HfsPlus => "HFS+",
+            DmgFilesystem::Apfs => "APFS",
+        }
+    }
+}
+
+/// Final image format: zlib, bzip2, lzfse (macOS 10.11+) or lzma (macOS 10.15+)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DmgFormat {
+    #[default]
+    #[serde(rename = "UDZO")]
+    Udzo,
+    #[serde(rename = "UDBZ")]
+    Udbz,
+    #[serde(rename = "ULFO")]
+    Ulfo,
+    #[serde(rename = "ULMO")]
+    Ulmo,
+}
+
+impl DmgFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DmgFormat::Udzo => "UDZO",
+            DmgFormat::Udbz => "UDBZ",
+            DmgFormat::Ulfo => "ULFO",
+            DmgFormat::Ulmo => "ULMO",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// Archive format, by default tar.gz on Linux and macOS and zip on Windows
+    #[serde(default)]
+    pub format: Option<ArchiveFormat>,
+
+    /// Compression level, within the range of the format
+    #[serde(default)]
+    pub level: Option<u32>,
+
+    /// Compression threads of tar.zst, tar.xz, 7z and zstd zip entries (0 = all cores)
+    #[serde(default)]
+    pub threads: Option<u32>,
+
+    /// Compression method of zip entries, `deflate` by default
+    #[serde(default)]
+    pub zip_method: Option<ZipMethod>,
+
+    /// Further file extensions stored without compression in zip archives
+    #[serde(default)]
+    pub store: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.xz")]
+    TarXz,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "tar.bz2")]
+    TarBz2,
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "7z")]
+    SevenZ,
+}
+
+impl ArchiveFormat {
+    pub const ALL: [ArchiveFormat; 6] = [
+        ArchiveFormat::TarGz,
+        ArchiveFormat::TarXz,
+        ArchiveFormat::TarZst,
+        ArchiveFormat::TarBz2,
+        ArchiveFormat::Zip,
+        ArchiveFormat::SevenZ,
+    ];
+
+    /// Name in the manifest, also the file extension
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarXz => "tar.xz",
+            ArchiveFormat::TarZst => "tar.zst",
+            ArchiveFormat::TarBz2 => "tar.bz2",
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::SevenZ => "7z",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.as_str() == name)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ZipMethod {
+    Store,
+    #[default]
+    Deflate,
+    Deflate64,
+    Zstd,
+}
+
+impl ZipMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ZipMethod::Store => "store",
+            ZipMethod::Deflate => "deflate",
+            ZipMethod::Deflate64 => "deflate64",
+            ZipMethod::Zstd => "zstd",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ChecksumsConfig {
+    /// Write a `.sha256` file next to every artifact
+    #[serde(default)]
+    pub sidecar: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MinisignConfig {
+    /// minisign secret key file
+    #[serde(default)]
+    pub secret_key: Option<String>,
+
+    /// Environment variable holding the secret key itself, used when `secret-key` is not set
+    #[serde(default)]
+    pub secret_key_env: Option<String>,
+
+    /// Environment variable holding the secret key password
+    #[serde(default)]
+    pub password_env: Option<String>,
+
+    /// minisign public key file, used by `cargo emerge verify`
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LicensesConfig {
+    #[serde(default)]
+    pub format: LicensesFormat,
+
+    /// Cargo.lock, by default next to Cargo.toml or in a parent directory
+    #[serde(default)]
+    pub lockfile: Option<String>,
+
+    /// `cargo vendor` directory, searched before the cargo registry
+    #[serde(default)]
+    pub vendor: Option<String>,
+
+    /// Target triple whose dependencies are shipped, by default the host of rustc
+    #[serde(default)]
+    pub target: Option<String>,
+
+    /// SPDX license identifiers allowed; empty allows every license
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// SPDX license identifiers that fail the run
+    #[serde(default)]
+    pub deny: Vec<String>,
+
+    /// Packages left out of the report and the policy checks
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LicensesFormat {
+    #[default]
+    Html,
+    Md,
+}
+
+impl LicensesFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LicensesFormat::Html => "html",
+            LicensesFormat::Md => "md",
+        }
+    }
+}
+
+/// `changelog`: a file, or a table with the file and strict mode
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum ChangelogConfig {
+    File(String),
+    Options(ChangelogOptions),
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ChangelogOptions {
+    pub file: String,
+
+    /// Fail when the changelog has no entry for the version
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl ChangelogConfig {
+    pub fn file(&self) -> &str {
+        match self {
+            ChangelogConfig::File(file) => file,
+            ChangelogConfig::Options(options) => &options.file,
+        }
+    }
+
+    pub fn strict(&self) -> bool {
+        match self {
+            ChangelogConfig::File(_) => false,
+            ChangelogConfig::Options(options) => options.strict,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct UpdatesConfig {
+    /// Download URL of an artifact, with $FILE, $VERSION, $NAME, $PLATFORM and $ARCH
+    pub url: String,
+
+    /// Link to the release notes, with $VERSION
+    #[serde(default)]
+    pub release_notes_url: Option<String>,
+
+    /// Release notes, as HTML in the appcast and as text in the JSON feed
+    #[serde(default)]
+    pub notes: Option<String>,
+
+    /// Generate or append to `appcast.xml`
+    #[serde(default = "default_true")]
+    pub appcast: bool,
+
+    /// Variants in order of preference for the one appcast item of each platform and arch;
+    /// variants not listed get no item
+    #[serde(default = "default_appcast_variants")]
+    pub appcast_variants: Vec<String>,
+
+    /// Write the `update.json` feed
+    #[serde(default = "default_true")]
+    pub feed: bool,
+}
+
+/// `dmg.background`: a picture file or a generated background
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum DmgBackground {
+    File(String),
+    Generated(GeneratedBackground),
+}
+
+/// Background drawn at the window size: a colour or gradient, an arrow and optional text
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratedBackground {
+    /// Background colour (`#rrggbb`), the top colour of a gradient
+    #[serde(default = "default_background_color")]
+    pub color: String,
+
+    /// Bottom colour of a vertical gradient
+    #[serde(default)]
+    pub gradient: Option<String>,
+
+    /// Arrow from the application to the Applications link
+    #[serde(default = "default_true")]
+    pub arrow: bool,
+
+    #[serde(default = "default_arrow_color")]
+    pub arrow_color: String,
+
+    /// Text shown below the icons
+    #[serde(default)]
+    pub text: Option<String>,
+
+    #[serde(default = "default_text_color")]
+    pub text_color: String,
+
+    /// Text size in points
+    #[serde(default = "default_text_size")]
+    pub text_size: f32,
+
+    /// TrueType/OpenType font for the text, defaults to a system font
+    #[serde(default)]
+    pub font: Option<String>,
+}
+
+fn default_appcast_variants() -> Vec<String> {
+    ["dmg", "nsis", "inno", "archive", "pkg"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_background_color() -> String {
+    "#ffffff".to_string()
+}
+
+fn default_arrow_color() -> String {
+    "#9a9a9a".to_string()
+}
+
+fn default_text_color() -> String {
+    "#505050".to_string()
+}
+
+fn default_text_size() -> f32 {
+    15.0
+}
+
+/// A file placed in the volume root next to the app bundle
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DmgFile {
     pub source: String,
+    /// Icon position in the window
     pub position: (i32, i32),
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct InstallerConfig {
+    /// Publisher shown in the installer and in "Apps & features"
+    #[serde(default)]
+    pub publisher: Option<String>,
+
+    /// Identifier used for the uninstall registry key and upgrade detection
+    #[serde(default)]
+    pub app_id: Option<String>,
+
+    /// Main executable (relative to the install directory) used for shortcuts
+    #[serde(default)]
+    pub executable: Option<String>,
+
+    /// License file shown on the license page
+    #[serde(default)]
+    pub license: Option<String>,
+
+    /// Install for the current user or for all users of the machine
+    #[serde(default)]
+    pub scope: InstallScope,
+
+    #[serde(default = "default_true")]
+    pub start_menu_shortcut: bool,
+
+    #[serde(default)]
+    pub desktop_shortcut: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallScope {
+    User,
+    #[default]
+    Machine,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct LinuxConfig {
+    /// Reverse-DNS application id used for the .desktop, icon and metainfo file names
+    #[serde(default)]
+    pub app_id: Option<String>,
+
+    /// Executable (relative to the package root) launched by the desktop entry
+    #[serde(default)]
+    pub executable: Option<String>,
+
+    /// SPDX license expression of the project, used in the AppStream metainfo
+    #[serde(default)]
+    pub project_license: Option<String>,
+
+    #[serde(default)]
+    pub homepage: Option<String>,
+
+    /// Desktop integration (.desktop entry, hicolor icons, AppStream metainfo)
+    #[serde(default)]
+    pub desktop: Option<DesktopConfig>,
+
+    /// Copy non-system shared libraries of staged executables into `lib-dir`
+    #[serde(default)]
+    pub bundle_libraries: bool,
+
+    /// Library directory inside the package (default: `lib`)
+    #[serde(default)]
+    pub lib_dir: Option<String>,
+
+    /// Additional directories searched for shared libraries
+    #[serde(default)]
+    pub library_paths: Vec<String>,
+
+    /// Additional libraries provided by the target system (e.g. `libssl.so`, `libgtk-3*`)
+    #[serde(default)]
+    pub system_libraries: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct MacosConfig {
+    /// Copy non-system dylibs into `Contents/Frameworks` and rewrite install names
+    #[serde(default)]
+    pub bundle_dylibs: bool,
+
+    /// Additional directories searched for dylibs by file name
+    #[serde(default)]
+    pub library_paths: Vec<String>,
+
+    /// Additional install name prefixes provided by the system
+    #[serde(default)]
+    pub system_libraries: Vec<String>,
+
+    /// Code signing, ad hoc unless an identity is configured
+    #[serde(default)]
+    pub signing: Option<MacosSigningConfig>,
+
+    /// Flat installer package (`--pkg`)
+    #[serde(default)]
+    pub pkg: Option<PkgConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PkgConfig {
+    /// Package identifier, defaults to the bundle identifier
+    #[serde(default)]
+    pub identifier: Option<String>,
+
+    /// Folder the app bundle is installed into, defaults to `/Applications`
+    #[serde(default)]
+    pub install_location: Option<String>,
+
+    /// Script run before the payload is installed
+    #[serde(default)]
+    pub preinstall: Option<String>,
+
+    /// Script run after the payload is installed
+    #[serde(default)]
+    pub postinstall: Option<String>,
+
+    /// Oldest macOS version the package installs on, such as "11.0"
+    #[serde(default)]
+    pub minimum_os_version: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct MacosSigningConfig {
+    /// PKCS#12 (.p12) file with the signing certificate and private key
+    #[serde(default)]
+    pub identity: Option<String>,
+
+    /// Environment variable holding the identity password
+    #[serde(default)]
+    pub password_env: Option<String>,
+
+    /// Entitlements property list embedded into the main executable
+    #[serde(default)]
+    pub entitlements: Option<String>,
+
+    /// Enable the hardened runtime (required for notarization)
+    #[serde(default)]
+    pub hardened_runtime: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct WindowsConfig {
+    /// Copy DLLs imported by staged executables next to them
+    #[serde(default)]
+    pub bundle_dlls: bool,
+
+    /// Directories searched for DLLs (e.g. /usr/x86_64-w64-mingw32/bin)
+    #[serde(default)]
+    pub library_paths: Vec<String>,
+
+    /// Additional DLL names provided by the system (`*` matches any suffix)
+    #[serde(default)]
+    pub system_libraries: Vec<String>,
+
+    /// Authenticode signing of executables, DLLs and installers
+    #[serde(default)]
+    pub signing: Option<WindowsSigningConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct WindowsSigningConfig {
+    /// PKCS#12 (.pfx) file with the signing certificate and private key
+    #[serde(default)]
+    pub certificate: Option<String>,
+
+    /// Environment variable holding the certificate password
+    #[serde(default)]
+    pub password_env: Option<String>,
+
+    /// RFC 3161 timestamp server (e.g. http://timestamp.digicert.com)
+    #[serde(default)]
+    pub timestamp_url: Option<String>,
+
+    /// Program name shown in the Windows consent dialog
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// Program URL embedded in the signature
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct DesktopConfig {
+    #[serde(default)]
+    pub categories: Vec<String>,
+
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+
+    /// Arguments appended to `Exec`, e.g. `%F`
+    #[serde(default)]
+    pub exec_args: Vec<String>,
+
+    #[serde(default)]
+    pub keywords: Vec<String>,
+
+    #[serde(default)]
+    pub startup_wm_class: Option<String>,
+
+    #[serde(default)]
+    pub comment: Option<String>,
+
+    #[serde(default)]
+    pub terminal: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
 /// Parsed and processed manifest information
 pub struct Manifest {
     pub name: String,
     pub version: String,
-    #[allow(dead_code)]
     pub description: String,
     pub title: String,
     pub filename: String,
     pub build_commands: Vec<String>,
     pub copy_operations: Vec<(PathBuf, PathBuf)>,
+    /// Explicit `location` of copy operations, by destination
+    pub copy_locations: HashMap<PathBuf, Location>,
+    /// Universal binaries (per-architecture sources, destination)
+    pub universal_binaries: Vec<(Vec<PathBuf>, PathBuf)>,
     pub output_folder: PathBuf,
-    pub icon: Option<PathBuf>,
+    pub icon: Option<IconSource>,
+    pub changelog: Option<ChangelogConfig>,
+    /// Section of the changelog for this version, see `changelog::load`
+    pub release_notes: Option<ReleaseNotes>,
+    pub archive: Option<ArchiveConfig>,
+    pub checksums: Option<ChecksumsConfig>,
+    pub minisign: Option<MinisignConfig>,
+    pub updates: Option<UpdatesConfig>,
+    pub licenses: Option<LicensesConfig>,
     pub dmg: Option<DmgConfig>,
+    pub installer: Option<InstallerConfig>,
+    pub linux: Option<LinuxConfig>,
+    pub macos: Option<MacosConfig>,
+    pub windows: Option<WindowsConfig>,
 }
 
 impl Manifest {
+    /// Copy a generated file into every package, unless a copy rule already provides
+    /// `destination`
+    pub fn include_file(&mut self, source: PathBuf, destination: PathBuf) {
+        if !self
+            .copy_operations
+            .iter()
+            .any(|(_, dst)| *dst == destination)
+        {
+            self.copy_operations.push((source, destination));
+        }
+    }
+
     /// Load and parse the manifest from Cargo.toml
     pub fn load(ctx: &Context) -> Result<Self> {
         let content = fs::read_to_string(&ctx.manifest_path)?;
@@ -318,11 +1067,61 @@ impl Manifest {
 
         // Process copy operations
         let mut copy_operations = Vec::new();
-        for copy_map in &emerge_config.copy {
-            for (src, dst) in copy_map {
-                let src_path = ctx.base_dir.join(tpl.parse(src));
-                let dst_path = PathBuf::from(tpl.parse(dst));
-                copy_operations.push((src_path, dst_path));
+        let mut copy_locations = HashMap::new();
+        let mut universal_binaries = Vec::new();
+        for rule in &emerge_config.copy {
+            match rule {
+                CopyRule::Paths(copy_map) => {
+                    // An invalid `from` rule falls back to a map in the untagged enum
+                    if copy_map.contains_key("from") {
+                        return Err(Error::InvalidManifest(format!(
+                            "Invalid copy rule {:?}: expected `from`, `to` and `location` \
+                             (macos, resources, frameworks, plugins or volume)",
+                            copy_map
+                        )));
+                    }
+                    for (src, dst) in copy_map {
+                        let src_path = ctx.base_dir.join(tpl.parse(src));
+                        let dst_path = PathBuf::from(tpl.parse(dst));
+                        copy_operations.push((src_path, dst_path));
+                    }
+                }
+                CopyRule::File(rule) => {
+                    let src_path = ctx.base_dir.join(tpl.parse(&rule.from));
+                    let dst_path = match &rule.to {
+                        Some(to) => PathBuf::from(tpl.parse(to)),
+                        None => PathBuf::from(src_path.file_name().ok_or_else(|| {
+                            Error::InvalidManifest(format!("Invalid copy source: {}", rule.from))
+                        })?),
+                    };
+                    if let Some(location) = rule.location {
+                        copy_locations.insert(dst_path.clone(), location);
+                    }
+                    copy_operations.push((src_path, dst_path));
+                }
+                CopyRule::Universal { universal, to } => {
+                    let sources: Vec<PathBuf> = universal
+                        .iter()
+                        .map(|src| ctx.base_dir.join(tpl.parse(src)))
+                        .collect();
+                    let dst_path = match (to, sources.first()) {
+                        (Some(to), _) => PathBuf::from(tpl.parse(to)),
+                        (None, Some(first)) => {
+                            PathBuf::from(first.file_name().ok_or_else(|| {
+                                Error::InvalidManifest(format!(
+                                    "Invalid universal source: {}",
+                                    first.display()
+                                ))
+                            })?)
+                        }
+                        (None, None) => {
+                            return Err(Error::InvalidManifest(
+                                "A universal copy rule needs at least one source".to_string(),
+                            ));
+                        }
+                    };
+                    universal_binaries.push((sources, dst_path));
+                }
             }
         }
 
@@ -331,7 +1130,33 @@ impl Manifest {
             .map(|f| ctx.base_dir.join(tpl.parse(&f)))
             .unwrap_or_else(|| ctx.base_dir.join("setup"));
 
-        let icon = emerge_config.icon.map(|i| ctx.base_dir.join(tpl.parse(&i)));
+        let icon = match emerge_config.icon {
+            None => None,
+            Some(IconConfig::File(path)) => {
+                Some(IconSource::File(ctx.base_dir.join(tpl.parse(&path))))
+            }
+            Some(IconConfig::Sizes(sizes)) => {
+                let mut resolved = BTreeMap::new();
+                for (size, path) in sizes {
+                    // "32" or "32x32"
+                    let pixels = size
+                        .split_once('x')
+                        .filter(|(w, h)| w == h)
+                        .map_or(size.as_str(), |(w, _)| w)
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|p| *p > 0)
+                        .ok_or_else(|| {
+                            Error::InvalidManifest(format!("Invalid icon size: {}", size))
+                        })?;
+                    resolved.insert(pixels, ctx.base_dir.join(tpl.parse(&path)));
+                }
+                if resolved.is_empty() {
+                    return Err(Error::InvalidManifest("Empty icon table".to_string()));
+                }
+                Some(IconSource::Sizes(resolved))
+            }
+        };
 
         Ok(Manifest {
             name: package.name.clone(),
@@ -341,9 +1166,148 @@ impl Manifest {
             filename,
             build_commands,
             copy_operations,
+            copy_locations,
+            universal_binaries,
             output_folder,
             icon,
-            dmg: emerge_config.dmg,
+            changelog: emerge_config.changelog.map(|changelog| match changelog {
+                ChangelogConfig::File(file) => ChangelogConfig::File(tpl.parse(&file)),
+                ChangelogConfig::Options(options) => ChangelogConfig::Options(ChangelogOptions {
+                    file: tpl.parse(&options.file),
+                    ..options
+                }),
+            }),
+            release_notes: None,
+            archive: emerge_config.archive,
+            checksums: emerge_config.checksums,
+            minisign: emerge_config.minisign,
+            updates: emerge_config.updates.map(|mut updates| {
+                updates.release_notes_url = updates.release_notes_url.map(|url| tpl.parse(&url));
+                updates
+            }),
+            licenses: emerge_config.licenses,
+            dmg: emerge_config.dmg.map(|mut dmg| {
+                dmg.volume_name = dmg.volume_name.map(|name| tpl.parse(&name));
+                dmg
+            }),
+            installer: emerge_config.installer,
+            linux: emerge_config.linux,
+            macos: emerge_config.macos,
+            windows: emerge_config.windows,
         })
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Manifest of a `sample` 1.2.3 package in /work with the given emerge configuration
+    pub fn sample_manifest(emerge: &str) -> (Context, Manifest) {
+        let ctx = Context::new(PathBuf::from("/work/Cargo.toml"), false);
+        let emerge: EmergeConfig = toml::from_str(emerge).unwrap();
+        let package: Package = toml::from_str(
+            r#"
+            name = "sample"
+            version = "1.2.3"
+            "#,
+        )
+        .unwrap();
+        let manifest = Manifest::process_manifest(&ctx, &package, emerge).unwrap();
+        (ctx, manifest)
+    }
+
+    #[test]
+    fn test_copy_rules() {
+        let config: EmergeConfig = toml::from_str(
+            r#"
+            copy = [
+                { "README.md" = "README.md" },
+                { universal = ["target/aarch64/app", "target/x86_64/app"] },
+                { universal = ["a/app", "b/app"], to = "bin/app" },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(&config.copy[0], CopyRule::Paths(map) if map["README.md"] == "README.md"));
+        assert!(
+            matches!(&config.copy[1], CopyRule::Universal { universal, to: None } if universal.len() == 2)
+        );
+        assert!(
+            matches!(&config.copy[2], CopyRule::Universal { to: Some(to), .. } if to == "bin/app")
+        );
+    }
+
+    #[test]
+    fn test_icon_sources() {
+        let (_, manifest) = sample_manifest("icon = \"assets/$NAME.svg\"");
+        assert_eq!(
+            manifest.icon,
+            Some(IconSource::File(PathBuf::from("/work/assets/sample.svg")))
+        );
+        assert!(manifest.icon.unwrap().file_with_extension("svg").is_some());
+
+        let (_, manifest) =
+            sample_manifest("[icon]\n16 = \"icon-16.png\"\n\"32x32\" = \"icon-32.png\"");
+        assert_eq!(
+            manifest.icon,
+            Some(IconSource::Sizes(BTreeMap::from([
+                (16, PathBuf::from("/work/icon-16.png")),
+                (32, PathBuf::from("/work/icon-32.png")),
+            ])))
+        );
+
+        let ctx = Context::new(PathBuf::from("/work/Cargo.toml"), false);
+        let package: Package = toml::from_str("name = \"sample\"\nversion = \"1.2.3\"").unwrap();
+        for icon in [
+            "[icon]\nsmall = \"a.png\"",
+            "[icon]\n16x32 = \"a.png\"",
+            "icon = {}",
+        ] {
+            let emerge: EmergeConfig = toml::from_str(icon).unwrap();
+            assert!(Manifest::process_manifest(&ctx, &package, emerge).is_err());
+        }
+    }
+
+    #[test]
+    fn test_file_rules() {
+        let (_, manifest) = sample_manifest(
+            r#"
+            copy = [
+                { from = "config/$NAME.json", location = "resources" },
+                { from = "assets", to = "data/assets" },
+                { "from" = "legacy" },
+                { "b" = "c" },
+            ]
+            "#,
+        );
+
+        assert_eq!(
+            manifest.copy_operations[..3],
+            [
+                (
+                    PathBuf::from("/work/config/sample.json"),
+                    PathBuf::from("sample.json")
+                ),
+                (PathBuf::from("/work/assets"), PathBuf::from("data/assets")),
+                (PathBuf::from("/work/legacy"), PathBuf::from("legacy")),
+            ]
+        );
+        assert_eq!(manifest.copy_operations.len(), 4);
+        assert_eq!(
+            manifest.copy_locations,
+            HashMap::from([(PathBuf::from("sample.json"), Location::Resources)])
+        );
+
+        let ctx = Context::new(PathBuf::from("/work/Cargo.toml"), false);
+        let package: Package = toml::from_str("name = \"sample\"\nversion = \"1.2.3\"").unwrap();
+        for copy in [
+            r#"copy = [{ from = "a", location = "elsewhere" }]"#,
+            r#"copy = [{ from = "a", into = "b" }]"#,
+        ] {
+            let emerge: EmergeConfig = toml::from_str(copy).unwrap();
+            assert!(Manifest::process_manifest(&ctx, &package, emerge).is_err());
+        }
+    }
+}
@@ -0,0 +1,607 @@
+//! minisign-compatible Ed25519 keys and detached signatures of the produced artifacts
+
+use crate::context::Context;
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::result::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Blake2b512, Digest};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable holding the secret key password unless configured otherwise
+pub const DEFAULT_PASSWORD_ENV: &str = "EMERGE_MINISIGN_PASSWORD";
+
+/// Extension of the signature files written next to the artifacts
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// Ed25519 over the data itself (legacy) and over its BLAKE2b-512 hash
+const SIG_ALG: &[u8; 2] = b"Ed";
+const PREHASHED_SIG_ALG: &[u8; 2] = b"ED";
+/// scrypt key derivation and BLAKE2b-256 checksum of secret keys
+const KDF_ALG: &[u8; 2] = b"Sc";
+const CHK_ALG: &[u8; 2] = b"B2";
+/// scrypt limits of new keys, those of rsign (N = 2^15, 32 MiB)
+const OPSLIMIT: u64 = 1 << 20;
+const MEMLIMIT: u64 = 1 << 25;
+
+/// Length of the (encrypted) key number, secret key and checksum
+const KEYNUM_SK_LEN: usize = 8 + 64 + 32;
+const SECRET_KEY_LEN: usize = 2 + 2 + 2 + 32 + 8 + 8 + KEYNUM_SK_LEN;
+
+/// An Ed25519 secret key with its minisign key number
+pub struct SecretKey {
+    keynum: [u8; 8],
+    key: SigningKey,
+}
+
+/// An Ed25519 public key with its minisign key number
+pub struct PublicKey {
+    keynum: [u8; 8],
+    key: VerifyingKey,
+}
+
+impl SecretKey {
+    /// Create a new random keypair
+    pub fn generate() -> Result<Self> {
+        let mut seed = [0; 32];
+        let mut keynum = [0; 8];
+        random(&mut seed)?;
+        random(&mut keynum)?;
+        Ok(Self {
+            keynum,
+            key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    /// Load the signing key configured in the `minisign` table, if any
+    pub fn from_manifest(ctx: &Context, manifest: &Manifest) -> Result<Option<Self>> {
+        let Some(config) = &manifest.minisign else {
+            return Ok(None);
+        };
+
+        let text = if let Some(path) = &config.secret_key {
+            let path = ctx.base_dir.join(path);
+            fs::read_to_string(&path).map_err(|e| {
+                Error::Signing(format!(
+                    "Unable to read secret key {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?
+        } else if let Some(env) = &config.secret_key_env {
+            match std::env::var(env) {
+                Ok(text) if !text.trim().is_empty() => text,
+                _ => {
+                    status!("Warning: {} is not set, artifacts are not signed", env);
+                    return Ok(None);
+                }
+            }
+        } else {
+            return Ok(None);
+        };
+
+        let password_env = config
+            .password_env
+            .as_deref()
+            .unwrap_or(DEFAULT_PASSWORD_ENV);
+        let password = std::env::var(password_env).unwrap_or_default();
+        Self::parse(&text, &password)
+            .map(Some)
+            .map_err(|e| match e {
+                Error::Signing(message) if password.is_empty() => {
+                    Error::Signing(format!("{} ({})", message, password_env))
+                }
+                e => e,
+            })
+    }
+
+    /// Parse a secret key file, or its base64 line alone, decrypting it with `password`
+    pub fn parse(text: &str, password: &str) -> Result<Self> {
+        let data = decode_key_line(text)?;
+        if data.len() != SECRET_KEY_LEN || &data[..2] != SIG_ALG || &data[4..6] != CHK_ALG {
+            return Err(Error::Signing(
+                "Unsupported minisign secret key".to_string(),
+            ));
+        }
+
+        let mut keynum_sk = data[SECRET_KEY_LEN - KEYNUM_SK_LEN..].to_vec();
+        match &data[2..4] {
+            [0, 0] => {}
+            kdf if kdf == KDF_ALG => {
+                if password.is_empty() {
+                    return Err(Error::Signing(
+                        "The minisign secret key is encrypted and no password is set".to_string(),
+                    ));
+                }
+                let salt = &data[6..38];
+                let opslimit = u64::from_le_bytes(data[38..46].try_into().unwrap());
+                let memlimit = u64::from_le_bytes(data[46..54].try_into().unwrap());
+                xor(&mut keynum_sk, &derive(password, salt, opslimit, memlimit)?);
+            }
+            _ => {
+                return Err(Error::Signing(
+                    "Unsupported minisign key derivation".to_string(),
+                ));
+            }
+        }
+
+        let keynum: [u8; 8] = keynum_sk[..8].try_into().unwrap();
+        let secret: [u8; 64] = keynum_sk[8..72].try_into().unwrap();
+        if checksum(&keynum, &secret) != keynum_sk[72..] {
+            return Err(Error::Signing(
+                "Wrong password for the minisign secret key".to_string(),
+            ));
+        }
+        let key = SigningKey::from_keypair_bytes(&secret)
+            .map_err(|e| Error::Signing(format!("Invalid minisign secret key: {}", e)))?;
+        Ok(Self { keynum, key })
+    }
+
+    /// Secret key file contents, encrypted unless `password` is empty
+    pub fn to_file(&self, password: &str) -> Result<String> {
+        self.encode(password, OPSLIMIT, MEMLIMIT)
+    }
+
+    fn encode(&self, password: &str, opslimit: u64, memlimit: u64) -> Result<String> {
+        let secret = self.key.to_keypair_bytes();
+        let mut keynum_sk = Vec::with_capacity(KEYNUM_SK_LEN);
+        keynum_sk.extend_from_slice(&self.keynum);
+        keynum_sk.extend_from_slice(&secret);
+        keynum_sk.extend_from_slice(&checksum(&self.keynum, &secret));
+
+        let mut salt = [0; 32];
+        let (kdf_alg, comment) = if password.is_empty() {
+            (&[0, 0], "minisign secret key")
+        } else {
+            random(&mut salt)?;
+            xor(
+                &mut keynum_sk,
+                &derive(password, &salt, opslimit, memlimit)?,
+            );
+            (KDF_ALG, "minisign encrypted secret key")
+        };
+
+        let mut data = Vec::with_capacity(SECRET_KEY_LEN);
+        data.extend_from_slice(SIG_ALG);
+        data.extend_from_slice(kdf_alg);
+        data.extend_from_slice(CHK_ALG);
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&opslimit.to_le_bytes());
+        data.extend_from_slice(&memlimit.to_le_bytes());
+        data.extend_from_slice(&keynum_sk);
+        Ok(format!(
+            "untrusted comment: {}\n{}\n",
+            comment,
+            STANDARD.encode(data)
+        ))
+    }
+
+    /// Plain Ed25519 signature of `data`, as used by Sparkle's `sparkle:edSignature`
+    pub fn sign_ed25519(&self, data: &[u8]) -> [u8; 64] {
+        self.key.sign(data).to_bytes()
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            keynum: self.keynum,
+            key: self.key.verifying_key(),
+        }
+    }
+
+    /// Sign `data` (hashed with BLAKE2b-512), returning the signature file contents
+    pub fn sign<R: Read>(&self, data: R, trusted_comment: &str) -> Result<String> {
+        let signature = self.key.sign(&prehash(data)?);
+        let mut line = Vec::with_capacity(74);
+        line.extend_from_slice(PREHASHED_SIG_ALG);
+        line.extend_from_slice(&self.keynum);
+        line.extend_from_slice(&signature.to_bytes());
+
+        // The global signature covers the trusted comment as well
+        let mut global = signature.to_bytes().to_vec();
+        global.extend_from_slice(trusted_comment.as_bytes());
+        let global = self.key.sign(&global);
+
+        Ok(format!(
+            "untrusted comment: signature from minisign secret key\n{}\ntrusted comment: {}\n{}\n",
+            STANDARD.encode(line),
+            trusted_comment,
+            STANDARD.encode(global.to_bytes())
+        ))
+    }
+}
+
+impl PublicKey {
+    /// Parse a public key file, or its base64 line alone
+    pub fn parse(text: &str) -> Result<Self> {
+        let data = decode_key_line(text)?;
+        if data.len() != 42 || &data[..2] != SIG_ALG {
+            return Err(Error::Signing(
+                "Unsupported minisign public key".to_string(),
+            ));
+        }
+        let key = VerifyingKey::from_bytes(data[10..].try_into().unwrap())
+            .map_err(|e| Error::Signing(format!("Invalid minisign public key: {}", e)))?;
+        Ok(Self {
+            keynum: data[2..10].try_into().unwrap(),
+            key,
+        })
+    }
+
+    /// Base64 of the plain Ed25519 key, Sparkle's `SUPublicEDKey`
+    pub fn to_ed25519_base64(&self) -> String {
+        STANDARD.encode(self.key.as_bytes())
+    }
+
+    /// Key ID as shown by minisign
+    pub fn id(&self) -> String {
+        format!("{:016X}", u64::from_le_bytes(self.keynum))
+    }
+
+    /// Base64 public key, as passed to `minisign -P`
+    pub fn to_base64(&self) -> String {
+        let mut data = Vec::with_capacity(42);
+        data.extend_from_slice(SIG_ALG);
+        data.extend_from_slice(&self.keynum);
+        data.extend_from_slice(self.key.as_bytes());
+        STANDARD.encode(data)
+    }
+
+    pub fn to_file(&self) -> String {
+        format!(
+            "untrusted comment: minisign public key {}\n{}\n",
+            self.id(),
+            self.to_base64()
+        )
+    }
+
+    /// Verify the signature file contents `signature` of `data`, returning its trusted comment
+    pub fn verify<R: Read>(&self, mut data: R, signature: &str) -> Result<String> {
+        let invalid = || Error::Signing("Invalid minisign signature file".to_string());
+        let mut lines = signature.lines();
+        let (Some(_), Some(line), Some(comment), Some(global)) =
+            (lines.next(), lines.next(), lines.next(), lines.next())
+        else {
+            return Err(invalid());
+        };
+        let line = STANDARD.decode(line.trim()).map_err(|_| invalid())?;
+        let trusted_comment = comment
+            .strip_prefix("trusted comment: ")
+            .ok_or_else(invalid)?;
+        let global = STANDARD.decode(global.trim()).map_err(|_| invalid())?;
+        if line.len() != 74 || global.len() != 64 {
+            return Err(invalid());
+        }
+        if line[2..10] != self.keynum {
+            return Err(Error::Signing(format!(
+                "Signature was made with another key than {}",
+                self.id()
+            )));
+        }
+
+        let message = match &line[..2] {
+            alg if alg == PREHASHED_SIG_ALG => prehash(data)?.to_vec(),
+            alg if alg == SIG_ALG => {
+                let mut message = Vec::new();
+                data.read_to_end(&mut message)?;
+                message
+            }
+            _ => return Err(invalid()),
+        };
+        let signature = Signature::from_slice(&line[10..]).map_err(|_| invalid())?;
+        let mut signed_comment = line[10..].to_vec();
+        signed_comment.extend_from_slice(trusted_comment.as_bytes());
+        let global = Signature::from_slice(&global).map_err(|_| invalid())?;
+
+        self.key
+            .verify(&message, &signature)
+            .and_then(|_| self.key.verify(&signed_comment, &global))
+            .map_err(|_| Error::Signing("Signature verification failed".to_string()))?;
+        Ok(trusted_comment.to_string())
+    }
+}
+
+/// Sign `artifacts`, writing `<artifact>.minisig` next to each; returns the signature files
+pub fn sign_artifacts(
+    key: &SecretKey,
+    manifest: &Manifest,
+    artifacts: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let mut signatures = Vec::new();
+    for artifact in artifacts {
+        let file_name = artifact.file_name().unwrap_or_default().to_string_lossy();
+        let comment = trusted_comment(timestamp, &file_name, &manifest.version);
+        let signature = key.sign(File::open(artifact)?, &comment)?;
+
+        let path = signature_path(artifact);
+        fs::write(&path, signature)?;
+        status!("Signed {} with key {}", file_name, key.public_key().id());
+        signatures.push(path);
+    }
+    Ok(signatures)
+}
+
+/// `<artifact>.minisig`
+pub fn signature_path(artifact: &Path) -> PathBuf {
+    let mut path = artifact.as_os_str().to_owned();
+    path.push(".");
+    path.push(SIGNATURE_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Write a new keypair, encrypting the secret key with the password in `EMERGE_MINISIGN_PASSWORD`
+pub fn keygen(secret_path: &Path, public_path: &Path, force: bool) -> Result<PublicKey> {
+    for path in [secret_path, public_path] {
+        if path.exists() && !force {
+            return Err(Error::Custom(format!(
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            )));
+        }
+    }
+
+    let password = std::env::var(DEFAULT_PASSWORD_ENV).unwrap_or_default();
+    if password.is_empty() {
+        status!(
+            "Warning: {} is not set, the secret key is not encrypted",
+            DEFAULT_PASSWORD_ENV
+        );
+    }
+
+    let key = SecretKey::generate()?;
+    let public_key = key.public_key();
+    for path in [secret_path, public_path] {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    write_secret(secret_path, &key.to_file(&password)?)?;
+    fs::write(public_path, public_key.to_file())?;
+    Ok(public_key)
+}
+
+/// Trusted comment of an artifact signature
+fn trusted_comment(timestamp: u64, file_name: &str, version: &str) -> String {
+    format!(
+        "timestamp:{}\tfile:{}\tversion:{}\thashed",
+        timestamp, file_name, version
+    )
+}
+
+/// Write the secret key readable by the owner only
+fn write_secret(path: &Path, contents: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(path)?, contents.as_bytes())?;
+    Ok(())
+}
+
+/// Decode the base64 line of a key file, skipping its untrusted comment
+fn decode_key_line(text: &str) -> Result<Vec<u8>> {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+        .unwrap_or_default();
+    STANDARD
+        .decode(line)
+        .map_err(|e| Error::Signing(format!("Invalid minisign key: {}", e)))
+}
+
+fn prehash<R: Read>(mut data: R) -> Result<[u8; 64]> {
+    let mut hasher = Blake2b512::new();
+    io::copy(&mut data, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// BLAKE2b-256 checksum of a secret key
+fn checksum(keynum: &[u8; 8], secret: &[u8; 64]) -> [u8; 32] {
+    let mut hasher = Blake2b::<U32>::new();
+    hasher.update(SIG_ALG);
+    hasher.update(keynum);
+    hasher.update(secret);
+    hasher.finalize().into()
+}
+
+/// Stream XORed with the secret key, derived with libsodium's scrypt parameter choice
+fn derive(password: &str, salt: &[u8], opslimit: u64, memlimit: u64) -> Result<Vec<u8>> {
+    let opslimit = opslimit.max(32768);
+    let r = 8u64;
+    let log_n = |max_n: u64| (1..63).find(|&n| 1u64 << n > max_n / 2).unwrap_or(63);
+    let (log_n, p) = if opslimit < memlimit / 32 {
+        (log_n(opslimit / (r * 4)), 1)
+    } else {
+        let log_n = log_n(memlimit / (r * 128));
+        let max_rp = ((opslimit / 4) >> log_n).min(0x3fff_ffff);
+        (log_n, (max_rp / r).max(1))
+    };
+
+    let params = scrypt::Params::new(
+        log_n as u8,
+        r as u32,
+        p as u32,
+        scrypt::Params::RECOMMENDED_LEN,
+    )
+    .map_err(|e| Error::Signing(format!("Invalid scrypt parameters: {}", e)))?;
+    let mut stream = vec![0; KEYNUM_SK_LEN];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut stream)
+        .map_err(|e| Error::Signing(format!("scrypt: {}", e)))?;
+    Ok(stream)
+}
+
+fn xor(data: &mut [u8], stream: &[u8]) {
+    data.iter_mut().zip(stream).for_each(|(d, s)| *d ^= s);
+}
+
+fn random(buffer: &mut [u8]) -> Result<()> {
+    getrandom::getrandom(buffer)
+        .map_err(|e| Error::Signing(format!("No random numbers available: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_key() -> SecretKey {
+        SecretKey {
+            keynum: *b"emergeid",
+            key: SigningKey::from_bytes(&[7; 32]),
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = sample_key();
+        let public_key = key.public_key();
+        let comment = trusted_comment(1700000000, "sample.tar.gz", "1.2.3");
+        let signature = key.sign(&b"sample\n"[..], &comment).unwrap();
+
+        let lines = signature.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "untrusted comment: signature from minisign secret key"
+        );
+        assert_eq!(
+            lines[2],
+            "trusted comment: timestamp:1700000000\tfile:sample.tar.gz\tversion:1.2.3\thashed"
+        );
+        assert_eq!(&STANDARD.decode(lines[1]).unwrap()[..10], b"EDemergeid");
+
+        assert_eq!(
+            public_key.verify(&b"sample\n"[..], &signature).unwrap(),
+            comment
+        );
+        assert!(public_key.verify(&b"sample!\n"[..], &signature).is_err());
+        let tampered = signature.replace("version:1.2.3", "version:1.2.4");
+        assert!(public_key.verify(&b"sample\n"[..], &tampered).is_err());
+
+        let other = SecretKey::generate().unwrap().public_key();
+        assert!(other.verify(&b"sample\n"[..], &signature).is_err());
+    }
+
+    #[test]
+    fn test_key_files() {
+        let key = sample_key();
+        let public_key = key.public_key();
+        assert_eq!(public_key.id(), "6469656772656D65");
+        let file = public_key.to_file();
+        assert!(file.starts_with("untrusted comment: minisign public key 6469656772656D65\n"));
+        let parsed = PublicKey::parse(&file).unwrap();
+        assert_eq!(parsed.to_base64(), public_key.to_base64());
+        assert_eq!(
+            PublicKey::parse(&parsed.to_base64()).unwrap().id(),
+            public_key.id()
+        );
+        assert_eq!(
+            STANDARD.decode(public_key.to_ed25519_base64()).unwrap(),
+            key.key.verifying_key().as_bytes()
+        );
+
+        // Unencrypted
+        let file = key.to_file("").unwrap();
+        assert!(file.starts_with("untrusted comment: minisign secret key\n"));
+        let parsed = SecretKey::parse(&file, "").unwrap();
+        assert_eq!(parsed.key.to_bytes(), key.key.to_bytes());
+        assert_eq!(parsed.keynum, key.keynum);
+
+        // Encrypted, with small scrypt limits to keep the test fast
+        let file = key.encode("secret", 32768, 1 << 20).unwrap();
+        assert!(file.starts_with("untrusted comment: minisign encrypted secret key\n"));
+        let parsed = SecretKey::parse(&file, "secret").unwrap();
+        assert_eq!(parsed.key.to_bytes(), key.key.to_bytes());
+        assert!(SecretKey::parse(&file, "wrong").is_err());
+        assert!(SecretKey::parse(&file, "").is_err());
+        assert!(SecretKey::parse("untrusted comment: x\nAAAA\n", "").is_err());
+    }
+
+    #[test]
+    fn test_derive_parameters() {
+        // libsodium picks N = 2^10, r = 8, p = 1 for these limits
+        let salt = [1; 32];
+        let stream = derive("secret", &salt, 32768, 1 << 20).unwrap();
+        let mut expected = vec![0; KEYNUM_SK_LEN];
+        let params = scrypt::Params::new(10, 8, 1, scrypt::Params::RECOMMENDED_LEN).unwrap();
+        scrypt::scrypt(b"secret", &salt, &params, &mut expected).unwrap();
+        assert_eq!(stream, expected);
+    }
+
+    #[test]
+    fn test_minisign_compatibility() {
+        use minisign::{PublicKeyBox, SecretKeyBox, SignatureBox};
+        use std::io::Cursor;
+
+        // Our signatures and public keys are accepted by minisign
+        let key = sample_key();
+        let signature = key.sign(&b"sample\n"[..], "file:sample.run").unwrap();
+        let public_key = PublicKeyBox::from_string(&key.public_key().to_file())
+            .unwrap()
+            .into_public_key()
+            .unwrap();
+        let signature_box = SignatureBox::from_string(&signature).unwrap();
+        assert_eq!(signature_box.trusted_comment().unwrap(), "file:sample.run");
+        minisign::verify(
+            &public_key,
+            &signature_box,
+            Cursor::new(b"sample\n"),
+            true,
+            false,
+            false,
+        )
+        .unwrap();
+
+        // Our encrypted secret keys are read by minisign, and its signatures verified by us
+        let file = key.encode("secret", 32768, 1 << 20).unwrap();
+        let secret_key = SecretKeyBox::from_string(&file)
+            .unwrap()
+            .into_secret_key(Some("secret".to_string()))
+            .unwrap();
+        let signature = minisign::sign(None, &secret_key, Cursor::new(b"sample\n"), None, None)
+            .unwrap()
+            .into_string();
+        assert!(
+            key.public_key()
+                .verify(&b"sample\n"[..], &signature)
+                .is_ok()
+        );
+
+        // Keys made by minisign are read by us
+        let pair = minisign::KeyPair::generate_unencrypted_keypair().unwrap();
+        let parsed = SecretKey::parse(&pair.sk.to_box(None).unwrap().into_string(), "").unwrap();
+        let public_key = PublicKey::parse(&pair.pk.to_box().unwrap().into_string()).unwrap();
+        assert_eq!(public_key.to_base64(), parsed.public_key().to_base64());
+    }
+
+    #[test]
+    fn test_keygen() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("keys/minisign.key");
+        let public_path = dir.path().join("keys/minisign.pub");
+        let public_key = keygen(&secret_path, &public_path, false).unwrap();
+        assert_eq!(
+            PublicKey::parse(&fs::read_to_string(&public_path).unwrap())
+                .unwrap()
+                .id(),
+            public_key.id()
+        );
+        assert!(keygen(&secret_path, &public_path, false).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&secret_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Terminal output: the cliclack UI and status lines, or JSON records (`--message-format json`)
+
+use crate::result::Result;
+use serde::Serialize;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Print status lines on stderr and records on stdout instead of the cliclack UI
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Print a status line on stdout, or on stderr when stdout carries JSON records
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::is_json() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// A cliclack spinner or progress bar, hidden in JSON mode
+pub struct Progress(Option<cliclack::ProgressBar>);
+
+impl Progress {
+    pub fn spinner() -> Self {
+        Self((!is_json()).then(cliclack::spinner))
+    }
+
+    /// Progress bar of `len` bytes
+    pub fn bytes(len: u64) -> Self {
+        Self((!is_json()).then(|| cliclack::progress_bar(len).with_download_template()))
+    }
+
+    pub fn start(&self, message: impl Display) {
+        if let Some(bar) = &self.0 {
+            bar.start(message);
+        }
+    }
+
+    pub fn inc(&self, delta: u64) {
+        if let Some(bar) = &self.0 {
+            bar.inc(delta);
+        }
+    }
+
+    pub fn stop(&self, message: impl Display) {
+        if let Some(bar) = &self.0 {
+            bar.stop(message);
+        }
+    }
+
+    pub fn error(&self, message: impl Display) {
+        if let Some(bar) = &self.0 {
+            bar.error(message);
+        }
+    }
+}
+
+pub fn intro(title: impl Display) -> io::Result<()> {
+    if is_json() {
+        Ok(())
+    } else {
+        cliclack::intro(title)
+    }
+}
+
+pub fn outro(message: impl Display) -> io::Result<()> {
+    if is_json() {
+        Ok(())
+    } else {
+        cliclack::outro(message)
+    }
+}
+
+pub fn outro_cancel(message: impl Display) -> io::Result<()> {
+    if is_json() {
+        eprintln!("{}", message);
+        Ok(())
+    } else {
+        cliclack::outro_cancel(message)
+    }
+}
+
+pub fn info(message: impl Display) -> io::Result<()> {
+    if is_json() {
+        Ok(())
+    } else {
+        cliclack::log::info(message)
+    }
+}
+
+/// Print `record` as one line of JSON on stdout (JSON mode only)
+pub fn record<T: Serialize>(record: &T) -> Result<()> {
+    if is_json() {
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer(&mut stdout, record)?;
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,376 @@
+//! `release.json`: a machine-readable description of every artifact of a release
+
+use crate::checksum::{self, Checksums};
+use crate::cmd;
+use crate::context::Context;
+use crate::manifest::{ArchiveFormat, Manifest};
+use crate::platform::Platform;
+use crate::result::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+/// Release manifest of the output folder
+pub const RELEASE_FILE: &str = "release.json";
+
+/// Version of the `release.json` schema, raised on incompatible changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// An artifact produced by this run, with the platform it is meant for
+pub struct Output {
+    pub path: PathBuf,
+    pub platform: Platform,
+    /// Packager that produced the artifact: archive, dmg, pkg, run, nsis or inno
+    pub variant: &'static str,
+}
+
+impl Output {
+    pub fn new(path: PathBuf, platform: Platform, variant: &'static str) -> Self {
+        Self {
+            path,
+            platform,
+            variant,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Release {
+    pub schema_version: u32,
+    pub name: String,
+    pub title: String,
+    pub version: String,
+    /// Commit of the project at the latest run, if it is a git checkout
+    pub git_commit: Option<String>,
+    /// Time of the latest run (RFC 3339, UTC)
+    pub build_timestamp: String,
+    /// Changelog section of the version (Markdown)
+    #[serde(default)]
+    pub notes: Option<String>,
+    pub artifacts: Vec<Artifact>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Artifact {
+    /// Path relative to the output folder
+    pub file: String,
+    /// Target platform: linux, macos or windows
+    pub platform: String,
+    /// Architecture of the packaged executable (x86_64, aarch64, universal, ...)
+    pub arch: String,
+    /// File format, such as tar.gz, zip, dmg, pkg, run or exe
+    pub format: String,
+    pub variant: String,
+    pub size: u64,
+    pub sha256: String,
+    pub blake3: String,
+    /// minisign signature file relative to the output folder
+    pub signature: Option<String>,
+    pub version: String,
+    pub git_commit: Option<String>,
+    pub build_timestamp: String,
+}
+
+impl Release {
+    /// Describe the `outputs` of this run with their checksums and signature files
+    pub fn new(
+        ctx: &Context,
+        manifest: &Manifest,
+        outputs: &[Output],
+        checksums: &[(PathBuf, Checksums)],
+        signatures: &[PathBuf],
+    ) -> Result<Self> {
+        let git_commit = git_commit(ctx);
+        let build_timestamp = OffsetDateTime::now_utc()
+            .replace_nanosecond(0)
+            .ok()
+            .and_then(|time| time.format(&Rfc3339).ok())
+            .unwrap_or_default();
+        let arch = arch(ctx, manifest);
+        let folder = &manifest.output_folder;
+
+        let mut artifacts = Vec::new();
+        for output in outputs {
+            let Some((_, sums)) = checksums.iter().find(|(path, _)| *path == output.path) else {
+                continue;
+            };
+            let file = checksum::entry_name(folder, &output.path);
+            let signature = crate::minisign::signature_path(&output.path);
+            artifacts.push(Artifact {
+                format: format(&file),
+                file,
+                platform: output.platform.as_str().to_string(),
+                arch: arch.clone(),
+                variant: output.variant.to_string(),
+                size: fs::metadata(&output.path)?.len(),
+                sha256: sums.sha256.clone(),
+                blake3: sums.blake3.clone(),
+                signature: signatures
+                    .contains(&signature)
+                    .then(|| checksum::entry_name(folder, &signature)),
+                version: manifest.version.clone(),
+                git_commit: git_commit.clone(),
+                build_timestamp: build_timestamp.clone(),
+            });
+        }
+
+        Ok(Self {
+            schema_version: SCHEMA_VERSION,
+            name: manifest.name.clone(),
+            title: manifest.title.clone(),
+            version: manifest.version.clone(),
+            git_commit,
+            build_timestamp,
+            notes: manifest.release_notes.as_ref().map(|n| n.text.clone()),
+            artifacts,
+        })
+    }
+
+    /// Add the artifacts of an earlier run of the same version that still exist in `folder`
+    pub fn merge(&mut self, earlier: Release, folder: &Path) {
+        if earlier.schema_version != self.schema_version
+            || earlier.name != self.name
+            || earlier.version != self.version
+        {
+            return;
+        }
+        for artifact in earlier.artifacts {
+            if !self.artifacts.iter().any(|a| a.file == artifact.file)
+                && folder.join(&artifact.file).is_file()
+            {
+                self.artifacts.push(artifact);
+            }
+        }
+        self.artifacts.sort_by(|a, b| a.file.cmp(&b.file));
+    }
+
+    /// Write `release.json` to the output folder, keeping the artifacts of earlier runs
+    pub fn write(&mut self, folder: &Path) -> Result<PathBuf> {
+        let path = folder.join(RELEASE_FILE);
+        if let Ok(text) = fs::read_to_string(&path) {
+            match serde_json::from_str(&text) {
+                Ok(earlier) => self.merge(earlier, folder),
+                Err(e) => status!("Warning: replacing unreadable {}: {}", path.display(), e),
+            }
+        }
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        fs::write(&path, json)?;
+        Ok(path)
+    }
+}
+
+/// Format of an artifact by its file name
+fn format(file: &str) -> String {
+    ArchiveFormat::ALL
+        .iter()
+        .map(|f| f.as_str())
+        .find(|f| file.ends_with(&format!(".{}", f)))
+        .map(str::to_string)
+        .or_else(|| {
+            Path::new(file)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+        })
+        .unwrap_or_default()
+}
+
+fn git_commit(ctx: &Context) -> Option<String> {
+    let dir = ctx.base_dir.to_str()?;
+    cmd::execute_with_output(ctx, "git", &["-C", dir, "rev-parse", "HEAD"])
+        .ok()
+        .map(|commit| commit.trim().to_string())
+        .filter(|commit| !commit.is_empty())
+}
+
+/// Architecture of the executable named after the package, the host architecture otherwise
+fn arch(ctx: &Context, manifest: &Manifest) -> String {
+    if !manifest.universal_binaries.is_empty() {
+        return "universal".to_string();
+    }
+    manifest
+        .copy_operations
+        .iter()
+        .map(|(source, _)| ctx.base_dir.join(source))
+        .filter(|source| {
+            source.is_file()
+                && source
+                    .file_stem()
+                    .is_some_and(|stem| stem.to_string_lossy() == manifest.name)
+        })
+        .find_map(|source| binary_arch(&fs::read(source).ok()?))
+        .unwrap_or_else(|| std::env::consts::ARCH.to_string())
+}
+
+/// Architecture of an ELF, PE or Mach-O binary
+fn binary_arch(data: &[u8]) -> Option<String> {
+    use goblin::Object;
+    use goblin::mach::{Mach, constants::cputype};
+
+    let arch = match Object::parse(data).ok()? {
+        Object::Elf(elf) => match elf.header.e_machine {
+            goblin::elf::header::EM_X86_64 => "x86_64",
+            goblin::elf::header::EM_AARCH64 => "aarch64",
+            goblin::elf::header::EM_386 => "x86",
+            goblin::elf::header::EM_ARM => "arm",
+            goblin::elf::header::EM_RISCV => "riscv64",
+            _ => return None,
+        },
+        Object::PE(pe) => match pe.header.coff_header.machine {
+            goblin::pe::header::COFF_MACHINE_X86_64 => "x86_64",
+            goblin::pe::header::COFF_MACHINE_ARM64 => "aarch64",
+            goblin::pe::header::COFF_MACHINE_X86 => "x86",
+            _ => return None,
+        },
+        Object::Mach(Mach::Fat(_)) => "universal",
+        Object::Mach(Mach::Binary(macho)) => match macho.header.cputype {
+            cputype::CPU_TYPE_X86_64 => "x86_64",
+            cputype::CPU_TYPE_ARM64 => "aarch64",
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(arch.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::tests::sample_manifest;
+
+    fn artifact(file: &str, version: &str) -> Artifact {
+        Artifact {
+            file: file.to_string(),
+            platform: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            format: format(file),
+            variant: "archive".to_string(),
+            size: 0,
+            sha256: String::new(),
+            blake3: String::new(),
+            signature: None,
+            version: version.to_string(),
+            git_commit: None,
+            build_timestamp: "2026-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("sample-1.2.3.tar.gz"), "tar.gz");
+        assert_eq!(format("sample-1.2.3.tar.zst"), "tar.zst");
+        assert_eq!(format("win/sample.7z"), "7z");
+        assert_eq!(format("sample.DMG"), "dmg");
+        assert_eq!(format("sample.run"), "run");
+    }
+
+    #[test]
+    fn test_new() {
+        let dir = tempfile::tempdir().unwrap();
+        let (ctx, mut manifest) = sample_manifest("");
+        manifest.output_folder = dir.path().to_path_buf();
+        let archive = dir.path().join("sample.tar.gz");
+        let run = dir.path().join("sample.run");
+        fs::write(&archive, b"archive").unwrap();
+        fs::write(&run, b"run").unwrap();
+
+        let outputs = [
+            Output::new(archive.clone(), Platform::Linux, "archive"),
+            Output::new(run.clone(), Platform::Linux, "run"),
+        ];
+        let checksums = [
+            (archive.clone(), checksum::compute(&archive).unwrap()),
+            (run.clone(), checksum::compute(&run).unwrap()),
+        ];
+        let signatures = [crate::minisign::signature_path(&run)];
+        let release = Release::new(&ctx, &manifest, &outputs, &checksums, &signatures).unwrap();
+
+        assert_eq!(release.schema_version, SCHEMA_VERSION);
+        assert_eq!(release.version, "1.2.3");
+        assert_eq!(release.artifacts.len(), 2);
+        let archive = &release.artifacts[0];
+        assert_eq!(archive.file, "sample.tar.gz");
+        assert_eq!(archive.format, "tar.gz");
+        assert_eq!(archive.variant, "archive");
+        assert_eq!(archive.size, 7);
+        assert_eq!(archive.sha256, checksums[0].1.sha256);
+        assert_eq!(archive.blake3, checksums[0].1.blake3);
+        assert_eq!(archive.signature, None);
+        assert_eq!(archive.arch, std::env::consts::ARCH);
+        assert_eq!(
+            release.artifacts[1].signature.as_deref(),
+            Some("sample.run.minisig")
+        );
+        assert!(release.build_timestamp.ends_with('Z'));
+        assert_eq!(archive.build_timestamp, release.build_timestamp);
+
+        let json = serde_json::to_value(&release).unwrap();
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["artifacts"][1]["signature"], "sample.run.minisig");
+        assert!(json["artifacts"][0]["signature"].is_null());
+    }
+
+    #[test]
+    fn test_write_merges_earlier_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["sample.zip", "sample.run", "sample.dmg"] {
+            fs::write(dir.path().join(file), b"").unwrap();
+        }
+        let release = |version: &str, files: &[&str]| Release {
+            schema_version: SCHEMA_VERSION,
+            name: "sample".to_string(),
+            title: "Sample".to_string(),
+            version: version.to_string(),
+            git_commit: None,
+            build_timestamp: "2026-01-01T00:00:00Z".to_string(),
+            notes: None,
+            artifacts: files.iter().map(|f| artifact(f, version)).collect(),
+        };
+
+        release("1.2.3", &["sample.zip", "sample.run", "sample.pkg"])
+            .write(dir.path())
+            .unwrap();
+        let mut current = release("1.2.3", &["sample.zip", "sample.dmg"]);
+        current.artifacts[0].size = 42;
+        current.write(dir.path()).unwrap();
+
+        // The earlier zip is replaced and the deleted pkg dropped
+        let written: Release =
+            serde_json::from_str(&fs::read_to_string(dir.path().join(RELEASE_FILE)).unwrap())
+                .unwrap();
+        let files = written
+            .artifacts
+            .iter()
+            .map(|a| a.file.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["sample.dmg", "sample.run", "sample.zip"]);
+        assert_eq!(written.artifacts[2].size, 42);
+
+        // Another version starts over
+        release("1.3.0", &["sample.zip"]).write(dir.path()).unwrap();
+        let written: Release =
+            serde_json::from_str(&fs::read_to_string(dir.path().join(RELEASE_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(written.artifacts.len(), 1);
+    }
+
+    #[test]
+    fn test_binary_arch() {
+        // Minimal ELF64 header for x86_64
+        let mut elf = vec![0u8; 64];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2; // 64-bit
+        elf[5] = 1; // little endian
+        elf[6] = 1; // version
+        elf[16] = 2; // ET_EXEC
+        elf[18] = 62; // EM_X86_64
+        elf[20] = 1;
+        elf[52] = 64; // e_ehsize
+        assert_eq!(binary_arch(&elf).as_deref(), Some("x86_64"));
+        elf[18] = 183; // EM_AARCH64
+        assert_eq!(binary_arch(&elf).as_deref(), Some("aarch64"));
+        assert_eq!(binary_arch(b"not a binary"), None);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::error::Error;
+use crate::result::Result;
+use cms::builder::{SignedDataBuilder, SignerInfoBuilder, create_signing_time_attribute};
+use cms::cert::{CertificateChoices, IssuerAndSerialNumber};
+use cms::signed_data::{EncapsulatedContentInfo, SignerIdentifier};
+use const_oid::ObjectIdentifier;
+use const_oid::db::rfc4519;
+use const_oid::db::rfc5912::ID_SHA_256;
+use der::asn1::{Ia5StringRef, PrintableStringRef, SetOfVec, Utf8StringRef};
+use der::{Any, Decode, Encode};
+use p12_keystore::KeyStore;
+use rsa::pkcs1v15::{Signature, SigningKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+use spki::AlgorithmIdentifierOwned;
+use std::fs;
+use std::path::Path;
+use x509_cert::Certificate;
+use x509_cert::attr::Attribute;
+use x509_cert::name::Name;
+
+/// Environment variable holding the identity password unless configured otherwise
+pub const DEFAULT_PASSWORD_ENV: &str = "EMERGE_SIGNING_PASSWORD";
+
+/// A code signing certificate with its private key, loaded from a PKCS#12 file
+pub struct Identity {
+    /// Signing (leaf) certificate
+    pub certificate: Certificate,
+    /// All certificates of the PKCS#12 file, leaf first
+    pub chain: Vec<Certificate>,
+    key: SigningKey<Sha256>,
+}
+
+impl Identity {
+    /// Load a PKCS#12 (.p12/.pfx) identity, reading the password from `password_env`
+    pub fn load(path: &Path, password_env: Option<&str>) -> Result<Self> {
+        let data = fs::read(path).map_err(|e| {
+            Error::Signing(format!("Unable to read identity {}: {}", path.display(), e))
+        })?;
+        let password =
+            std::env::var(password_env.unwrap_or(DEFAULT_PASSWORD_ENV)).unwrap_or_default();
+        Self::from_pkcs12(&data, &password)
+            .map_err(|e| Error::Signing(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn from_pkcs12(data: &[u8], password: &str) -> Result<Self> {
+        let keystore = KeyStore::from_pkcs12(data, password)
+            .map_err(|e| Error::Signing(format!("Invalid PKCS#12 identity: {}", e)))?;
+        let (_, key_chain) = keystore.private_key_chain().ok_or_else(|| {
+            Error::Signing("PKCS#12 identity contains no private key".to_string())
+        })?;
+
+        let private_key = RsaPrivateKey::from_pkcs8_der(key_chain.key()).map_err(|e| {
+            Error::Signing(format!("Unsupported private key (RSA required): {}", e))
+        })?;
+        let public_key = private_key.to_public_key();
+
+        let mut chain = key_chain
+            .chain()
+            .iter()
+            .map(|cert| Certificate::from_der(cert.as_der()))
+            .collect::<der::Result<Vec<_>>>()?;
+
+        // The leaf is the certificate matching the private key
+        let leaf = chain
+            .iter()
+            .position(|cert| {
+                cert.tbs_certificate
+                    .subject_public_key_info
+                    .to_der()
+                    .ok()
+                    .and_then(|spki| RsaPublicKey::from_public_key_der(&spki).ok())
+                    .is_some_and(|key| key == public_key)
+            })
+            .ok_or_else(|| Error::Signing("No certificate matches the private key".to_string()))?;
+        chain.swap(0, leaf);
+
+        Ok(Self {
+            certificate: chain[0].clone(),
+            chain,
+            key: SigningKey::new(private_key),
+        })
+    }
+
+    /// Common name of the signing certificate
+    pub fn common_name(&self) -> Option<String> {
+        name_attribute(&self.certificate.tbs_certificate.subject, rfc4519::CN)
+    }
+
+    /// Organizational unit of the signing certificate (the Team ID of Apple certificates)
+    pub fn organizational_unit(&self) -> Option<String> {
+        name_attribute(&self.certificate.tbs_certificate.subject, rfc4519::OU)
+    }
+
+    /// Create a DER-encoded CMS `SignedData` with a SHA-256 RSA signature.
+    /// `detached_digest` is the SHA-256 digest of external content (`content` must be `None`);
+    /// the signing time is always added to the signed attributes.
+    pub fn sign(
+        &self,
+        content_type: ObjectIdentifier,
+        content: Option<Any>,
+        detached_digest: Option<&[u8]>,
+        attributes: Vec<Attribute>,
+    ) -> Result<Vec<u8>> {
+        let digest_algorithm = AlgorithmIdentifierOwned {
+            oid: ID_SHA_256,
+            parameters: None,
+        };
+        let content_info = EncapsulatedContentInfo {
+            econtent_type: content_type,
+            econtent: content,
+        };
+        let signer = SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+            issuer: self.certificate.tbs_certificate.issuer.clone(),
+            serial_number: self.certificate.tbs_certificate.serial_number.clone(),
+        });
+
+        let mut signer_info = SignerInfoBuilder::new(
+            &self.key,
+            signer,
+            digest_algorithm.clone(),
+            &content_info,
+            detached_digest,
+        )
+        .map_err(cms_error)?;
+        signer_info
+            .add_signed_attribute(create_signing_time_attribute().map_err(cms_error)?)
+            .map_err(cms_error)?;
+        for attribute in attributes {
+            signer_info
+                .add_signed_attribute(attribute)
+                .map_err(cms_error)?;
+        }
+
+        let mut builder = SignedDataBuilder::new(&content_info);
+        builder
+            .add_digest_algorithm(digest_algorithm)
+            .map_err(cms_error)?;
+        for cert in &self.chain {
+            builder
+                .add_certificate(CertificateChoices::Certificate(cert.clone()))
+                .map_err(cms_error)?;
+        }
+        builder
+            .add_signer_info::<_, Signature>(signer_info)
+            .map_err(cms_error)?;
+
+        Ok(builder.build().map_err(cms_error)?.to_der()?)
+    }
+
+    /// Upper bound of the size of a signature created by [`Identity::sign`] for small content
+    pub fn signature_size_estimate(&self) -> usize {
+        let certificates: usize = self
+            .chain
+            .iter()
+            .map(|cert| cert.to_der().map(|der| der.len()).unwrap_or(4096))
+            .sum();
+        certificates + 4096
+    }
+}
+
+/// Build a signed attribute with a single value
+pub fn attribute(oid: ObjectIdentifier, value: Any) -> Result<Attribute> {
+    Ok(Attribute {
+        oid,
+        values: SetOfVec::try_from(vec![value])?,
+    })
+}
+
+/// First string value of an attribute in a distinguished name
+fn name_attribute(name: &Name, oid: ObjectIdentifier) -> Option<String> {
+    name.0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|atv| atv.oid == oid)
+        .and_then(|atv| {
+            let value = &atv.value;
+            Utf8StringRef::try_from(value)
+                .map(|s| s.to_string())
+                .or_else(|_| PrintableStringRef::try_from(value).map(|s| s.to_string()))
+                .or_else(|_| Ia5StringRef::try_from(value).map(|s| s.to_string()))
+                .ok()
+        })
+}
+
+fn cms_error(error: impl std::fmt::Display) -> Error {
+    Error::Signing(format!("CMS: {}", error))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use cms::content_info::ContentInfo;
+    use cms::signed_data::SignedData;
+    use rsa::pkcs1v15::VerifyingKey;
+    use rsa::signature::Verifier;
+
+    pub fn test_identity() -> Identity {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/signing/identity.p12");
+        Identity::from_pkcs12(&fs::read(path).unwrap(), "emerge").unwrap()
+    }
+
+    /// Verify the RSA signature of the first signer over its signed attributes
+    pub fn verify_signed_data(der: &[u8], identity: &Identity) -> SignedData {
+        let content_info = ContentInfo::from_der(der).unwrap();
+        let signed_data: SignedData = content_info.content.decode_as().unwrap();
+        let signer = signed_data.signer_infos.0.get(0).unwrap();
+        let signed_attributes = signer.signed_attrs.as_ref().unwrap().to_der().unwrap();
+        let public_key = RsaPublicKey::from_public_key_der(
+            &identity
+                .certificate
+                .tbs_certificate
+                .subject_public_key_info
+                .to_der()
+                .unwrap(),
+        )
+        .unwrap();
+        let signature = Signature::try_from(signer.signature.as_bytes()).unwrap();
+        VerifyingKey::<Sha256>::new(public_key)
+            .verify(&signed_attributes, &signature)
+            .unwrap();
+        signed_data
+    }
+
+    #[test]
+    fn test_load_identity() {
+        let identity = test_identity();
+        assert_eq!(
+            identity.common_name().as_deref(),
+            Some("Emerge Test Signing")
+        );
+        assert_eq!(
+            identity.organizational_unit().as_deref(),
+            Some("EMERGETEST")
+        );
+        assert_eq!(identity.chain.len(), 1);
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/signing/identity.p12");
+        assert!(Identity::from_pkcs12(&fs::read(path).unwrap(), "wrong").is_err());
+    }
+
+    #[test]
+    fn test_sign_detached() {
+        use sha2::Digest;
+        let identity = test_identity();
+        let digest = Sha256::digest(b"content");
+        let der = identity
+            .sign(
+                const_oid::db::rfc5911::ID_DATA,
+                None,
+                Some(&digest),
+                Vec::new(),
+            )
+            .unwrap();
+        assert!(der.len() < identity.signature_size_estimate());
+
+        let signed_data = verify_signed_data(&der, &identity);
+        assert!(signed_data.encap_content_info.econtent.is_none());
+        assert_eq!(signed_data.certificates.unwrap().0.len(), 1);
+    }
+}
@@ -0,0 +1,539 @@
+//! Update feeds of the output folder: a Sparkle `appcast.xml` and a generic `update.json`
+
+use crate::manifest::{Manifest, UpdatesConfig};
+use crate::minisign::SecretKey;
+use crate::release::{Artifact, Release};
+use crate::result::Result;
+use crate::tpl::Tpl;
+use crate::utils::xml_escape;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc2822;
+
+/// Sparkle appcast of the output folder
+pub const APPCAST_FILE: &str = "appcast.xml";
+
+/// Generic update feed of the output folder
+pub const FEED_FILE: &str = "update.json";
+
+/// Version of the `update.json` schema, raised on incompatible changes
+pub const FEED_SCHEMA_VERSION: u32 = 1;
+
+const SPARKLE_NS: &str = "http://www.andymatuschak.org/xml-namespaces/sparkle";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+
+#[derive(Debug, Serialize)]
+pub struct Feed {
+    pub schema_version: u32,
+    pub name: String,
+    pub version: String,
+    /// Time of the latest run (RFC 3339, UTC)
+    pub pub_date: String,
+    pub notes: Option<String>,
+    pub notes_url: Option<String>,
+    /// Downloads by `platform-arch`, such as `macos-universal` or `linux-x86_64`
+    pub platforms: BTreeMap<String, Vec<Download>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Download {
+    pub url: String,
+    pub file: String,
+    pub format: String,
+    pub variant: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Contents of the minisign signature file
+    pub signature: Option<String>,
+}
+
+/// Write the update feeds configured in `[package.metadata.emerge.updates]` for the
+/// artifacts of `release`, returning the files written
+pub fn write(
+    manifest: &Manifest,
+    release: &Release,
+    key: Option<&SecretKey>,
+) -> Result<Vec<PathBuf>> {
+    let Some(config) = &manifest.updates else {
+        return Ok(Vec::new());
+    };
+    let folder = &manifest.output_folder;
+    let mut written = Vec::new();
+
+    if config.appcast {
+        let items = appcast_artifacts(release, &config.appcast_variants)
+            .into_iter()
+            .map(|artifact| appcast_item(manifest, config, release, artifact, key))
+            .collect::<Result<Vec<_>>>()?;
+        if !items.is_empty() {
+            let path = folder.join(APPCAST_FILE);
+            let existing = fs::read_to_string(&path).ok();
+            let xml = appcast(
+                &manifest.title,
+                &manifest.version,
+                existing.as_deref(),
+                &items,
+            );
+            fs::write(&path, xml)?;
+            written.push(path);
+        }
+    }
+
+    if config.feed {
+        let path = folder.join(FEED_FILE);
+        let mut json = serde_json::to_string_pretty(&feed(manifest, config, release, folder))?;
+        json.push('\n');
+        fs::write(&path, json)?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Download URL of `artifact` from the `url` template
+fn download_url(manifest: &Manifest, config: &UpdatesConfig, artifact: &Artifact) -> String {
+    let mut tpl = Tpl::new();
+    tpl.register("FILE", artifact.file.as_str());
+    tpl.register("VERSION", manifest.version.as_str());
+    tpl.register("NAME", manifest.name.as_str());
+    tpl.register("PLATFORM", artifact.platform.as_str());
+    tpl.register("ARCH", artifact.arch.as_str());
+    tpl.parse(&config.url)
+}
+
+/// `sparkle:os` of an artifact, `None` for platforms without Sparkle
+fn sparkle_os(artifact: &Artifact) -> Option<&'static str> {
+    match artifact.platform.as_str() {
+        "macos" => Some("macos"),
+        // WinSparkle picks the download matching its own architecture
+        "windows" => Some(match artifact.arch.as_str() {
+            "x86_64" => "windows-x64",
+            "aarch64" => "windows-arm64",
+            "x86" => "windows-x86",
+            _ => "windows",
+        }),
+        _ => None,
+    }
+}
+
+/// The artifact of each Sparkle platform and arch that gets an appcast item: the one whose
+/// variant comes first in `variants`. The release holds the artifacts of earlier runs of the
+/// version as well, so a version has one item per platform and arch however it was built.
+fn appcast_artifacts<'a>(release: &'a Release, variants: &[String]) -> Vec<&'a Artifact> {
+    let mut chosen = BTreeMap::<(&str, &str), (usize, &Artifact)>::new();
+    for artifact in release.artifacts.iter().filter(|a| sparkle_os(a).is_some()) {
+        let Some(rank) = variants.iter().position(|v| *v == artifact.variant) else {
+            continue;
+        };
+        let key = (artifact.platform.as_str(), artifact.arch.as_str());
+        if chosen.get(&key).is_none_or(|(best, _)| rank < *best) {
+            chosen.insert(key, (rank, artifact));
+        }
+    }
+    chosen.into_values().map(|(_, artifact)| artifact).collect()
+}
+
+fn appcast_item(
+    manifest: &Manifest,
+    config: &UpdatesConfig,
+    release: &Release,
+    artifact: &Artifact,
+    key: Option<&SecretKey>,
+) -> Result<String> {
+    let url = download_url(manifest, config, artifact);
+    let os = sparkle_os(artifact).unwrap_or_default();
+
+    let signature = match key {
+        Some(key) => {
+            let data = fs::read(manifest.output_folder.join(&artifact.file))?;
+            format!(
+                " sparkle:edSignature=\"{}\"",
+                STANDARD.encode(key.sign_ed25519(&data))
+            )
+        }
+        None => {
+            status!(
+                "Warning: {} has no sparkle:edSignature, configure a minisign secret key to sign it",
+                artifact.file
+            );
+            String::new()
+        }
+    };
+
+    let mut item = String::from("    <item>\n");
+    item.push_str(&format!(
+        "      <title>Version {}</title>\n",
+        xml_escape(&manifest.version)
+    ));
+    if let Ok(date) = OffsetDateTime::now_utc().format(&Rfc2822) {
+        item.push_str(&format!("      <pubDate>{}</pubDate>\n", date));
+    }
+    item.push_str(&format!(
+        "      <sparkle:version>{0}</sparkle:version>\n      <sparkle:shortVersionString>{0}</sparkle:shortVersionString>\n",
+        xml_escape(&manifest.version)
+    ));
+    if artifact.platform == "macos" {
+        // Same as LSMinimumSystemVersion of the bundle
+        item.push_str(&format!(
+            "      <sparkle:minimumSystemVersion>{}</sparkle:minimumSystemVersion>\n",
+            crate::macos::dmg::MINIMUM_SYSTEM_VERSION
+        ));
+    }
+    if let Some(link) = &config.release_notes_url {
+        item.push_str(&format!(
+            "      <sparkle:releaseNotesLink>{}</sparkle:releaseNotesLink>\n",
+            xml_escape(link)
+        ));
+    }
+    // Inline notes are HTML, changelog notes Markdown (Sparkle 2.4 and later)
+    let description = match (&config.notes, &release.notes) {
+        (Some(notes), _) => Some(("", notes)),
+        (None, Some(notes)) => Some((" sparkle:format=\"markdown\"", notes)),
+        (None, None) => None,
+    };
+    if let Some((format, notes)) = description {
+        item.push_str(&format!(
+            "      <description{}><![CDATA[{}]]></description>\n",
+            format,
+            notes.replace("]]>", "]]]]><![CDATA[>")
+        ));
+    }
+    // Sparkle runs packages with the macOS Installer instead of replacing the bundle
+    let installation_type = match artifact.variant.as_str() {
+        "pkg" => " sparkle:installationType=\"package\"",
+        _ => "",
+    };
+    item.push_str(&format!(
+        "      <enclosure url=\"{}\" length=\"{}\" type=\"application/octet-stream\" sparkle:os=\"{}\"{}{} />\n",
+        xml_escape(&url),
+        artifact.size,
+        os,
+        installation_type,
+        signature
+    ));
+    item.push_str("    </item>\n");
+    Ok(item)
+}
+
+/// The appcast with `items` of `version` first, followed by the items of `existing` that
+/// are not replaced: those of the same version or with the same enclosure URL
+fn appcast(title: &str, version: &str, existing: Option<&str>, items: &[String]) -> String {
+    let (prefix, earlier, suffix) = match existing.and_then(split_appcast) {
+        Some(parts) => parts,
+        None => {
+            let prefix = format!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\" xmlns:sparkle=\"{}\" xmlns:dc=\"{}\">\n  <channel>\n    <title>{}</title>\n",
+                SPARKLE_NS,
+                DC_NS,
+                xml_escape(title)
+            );
+            (prefix, Vec::new(), "  </channel>\n</rss>\n".to_string())
+        }
+    };
+
+    let urls = items
+        .iter()
+        .filter_map(|i| enclosure_url(i))
+        .collect::<Vec<_>>();
+    let mut xml = prefix;
+    for item in items {
+        xml.push_str(item);
+    }
+    let version = format!("<sparkle:version>{}</sparkle:version>", xml_escape(version));
+    for item in earlier {
+        if !item.contains(&version) && !enclosure_url(&item).is_some_and(|url| urls.contains(&url))
+        {
+            xml.push_str(&item);
+        }
+    }
+    xml.push_str(&suffix);
+    xml
+}
+
+/// Split an appcast into the text before its items, the items (with their indentation
+/// and line ending) and the text after them
+fn split_appcast(xml: &str) -> Option<(String, Vec<String>, String)> {
+    let channel_end = xml.find("</channel>")?;
+    let mut items = Vec::new();
+    let mut rest = &xml[..channel_end];
+    let mut prefix = None;
+    while let Some(start) = rest.find("<item>") {
+        // Keep the indentation of the item with it
+        let start = rest[..start].rfind('\n').map_or(start, |n| n + 1);
+        let end = rest[start..].find("</item>")? + start + "</item>".len();
+        let end = rest[end..]
+            .find('\n')
+            .filter(|n| rest[end..end + n].trim().is_empty())
+            .map_or(end, |n| end + n + 1);
+        prefix.get_or_insert_with(|| rest[..start].to_string());
+        items.push(rest[start..end].to_string());
+        rest = &rest[end..];
+    }
+    let prefix = prefix.unwrap_or_else(|| rest.to_string());
+    let suffix = match items.is_empty() {
+        true => xml[channel_end..].to_string(),
+        false => format!("{}{}", rest, &xml[channel_end..]),
+    };
+    Some((prefix, items, suffix))
+}
+
+/// `url` attribute of the enclosure of an appcast item
+fn enclosure_url(item: &str) -> Option<String> {
+    let enclosure = &item[item.find("<enclosure")?..];
+    let start = enclosure.find("url=\"")? + "url=\"".len();
+    let end = enclosure[start..].find('"')? + start;
+    Some(enclosure[start..end].to_string())
+}
+
+fn feed(manifest: &Manifest, config: &UpdatesConfig, release: &Release, folder: &Path) -> Feed {
+    let mut platforms = BTreeMap::<String, Vec<Download>>::new();
+    for artifact in &release.artifacts {
+        platforms
+            .entry(format!("{}-{}", artifact.platform, artifact.arch))
+            .or_default()
+            .push(Download {
+                url: download_url(manifest, config, artifact),
+                file: artifact.file.clone(),
+                format: artifact.format.clone(),
+                variant: artifact.variant.clone(),
+                size: artifact.size,
+                sha256: artifact.sha256.clone(),
+                signature: artifact
+                    .signature
+                    .as_ref()
+                    .and_then(|file| fs::read_to_string(folder.join(file)).ok()),
+            });
+    }
+
+    Feed {
+        schema_version: FEED_SCHEMA_VERSION,
+        name: release.name.clone(),
+        version: release.version.clone(),
+        pub_date: release.build_timestamp.clone(),
+        notes: config.notes.clone().or_else(|| release.notes.clone()),
+        notes_url: config.release_notes_url.clone(),
+        platforms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum;
+    use crate::manifest::tests::sample_manifest;
+    use crate::platform::Platform;
+    use crate::release::Output;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    fn item(url: &str) -> String {
+        format!(
+            "    <item>\n      <enclosure url=\"{}\" length=\"1\" />\n    </item>\n",
+            url
+        )
+    }
+
+    #[test]
+    fn test_appcast_append() {
+        let first = appcast("Sample & Co", "1", None, &[item("https://x/a-1.dmg")]);
+        assert!(first.starts_with("<?xml"));
+        assert!(first.contains("<title>Sample &amp; Co</title>"));
+        assert!(first.ends_with("  </channel>\n</rss>\n"));
+
+        let second = appcast(
+            "Sample",
+            "2",
+            Some(&first),
+            &[item("https://x/a-2.dmg"), item("https://x/a-2.exe")],
+        );
+        // The title of the existing channel is kept, new items come first
+        assert!(second.contains("<title>Sample &amp; Co</title>"));
+        let urls = split_appcast(&second)
+            .unwrap()
+            .1
+            .iter()
+            .filter_map(|i| enclosure_url(i))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "https://x/a-2.dmg",
+                "https://x/a-2.exe",
+                "https://x/a-1.dmg"
+            ]
+        );
+
+        // Regenerating a version replaces its items instead of duplicating them
+        let third = appcast("Sample", "2", Some(&second), &[item("https://x/a-2.dmg")]);
+        assert_eq!(third.matches("https://x/a-2.dmg").count(), 1);
+        assert_eq!(split_appcast(&third).unwrap().1.len(), 3);
+        assert!(third.ends_with("  </channel>\n</rss>\n"));
+
+        // The items of a version replace all its earlier items
+        let versioned = |version: &str, url: &str| {
+            item(url).replace(
+                "<enclosure",
+                &format!("<sparkle:version>{}</sparkle:version><enclosure", version),
+            )
+        };
+        let first = appcast(
+            "Sample",
+            "1",
+            None,
+            &[
+                versioned("1", "https://x/a-1.pkg"),
+                versioned("1", "https://x/a-1.tar.gz"),
+            ],
+        );
+        let second = appcast(
+            "Sample",
+            "1",
+            Some(&first),
+            &[versioned("1", "https://x/a-1.dmg")],
+        );
+        let items = split_appcast(&second).unwrap().1;
+        assert_eq!(items.len(), 1);
+        assert_eq!(enclosure_url(&items[0]).unwrap(), "https://x/a-1.dmg");
+    }
+
+    #[test]
+    fn test_appcast_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let (ctx, mut manifest) = sample_manifest(
+            r#"
+            [updates]
+            url = "https://example.com/$FILE"
+            feed = false
+            "#,
+        );
+        manifest.output_folder = dir.path().to_path_buf();
+        let output = |file: &str, platform, variant| {
+            let path = dir.path().join(file);
+            fs::write(&path, file).unwrap();
+            (Output::new(path.clone(), platform, variant), path)
+        };
+        let (outputs, paths): (Vec<_>, Vec<_>) = [
+            output("sample.pkg", Platform::MacOS, "pkg"),
+            output("sample-macos.tar.gz", Platform::MacOS, "archive"),
+            output("sample.dmg", Platform::MacOS, "dmg"),
+            output("sample.zip", Platform::Windows, "archive"),
+            output("sample-setup-inno.exe", Platform::Windows, "inno"),
+            output("sample-setup-nsis.exe", Platform::Windows, "nsis"),
+        ]
+        .into_iter()
+        .unzip();
+        let checksums = paths
+            .iter()
+            .map(|p| (p.clone(), checksum::compute(p).unwrap()))
+            .collect::<Vec<_>>();
+        let release = Release::new(&ctx, &manifest, &outputs, &checksums, &[]).unwrap();
+        let files = |manifest: &Manifest| {
+            write(manifest, &release, None).unwrap();
+            let xml = fs::read_to_string(dir.path().join(APPCAST_FILE)).unwrap();
+            fs::remove_file(dir.path().join(APPCAST_FILE)).unwrap();
+            split_appcast(&xml)
+                .unwrap()
+                .1
+                .iter()
+                .map(|item| (enclosure_url(item).unwrap(), item.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        // One item per platform and arch, by the default preference
+        let items = files(&manifest);
+        let urls = items
+            .iter()
+            .map(|(url, _)| url.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/sample.dmg",
+                "https://example.com/sample-setup-nsis.exe"
+            ]
+        );
+        assert!(!items[0].1.contains("installationType"));
+
+        // Packages are marked for the macOS Installer
+        manifest.updates.as_mut().unwrap().appcast_variants =
+            vec!["pkg".to_string(), "archive".to_string()];
+        let items = files(&manifest);
+        assert_eq!(items[0].0, "https://example.com/sample.pkg");
+        assert!(items[0].1.contains(" sparkle:installationType=\"package\""));
+        assert_eq!(items[1].0, "https://example.com/sample.zip");
+        assert!(!items[1].1.contains("installationType"));
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let (ctx, mut manifest) = sample_manifest(
+            r#"
+            [updates]
+            url = "https://example.com/$VERSION/$PLATFORM-$ARCH/$FILE"
+            notes = "Fixes ]]> escaping"
+            "#,
+        );
+        manifest.output_folder = dir.path().to_path_buf();
+        let dmg = dir.path().join("sample.dmg");
+        let archive = dir.path().join("sample.tar.gz");
+        fs::write(&dmg, b"disk image").unwrap();
+        fs::write(&archive, b"archive").unwrap();
+
+        let outputs = [
+            Output::new(dmg.clone(), Platform::MacOS, "dmg"),
+            Output::new(archive.clone(), Platform::Linux, "archive"),
+        ];
+        let checksums = [
+            (dmg.clone(), checksum::compute(&dmg).unwrap()),
+            (archive.clone(), checksum::compute(&archive).unwrap()),
+        ];
+        let release = Release::new(&ctx, &manifest, &outputs, &checksums, &[]).unwrap();
+        let arch = release.artifacts[0].arch.clone();
+        let key = SecretKey::generate().unwrap();
+
+        let written = write(&manifest, &release, Some(&key)).unwrap();
+        assert_eq!(
+            written,
+            [dir.path().join(APPCAST_FILE), dir.path().join(FEED_FILE)]
+        );
+
+        // Only the DMG has a Sparkle item, signed with the plain Ed25519 key
+        let xml = fs::read_to_string(dir.path().join(APPCAST_FILE)).unwrap();
+        let items = split_appcast(&xml).unwrap().1;
+        assert_eq!(items.len(), 1);
+        let url = format!("https://example.com/1.2.3/macos-{}/sample.dmg", arch);
+        assert_eq!(enclosure_url(&items[0]).unwrap(), url);
+        assert!(items[0].contains("length=\"10\""));
+        assert!(items[0].contains("sparkle:os=\"macos\""));
+        assert!(items[0].contains("<sparkle:version>1.2.3</sparkle:version>"));
+        assert!(items[0].contains("<![CDATA[Fixes ]]]]><![CDATA[> escaping]]>"));
+
+        let start = xml.find("sparkle:edSignature=\"").unwrap() + "sparkle:edSignature=\"".len();
+        let end = xml[start..].find('"').unwrap() + start;
+        let signature = STANDARD.decode(&xml[start..end]).unwrap();
+        let public_key: [u8; 32] = STANDARD
+            .decode(key.public_key().to_ed25519_base64())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        VerifyingKey::from_bytes(&public_key)
+            .unwrap()
+            .verify(b"disk image", &Signature::from_slice(&signature).unwrap())
+            .unwrap();
+
+        // The JSON feed has every platform
+        let feed: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join(FEED_FILE)).unwrap()).unwrap();
+        assert_eq!(feed["version"], "1.2.3");
+        assert_eq!(feed["notes"], "Fixes ]]> escaping");
+        let linux = &feed["platforms"][format!("linux-{}", arch)][0];
+        assert_eq!(linux["file"], "sample.tar.gz");
+        assert_eq!(linux["size"], 7);
+        assert_eq!(feed["platforms"][format!("macos-{}", arch)][0]["url"], url);
+    }
+}
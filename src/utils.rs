@@ -1,3 +1,4 @@
+use crate::context::Context;
 use crate::result::Result;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -60,6 +61,37 @@ pub fn ensure_dir(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// A path as UTF-8, for the arguments of external programs
+pub fn path_str(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| {
+        crate::error::Error::Custom(format!("Path is not valid UTF-8: {}", path.display()))
+    })
+}
+
+/// Make a copied file writable; copies keep the mode of read-only sources such as the Nix store
+pub fn make_writable(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    #[cfg(not(unix))]
+    {
+        let mut perms = fs::metadata(path)?.permissions();
+        perms.set_readonly(false);
+        fs::set_permissions(path, perms)?;
+    }
+    Ok(())
+}
+
+/// Cargo target directory: `CARGO_TARGET_DIR` or `target` next to the manifest
+pub fn target_dir(ctx: &Context) -> PathBuf {
+    match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(dir) => ctx.base_dir.join(dir),
+        None => ctx.base_dir.join("target"),
+    }
+}
+
 /// Get the current platform identifier as a string
 pub fn platform_string() -> &'static str {
     if cfg!(target_os = "macos") {
@@ -72,3 +104,46 @@ pub fn platform_string() -> &'static str {
         "unknown"
     }
 }
+
+/// Locate an executable on PATH
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    let candidates: &[&str] = if cfg!(windows) {
+        &["", ".exe", ".cmd", ".bat"]
+    } else {
+        &[""]
+    };
+
+    std::env::split_paths(&path).find_map(|dir| {
+        candidates
+            .iter()
+            .map(|ext| dir.join(format!("{}{}", name, ext)))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+/// Escape a string for use in XML text and attribute values
+pub fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Lowercase hex encoding of `bytes`
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Render a path with `/` separators, as used in archives and bundle manifests
+pub fn unix_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
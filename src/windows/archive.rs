@@ -1,87 +1,75 @@
+use crate::archive::{self, Contents};
 use crate::context::Context;
-use crate::error::Error;
-use crate::manifest::Manifest;
+use crate::layout::Layout;
+use crate::manifest::{ArchiveFormat, Manifest};
 use crate::result::Result;
 use crate::utils;
-use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::path::Path;
-use walkdir::WalkDir;
-use zip::ZipWriter;
-use zip::write::FileOptions;
-
-pub fn create_zip(ctx: &Context, manifest: &Manifest) -> Result<()> {
-    println!("Creating zip archive for Windows...");
+use crate::windows::authenticode::Signer;
+use crate::windows::dlls;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Create the archive of `--archive` on Windows (zip unless configured otherwise)
+pub fn create(ctx: &Context, manifest: &Manifest) -> Result<PathBuf> {
+    let options = archive::Options::from_manifest(manifest, ArchiveFormat::Zip)?;
+    status!(
+        "Creating {} archive for Windows...",
+        options.format.as_str()
+    );
 
     // Ensure output folder exists
     utils::ensure_dir(&manifest.output_folder)?;
 
-    // Create temporary directory for archive contents
+    // Signed files are staged; everything else is read in place
     let temp_dir = std::env::temp_dir().join(format!("emerge-{}", manifest.name));
     if temp_dir.exists() {
         fs::remove_dir_all(&temp_dir)?;
     }
     fs::create_dir_all(&temp_dir)?;
 
-    // Create application directory
-    let app_dir = temp_dir.join(&manifest.name);
-    fs::create_dir_all(&app_dir)?;
-
-    // Copy files according to copy operations
-    for (src, dst) in &manifest.copy_operations {
-        let dest_path = app_dir.join(dst);
-
-        if ctx.verbose {
-            println!("Copying {} to {}", src.display(), dest_path.display());
-        }
-
-        // Ensure parent directory exists
-        if let Some(parent) = dest_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-
-        utils::copy_recursively(src, &dest_path)?;
-    }
+    let contents = contents(ctx, manifest, &temp_dir)?;
 
-    // Create zip archive
-    let archive_filename = format!("{}.zip", manifest.filename);
+    // Create the archive
+    let archive_filename = options.file_name(&manifest.filename);
     let archive_path = manifest.output_folder.join(&archive_filename);
 
-    create_zip_file(&temp_dir, &archive_path)?;
+    archive::write_with_progress(&options, &contents, &archive_path)?;
 
     // Clean up temp directory
     fs::remove_dir_all(&temp_dir)?;
 
-    println!("Archive created successfully: {}", archive_path.display());
-    Ok(())
+    status!("Archive created successfully: {}", archive_path.display());
+    Ok(archive_path)
 }
 
-fn create_zip_file(source_dir: &Path, output_path: &Path) -> Result<()> {
-    let file = File::create(output_path)?;
-    let mut zip = ZipWriter::new(file);
-
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .unix_permissions(0o755);
-
-    let walkdir = WalkDir::new(source_dir);
-    let it = walkdir.into_iter().filter_map(|e| e.ok());
-
-    for entry in it {
-        let path = entry.path();
-        let name = path.strip_prefix(source_dir).unwrap();
-
-        if path.is_file() {
-            zip.start_file(name.to_string_lossy().to_string(), options)?;
-            let mut f = File::open(path)?;
-            let mut buffer = Vec::new();
-            f.read_to_end(&mut buffer)?;
-            zip.write_all(&buffer)?;
-        } else if !name.as_os_str().is_empty() {
-            zip.add_directory(name.to_string_lossy().to_string(), options)?;
+/// Entries of the archive below `<name>/`, read from their sources except for the
+/// executables and DLLs that are signed in `temp_dir`
+pub fn contents(ctx: &Context, manifest: &Manifest, temp_dir: &Path) -> Result<Contents> {
+    // Imported DLLs that are not part of the copy operations (opt-in)
+    let dll_copies = dlls::bundle(ctx, manifest)?;
+
+    let mut contents = Contents::default();
+    let layout = Layout::flat(Path::new(&manifest.name));
+    let operations = manifest.copy_operations.iter().chain(&dll_copies);
+    for placement in layout.place(manifest, operations) {
+        let path = utils::unix_path(&placement.destination);
+        if ctx.verbose {
+            status!("Adding {} as {}", placement.source.display(), path);
         }
+        contents.add(&placement.source, &path)?;
+    }
+
+    // Authenticode-sign copies of the executables and DLLs
+    if let Some(signer) = Signer::from_manifest(ctx, manifest)? {
+        contents.stage(temp_dir, dlls::is_pe_file)?;
+        let signed = signer.sign_tree(ctx, temp_dir)?;
+        status!(
+            "Signed {} files with {}",
+            signed.len(),
+            signer.identity.common_name().unwrap_or_default()
+        );
+        contents.add(temp_dir, "")?;
     }
 
-    zip.finish()?;
-    Ok(())
+    Ok(contents)
 }
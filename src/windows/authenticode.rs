@@ -0,0 +1,609 @@
+use crate::context::Context;
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::result::Result;
+use crate::signing::{self, Identity};
+use crate::windows::dlls;
+use cmpv2::status::PkiStatus;
+use cms::content_info::{CmsVersion, ContentInfo};
+use cms::signed_data::{SignedData, SignerInfos};
+use const_oid::ObjectIdentifier;
+use const_oid::db::rfc5911::ID_SIGNED_DATA;
+use const_oid::db::rfc5912::ID_SHA_256;
+use der::asn1::{Int, OctetString, SetOfVec};
+use der::{Any, Decode, Encode};
+use sha2::{Digest, Sha256};
+use spki::AlgorithmIdentifierOwned;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use x509_tsp::{MessageImprint, TimeStampReq, TimeStampResp, TspVersion};
+
+/// Environment variable naming the .pfx file when `windows.signing.certificate` is not set
+pub const CERTIFICATE_ENV: &str = "EMERGE_WINDOWS_CERTIFICATE";
+
+const SPC_INDIRECT_DATA_OBJID: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.2.1.4");
+const SPC_STATEMENT_TYPE_OBJID: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.2.1.11");
+const SPC_SP_OPUS_INFO_OBJID: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.2.1.12");
+const SPC_PE_IMAGE_DATAOBJ: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.2.1.15");
+const SPC_INDIVIDUAL_SP_KEY_PURPOSE_OBJID: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.2.1.21");
+/// Unsigned attribute holding an RFC 3161 timestamp token
+const SPC_RFC3161_OBJID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.311.3.3.1");
+
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+const IMAGE_DIRECTORY_ENTRY_SECURITY: usize = 4;
+const WIN_CERT_REVISION_2_0: u16 = 0x0200;
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 0x0002;
+
+/// Authenticode signer configured by `windows.signing`
+pub struct Signer {
+    pub identity: Identity,
+    pub timestamp_url: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+}
+
+impl Signer {
+    /// Load the signing certificate from the manifest or [`CERTIFICATE_ENV`];
+    /// `None` when no certificate is configured
+    pub fn from_manifest(ctx: &Context, manifest: &Manifest) -> Result<Option<Self>> {
+        let config = manifest
+            .windows
+            .as_ref()
+            .and_then(|w| w.signing.clone())
+            .unwrap_or_default();
+
+        let certificate = config
+            .certificate
+            .as_ref()
+            .map(|path| ctx.base_dir.join(path))
+            .or_else(|| std::env::var_os(CERTIFICATE_ENV).map(PathBuf::from));
+        let Some(certificate) = certificate else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            identity: Identity::load(&certificate, config.password_env.as_deref())?,
+            timestamp_url: config.timestamp_url,
+            description: Some(config.description.unwrap_or_else(|| manifest.title.clone())),
+            url: config.url,
+        }))
+    }
+
+    /// Sign a PE file in place
+    pub fn sign_file(&self, path: &Path) -> Result<()> {
+        let data = fs::read(path)?;
+        let signed = self
+            .sign(&data)
+            .map_err(|e| Error::Signing(format!("{}: {}", path.display(), e)))?;
+        fs::write(path, signed)?;
+        Ok(())
+    }
+
+    /// Sign every PE file below `path` (or `path` itself), returning the signed files
+    pub fn sign_tree(&self, ctx: &Context, path: &Path) -> Result<Vec<PathBuf>> {
+        let mut signed = Vec::new();
+        for entry in WalkDir::new(path).sort_by_file_name() {
+            let entry = entry.map_err(|e| Error::Custom(e.to_string()))?;
+            if dlls::is_pe_file(entry.path()) {
+                self.sign_file(entry.path())?;
+                if ctx.verbose {
+                    status!("Signed {}", entry.path().display());
+                }
+                signed.push(entry.into_path());
+            }
+        }
+        Ok(signed)
+    }
+
+    /// Embed an Authenticode signature into a PE image, replacing an existing one
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut layout = PeLayout::parse(data)?;
+
+        let mut image = data[..layout.image_end].to_vec();
+        image[layout.security_directory..layout.security_directory + 8].fill(0);
+        // The certificate table is 8-byte aligned and the padding is part of the hash
+        image.resize(image.len().next_multiple_of(8), 0);
+        layout.image_end = image.len();
+
+        let signature = self.signature(&image_digest(&image, &layout))?;
+
+        let length = (8 + signature.len()).next_multiple_of(8);
+        let offset = image.len();
+        image.extend_from_slice(&(length as u32).to_le_bytes());
+        image.extend_from_slice(&WIN_CERT_REVISION_2_0.to_le_bytes());
+        image.extend_from_slice(&WIN_CERT_TYPE_PKCS_SIGNED_DATA.to_le_bytes());
+        image.extend_from_slice(&signature);
+        image.resize(offset + length, 0);
+
+        let directory = layout.security_directory;
+        image[directory..directory + 4].copy_from_slice(&(offset as u32).to_le_bytes());
+        image[directory + 4..directory + 8].copy_from_slice(&(length as u32).to_le_bytes());
+
+        let sum = checksum(&image, layout.checksum);
+        image[layout.checksum..layout.checksum + 4].copy_from_slice(&sum.to_le_bytes());
+
+        Ok(image)
+    }
+
+    /// PKCS#7 `SignedData` over the `SpcIndirectDataContent` of an image digest
+    fn signature(&self, digest: &[u8]) -> Result<Vec<u8>> {
+        let content = Any::from_der(&indirect_data_content(digest)?)?;
+        let attributes = vec![
+            signing::attribute(
+                SPC_STATEMENT_TYPE_OBJID,
+                Any::from_der(&tlv(0x30, &SPC_INDIVIDUAL_SP_KEY_PURPOSE_OBJID.to_der()?))?,
+            )?,
+            signing::attribute(
+                SPC_SP_OPUS_INFO_OBJID,
+                Any::from_der(&opus_info(self.description.as_deref(), self.url.as_deref()))?,
+            )?,
+        ];
+        let der = self
+            .identity
+            .sign(SPC_INDIRECT_DATA_OBJID, Some(content), None, attributes)?;
+
+        let mut signed_data: SignedData = ContentInfo::from_der(&der)?.content.decode_as()?;
+        // Authenticode requires version 1 regardless of the content type
+        signed_data.version = CmsVersion::V1;
+
+        if let Some(url) = &self.timestamp_url {
+            let signature = signed_data
+                .signer_infos
+                .0
+                .get(0)
+                .map(|signer| signer.signature.as_bytes().to_vec())
+                .unwrap_or_default();
+            let token = request_timestamp(url, &signature)?;
+            add_timestamp(&mut signed_data, token)?;
+        }
+
+        Ok(ContentInfo {
+            content_type: ID_SIGNED_DATA,
+            content: Any::encode_from(&signed_data)?,
+        }
+        .to_der()?)
+    }
+}
+
+/// Offsets of the fields excluded from the Authenticode hash
+#[derive(Debug)]
+struct PeLayout {
+    checksum: usize,
+    security_directory: usize,
+    /// End of the image without its certificate table
+    image_end: usize,
+}
+
+impl PeLayout {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let invalid = |message: &str| Error::Signing(format!("Invalid PE image: {}", message));
+
+        if data.get(..2) != Some(b"MZ") {
+            return Err(invalid("missing MZ header"));
+        }
+        let pe = read_u32(data, 0x3c).ok_or_else(|| invalid("truncated DOS header"))? as usize;
+        if data.get(pe..pe + 4) != Some(b"PE\0\0") {
+            return Err(invalid("missing PE signature"));
+        }
+
+        let optional_header = pe + 24;
+        let (rva_count, directories) =
+            match read_u16(data, optional_header).ok_or_else(|| invalid("truncated header"))? {
+                PE32_MAGIC => (optional_header + 92, optional_header + 96),
+                PE32_PLUS_MAGIC => (optional_header + 108, optional_header + 112),
+                _ => return Err(invalid("unknown optional header magic")),
+            };
+        let rva_count = read_u32(data, rva_count).ok_or_else(|| invalid("truncated header"))?;
+        if (rva_count as usize) <= IMAGE_DIRECTORY_ENTRY_SECURITY {
+            return Err(invalid("no certificate table directory"));
+        }
+
+        let security_directory = directories + 8 * IMAGE_DIRECTORY_ENTRY_SECURITY;
+        let table = read_u32(data, security_directory)
+            .zip(read_u32(data, security_directory + 4))
+            .ok_or_else(|| invalid("truncated data directories"))?;
+
+        let image_end = match table {
+            (_, 0) => data.len(),
+            (offset, size) => {
+                let (start, end) = (offset as usize, offset as usize + size as usize);
+                // Only a trailing certificate table can be replaced without moving data
+                if end > data.len() || end.next_multiple_of(8) < data.len() {
+                    return Err(invalid("certificate table is not at the end of the file"));
+                }
+                start
+            }
+        };
+
+        Ok(Self {
+            checksum: optional_header + 64,
+            security_directory,
+            image_end,
+        })
+    }
+}
+
+/// Authenticode SHA-256 digest: the image without checksum, certificate table entry and table
+fn image_digest(data: &[u8], layout: &PeLayout) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(&data[..layout.checksum]);
+    hasher.update(&data[layout.checksum + 4..layout.security_directory]);
+    hasher.update(&data[layout.security_directory + 8..layout.image_end.min(data.len())]);
+    hasher.finalize().to_vec()
+}
+
+/// PE checksum as computed by `CheckSumMappedFile`
+fn checksum(data: &[u8], checksum_offset: usize) -> u32 {
+    let mut sum: u32 = 0;
+    for (index, chunk) in data.chunks(2).enumerate() {
+        let offset = index * 2;
+        if offset == checksum_offset || offset == checksum_offset + 2 {
+            continue;
+        }
+        let word = u16::from_le_bytes([chunk[0], chunk.get(1).copied().unwrap_or(0)]);
+        sum += word as u32;
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum.wrapping_add(data.len() as u32)
+}
+
+/// DER of `SpcIndirectDataContent` for a PE image digest
+fn indirect_data_content(digest: &[u8]) -> Result<Vec<u8>> {
+    // SpcPeImageData: no flags, file = <<<Obsolete>>> as required by Windows
+    let obsolete = bmp_string("<<<Obsolete>>>");
+    let pe_image_data = tlv(
+        0x30,
+        &[
+            &[0x03, 0x01, 0x00][..],
+            &tlv(0xa0, &tlv(0xa2, &tlv(0x80, &obsolete))),
+        ]
+        .concat(),
+    );
+    let data = tlv(
+        0x30,
+        &[SPC_PE_IMAGE_DATAOBJ.to_der()?, pe_image_data].concat(),
+    );
+
+    let algorithm = AlgorithmIdentifierOwned {
+        oid: ID_SHA_256,
+        parameters: Some(Any::null()),
+    };
+    let digest_info = tlv(
+        0x30,
+        &[algorithm.to_der()?, OctetString::new(digest)?.to_der()?].concat(),
+    );
+
+    Ok(tlv(0x30, &[data, digest_info].concat()))
+}
+
+/// DER of `SpcSpOpusInfo` with the optional program name and URL
+fn opus_info(description: Option<&str>, url: Option<&str>) -> Vec<u8> {
+    let mut content = Vec::new();
+    if let Some(description) = description {
+        content.extend(tlv(0xa0, &tlv(0x80, &bmp_string(description))));
+    }
+    if let Some(url) = url {
+        content.extend(tlv(0xa1, &tlv(0x80, url.as_bytes())));
+    }
+    tlv(0x30, &content)
+}
+
+/// DER `TimeStampReq` for the signature value of a signer
+fn timestamp_request(signature: &[u8]) -> Result<Vec<u8>> {
+    let nonce = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    let request = TimeStampReq {
+        version: TspVersion::V1,
+        message_imprint: MessageImprint {
+            hash_algorithm: AlgorithmIdentifierOwned {
+                oid: ID_SHA_256,
+                parameters: Some(Any::null()),
+            },
+            hashed_message: OctetString::new(Sha256::digest(signature).to_vec())?,
+        },
+        req_policy: None,
+        nonce: Some(Int::new(&nonce.to_be_bytes())?),
+        cert_req: true,
+        extensions: None,
+    };
+    Ok(request.to_der()?)
+}
+
+/// Timestamp token of a DER `TimeStampResp`
+fn timestamp_token(response: &[u8]) -> Result<ContentInfo> {
+    let response = TimeStampResp::from_der(response)?;
+    match response.status.status {
+        PkiStatus::Accepted | PkiStatus::GrantedWithMods => {}
+        status => {
+            return Err(Error::Signing(format!(
+                "Timestamp request rejected: {:?}",
+                status
+            )));
+        }
+    }
+    response
+        .time_stamp_token
+        .ok_or_else(|| Error::Signing("Timestamp response contains no token".to_string()))
+}
+
+/// Request an RFC 3161 timestamp for the signature value of a signer
+fn request_timestamp(url: &str, signature: &[u8]) -> Result<ContentInfo> {
+    let request = timestamp_request(signature)?;
+    let mut response = ureq::post(url)
+        .header("Content-Type", "application/timestamp-query")
+        .send(&request[..])
+        .map_err(|e| Error::Signing(format!("Timestamp server {}: {}", url, e)))?;
+    let body = response
+        .body_mut()
+        .read_to_vec()
+        .map_err(|e| Error::Signing(format!("Timestamp server {}: {}", url, e)))?;
+    timestamp_token(&body)
+}
+
+/// Attach a timestamp token as an unsigned attribute of the first signer
+fn add_timestamp(signed_data: &mut SignedData, token: ContentInfo) -> Result<()> {
+    let attribute = signing::attribute(SPC_RFC3161_OBJID, Any::encode_from(&token)?)?;
+    let mut signers = signed_data.signer_infos.0.clone().into_vec();
+    let Some(signer) = signers.first_mut() else {
+        return Err(Error::Signing("SignedData has no signer".to_string()));
+    };
+    signer.unsigned_attrs = Some(SetOfVec::try_from(vec![attribute])?);
+    signed_data.signer_infos = SignerInfos(SetOfVec::try_from(signers)?);
+    Ok(())
+}
+
+/// DER tag-length-value with a definite length
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let length = content.len();
+    if length < 0x80 {
+        out.push(length as u8);
+    } else {
+        let bytes = length.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn bmp_string(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::tests::{test_identity, verify_signed_data};
+    use const_oid::db::rfc5911::ID_MESSAGE_DIGEST;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/pe")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    fn test_signer() -> Signer {
+        Signer {
+            identity: test_identity(),
+            timestamp_url: None,
+            description: Some("Sample App".to_string()),
+            url: Some("https://example.com".to_string()),
+        }
+    }
+
+    /// Extract and verify the signature of a signed image
+    fn verify(signed: &[u8], identity: &Identity) -> SignedData {
+        let layout = PeLayout::parse(signed).unwrap();
+        let offset = read_u32(signed, layout.security_directory).unwrap() as usize;
+        let length = read_u32(signed, layout.security_directory + 4).unwrap() as usize;
+        assert_eq!(offset, layout.image_end);
+        assert_eq!(offset % 8, 0);
+        assert_eq!(offset + length, signed.len());
+        assert_eq!(read_u16(signed, offset + 4), Some(WIN_CERT_REVISION_2_0));
+        assert_eq!(
+            read_u16(signed, offset + 6),
+            Some(WIN_CERT_TYPE_PKCS_SIGNED_DATA)
+        );
+        assert_eq!(
+            read_u32(signed, layout.checksum),
+            Some(checksum(signed, layout.checksum))
+        );
+
+        // Trailing zero padding is not part of the DER
+        let mut reader = der::SliceReader::new(&signed[offset + 8..offset + length]).unwrap();
+        let content_info = ContentInfo::decode(&mut reader).unwrap();
+        let signed_data = verify_signed_data(&content_info.to_der().unwrap(), identity);
+        assert_eq!(signed_data.version, CmsVersion::V1);
+        assert_eq!(
+            signed_data.encap_content_info.econtent_type,
+            SPC_INDIRECT_DATA_OBJID
+        );
+
+        // SpcIndirectDataContent carries the image digest
+        let econtent = signed_data.encap_content_info.econtent.clone().unwrap();
+        let expected = indirect_data_content(&image_digest(signed, &layout)).unwrap();
+        assert_eq!(econtent.to_der().unwrap(), expected);
+
+        // messageDigest covers the content octets without tag and length
+        let signer = signed_data.signer_infos.0.get(0).unwrap();
+        let message_digest = signer
+            .signed_attrs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|attribute| attribute.oid == ID_MESSAGE_DIGEST)
+            .unwrap();
+        let value = OctetString::from_der(&message_digest.values.get(0).unwrap().to_der().unwrap())
+            .unwrap();
+        assert_eq!(
+            value.as_bytes(),
+            Sha256::digest(econtent.value()).as_slice()
+        );
+
+        signed_data
+    }
+
+    #[test]
+    fn test_sign_executable() {
+        let signer = test_signer();
+        let data = fixture("app.exe");
+        let signed = signer.sign(&data).unwrap();
+
+        let directories_end = PeLayout::parse(&data).unwrap().security_directory + 8;
+        assert_eq!(signed[directories_end..data.len()], data[directories_end..]);
+        verify(&signed, &signer.identity);
+
+        // The image still parses and keeps its imports
+        assert_eq!(
+            dlls::imports(&signed).unwrap(),
+            dlls::imports(&data).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sign_unaligned_image() {
+        // Installers append their payload, so the image rarely ends on 8 bytes
+        let signer = test_signer();
+        let mut data = fixture("app.exe");
+        data.extend_from_slice(b"abc");
+        let signed = signer.sign(&data).unwrap();
+
+        assert_eq!(signed[data.len()..data.len() + 5], [0; 5]);
+        verify(&signed, &signer.identity);
+        verify(&signer.sign(&signed).unwrap(), &signer.identity);
+    }
+
+    #[test]
+    fn test_resign_replaces_signature() {
+        let signer = test_signer();
+        let once = signer.sign(&fixture("foo.dll")).unwrap();
+        let twice = signer.sign(&once).unwrap();
+
+        assert_eq!(once.len(), twice.len());
+        // Only the checksum differs, as the signing time may have changed
+        let layout = PeLayout::parse(&twice).unwrap();
+        let checksum = layout.checksum..layout.checksum + 4;
+        assert_eq!(once[..checksum.start], twice[..checksum.start]);
+        assert_eq!(
+            once[checksum.end..layout.image_end],
+            twice[checksum.end..layout.image_end]
+        );
+        verify(&twice, &signer.identity);
+    }
+
+    #[test]
+    fn test_reject_invalid_images() {
+        let signer = test_signer();
+        assert!(signer.sign(b"#!/bin/sh\n").is_err());
+
+        // Data after the certificate table cannot be re-signed
+        let mut signed = signer.sign(&fixture("bar.dll")).unwrap();
+        signed.extend_from_slice(&[0u8; 64]);
+        assert!(signer.sign(&signed).is_err());
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let identity = test_identity();
+        let request = TimeStampReq::from_der(&timestamp_request(b"signature").unwrap()).unwrap();
+        assert_eq!(
+            request.message_imprint.hashed_message.as_bytes(),
+            Sha256::digest(b"signature").as_slice()
+        );
+        assert!(request.cert_req);
+
+        // A token is a SignedData of the TSA; any SignedData exercises the plumbing
+        let token = ContentInfo::from_der(
+            &identity
+                .sign(
+                    const_oid::db::rfc5911::ID_DATA,
+                    None,
+                    Some(&[0u8; 32]),
+                    Vec::new(),
+                )
+                .unwrap(),
+        )
+        .unwrap();
+        let response = TimeStampResp {
+            status: cmpv2::status::PkiStatusInfo {
+                status: PkiStatus::Accepted,
+                status_string: None,
+                fail_info: None,
+            },
+            time_stamp_token: Some(token.clone()),
+        };
+        assert_eq!(timestamp_token(&response.to_der().unwrap()).unwrap(), token);
+
+        let rejected = TimeStampResp {
+            status: cmpv2::status::PkiStatusInfo {
+                status: PkiStatus::Rejection,
+                status_string: None,
+                fail_info: None,
+            },
+            time_stamp_token: None,
+        };
+        assert!(timestamp_token(&rejected.to_der().unwrap()).is_err());
+
+        // The token is attached unsigned, so the signature stays valid
+        let signer = test_signer();
+        let der = signer.signature(&[0u8; 32]).unwrap();
+        let mut signed_data: SignedData = ContentInfo::from_der(&der)
+            .unwrap()
+            .content
+            .decode_as()
+            .unwrap();
+        add_timestamp(&mut signed_data, token.clone()).unwrap();
+        let der = ContentInfo {
+            content_type: ID_SIGNED_DATA,
+            content: Any::encode_from(&signed_data).unwrap(),
+        }
+        .to_der()
+        .unwrap();
+        let signed_data = verify_signed_data(&der, &signer.identity);
+        let unsigned = signed_data
+            .signer_infos
+            .0
+            .get(0)
+            .unwrap()
+            .unsigned_attrs
+            .clone()
+            .unwrap();
+        let attribute = unsigned.get(0).unwrap();
+        assert_eq!(attribute.oid, SPC_RFC3161_OBJID);
+        assert_eq!(
+            attribute
+                .values
+                .get(0)
+                .unwrap()
+                .decode_as::<ContentInfo>()
+                .unwrap(),
+            token
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use crate::context::Context;
+use crate::deps::{self, Resolution};
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::result::Result;
+use goblin::pe::PE;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// DLLs that ship with every supported Windows version and must never be bundled.
+/// Names are compared case-insensitively; a trailing `*` matches any suffix.
+pub const SYSTEM_DLLS: &[&str] = &[
+    // API sets are resolved by the loader
+    "api-ms-win-*",
+    "ext-ms-*",
+    // Core
+    "kernel32.dll",
+    "kernelbase.dll",
+    "ntdll.dll",
+    "user32.dll",
+    "gdi32.dll",
+    "gdiplus.dll",
+    "advapi32.dll",
+    "shell32.dll",
+    "shlwapi.dll",
+    "ole32.dll",
+    "oleaut32.dll",
+    "oleacc.dll",
+    "combase.dll",
+    "comctl32.dll",
+    "comdlg32.dll",
+    "rpcrt4.dll",
+    "msvcrt.dll",
+    "ucrtbase.dll",
+    "version.dll",
+    "psapi.dll",
+    "userenv.dll",
+    "setupapi.dll",
+    "cfgmgr32.dll",
+    "powrprof.dll",
+    "propsys.dll",
+    "dbghelp.dll",
+    "imm32.dll",
+    "winmm.dll",
+    "winspool.drv",
+    "uxtheme.dll",
+    "dwmapi.dll",
+    "usp10.dll",
+    "msimg32.dll",
+    "hid.dll",
+    "runtimeobject.dll",
+    // Security and networking
+    "bcrypt.dll",
+    "bcryptprimitives.dll",
+    "ncrypt.dll",
+    "crypt32.dll",
+    "secur32.dll",
+    "sspicli.dll",
+    "wintrust.dll",
+    "ws2_32.dll",
+    "mswsock.dll",
+    "iphlpapi.dll",
+    "dnsapi.dll",
+    "netapi32.dll",
+    "wldap32.dll",
+    "normaliz.dll",
+    "mpr.dll",
+    "winhttp.dll",
+    "wininet.dll",
+    "urlmon.dll",
+    // Graphics
+    "opengl32.dll",
+    "glu32.dll",
+    "d2d1.dll",
+    "dwrite.dll",
+    "d3d9.dll",
+    "d3d11.dll",
+    "d3d12.dll",
+    "dxgi.dll",
+    "d3dcompiler_47.dll",
+    "windowscodecs.dll",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct BundleOptions {
+    /// Directories searched for imported DLLs
+    pub search_paths: Vec<PathBuf>,
+    /// Additional patterns treated as system DLLs
+    pub system_libraries: Vec<String>,
+}
+
+/// Result of resolving the import tables of the staged files
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedImports {
+    pub dependencies: BTreeMap<String, Resolution>,
+    /// Additional copy operations (source, destination relative to the install root)
+    pub copies: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Resolve the DLLs imported by the staged executables if `windows.bundle-dlls` is enabled.
+/// Returns additional copy operations (source, destination relative to the install root).
+pub fn bundle(ctx: &Context, manifest: &Manifest) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let Some(windows) = manifest.windows.as_ref().filter(|w| w.bundle_dlls) else {
+        return Ok(Vec::new());
+    };
+
+    let options = BundleOptions {
+        search_paths: windows
+            .library_paths
+            .iter()
+            .map(|p| ctx.base_dir.join(p))
+            .collect(),
+        system_libraries: windows.system_libraries.clone(),
+    };
+
+    let resolved = resolve_imports(ctx, &manifest.copy_operations, &options)?;
+    deps::report("DLL dependencies", &resolved.dependencies);
+
+    Ok(resolved.copies)
+}
+
+/// Walk the import tables of the PE files among `copy_operations` and resolve every
+/// non-system DLL. Resolved DLLs are placed next to each staged `.exe`; fails with the
+/// list of DLLs that could not be found.
+pub fn resolve_imports(
+    ctx: &Context,
+    copy_operations: &[(PathBuf, PathBuf)],
+    options: &BundleOptions,
+) -> Result<ResolvedImports> {
+    // Expand directories into (file on disk, destination) pairs
+    let mut staged = Vec::new();
+    for (src, dst) in copy_operations {
+        if src.is_dir() {
+            for entry in WalkDir::new(src).into_iter().filter_map(|e| e.ok()) {
+                if entry.file_type().is_file() {
+                    let relative = entry.path().strip_prefix(src).unwrap();
+                    staged.push((entry.path().to_path_buf(), dst.join(relative)));
+                }
+            }
+        } else {
+            staged.push((src.clone(), dst.clone()));
+        }
+    }
+
+    let staged_names: HashSet<String> = staged
+        .iter()
+        .filter_map(|(_, dst)| dst.file_name())
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .collect();
+
+    // DLLs are loaded from the directory of the executable
+    let mut target_dirs: Vec<PathBuf> = staged
+        .iter()
+        .filter(|(_, dst)| has_extension(dst, "exe"))
+        .map(|(_, dst)| dst.parent().map(Path::to_path_buf).unwrap_or_default())
+        .collect();
+    target_dirs.sort();
+    target_dirs.dedup();
+    if target_dirs.is_empty() {
+        target_dirs.push(PathBuf::new());
+    }
+
+    let mut pending: Vec<PathBuf> = staged
+        .iter()
+        .filter(|(src, dst)| {
+            (has_extension(dst, "exe") || has_extension(dst, "dll")) && is_pe_file(src)
+        })
+        .map(|(src, _)| src.clone())
+        .collect();
+
+    let mut dependencies = BTreeMap::new();
+    let mut seen = HashSet::new();
+    let mut copies = Vec::new();
+
+    while let Some(path) = pending.pop() {
+        for name in imports(&fs::read(&path)?)? {
+            if !seen.insert(name.to_ascii_lowercase()) {
+                continue;
+            }
+
+            let resolution = if is_system_dll(&name, &options.system_libraries) {
+                Resolution::System
+            } else if staged_names.contains(&name.to_ascii_lowercase()) {
+                Resolution::Staged
+            } else if let Some(found) = find_dll(&name, &options.search_paths) {
+                if ctx.verbose {
+                    status!("Bundling {} from {}", name, found.display());
+                }
+                let file_name = found.file_name().unwrap();
+                for dir in &target_dirs {
+                    copies.push((found.clone(), dir.join(file_name)));
+                }
+                pending.push(found.clone());
+                Resolution::Bundled(found)
+            } else {
+                Resolution::Missing
+            };
+
+            dependencies.insert(name, resolution);
+        }
+    }
+
+    let missing = deps::missing(&dependencies);
+    if !missing.is_empty() {
+        return Err(Error::Custom(format!(
+            "Unable to resolve DLLs: {} (add their directories to windows.library-paths)",
+            missing.join(", ")
+        )));
+    }
+
+    Ok(ResolvedImports {
+        dependencies,
+        copies,
+    })
+}
+
+/// Names of the DLLs in the import directory of a PE image
+pub fn imports(data: &[u8]) -> Result<Vec<String>> {
+    let pe = PE::parse(data)?;
+    Ok(pe.libraries.iter().map(|name| name.to_string()).collect())
+}
+
+/// Whether a DLL name refers to a library shipped with Windows
+pub fn is_system_dll(name: &str, extra: &[String]) -> bool {
+    let name = name.to_ascii_lowercase();
+    SYSTEM_DLLS
+        .iter()
+        .copied()
+        .chain(extra.iter().map(|s| s.as_str()))
+        .any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            }
+        })
+}
+
+/// Find a DLL in the search paths, ignoring case like the Windows loader does
+fn find_dll(name: &str, search_paths: &[PathBuf]) -> Option<PathBuf> {
+    search_paths.iter().find_map(|dir| {
+        let exact = dir.join(name);
+        if is_pe_file(&exact) {
+            return Some(exact);
+        }
+        fs::read_dir(dir)
+            .ok()?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .find(|path| {
+                path.file_name()
+                    .is_some_and(|f| f.to_string_lossy().eq_ignore_ascii_case(name))
+                    && is_pe_file(path)
+            })
+    })
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case(extension))
+}
+
+pub(crate) fn is_pe_file(path: &Path) -> bool {
+    use std::io::Read;
+    let mut magic = [0u8; 2];
+    path.is_file()
+        && fs::File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .is_ok()
+        && &magic == b"MZ"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/pe")
+            .join(name)
+    }
+
+    #[test]
+    fn test_imports() {
+        let data = fs::read(fixture("app.exe")).unwrap();
+        assert_eq!(
+            imports(&data).unwrap(),
+            vec![
+                "KERNEL32.dll",
+                "api-ms-win-crt-runtime-l1-1-0.dll",
+                "foo.dll"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_imports() {
+        let ctx = Context::new(PathBuf::from("Cargo.toml"), false);
+        let copy_operations = vec![(fixture("app.exe"), PathBuf::from("bin/app.exe"))];
+        let options = BundleOptions {
+            search_paths: vec![fixture("")],
+            system_libraries: Vec::new(),
+        };
+
+        let ResolvedImports {
+            dependencies: deps,
+            copies,
+        } = resolve_imports(&ctx, &copy_operations, &options).unwrap();
+
+        assert_eq!(deps["KERNEL32.dll"], Resolution::System);
+        assert_eq!(
+            deps["api-ms-win-crt-runtime-l1-1-0.dll"],
+            Resolution::System
+        );
+        assert_eq!(deps["msvcrt.dll"], Resolution::System);
+        assert_eq!(deps["foo.dll"], Resolution::Bundled(fixture("foo.dll")));
+        assert_eq!(deps["BAR.dll"], Resolution::Bundled(fixture("bar.dll")));
+        assert_eq!(
+            copies,
+            vec![
+                (fixture("foo.dll"), PathBuf::from("bin/foo.dll")),
+                (fixture("bar.dll"), PathBuf::from("bin/bar.dll")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_imports_staged() {
+        let ctx = Context::new(PathBuf::from("Cargo.toml"), false);
+        let copy_operations = vec![
+            (fixture("app.exe"), PathBuf::from("app.exe")),
+            (fixture("foo.dll"), PathBuf::from("foo.dll")),
+        ];
+        let options = BundleOptions {
+            search_paths: vec![fixture("")],
+            system_libraries: Vec::new(),
+        };
+
+        let ResolvedImports {
+            dependencies: deps,
+            copies,
+        } = resolve_imports(&ctx, &copy_operations, &options).unwrap();
+
+        assert_eq!(deps["foo.dll"], Resolution::Staged);
+        assert_eq!(copies, vec![(fixture("bar.dll"), PathBuf::from("bar.dll"))]);
+    }
+
+    #[test]
+    fn test_resolve_imports_missing() {
+        let ctx = Context::new(PathBuf::from("Cargo.toml"), false);
+        let copy_operations = vec![(fixture("app.exe"), PathBuf::from("app.exe"))];
+
+        let err = resolve_imports(&ctx, &copy_operations, &BundleOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("foo.dll"));
+    }
+
+    #[test]
+    fn test_system_dlls() {
+        assert!(is_system_dll("KERNEL32.dll", &[]));
+        assert!(is_system_dll("api-ms-win-core-synch-l1-2-0.dll", &[]));
+        assert!(!is_system_dll("libgtk-3-0.dll", &[]));
+        assert!(is_system_dll(
+            "VCRUNTIME140.dll",
+            &["vcruntime*".to_string()]
+        ));
+    }
+}
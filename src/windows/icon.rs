@@ -0,0 +1,27 @@
+use crate::icon::IconSet;
+use crate::result::Result;
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use std::fs;
+use std::path::Path;
+
+/// Icon sizes embedded into generated .ico files
+const ICO_SIZES: [u32; 7] = [16, 24, 32, 48, 64, 128, 256];
+
+/// Generate an ICO file with PNG frames from the icon set
+pub fn generate_ico(icon_set: &IconSet, output_path: &Path) -> Result<()> {
+    let mut frames = Vec::with_capacity(ICO_SIZES.len());
+    for size in ICO_SIZES {
+        let rgba = icon_set.image(size)?;
+        frames.push(IcoFrame::as_png(
+            rgba.as_raw(),
+            size,
+            size,
+            image::ExtendedColorType::Rgba8,
+        )?);
+    }
+
+    let output_file = fs::File::create(output_path)?;
+    IcoEncoder::new(output_file).encode_images(&frames)?;
+
+    Ok(())
+}
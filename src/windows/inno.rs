@@ -0,0 +1,181 @@
+use crate::manifest::InstallScope;
+use crate::windows::installer::{InstallerSpec, windows_path};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Generate a complete Inno Setup (.iss) script for the given installer spec
+pub fn generate(spec: &InstallerSpec) -> String {
+    let mut s = String::new();
+    let exe = windows_path(&spec.executable);
+    let privileges = match spec.scope {
+        InstallScope::User => "lowest",
+        InstallScope::Machine => "admin",
+    };
+
+    // Writing to a String is infallible, hence the unwraps below
+    writeln!(s, "; Generated by cargo-emerge, do not edit by hand").unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "[Setup]").unwrap();
+    // The AppId identifies previous installations, which makes upgrades replace them in place
+    writeln!(s, "AppId={}", constant(&spec.app_id)).unwrap();
+    writeln!(s, "AppName={}", constant(&spec.title)).unwrap();
+    writeln!(s, "AppVersion={}", constant(&spec.version)).unwrap();
+    writeln!(
+        s,
+        "AppVerName={} {}",
+        constant(&spec.title),
+        constant(&spec.version)
+    )
+    .unwrap();
+    writeln!(s, "AppPublisher={}", constant(&spec.publisher)).unwrap();
+    writeln!(s, "AppComments={}", constant(&spec.description)).unwrap();
+    writeln!(s, "DefaultDirName={{autopf}}\\{}", constant(&spec.title)).unwrap();
+    writeln!(s, "DefaultGroupName={}", constant(&spec.title)).unwrap();
+    writeln!(s, "DisableProgramGroupPage=yes").unwrap();
+    writeln!(s, "UsePreviousAppDir=yes").unwrap();
+    writeln!(s, "CloseApplications=yes").unwrap();
+    writeln!(s, "PrivilegesRequired={}", privileges).unwrap();
+    writeln!(s, "ArchitecturesAllowed=x64compatible").unwrap();
+    writeln!(s, "ArchitecturesInstallIn64BitMode=x64compatible").unwrap();
+    writeln!(
+        s,
+        "OutputDir={}",
+        constant(&spec.output_dir.to_string_lossy())
+    )
+    .unwrap();
+    writeln!(s, "OutputBaseFilename={}", constant(&spec.output_basename)).unwrap();
+    if let Some(icon) = &spec.icon {
+        writeln!(s, "SetupIconFile={}", constant(&icon.to_string_lossy())).unwrap();
+    }
+    if let Some(license) = &spec.license {
+        writeln!(s, "LicenseFile={}", constant(&license.to_string_lossy())).unwrap();
+    }
+    writeln!(s, "UninstallDisplayIcon={{app}}\\{}", constant(&exe)).unwrap();
+    writeln!(s, "Compression=lzma2").unwrap();
+    writeln!(s, "SolidCompression=yes").unwrap();
+    writeln!(s, "WizardStyle=modern").unwrap();
+    writeln!(s).unwrap();
+
+    writeln!(s, "[Languages]").unwrap();
+    writeln!(
+        s,
+        "Name: \"english\"; MessagesFile: \"compiler:Default.isl\""
+    )
+    .unwrap();
+    writeln!(s).unwrap();
+
+    if spec.desktop_shortcut {
+        writeln!(s, "[Tasks]").unwrap();
+        writeln!(
+            s,
+            "Name: \"desktopicon\"; Description: \"{{cm:CreateDesktopIcon}}\"; GroupDescription: \"{{cm:AdditionalIcons}}\""
+        )
+        .unwrap();
+        writeln!(s).unwrap();
+    }
+
+    writeln!(s, "[Files]").unwrap();
+    for file in &spec.files {
+        if file.is_dir {
+            writeln!(
+                s,
+                "Source: \"{}\"; DestDir: \"{}\"; Flags: ignoreversion recursesubdirs createallsubdirs",
+                quote(&file.source.join("*").to_string_lossy()),
+                app_dir(&file.destination)
+            )
+            .unwrap();
+        } else {
+            let parent = file.destination.parent().unwrap_or(Path::new(""));
+            let name = file
+                .destination
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            writeln!(
+                s,
+                "Source: \"{}\"; DestDir: \"{}\"; DestName: \"{}\"; Flags: ignoreversion",
+                quote(&file.source.to_string_lossy()),
+                app_dir(parent),
+                quote(&name)
+            )
+            .unwrap();
+        }
+    }
+    writeln!(s).unwrap();
+
+    if spec.start_menu_shortcut || spec.desktop_shortcut {
+        writeln!(s, "[Icons]").unwrap();
+        if spec.start_menu_shortcut {
+            writeln!(
+                s,
+                "Name: \"{{group}}\\{}\"; Filename: \"{{app}}\\{}\"",
+                quote(&spec.title),
+                quote(&exe)
+            )
+            .unwrap();
+        }
+        if spec.desktop_shortcut {
+            writeln!(
+                s,
+                "Name: \"{{autodesktop}}\\{}\"; Filename: \"{{app}}\\{}\"; Tasks: desktopicon",
+                quote(&spec.title),
+                quote(&exe)
+            )
+            .unwrap();
+        }
+        writeln!(s).unwrap();
+    }
+
+    writeln!(s, "[Run]").unwrap();
+    writeln!(
+        s,
+        "Filename: \"{{app}}\\{}\"; Description: \"{{cm:LaunchProgram,{}}}\"; Flags: nowait postinstall skipifsilent",
+        quote(&exe),
+        quote(&spec.title)
+    )
+    .unwrap();
+
+    s
+}
+
+/// `{app}` joined with a relative destination directory
+fn app_dir(relative: &Path) -> String {
+    let relative = windows_path(relative);
+    if relative.is_empty() {
+        "{app}".to_string()
+    } else {
+        format!("{{app}}\\{}", quote(&relative))
+    }
+}
+
+/// Escape braces, which Inno Setup otherwise expands as constants
+fn constant(value: &str) -> String {
+    value.replace('{', "{{")
+}
+
+/// Escape a value for use inside a double-quoted Inno Setup parameter
+fn quote(value: &str) -> String {
+    constant(value).replace('"', "\"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::windows::installer::tests::sample_spec;
+
+    #[test]
+    fn test_inno_golden() {
+        let expected = include_str!("../../tests/fixtures/installer/sample.iss");
+        assert_eq!(generate(&sample_spec()), expected);
+    }
+
+    #[test]
+    fn test_inno_user_scope() {
+        let mut spec = sample_spec();
+        spec.scope = InstallScope::User;
+        spec.desktop_shortcut = false;
+        let script = generate(&spec);
+        assert!(script.contains("PrivilegesRequired=lowest"));
+        assert!(!script.contains("[Tasks]"));
+    }
+}
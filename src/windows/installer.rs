@@ -0,0 +1,327 @@
+use crate::cmd;
+use crate::context::Context;
+use crate::icon::{self, IconSet};
+use crate::layout::Layout;
+use crate::manifest::{InstallScope, Manifest};
+use crate::result::Result;
+use crate::utils;
+use crate::windows::authenticode::Signer;
+use crate::windows::{self, dlls, inno, nsis};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Everything an installer script generator needs, resolved from the manifest
+#[derive(Debug, Clone)]
+pub struct InstallerSpec {
+    pub title: String,
+    pub version: String,
+    pub description: String,
+    pub publisher: String,
+    pub app_id: String,
+    /// Main executable, relative to the install directory
+    pub executable: PathBuf,
+    pub icon: Option<PathBuf>,
+    pub license: Option<PathBuf>,
+    pub scope: InstallScope,
+    pub start_menu_shortcut: bool,
+    pub desktop_shortcut: bool,
+    pub files: Vec<InstallerFile>,
+    pub output_dir: PathBuf,
+    pub output_basename: String,
+}
+
+/// A single copy operation as seen by the installer
+#[derive(Debug, Clone)]
+pub struct InstallerFile {
+    pub source: PathBuf,
+    /// Destination relative to the install directory
+    pub destination: PathBuf,
+    pub is_dir: bool,
+}
+
+impl InstallerSpec {
+    /// Resolve the installer contents; with a `signer`, executables and DLLs are
+    /// replaced by signed copies staged in a temporary directory
+    pub fn from_manifest(
+        ctx: &Context,
+        manifest: &Manifest,
+        signer: Option<&Signer>,
+    ) -> Result<Self> {
+        let config = manifest.installer.clone().unwrap_or_default();
+
+        let icon = match icon::passthrough(manifest, "ico") {
+            Some(ico) => Some(ico.to_path_buf()),
+            None => match IconSet::load(ctx, manifest)? {
+                Some(icon_set) => {
+                    let ico_path = manifest
+                        .output_folder
+                        .join(format!("{}.ico", manifest.filename));
+                    if ctx.verbose {
+                        status!("Converting icon to ICO format...");
+                    }
+                    windows::icon::generate_ico(&icon_set, &ico_path)?;
+                    Some(ico_path)
+                }
+                None => None,
+            },
+        };
+
+        let dll_copies = dlls::bundle(ctx, manifest)?;
+        // Paths relative to the installation directory
+        let files = Layout::flat(Path::new(""))
+            .place(manifest, manifest.copy_operations.iter().chain(&dll_copies))
+            .into_iter()
+            .map(|placement| InstallerFile {
+                is_dir: placement.source.is_dir(),
+                source: placement.source,
+                destination: placement.destination,
+            })
+            .collect::<Vec<_>>();
+        let files = match signer {
+            Some(signer) => stage_signed(ctx, manifest, signer, files)?,
+            None => files,
+        };
+
+        Ok(Self {
+            title: manifest.title.clone(),
+            version: manifest.version.clone(),
+            description: manifest.description.clone(),
+            publisher: config.publisher.unwrap_or_else(|| manifest.title.clone()),
+            app_id: config.app_id.unwrap_or_else(|| manifest.name.clone()),
+            executable: config
+                .executable
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(format!("{}.exe", manifest.name))),
+            icon,
+            license: config.license.map(|l| ctx.base_dir.join(l)),
+            scope: config.scope,
+            start_menu_shortcut: config.start_menu_shortcut,
+            desktop_shortcut: config.desktop_shortcut,
+            files,
+            output_dir: manifest.output_folder.clone(),
+            output_basename: manifest.filename.clone(),
+        })
+    }
+}
+
+/// An installer script format and the compiler that builds it
+struct Generator {
+    /// Name shown in the status messages
+    name: &'static str,
+    /// Release variant, also the suffix of the installer: `<filename>-setup-<variant>.exe`
+    variant: &'static str,
+    program: &'static str,
+    extension: &'static str,
+    generate: fn(&InstallerSpec) -> String,
+}
+
+const NSIS: Generator = Generator {
+    name: "NSIS",
+    variant: "nsis",
+    program: "makensis",
+    extension: "nsi",
+    generate: nsis::generate,
+};
+
+const INNO: Generator = Generator {
+    name: "Inno Setup",
+    variant: "inno",
+    program: "iscc",
+    extension: "iss",
+    generate: inno::generate,
+};
+
+/// Generate an NSIS script and compile it if `makensis` is available
+///
+/// Returns the compiled installer, if any
+pub fn create_nsis(ctx: &Context, manifest: &Manifest) -> Result<Option<PathBuf>> {
+    create(ctx, manifest, &NSIS)
+}
+
+/// Generate an Inno Setup script and compile it if `iscc` is available
+///
+/// Returns the compiled installer, if any
+pub fn create_inno(ctx: &Context, manifest: &Manifest) -> Result<Option<PathBuf>> {
+    create(ctx, manifest, &INNO)
+}
+
+fn create(ctx: &Context, manifest: &Manifest, generator: &Generator) -> Result<Option<PathBuf>> {
+    status!("Generating {} installer script...", generator.name);
+
+    utils::ensure_dir(&manifest.output_folder)?;
+
+    let signer = Signer::from_manifest(ctx, manifest)?;
+    let mut spec = InstallerSpec::from_manifest(ctx, manifest, signer.as_ref())?;
+    // Both installers can be built in one run
+    spec.output_basename = installer_basename(manifest, generator);
+    let script_path = manifest
+        .output_folder
+        .join(format!("{}.{}", manifest.filename, generator.extension));
+    fs::write(&script_path, (generator.generate)(&spec))?;
+
+    status!(
+        "{} script written: {}",
+        generator.name,
+        script_path.display()
+    );
+
+    if utils::find_program(generator.program).is_some() {
+        cmd::execute(ctx, generator.program, &[utils::path_str(&script_path)?])?;
+        let installer_path = manifest
+            .output_folder
+            .join(format!("{}.exe", spec.output_basename));
+        if let Some(signer) = &signer {
+            signer.sign_file(&installer_path)?;
+        }
+        status!(
+            "Installer created successfully: {}",
+            installer_path.display()
+        );
+        Ok(Some(installer_path))
+    } else {
+        status!(
+            "{} not found on PATH, skipping installer compilation",
+            generator.program
+        );
+        Ok(None)
+    }
+}
+
+fn installer_basename(manifest: &Manifest, generator: &Generator) -> String {
+    format!("{}-setup-{}", manifest.filename, generator.variant)
+}
+
+/// Copy files and directories containing PE images to a staging directory and sign them there,
+/// so the build output itself is left untouched
+fn stage_signed(
+    ctx: &Context,
+    manifest: &Manifest,
+    signer: &Signer,
+    files: Vec<InstallerFile>,
+) -> Result<Vec<InstallerFile>> {
+    let staging_dir = std::env::temp_dir().join(format!("emerge-{}-signed", manifest.name));
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)?;
+    }
+
+    let mut signed = 0;
+    let mut staged = Vec::with_capacity(files.len());
+    for file in files {
+        let has_images = WalkDir::new(&file.source)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .any(|e| dlls::is_pe_file(e.path()));
+        if !has_images {
+            staged.push(file);
+            continue;
+        }
+
+        let dest_path = staging_dir.join(&file.destination);
+        utils::copy_recursively(&file.source, &dest_path)?;
+        signed += signer.sign_tree(ctx, &dest_path)?.len();
+        staged.push(InstallerFile {
+            source: dest_path,
+            ..file
+        });
+    }
+
+    status!(
+        "Signed {} files with {}",
+        signed,
+        signer.identity.common_name().unwrap_or_default()
+    );
+    Ok(staged)
+}
+
+/// Render a relative path with Windows separators
+pub fn windows_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\\")
+}
+
+/// Directories (relative to the install root) that hold installed files, deepest first
+pub fn installed_directories(files: &[InstallerFile]) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    for file in files {
+        let mut parent = file.destination.parent();
+        while let Some(dir) = parent {
+            if dir.as_os_str().is_empty() {
+                break;
+            }
+            if !dirs.iter().any(|d: &PathBuf| d == dir) {
+                dirs.push(dir.to_path_buf());
+            }
+            parent = dir.parent();
+        }
+    }
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+    dirs
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Deterministic spec used by the golden file tests
+    pub fn sample_spec() -> InstallerSpec {
+        InstallerSpec {
+            title: "Sample App".to_string(),
+            version: "1.2.3".to_string(),
+            description: "A sample application".to_string(),
+            publisher: "Sample Corp".to_string(),
+            app_id: "sample-app".to_string(),
+            executable: PathBuf::from("sample.exe"),
+            icon: Some(PathBuf::from("/work/setup/sample.ico")),
+            license: Some(PathBuf::from("/work/LICENSE.txt")),
+            scope: InstallScope::Machine,
+            start_menu_shortcut: true,
+            desktop_shortcut: true,
+            files: vec![
+                InstallerFile {
+                    source: PathBuf::from("/work/target/release/sample.exe"),
+                    destination: PathBuf::from("sample.exe"),
+                    is_dir: false,
+                },
+                InstallerFile {
+                    source: PathBuf::from("/work/README.md"),
+                    destination: PathBuf::from("docs/README.md"),
+                    is_dir: false,
+                },
+                InstallerFile {
+                    source: PathBuf::from("/work/assets"),
+                    destination: PathBuf::from("assets"),
+                    is_dir: true,
+                },
+            ],
+            output_dir: PathBuf::from("/work/setup"),
+            output_basename: "sample-windows-1.2.3".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_windows_path() {
+        assert_eq!(windows_path(Path::new("a/b/c.exe")), "a\\b\\c.exe");
+        assert_eq!(windows_path(Path::new("c.exe")), "c.exe");
+    }
+
+    #[test]
+    fn test_installer_basenames() {
+        let (_, manifest) = crate::manifest::tests::sample_manifest("");
+        let nsis = installer_basename(&manifest, &NSIS);
+        let inno = installer_basename(&manifest, &INNO);
+        assert_eq!(nsis, format!("{}-setup-nsis", manifest.filename));
+        assert_ne!(nsis, inno);
+    }
+
+    #[test]
+    fn test_installed_directories() {
+        let dirs = installed_directories(&sample_spec().files);
+        assert_eq!(dirs, vec![PathBuf::from("docs")]);
+    }
+}
@@ -1,9 +1,17 @@
 pub mod archive;
+pub mod authenticode;
+pub mod dlls;
+pub mod icon;
+pub mod inno;
+pub mod installer;
+pub mod nsis;
 
 use crate::context::Context;
 use crate::manifest::Manifest;
 use crate::result::Result;
+use std::path::PathBuf;
 
-pub fn build(ctx: &Context, manifest: &Manifest) -> Result<()> {
-    archive::create_zip(ctx, manifest)
+#[allow(dead_code)]
+pub fn build(ctx: &Context, manifest: &Manifest) -> Result<PathBuf> {
+    archive::create(ctx, manifest)
 }
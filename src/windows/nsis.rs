@@ -0,0 +1,259 @@
+use crate::manifest::InstallScope;
+use crate::windows::installer::{InstallerSpec, installed_directories, windows_path};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Generate a complete NSIS (.nsi) script for the given installer spec
+pub fn generate(spec: &InstallerSpec) -> String {
+    let mut s = String::new();
+    let exe = windows_path(&spec.executable);
+    let (exec_level, install_root, shell_context) = match spec.scope {
+        InstallScope::User => ("user", "$LOCALAPPDATA\\Programs", "current"),
+        InstallScope::Machine => ("admin", "$PROGRAMFILES64", "all"),
+    };
+
+    // Writing to a String is infallible, hence the unwraps below
+    writeln!(s, "; Generated by cargo-emerge, do not edit by hand").unwrap();
+    writeln!(s, "Unicode true").unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "!define APP_NAME \"{}\"", quote(&spec.title)).unwrap();
+    writeln!(s, "!define APP_VERSION \"{}\"", quote(&spec.version)).unwrap();
+    writeln!(s, "!define APP_PUBLISHER \"{}\"", quote(&spec.publisher)).unwrap();
+    writeln!(s, "!define APP_EXE \"{}\"", quote(&exe)).unwrap();
+    writeln!(
+        s,
+        "!define UNINSTALL_KEY \"Software\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\{}\"",
+        quote(&spec.app_id)
+    )
+    .unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "Name \"${{APP_NAME}}\"").unwrap();
+    writeln!(
+        s,
+        "OutFile \"{}\"",
+        quote_path(
+            &spec
+                .output_dir
+                .join(format!("{}.exe", spec.output_basename))
+        )
+    )
+    .unwrap();
+    writeln!(s, "InstallDir \"{}\\${{APP_NAME}}\"", install_root).unwrap();
+    writeln!(
+        s,
+        "InstallDirRegKey SHCTX \"${{UNINSTALL_KEY}}\" \"InstallLocation\""
+    )
+    .unwrap();
+    writeln!(s, "RequestExecutionLevel {}", exec_level).unwrap();
+    writeln!(s, "SetCompressor /SOLID lzma").unwrap();
+    writeln!(s).unwrap();
+
+    // Pages
+    writeln!(s, "!include \"MUI2.nsh\"").unwrap();
+    if let Some(icon) = &spec.icon {
+        writeln!(s, "!define MUI_ICON \"{}\"", quote_path(icon)).unwrap();
+        writeln!(s, "!define MUI_UNICON \"{}\"", quote_path(icon)).unwrap();
+    }
+    writeln!(s, "!insertmacro MUI_PAGE_WELCOME").unwrap();
+    if let Some(license) = &spec.license {
+        writeln!(
+            s,
+            "!insertmacro MUI_PAGE_LICENSE \"{}\"",
+            quote_path(license)
+        )
+        .unwrap();
+    }
+    writeln!(s, "!insertmacro MUI_PAGE_DIRECTORY").unwrap();
+    writeln!(s, "!insertmacro MUI_PAGE_INSTFILES").unwrap();
+    writeln!(s, "!define MUI_FINISHPAGE_RUN \"$INSTDIR\\${{APP_EXE}}\"").unwrap();
+    writeln!(s, "!insertmacro MUI_PAGE_FINISH").unwrap();
+    writeln!(s, "!insertmacro MUI_UNPAGE_CONFIRM").unwrap();
+    writeln!(s, "!insertmacro MUI_UNPAGE_INSTFILES").unwrap();
+    writeln!(s, "!insertmacro MUI_LANGUAGE \"English\"").unwrap();
+    writeln!(s).unwrap();
+
+    // Upgrade detection: run the uninstaller of a previous installation in place
+    writeln!(s, "Function .onInit").unwrap();
+    writeln!(s, "  SetShellVarContext {}", shell_context).unwrap();
+    writeln!(s, "  SetRegView 64").unwrap();
+    writeln!(
+        s,
+        "  ReadRegStr $0 SHCTX \"${{UNINSTALL_KEY}}\" \"UninstallString\""
+    )
+    .unwrap();
+    writeln!(s, "  StrCmp $0 \"\" done").unwrap();
+    writeln!(
+        s,
+        "  ReadRegStr $1 SHCTX \"${{UNINSTALL_KEY}}\" \"InstallLocation\""
+    )
+    .unwrap();
+    writeln!(
+        s,
+        "  MessageBox MB_OKCANCEL|MB_ICONINFORMATION \"${{APP_NAME}} is already installed.$\\n$\\nClick OK to upgrade the existing installation.\" /SD IDOK IDOK upgrade"
+    )
+    .unwrap();
+    writeln!(s, "  Abort").unwrap();
+    writeln!(s, "  upgrade:").unwrap();
+    writeln!(s, "  ExecWait '$0 /S _?=$1'").unwrap();
+    writeln!(s, "  done:").unwrap();
+    writeln!(s, "FunctionEnd").unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "Function un.onInit").unwrap();
+    writeln!(s, "  SetShellVarContext {}", shell_context).unwrap();
+    writeln!(s, "  SetRegView 64").unwrap();
+    writeln!(s, "FunctionEnd").unwrap();
+    writeln!(s).unwrap();
+
+    // Install section
+    writeln!(s, "Section \"Install\"").unwrap();
+    for file in &spec.files {
+        if file.is_dir {
+            writeln!(s, "  SetOutPath \"{}\"", install_dir(&file.destination)).unwrap();
+            writeln!(s, "  File /r \"{}\"", quote_path(&file.source.join("*"))).unwrap();
+        } else {
+            let parent = file.destination.parent().unwrap_or(Path::new(""));
+            let name = file
+                .destination
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            writeln!(s, "  SetOutPath \"{}\"", install_dir(parent)).unwrap();
+            writeln!(
+                s,
+                "  File \"/oname={}\" \"{}\"",
+                quote(&name),
+                quote_path(&file.source)
+            )
+            .unwrap();
+        }
+    }
+    writeln!(s, "  SetOutPath \"$INSTDIR\"").unwrap();
+    writeln!(s, "  WriteUninstaller \"$INSTDIR\\uninstall.exe\"").unwrap();
+    if spec.start_menu_shortcut {
+        writeln!(s, "  CreateDirectory \"$SMPROGRAMS\\${{APP_NAME}}\"").unwrap();
+        writeln!(
+            s,
+            "  CreateShortcut \"$SMPROGRAMS\\${{APP_NAME}}\\${{APP_NAME}}.lnk\" \"$INSTDIR\\${{APP_EXE}}\""
+        )
+        .unwrap();
+    }
+    if spec.desktop_shortcut {
+        writeln!(
+            s,
+            "  CreateShortcut \"$DESKTOP\\${{APP_NAME}}.lnk\" \"$INSTDIR\\${{APP_EXE}}\""
+        )
+        .unwrap();
+    }
+    for (key, value) in [
+        ("DisplayName", "${APP_NAME}".to_string()),
+        ("DisplayVersion", "${APP_VERSION}".to_string()),
+        ("Publisher", "${APP_PUBLISHER}".to_string()),
+        ("Comments", quote(&spec.description)),
+        ("DisplayIcon", "$INSTDIR\\${APP_EXE}".to_string()),
+        ("InstallLocation", "$INSTDIR".to_string()),
+        (
+            "UninstallString",
+            "$\\\"$INSTDIR\\uninstall.exe$\\\"".to_string(),
+        ),
+        (
+            "QuietUninstallString",
+            "$\\\"$INSTDIR\\uninstall.exe$\\\" /S".to_string(),
+        ),
+    ] {
+        writeln!(
+            s,
+            "  WriteRegStr SHCTX \"${{UNINSTALL_KEY}}\" \"{}\" \"{}\"",
+            key, value
+        )
+        .unwrap();
+    }
+    writeln!(
+        s,
+        "  WriteRegDWORD SHCTX \"${{UNINSTALL_KEY}}\" \"NoModify\" 1"
+    )
+    .unwrap();
+    writeln!(
+        s,
+        "  WriteRegDWORD SHCTX \"${{UNINSTALL_KEY}}\" \"NoRepair\" 1"
+    )
+    .unwrap();
+    writeln!(s, "SectionEnd").unwrap();
+    writeln!(s).unwrap();
+
+    // Uninstall section removes exactly what was installed
+    writeln!(s, "Section \"Uninstall\"").unwrap();
+    for file in &spec.files {
+        let target = quote(&windows_path(&file.destination));
+        if file.is_dir {
+            writeln!(s, "  RMDir /r \"$INSTDIR\\{}\"", target).unwrap();
+        } else {
+            writeln!(s, "  Delete \"$INSTDIR\\{}\"", target).unwrap();
+        }
+    }
+    for dir in installed_directories(&spec.files) {
+        writeln!(s, "  RMDir \"$INSTDIR\\{}\"", quote(&windows_path(&dir))).unwrap();
+    }
+    writeln!(s, "  Delete \"$INSTDIR\\uninstall.exe\"").unwrap();
+    writeln!(s, "  RMDir \"$INSTDIR\"").unwrap();
+    if spec.start_menu_shortcut {
+        writeln!(
+            s,
+            "  Delete \"$SMPROGRAMS\\${{APP_NAME}}\\${{APP_NAME}}.lnk\""
+        )
+        .unwrap();
+        writeln!(s, "  RMDir \"$SMPROGRAMS\\${{APP_NAME}}\"").unwrap();
+    }
+    if spec.desktop_shortcut {
+        writeln!(s, "  Delete \"$DESKTOP\\${{APP_NAME}}.lnk\"").unwrap();
+    }
+    writeln!(s, "  DeleteRegKey SHCTX \"${{UNINSTALL_KEY}}\"").unwrap();
+    writeln!(s, "SectionEnd").unwrap();
+
+    s
+}
+
+/// `$INSTDIR` joined with a relative destination directory
+fn install_dir(relative: &Path) -> String {
+    let relative = windows_path(relative);
+    if relative.is_empty() {
+        "$INSTDIR".to_string()
+    } else {
+        format!("$INSTDIR\\{}", quote(&relative))
+    }
+}
+
+/// Escape a value for use inside a double-quoted NSIS string
+fn quote(value: &str) -> String {
+    value.replace('$', "$$").replace('"', "$\\\"")
+}
+
+fn quote_path(path: &Path) -> String {
+    quote(&path.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::windows::installer::tests::sample_spec;
+
+    #[test]
+    fn test_nsis_golden() {
+        let expected = include_str!("../../tests/fixtures/installer/sample.nsi");
+        assert_eq!(generate(&sample_spec()), expected);
+    }
+
+    #[test]
+    fn test_nsis_user_scope() {
+        let mut spec = sample_spec();
+        spec.scope = InstallScope::User;
+        let script = generate(&spec);
+        assert!(script.contains("RequestExecutionLevel user"));
+        assert!(script.contains("InstallDir \"$LOCALAPPDATA\\Programs\\${APP_NAME}\""));
+        assert!(script.contains("SetShellVarContext current"));
+    }
+
+    #[test]
+    fn test_nsis_quote() {
+        assert_eq!(quote("a \"b\" $c"), "a $\\\"b$\\\" $$c");
+    }
+}
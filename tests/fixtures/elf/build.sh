@@ -0,0 +1,16 @@
+#!/bin/sh
+# Rebuilds the ELF fixtures used by the shared library bundling tests (x86_64, gcc).
+# libfoo depends on libbar; app depends on libfoo and libm and carries a
+# placeholder RUNPATH long enough to be rewritten in place.
+set -e
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+echo 'int bar(void) { return 1; }' >"$tmp/bar.c"
+echo 'int bar(void); int foo(void) { return bar() + 1; }' >"$tmp/foo.c"
+printf '#include <math.h>\nint foo(void);\nint main(int c, char **v) { (void)v; return foo() + (int)sqrt((double)c); }\n' >"$tmp/app.c"
+gcc -Os -s -shared -fPIC -Wl,-soname,libbar.so -o libbar.so "$tmp/bar.c"
+gcc -Os -s -shared -fPIC -Wl,-soname,libfoo.so -o libfoo.so "$tmp/foo.c" -L. -lbar
+gcc -Os -s -o app "$tmp/app.c" -L. -lfoo -lm -Wl,-rpath-link,. \
+    -Wl,--enable-new-dtags,-rpath,'$ORIGIN/xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx'
+gcc -Os -s -o app-no-runpath "$tmp/app.c" -L. -lfoo -lm -Wl,-rpath-link,.
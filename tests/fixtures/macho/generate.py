@@ -0,0 +1,98 @@
+#!/usr/bin/env python3
+"""Generates the minimal Mach-O fixtures used by the dylib bundling tests.
+
+The files are structurally valid 64-bit Mach-O images (header, __TEXT segment
+with a __text section, __LINKEDIT segment, dylib and rpath load commands) but
+contain no runnable code.
+
+  app           MH_EXECUTE x86_64, loads libSystem, @rpath/libfoo.dylib and
+                /opt/homebrew/lib/libbar.dylib, LC_RPATH /opt/homebrew/lib
+  app-arm64     same as app for arm64
+  libfoo.dylib  MH_DYLIB, id @rpath/libfoo.dylib, loads /opt/homebrew/lib/libbar.dylib
+  libbar.dylib  MH_DYLIB, id /opt/homebrew/lib/libbar.dylib
+"""
+import os
+import struct
+
+MH_MAGIC_64 = 0xFEEDFACF
+CPU_TYPE_X86_64 = 0x01000007
+CPU_TYPE_ARM64 = 0x0100000C
+MH_EXECUTE = 2
+MH_DYLIB = 6
+LC_SEGMENT_64 = 0x19
+LC_LOAD_DYLIB = 0xC
+LC_ID_DYLIB = 0xD
+LC_RPATH = 0x8000001C
+
+TEXT_OFFSET = 0x800
+FILE_SIZE = 0x1000
+LINKEDIT_SIZE = 0x40
+
+
+def pad(data, align=8):
+    return data + b"\0" * (-len(data) % align)
+
+
+def segment(name, vmaddr, vmsize, fileoff, filesize, sections=()):
+    cmd = struct.pack("<II16sQQQQiiII", LC_SEGMENT_64, 72 + 80 * len(sections),
+                      name.encode(), vmaddr, vmsize, fileoff, filesize, 7, 5,
+                      len(sections), 0)
+    for sectname, addr, size, offset in sections:
+        cmd += struct.pack("<16s16sQQIIIIIIII", sectname.encode(), name.encode(),
+                           addr, size, offset, 4, 0, 0, 0x80000400, 0, 0, 0)
+    return cmd
+
+
+def dylib(cmd, name):
+    body = pad(struct.pack("<IIIIII", cmd, 0, 24, 2, 0x10000, 0x10000) + name.encode() + b"\0")
+    return body[:4] + struct.pack("<I", len(body)) + body[8:]
+
+
+def rpath(path):
+    body = pad(struct.pack("<III", LC_RPATH, 0, 12) + path.encode() + b"\0")
+    return body[:4] + struct.pack("<I", len(body)) + body[8:]
+
+
+def image(cputype, filetype, commands):
+    text = segment("__TEXT", 0x100000000, FILE_SIZE, 0, FILE_SIZE,
+                   [("__text", 0x100000000 + TEXT_OFFSET, 16, TEXT_OFFSET)])
+    cmds = []
+    if filetype == MH_EXECUTE:
+        cmds.append(segment("__PAGEZERO", 0, 0x100000000, 0, 0))
+    cmds.append(text)
+    cmds.append(segment("__LINKEDIT", 0x100000000 + FILE_SIZE, 0x1000, FILE_SIZE, LINKEDIT_SIZE))
+    cmds.extend(commands)
+    blob = b"".join(cmds)
+    flags = 0x00200085
+    header = struct.pack("<IiiIIIII", MH_MAGIC_64, cputype, 3 if cputype == CPU_TYPE_X86_64 else 0,
+                         filetype, len(cmds), len(blob), flags, 0)
+    data = header + blob
+    assert len(data) <= TEXT_OFFSET
+    data += b"\0" * (TEXT_OFFSET - len(data))
+    data += b"\xc3" * 16
+    data += b"\0" * (FILE_SIZE - len(data))
+    return data + bytes(range(LINKEDIT_SIZE))
+
+
+def write(name, data):
+    with open(os.path.join(os.path.dirname(os.path.abspath(__file__)), name), "wb") as f:
+        f.write(data)
+
+
+app_commands = [
+    dylib(LC_LOAD_DYLIB, "/usr/lib/libSystem.B.dylib"),
+    dylib(LC_LOAD_DYLIB, "@rpath/libfoo.dylib"),
+    dylib(LC_LOAD_DYLIB, "/opt/homebrew/lib/libbar.dylib"),
+    rpath("/opt/homebrew/lib"),
+]
+write("app", image(CPU_TYPE_X86_64, MH_EXECUTE, app_commands))
+write("app-arm64", image(CPU_TYPE_ARM64, MH_EXECUTE, app_commands))
+write("libfoo.dylib", image(CPU_TYPE_X86_64, MH_DYLIB, [
+    dylib(LC_ID_DYLIB, "@rpath/libfoo.dylib"),
+    dylib(LC_LOAD_DYLIB, "/usr/lib/libSystem.B.dylib"),
+    dylib(LC_LOAD_DYLIB, "/opt/homebrew/lib/libbar.dylib"),
+]))
+write("libbar.dylib", image(CPU_TYPE_X86_64, MH_DYLIB, [
+    dylib(LC_ID_DYLIB, "/opt/homebrew/lib/libbar.dylib"),
+    dylib(LC_LOAD_DYLIB, "/usr/lib/libSystem.B.dylib"),
+]))
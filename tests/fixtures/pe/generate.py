@@ -0,0 +1,136 @@
+#!/usr/bin/env python3
+"""Generates the minimal PE fixtures used by the DLL bundling tests.
+
+The files are structurally valid PE32+ (x86_64) images with a .text section and
+an .idata section holding the import directory, but contain no runnable code.
+
+  app.exe   imports KERNEL32.dll, api-ms-win-crt-runtime-l1-1-0.dll and foo.dll
+  foo.dll   imports BAR.dll (shipped as bar.dll, lookup is case-insensitive) and msvcrt.dll
+  bar.dll   imports KERNEL32.dll
+"""
+import os
+import struct
+
+FILE_ALIGNMENT = 0x200
+SECTION_ALIGNMENT = 0x1000
+IMAGE_BASE = 0x140000000
+TEXT_RVA = 0x1000
+IDATA_RVA = 0x2000
+HEADERS_SIZE = 0x400
+
+IMAGE_FILE_EXECUTABLE_IMAGE = 0x0002
+IMAGE_FILE_LARGE_ADDRESS_AWARE = 0x0020
+IMAGE_FILE_DLL = 0x2000
+
+
+def align(value, alignment):
+    return (value + alignment - 1) // alignment * alignment
+
+
+def idata(imports):
+    """Build the .idata section: descriptors, lookup tables, hint/names, DLL names"""
+    descriptors_size = 20 * (len(imports) + 1)
+    tables = b""
+    strings = b""
+    layout = []
+    # Lookup tables (ILT and IAT have identical contents)
+    for dll, functions in imports:
+        layout.append((dll, functions, len(tables)))
+        tables += b"\0" * (8 * (len(functions) + 1)) * 2
+
+    strings_base = descriptors_size + len(tables)
+    descriptors = b""
+    tables = bytearray(tables)
+    for dll, functions, table_offset in layout:
+        thunks = []
+        for function in functions:
+            thunks.append(IDATA_RVA + strings_base + len(strings))
+            strings += struct.pack("<H", 0) + function.encode() + b"\0"
+            strings += b"\0" * (len(strings) % 2)
+        name_rva = IDATA_RVA + strings_base + len(strings)
+        strings += dll.encode() + b"\0"
+        strings += b"\0" * (len(strings) % 2)
+
+        ilt = descriptors_size + table_offset
+        iat = ilt + 8 * (len(functions) + 1)
+        for i, thunk in enumerate(thunks):
+            struct.pack_into("<Q", tables, table_offset + 8 * i, thunk)
+            struct.pack_into("<Q", tables, table_offset + 8 * (len(functions) + 1) + 8 * i, thunk)
+        descriptors += struct.pack("<IIIII", IDATA_RVA + ilt, 0, 0, name_rva, IDATA_RVA + iat)
+    descriptors += b"\0" * 20
+    return descriptors + bytes(tables) + strings, descriptors_size
+
+
+def image(imports, dll):
+    text = b"\xc3" * 0x10
+    import_data, import_size = idata(imports)
+
+    sections = [
+        (b".text", text, TEXT_RVA, 0x60000020),
+        (b".idata", import_data, IDATA_RVA, 0xC0000040),
+    ]
+
+    dos = bytearray(0x80)
+    dos[0:2] = b"MZ"
+    struct.pack_into("<I", dos, 0x3C, 0x80)
+
+    characteristics = IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE
+    if dll:
+        characteristics |= IMAGE_FILE_DLL
+    optional_size = 112 + 16 * 8
+    coff = struct.pack("<4sHHIIIHH", b"PE\0\0", 0x8664, len(sections), 0, 0, 0,
+                       optional_size, characteristics)
+
+    size_of_image = IDATA_RVA + align(len(import_data), SECTION_ALIGNMENT)
+    optional = struct.pack(
+        "<HBBIIIIIQIIHHHHHHIIIIHHQQQQII",
+        0x20B, 14, 0,
+        align(len(text), FILE_ALIGNMENT), align(len(import_data), FILE_ALIGNMENT), 0,
+        TEXT_RVA, TEXT_RVA,
+        IMAGE_BASE if not dll else 0x180000000,
+        SECTION_ALIGNMENT, FILE_ALIGNMENT,
+        6, 0, 0, 0, 6, 0, 0,
+        size_of_image, HEADERS_SIZE, 0,
+        3 if not dll else 2, 0x8160,
+        0x100000, 0x1000, 0x100000, 0x1000, 0, 16,
+    )
+    directories = [(0, 0)] * 16
+    directories[1] = (IDATA_RVA, import_size)
+    optional += b"".join(struct.pack("<II", rva, size) for rva, size in directories)
+
+    headers = bytes(dos) + coff + optional
+    raw_offset = HEADERS_SIZE
+    section_table = b""
+    bodies = b""
+    for name, data, rva, flags in sections:
+        raw_size = align(len(data), FILE_ALIGNMENT)
+        section_table += struct.pack("<8sIIIIIIHHI", name, len(data), rva, raw_size,
+                                     raw_offset, 0, 0, 0, 0, flags)
+        bodies += data + b"\0" * (raw_size - len(data))
+        raw_offset += raw_size
+    headers += section_table
+    headers += b"\0" * (HEADERS_SIZE - len(headers))
+    return headers + bodies
+
+
+def main():
+    here = os.path.dirname(os.path.abspath(__file__))
+    files = {
+        "app.exe": (
+            [
+                ("KERNEL32.dll", ["ExitProcess"]),
+                ("api-ms-win-crt-runtime-l1-1-0.dll", ["_initterm"]),
+                ("foo.dll", ["foo"]),
+            ],
+            False,
+        ),
+        "foo.dll": ([("BAR.dll", ["bar"]), ("msvcrt.dll", ["malloc"])], True),
+        "bar.dll": ([("KERNEL32.dll", ["GetLastError"])], True),
+    }
+    for name, (imports, dll) in files.items():
+        with open(os.path.join(here, name), "wb") as f:
+            f.write(image(imports, dll))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,14 @@
+#!/bin/sh
+# Regenerates the self-signed code signing identity used by the signing tests.
+# identity.p12 holds an RSA-2048 key and certificate, password "emerge".
+set -e
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+openssl req -x509 -newkey rsa:2048 -nodes -days 36500 \
+    -subj "/CN=Emerge Test Signing/OU=EMERGETEST/O=Emerge" \
+    -addext "extendedKeyUsage=codeSigning" \
+    -addext "keyUsage=digitalSignature" \
+    -keyout "$tmp/key.pem" -out "$tmp/cert.pem"
+openssl pkcs12 -export -inkey "$tmp/key.pem" -in "$tmp/cert.pem" \
+    -name "Emerge Test Signing" -passout pass:emerge -out identity.p12